@@ -0,0 +1,56 @@
+//! Inputs for the proof aggregation benches.
+//!
+//! These are kept separate from `inputs` & `env_vars` because only the
+//! Criterion benches use them, and the other bench targets would otherwise
+//! compile them as dead code.
+
+use dapol::{percentage::Percentage, AggregationFactor};
+use once_cell::sync::Lazy;
+use std::str::FromStr;
+
+/// Sets the lower bound on the aggregation percentage for proof benchmarks.
+///
+/// A pre-determined list of percentages is looped over, and each one is used
+/// as input for a benchmark. This env var sets the lower bound in that list.
+pub static MIN_AGGREGATION_PERCENT: Lazy<Percentage> = Lazy::new(|| {
+    Percentage::from_str(
+        std::env::var("MIN_AGGREGATION_PERCENT")
+            .unwrap_or("0".to_string())
+            .as_str(),
+    )
+    .expect("MIN_AGGREGATION_PERCENT env var string parsing error")
+});
+
+/// Sets the upper bound on the aggregation percentage for proof benchmarks.
+///
+/// A pre-determined list of percentages is looped over, and each one is used
+/// as input for a benchmark. This env var sets the upper bound in that list.
+pub static MAX_AGGREGATION_PERCENT: Lazy<Percentage> = Lazy::new(|| {
+    Percentage::from_str(
+        std::env::var("MAX_AGGREGATION_PERCENT")
+            .unwrap_or("100".to_string())
+            .as_str(),
+    )
+    .expect("MAX_AGGREGATION_PERCENT env var string parsing error")
+});
+
+/// Aggregation percentages used for the proof generation benches.
+///
+/// The 2 end points give fully individual and fully aggregated range proofs.
+pub fn aggregation_percentages() -> Vec<Percentage> {
+    Vec::from([0, 25, 50, 75, 100])
+        .into_iter()
+        .map(Percentage::expect_from)
+        .collect()
+}
+
+pub fn aggregation_factors_in_range(
+    lower: Percentage,
+    upper: Percentage,
+) -> Vec<AggregationFactor> {
+    aggregation_percentages()
+        .into_iter()
+        .filter(|x| lower.value() <= x.value() && x.value() <= upper.value())
+        .map(AggregationFactor::Percent)
+        .collect()
+}
@@ -15,11 +15,19 @@ use criterion::{criterion_group, criterion_main};
 use criterion::{BenchmarkId, Criterion, SamplingMode};
 use statistical::*;
 
-use dapol::{DapolConfigBuilder, DapolTree, InclusionProof, Secret, InclusionProofFileType};
+use dapol::{
+    percentage::Percentage, AggregationFactor, DapolConfigBuilder, DapolTree, InclusionProof,
+    InclusionProofFileType, Secret,
+};
 
 mod inputs;
 use inputs::{max_thread_counts_greater_than, num_entities_in_range, tree_heights_in_range};
 
+mod aggregation_inputs;
+use aggregation_inputs::{
+    aggregation_factors_in_range, MAX_AGGREGATION_PERCENT, MIN_AGGREGATION_PERCENT,
+};
+
 mod memory_usage_estimation;
 use memory_usage_estimation::estimated_total_memory_usage_mb;
 
@@ -196,6 +204,56 @@ pub fn bench_build_tree<T: Measurement>(c: &mut Criterion<T>) {
     }
 }
 
+/// Loop over height, with a completely full bottom layer.
+///
+/// Dense bottom layers are where the multi-threaded builder has to split the
+/// most leaves at each level of the recursion (see `num_nodes_left_of`), so
+/// this is the bench to look at for changes to the splitting code. Heights
+/// whose bottom layer holds more than `MAX_ENTITIES` are skipped.
+pub fn bench_build_dense_tree<T: Measurement>(c: &mut Criterion<T>) {
+    let master_secret = Secret::from_str("secret").unwrap();
+
+    dapol::initialize_machine_parallelism();
+    dapol::utils::activate_logging(*LOG_VERBOSITY);
+
+    let mut group = c.benchmark_group("build_tree");
+    group.sampling_mode(SamplingMode::Flat);
+
+    for h in tree_heights_in_range(*MIN_HEIGHT, *MAX_HEIGHT).into_iter() {
+        let n = h.max_bottom_layer_nodes();
+
+        if n > *MAX_ENTITIES {
+            println!(
+                "Skipping dense input height_{} since number of entities {} is greater than \
+                      MAX_ENTITIES",
+                h.as_u32(),
+                n
+            );
+
+            continue;
+        }
+
+        group.bench_with_input(
+            BenchmarkId::new("build_dense_tree", format!("height_{}", h.as_u32())),
+            &(h, n),
+            |bench, tup| {
+                bench.iter(|| {
+                    DapolConfigBuilder::default()
+                        .accumulator_type(dapol::AccumulatorType::NdmSmt)
+                        .height(tup.0)
+                        .num_random_entities(tup.1)
+                        .master_secret(master_secret.clone())
+                        .min_sparsity_warning(0)
+                        .build()
+                        .expect("Unable to build DapolConfig")
+                        .parse()
+                        .expect("Unable to parse NdmSmtConfig")
+                });
+            },
+        );
+    }
+}
+
 /// We only loop through `tree_heights` & `num_entities` because we want proof
 /// generation to have maximum threads.
 pub fn bench_generate_proof<T: Measurement>(c: &mut Criterion<T>) {
@@ -306,6 +364,100 @@ pub fn bench_generate_proof<T: Measurement>(c: &mut Criterion<T>) {
     }
 }
 
+/// Proof generation & proof serialization, benched separately, for different
+/// aggregation factors.
+///
+/// [bench_generate_proof] only uses the default aggregation factor. Here the
+/// tree is fixed for each height and the aggregation factor is varied, which
+/// isolates the cost of generating the Bulletproofs. Only the height of the
+/// tree and the aggregation factor affect the range proofs, so the number of
+/// entities is kept small.
+pub fn bench_generate_proof_by_aggregation<T: Measurement>(c: &mut Criterion<T>) {
+    let mut group = c.benchmark_group("proofs");
+
+    let master_secret = Secret::from_str("secret").unwrap();
+    let num_entities = 10;
+
+    dapol::initialize_machine_parallelism();
+    dapol::utils::activate_logging(*LOG_VERBOSITY);
+
+    let src_dir = env!("CARGO_MANIFEST_DIR");
+    let target_dir = Path::new(&src_dir).join("target");
+    let dir = target_dir.join("serialized_proofs");
+    std::fs::create_dir_all(dir.clone()).unwrap();
+
+    for h in tree_heights_in_range(*MIN_HEIGHT, *MAX_HEIGHT).into_iter() {
+        let dapol_tree = DapolConfigBuilder::default()
+            .accumulator_type(dapol::AccumulatorType::NdmSmt)
+            .master_secret(master_secret.clone())
+            .height(h)
+            .num_random_entities(num_entities)
+            .build()
+            .expect("Unable to build DapolConfig")
+            .parse()
+            .expect("Unable to parse NdmSmtConfig");
+
+        let entity_id = dapol_tree
+            .entity_mapping()
+            .unwrap()
+            .keys()
+            .next()
+            .expect("Tree should have at least 1 entity");
+
+        for aggregation_factor in aggregation_factors_in_range(
+            MIN_AGGREGATION_PERCENT.clone(),
+            MAX_AGGREGATION_PERCENT.clone(),
+        )
+        .into_iter()
+        {
+            let mut proof = Option::<InclusionProof>::None;
+
+            group.bench_function(
+                BenchmarkId::new(
+                    "generate_proof_by_aggregation",
+                    format!(
+                        "height_{}/aggregation_factor_{}",
+                        h.as_u32(),
+                        aggregation_factor
+                    ),
+                ),
+                |bench| {
+                    bench.iter(|| {
+                        proof = Some(
+                            dapol_tree
+                                .generate_inclusion_proof_with(
+                                    entity_id,
+                                    aggregation_factor.clone(),
+                                )
+                                .expect("Proof should have been generated successfully"),
+                        );
+                    });
+                },
+            );
+
+            let proof = proof.expect("Proof should be set");
+
+            group.bench_function(
+                BenchmarkId::new(
+                    "serialize_proof",
+                    format!(
+                        "height_{}/aggregation_factor_{}",
+                        h.as_u32(),
+                        aggregation_factor
+                    ),
+                ),
+                |bench| {
+                    bench.iter(|| {
+                        proof
+                            .serialize(entity_id, dir.clone(), InclusionProofFileType::Binary)
+                            .unwrap()
+                    });
+                },
+            );
+        }
+    }
+}
+
 /// We only loop through `tree_heights` & `num_entities` because proof
 /// verification does not depend on number of threads.
 pub fn bench_verify_proof<T: Measurement>(c: &mut Criterion<T>) {
@@ -394,6 +546,155 @@ pub fn bench_verify_proof<T: Measurement>(c: &mut Criterion<T>) {
     }
 }
 
+/// Compare sequential & parallel verification of the individual range proofs.
+///
+/// The individual range proofs are verified in parallel using rayon, so
+/// sequential verification is simulated by running the verification in a
+/// thread pool with a single thread. Only the height of the tree and the
+/// aggregation factor affect the number of individual range proofs, so the
+/// number of entities is kept small.
+pub fn bench_verify_individual_range_proofs<T: Measurement>(c: &mut Criterion<T>) {
+    let mut group = c.benchmark_group("proofs");
+
+    let master_secret = Secret::from_str("secret").unwrap();
+    let num_entities = 10;
+
+    dapol::initialize_machine_parallelism();
+    dapol::utils::activate_logging(*LOG_VERBOSITY);
+
+    let sequential_pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(1)
+        .build()
+        .expect("Unable to build single-threaded thread pool");
+
+    for h in tree_heights_in_range(*MIN_HEIGHT, *MAX_HEIGHT).into_iter() {
+        let dapol_tree = DapolConfigBuilder::default()
+            .accumulator_type(dapol::AccumulatorType::NdmSmt)
+            .master_secret(master_secret.clone())
+            .height(h)
+            .num_random_entities(num_entities)
+            .build()
+            .expect("Unable to build DapolConfig")
+            .parse()
+            .expect("Unable to parse NdmSmtConfig");
+
+        let root_hash = dapol_tree.root_hash();
+
+        let entity_id = dapol_tree
+            .entity_mapping()
+            .unwrap()
+            .keys()
+            .next()
+            .expect("Tree should have at least 1 entity");
+
+        for p in [0u8, 25, 50, 75] {
+            let proof = dapol_tree
+                .generate_inclusion_proof_with(
+                    entity_id,
+                    AggregationFactor::Percent(Percentage::expect_from(p)),
+                )
+                .expect("Proof should have been generated successfully");
+
+            group.bench_function(
+                BenchmarkId::new(
+                    "verify_proof_sequential",
+                    format!("height_{}/aggregation_percent_{}", h.as_u32(), p),
+                ),
+                |bench| {
+                    bench.iter(|| sequential_pool.install(|| proof.verify(*root_hash)));
+                },
+            );
+
+            group.bench_function(
+                BenchmarkId::new(
+                    "verify_proof_parallel",
+                    format!("height_{}/aggregation_percent_{}", h.as_u32(), p),
+                ),
+                |bench| {
+                    bench.iter(|| proof.verify(*root_hash));
+                },
+            );
+        }
+    }
+}
+
+/// Predicted serialized proof size for different aggregation factors.
+///
+/// More aggregation gives smaller proofs, so the predicted size for each file
+/// type is printed alongside the time it takes to compute it. Only the height
+/// of the tree and the aggregation factor affect the proof size, so the
+/// number of entities is kept small.
+pub fn bench_proof_size<T: Measurement>(c: &mut Criterion<T>) {
+    let mut group = c.benchmark_group("proofs");
+
+    let master_secret = Secret::from_str("secret").unwrap();
+    let num_entities = 10;
+
+    dapol::initialize_machine_parallelism();
+    dapol::utils::activate_logging(*LOG_VERBOSITY);
+
+    for h in tree_heights_in_range(*MIN_HEIGHT, *MAX_HEIGHT).into_iter() {
+        let dapol_tree = DapolConfigBuilder::default()
+            .accumulator_type(dapol::AccumulatorType::NdmSmt)
+            .master_secret(master_secret.clone())
+            .height(h)
+            .num_random_entities(num_entities)
+            .build()
+            .expect("Unable to build DapolConfig")
+            .parse()
+            .expect("Unable to parse NdmSmtConfig");
+
+        let entity_id = dapol_tree
+            .entity_mapping()
+            .unwrap()
+            .keys()
+            .next()
+            .expect("Tree should have at least 1 entity");
+
+        for p in [0u8, 25, 50, 75, 100] {
+            let proof = dapol_tree
+                .generate_inclusion_proof_with(
+                    entity_id,
+                    AggregationFactor::Percent(Percentage::expect_from(p)),
+                )
+                .expect("Proof should have been generated successfully");
+
+            for file_type in [
+                InclusionProofFileType::Binary,
+                InclusionProofFileType::Json,
+                InclusionProofFileType::MessagePack,
+            ] {
+                let size = proof
+                    .serialized_size(file_type.clone())
+                    .expect("Proof size should have been computed successfully");
+
+                println!(
+                    "\nPredicted {} proof size for height {} & aggregation percent {}: {}\n",
+                    file_type,
+                    h.as_u32(),
+                    p,
+                    bytes_to_string(size)
+                );
+
+                group.bench_function(
+                    BenchmarkId::new(
+                        "proof_size",
+                        format!(
+                            "height_{}/aggregation_percent_{}/{}",
+                            h.as_u32(),
+                            p,
+                            file_type
+                        ),
+                    ),
+                    |bench| {
+                        bench.iter(|| proof.serialized_size(file_type.clone()));
+                    },
+                );
+            }
+        }
+    }
+}
+
 // -------------------------------------------------------------------------------------------------
 // Macros.
 
@@ -402,7 +703,7 @@ use std::time::Duration;
 criterion_group! {
     name = wall_clock_time;
     config = Criterion::default().sample_size(10).measurement_time(Duration::from_secs(600));
-    targets = bench_build_tree, bench_generate_proof, bench_verify_proof
+    targets = bench_build_tree, bench_build_dense_tree, bench_generate_proof, bench_generate_proof_by_aggregation, bench_verify_proof, bench_verify_individual_range_proofs, bench_proof_size
 }
 
 // Does not work, see memory_measurement.rs
@@ -410,7 +711,7 @@ criterion_group! {
 // criterion_group! {
 //     name = memory_usage;
 //     config = Criterion::default().sample_size(10).measurement_time(Duration::from_secs(60)).with_measurement(memory_measurement::Memory);
-//     targets = bench_build_tree, bench_generate_proof, bench_verify_proof,
+//     targets = bench_build_tree, bench_build_dense_tree, bench_generate_proof, bench_verify_proof,
 // }
 
 criterion_main!(wall_clock_time);
@@ -0,0 +1,66 @@
+//! Parse throughput of the entities parser, comparing the buffered reader to
+//! memory-mapping the file.
+//!
+//! Run with `cargo bench --features mmap --bench entities_parser_benches`.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use criterion::{criterion_group, criterion_main};
+use criterion::{BenchmarkId, Criterion, Throughput};
+
+use dapol::EntitiesParser;
+
+/// Write a CSV file with `num_entities` entity records to the temp dir.
+fn write_entities_file(num_entities: u64) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("dapol_bench_entities_{}.csv", num_entities));
+
+    let mut writer = BufWriter::new(File::create(&path).expect("Unable to create entities file"));
+    writeln!(writer, "id,liability").unwrap();
+    for i in 0..num_entities {
+        writeln!(writer, "entity_{}@example.com,{}", i, i * 7919 % 1_000_000).unwrap();
+    }
+    writer.flush().unwrap();
+
+    path
+}
+
+pub fn bench_parse_entities_file(c: &mut Criterion) {
+    let mut group = c.benchmark_group("entities_parser");
+
+    for num_entities in [10_000u64, 100_000, 1_000_000] {
+        let path = write_entities_file(num_entities);
+        let file_size = std::fs::metadata(&path).unwrap().len();
+        group.throughput(Throughput::Bytes(file_size));
+
+        for mmap in [false, true] {
+            group.bench_with_input(
+                BenchmarkId::new(
+                    if mmap { "parse_mmap" } else { "parse_buffered" },
+                    format!("num_entities_{}", num_entities),
+                ),
+                &path,
+                |bench, path| {
+                    bench.iter(|| {
+                        EntitiesParser::new()
+                            .with_path(path.clone())
+                            .with_mmap(mmap)
+                            .parse_file()
+                            .expect("Unable to parse entities file")
+                    });
+                },
+            );
+        }
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+criterion_group! {
+    name = parse_throughput;
+    config = Criterion::default().sample_size(10);
+    targets = bench_parse_entities_file
+}
+
+criterion_main!(parse_throughput);
@@ -12,10 +12,10 @@ use std::fmt;
 mod ndm_smt;
 pub use ndm_smt::{NdmSmt, NdmSmtError, RandomXCoordGenerator};
 
-use crate::Height;
+use crate::{Coordinate, Height};
 
 /// Supported accumulators, with their linked data.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Accumulator {
     NdmSmt(ndm_smt::NdmSmt),
     // TODO add other accumulators..
@@ -29,6 +29,21 @@ impl Accumulator {
         }
     }
 
+    /// Number of nodes in the tree's store.
+    pub fn stored_node_count(&self) -> usize {
+        match self {
+            Accumulator::NdmSmt(ndm_smt) => ndm_smt.stored_node_count(),
+        }
+    }
+
+    /// Coordinates of the nodes in the tree's store, sorted by `y` and then
+    /// `x`.
+    pub fn stored_coordinates(&self) -> Vec<Coordinate> {
+        match self {
+            Accumulator::NdmSmt(ndm_smt) => ndm_smt.stored_coordinates(),
+        }
+    }
+
     /// Return the accumulator type.
     pub fn get_type(&self) -> AccumulatorType {
         match self {
@@ -66,7 +81,7 @@ impl Accumulator {
 }
 
 /// Various supported accumulator types.
-#[derive(Clone, Deserialize, Debug, ValueEnum, PartialEq)]
+#[derive(Clone, Serialize, Deserialize, Debug, ValueEnum, PartialEq)]
 #[serde(rename_all = "kebab-case")]
 pub enum AccumulatorType {
     NdmSmt,
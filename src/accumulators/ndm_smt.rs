@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::sync::Arc;
 
 use curve25519_dalek_ng::{ristretto::RistrettoPoint, scalar::Scalar};
 use primitive_types::H256;
@@ -11,11 +12,12 @@ use rayon::prelude::*;
 
 use crate::{
     binary_tree::{
-        BinaryTree, BinaryTreeBuilder, Coordinate, FullNodeContent, Height, InputLeafNode,
-        PathSiblings,
+        BinaryTree, BinaryTreeBuilder, Coordinate, FullNodeContent, Height, HiddenNodeContent,
+        InputLeafNode, Node, PathSiblings,
     },
+    build_options::{BuildOptions, PaddingFn},
     entity::{Entity, EntityId},
-    inclusion_proof::{AggregationFactor, InclusionProof},
+    inclusion_proof::{AggregationFactor, InclusionProof, JointInclusionProof, MembershipProof},
     kdf, MaxThreadCount, Salt, Secret,
 };
 
@@ -44,10 +46,17 @@ type Content = FullNodeContent;
 /// mapped to a leaf node, and this assignment is non-deterministic. The map
 /// keeps track of which entity is assigned to which leaf node.
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NdmSmt {
     binary_tree: BinaryTree<Content>,
     entity_mapping: HashMap<EntityId, u64>,
+    domain_separator: Option<String>,
+    /// Whether the tree was built with a custom padding function. The
+    /// function itself cannot be serialized, so this is used to detect when
+    /// it has not been set again after deserialization.
+    custom_padding: bool,
+    #[serde(skip)]
+    padding_fn: Option<PaddingFn>,
 }
 
 impl NdmSmt {
@@ -62,13 +71,17 @@ impl NdmSmt {
     #[doc = include_str!("../shared_docs/salt_s.md")]
     /// - `height`:
     #[doc = include_str!("../shared_docs/height.md")]
-    /// - `max_thread_count`:
-    #[doc = include_str!("../shared_docs/max_thread_count.md")]
     /// - `entities`:
     #[doc = include_str!("../shared_docs/entities_vector.md")]
     /// Each element in `entities` is converted to an
     /// [input leaf node] and randomly assigned a position on the
     /// bottom layer of the tree.
+    /// - `options`: optional build parameters, see [BuildOptions]. All the
+    ///   options that are set are applied together: the max thread count,
+    ///   build strategy, cancellation token, sparsity warning threshold &
+    ///   store capacity are passed on to the [BinaryTreeBuilder], the leaf mapping chunk size is
+    ///   used when mapping the entities to leaf nodes, and the padding
+    ///   function & domain separator are used to generate the node content.
     ///
     /// An [NdmSmtError] is returned if:
     /// 1. There are more entities than the height allows i.e. more entities
@@ -87,89 +100,63 @@ impl NdmSmt {
         salt_b: Salt,
         salt_s: Salt,
         height: Height,
-        max_thread_count: MaxThreadCount,
         entities: Vec<Entity>,
+        options: BuildOptions,
     ) -> Result<Self, NdmSmtError> {
-        let x_coord_generator = RandomXCoordGenerator::new(&height);
-
-        NdmSmt::new_with_random_x_coord_generator(
-            master_secret,
-            salt_b,
-            salt_s,
-            height,
+        // The max liability is only used by the tree, for the range proofs.
+        let BuildOptions {
+            max_liability: _,
             max_thread_count,
-            entities,
-            x_coord_generator,
-        )
-    }
+            build_strategy,
+            cancellation_token,
+            leaf_mapping_chunk_size,
+            min_sparsity_warning,
+            padding_fn,
+            domain_separator,
+            store_capacity_bytes,
+            #[cfg(any(test, feature = "testing"))]
+            random_seed,
+        } = options;
 
-    /// Constructor for testing purposes.
-    ///
-    /// Note: This is **not** cryptographically secure and should only be used
-    /// for testing.
-    ///
-    /// Parameters:
-    /// - `master_secret`:
-    #[doc = include_str!("../shared_docs/master_secret.md")]
-    /// - `salt_b`:
-    #[doc = include_str!("../shared_docs/salt_b.md")]
-    /// - `salt_s`:
-    #[doc = include_str!("../shared_docs/salt_s.md")]
-    /// - `height`:
-    #[doc = include_str!("../shared_docs/height.md")]
-    /// - `max_thread_count`:
-    #[doc = include_str!("../shared_docs/max_thread_count.md")]
-    /// - `entities`:
-    #[doc = include_str!("../shared_docs/entities_vector.md")]
-    /// Each element in `entities` is converted to an
-    /// [input leaf node] and randomly assigned a position on the
-    /// bottom layer of the tree.
-    /// - `seed`: random seed for the x-coord PRNG mapping algorithm.
-    ///
-    /// An [NdmSmtError] is returned if:
-    /// 1. There are more entities than the height allows i.e. more entities
-    /// than would fit on the bottom layer.
-    /// 2. The tree build fails for some reason.
-    /// 3. There are duplicate entity IDs.
-    ///
-    /// The function will panic if there is a problem joining onto a spawned
-    /// thread, or if concurrent variables are not able to be locked. It's not
-    /// clear how to recover from these scenarios because variables may be in
-    /// an unknown state, so rather panic.
-    ///
-    /// [input leaf node]: crate::binary_tree::InputLeafNode
-    #[cfg(any(test, feature = "testing"))]
-    pub fn new_with_random_seed(
-        master_secret: Secret,
-        salt_b: Salt,
-        salt_s: Salt,
-        height: Height,
-        max_thread_count: MaxThreadCount,
-        entities: Vec<Entity>,
-        seed: u64,
-    ) -> Result<Self, NdmSmtError> {
-        let x_coord_generator = RandomXCoordGenerator::new_with_seed(&height, seed);
+        #[cfg(any(test, feature = "testing"))]
+        let mut x_coord_generator = match random_seed {
+            Some(seed) => RandomXCoordGenerator::new_with_seed(&height, seed),
+            None => RandomXCoordGenerator::new(&height),
+        };
+        #[cfg(not(any(test, feature = "testing")))]
+        let mut x_coord_generator = RandomXCoordGenerator::new(&height);
 
-        NdmSmt::new_with_random_x_coord_generator(
-            master_secret,
-            salt_b,
-            salt_s,
-            height,
-            max_thread_count,
-            entities,
-            x_coord_generator,
+        let mut tree_builder = BinaryTreeBuilder::new()
+            .with_max_thread_count(max_thread_count)
+            .with_build_strategy(build_strategy);
+        if let Some(cancellation_token) = cancellation_token {
+            tree_builder = tree_builder.with_cancellation_token(cancellation_token);
+        }
+        if let Some(min_sparsity_warning) = min_sparsity_warning {
+            tree_builder = tree_builder.with_min_sparsity_warning(min_sparsity_warning);
+        }
+        if let Some(store_capacity_bytes) = store_capacity_bytes {
+            tree_builder = tree_builder.with_store_capacity_bytes(store_capacity_bytes);
+        }
+
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!(
+            "NdmSmt::new",
+            height = height.as_u32(),
+            num_entities = entities.len()
         )
-    }
+        .entered();
+
+        // Fail before doing any KDF work, rather than waiting for the x-coord
+        // generator to run out of coordinates.
+        let max_entities = height.max_bottom_layer_nodes();
+        if entities.len() as u64 > max_entities {
+            return Err(NdmSmtError::TooManyEntities {
+                given: entities.len() as u64,
+                max: max_entities,
+            });
+        }
 
-    fn new_with_random_x_coord_generator(
-        master_secret: Secret,
-        salt_b: Salt,
-        salt_s: Salt,
-        height: Height,
-        max_thread_count: MaxThreadCount,
-        entities: Vec<Entity>,
-        mut x_coord_generator: RandomXCoordGenerator,
-    ) -> Result<Self, NdmSmtError> {
         let master_secret_bytes = master_secret.as_bytes();
         let salt_b_bytes = salt_b.as_bytes();
         let salt_s_bytes = salt_s.as_bytes();
@@ -196,6 +183,9 @@ impl NdmSmt {
         let (leaf_nodes, entity_coord_tuples) = {
             // Map the entities to bottom-layer leaf nodes.
 
+            #[cfg(feature = "tracing")]
+            let _span = tracing::info_span!("kdf", num_entities = entities.len()).entered();
+
             let tmr = timer!(Level::Debug; "Entity to leaf node conversion");
 
             let mut x_coords = Vec::<u64>::with_capacity(entities.len());
@@ -209,28 +199,47 @@ impl NdmSmt {
                 .zip(x_coords.into_iter())
                 .collect::<Vec<(Entity, u64)>>();
 
-            let leaf_nodes = entity_coord_tuples
-                .par_iter()
-                .map(|(entity, x_coord)| {
-                    // `w` is the letter used in the DAPOL+ paper.
-                    let entity_secret: [u8; 32] =
-                        kdf::generate_key(None, master_secret_bytes, Some(&x_coord.to_le_bytes()))
-                            .into();
-                    let blinding_factor =
-                        kdf::generate_key(Some(salt_b_bytes), &entity_secret, None);
-                    let entity_salt = kdf::generate_key(Some(salt_s_bytes), &entity_secret, None);
-
-                    InputLeafNode {
-                        content: Content::new_leaf(
-                            entity.liability,
-                            blinding_factor.into(),
-                            entity.id.clone(),
-                            entity_salt.into(),
-                        ),
-                        x_coord: *x_coord,
-                    }
-                })
-                .collect::<Vec<InputLeafNode<Content>>>();
+            let to_leaf_node = |(entity, x_coord): &(Entity, u64)| {
+                let (derived_blinding_factor, derived_salt) = kdf::derive_entity_secrets(
+                    master_secret_bytes,
+                    salt_b_bytes,
+                    salt_s_bytes,
+                    *x_coord,
+                );
+                // An externally-supplied blinding factor or salt takes
+                // precedence over the derived one.
+                let blinding_factor: Secret = match entity.blinding_factor {
+                    Some(blinding_factor) => blinding_factor.into(),
+                    None => derived_blinding_factor.into(),
+                };
+                let entity_salt: Secret = match &entity.salt {
+                    Some(salt) => salt.clone().into(),
+                    None => derived_salt.into(),
+                };
+
+                InputLeafNode {
+                    content: Content::new_leaf_with_domain_separator(
+                        entity.liability,
+                        blinding_factor,
+                        entity.id.clone(),
+                        entity_salt,
+                        domain_separator.as_deref(),
+                    ),
+                    x_coord: *x_coord,
+                }
+            };
+
+            let leaf_nodes = match leaf_mapping_chunk_size {
+                Some(chunk_size) => entity_coord_tuples
+                    .par_iter()
+                    .with_min_len(chunk_size)
+                    .map(to_leaf_node)
+                    .collect::<Vec<InputLeafNode<Content>>>(),
+                None => entity_coord_tuples
+                    .par_iter()
+                    .map(to_leaf_node)
+                    .collect::<Vec<InputLeafNode<Content>>>(),
+            };
 
             logging_timer::finish!(
                 tmr,
@@ -252,19 +261,33 @@ impl NdmSmt {
             entity_mapping.insert(entity.id, x_coord);
         }
 
-        let tree = BinaryTreeBuilder::new()
-            .with_height(height)
-            .with_leaf_nodes(leaf_nodes)
-            .with_max_thread_count(max_thread_count)
-            .build_using_multi_threaded_algorithm(new_padding_node_content_closure(
-                *master_secret_bytes,
-                *salt_b_bytes,
-                *salt_s_bytes,
-            ))?;
+        let tree = {
+            #[cfg(feature = "tracing")]
+            let _span = tracing::info_span!(
+                "build_tree",
+                height = height.as_u32(),
+                num_leaf_nodes = leaf_nodes.len()
+            )
+            .entered();
+
+            tree_builder
+                .with_height(height)
+                .with_leaf_nodes(leaf_nodes)
+                .build(new_padding_node_content_closure(
+                    padding_fn.clone(),
+                    domain_separator.clone(),
+                    *master_secret_bytes,
+                    *salt_b_bytes,
+                    *salt_s_bytes,
+                ))?
+        };
 
         Ok(NdmSmt {
             binary_tree: tree,
             entity_mapping,
+            domain_separator,
+            custom_padding: padding_fn.is_some(),
+            padding_fn,
         })
     }
 
@@ -299,17 +322,22 @@ impl NdmSmt {
         aggregation_factor: AggregationFactor,
         upper_bound_bit_length: u8,
     ) -> Result<InclusionProof, NdmSmtError> {
-        let master_secret_bytes = master_secret.as_bytes();
-        let salt_b_bytes = salt_b.as_bytes();
-        let salt_s_bytes = salt_s.as_bytes();
-        let new_padding_node_content =
-            new_padding_node_content_closure(*master_secret_bytes, *salt_b_bytes, *salt_s_bytes);
+        self.check_padding_fn()?;
 
-        let leaf_node = self
-            .entity_mapping
-            .get(entity_id)
-            .and_then(|leaf_x_coord| self.binary_tree.get_leaf_node(*leaf_x_coord))
-            .ok_or(NdmSmtError::EntityIdNotFound(entity_id.clone()))?;
+        // The entity ID is left out of the span because it may be personal
+        // data (e.g. an email address).
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!(
+            "generate_inclusion_proof",
+            height = self.binary_tree.height().as_u32(),
+            %aggregation_factor,
+            upper_bound_bit_length
+        )
+        .entered();
+
+        let new_padding_node_content = self.padding_closure(master_secret, salt_b, salt_s);
+
+        let leaf_node = self.leaf_for(entity_id)?;
 
         let path_siblings = PathSiblings::build_using_multi_threaded_algorithm(
             &self.binary_tree,
@@ -325,116 +353,1167 @@ impl NdmSmt {
         )?)
     }
 
-    #[doc = include_str!("../shared_docs/root_hash.md")]
-    pub fn root_hash(&self) -> &H256 {
-        &self.binary_tree.root().content.hash
-    }
+    /// Generate an inclusion proof for the bottom-layer leaf node at
+    /// `x_coord`, whether it is an entity's leaf node or a padding node.
+    ///
+    /// This is useful for spot-checking specific positions in the tree
+    /// without knowing which entity (if any) is mapped there. Padding nodes
+    /// that are not in the store are regenerated from the secrets.
+    ///
+    /// Parameters:
+    /// - `master_secret`:
+    #[doc = include_str!("../shared_docs/master_secret.md")]
+    /// - `salt_b`:
+    #[doc = include_str!("../shared_docs/salt_b.md")]
+    /// - `salt_s`:
+    #[doc = include_str!("../shared_docs/salt_s.md")]
+    /// - `x_coord`: x-coord of the bottom-layer node that the proof will be
+    ///   generated for.
+    /// - `aggregation_factor`:
+    #[doc = include_str!("../shared_docs/aggregation_factor.md")]
+    /// - `upper_bound_bit_length`:
+    #[doc = include_str!("../shared_docs/upper_bound_bit_length.md")]
+    ///
+    /// An error is returned if `x_coord` is outside the bottom layer of the
+    /// tree.
+    pub fn generate_inclusion_proof_by_coord(
+        &self,
+        master_secret: &Secret,
+        salt_b: &Salt,
+        salt_s: &Salt,
+        x_coord: u64,
+        aggregation_factor: AggregationFactor,
+        upper_bound_bit_length: u8,
+    ) -> Result<InclusionProof, NdmSmtError> {
+        self.check_padding_fn()?;
 
-    #[doc = include_str!("../shared_docs/root_hash.md")]
-    pub fn root_commitment(&self) -> &RistrettoPoint {
-        &self.binary_tree.root().content.commitment
-    }
+        let max_bottom_layer_nodes = self.height().max_bottom_layer_nodes();
+        if x_coord >= max_bottom_layer_nodes {
+            return Err(NdmSmtError::XCoordOutOfBounds {
+                x_coord,
+                max_bottom_layer_nodes,
+            });
+        }
 
-    #[doc = include_str!("../shared_docs/root_liability.md")]
-    pub fn root_liability(&self) -> u64 {
-        self.binary_tree.root().content.liability
-    }
+        let new_padding_node_content = self.padding_closure(master_secret, salt_b, salt_s);
 
-    #[doc = include_str!("../shared_docs/root_blinding_factor.md")]
-    pub fn root_blinding_factor(&self) -> &Scalar {
-        &self.binary_tree.root().content.blinding_factor
-    }
+        let leaf_node = self.binary_tree.get_leaf_node(x_coord).unwrap_or_else(|| {
+            let coord = Coordinate { x: x_coord, y: 0 };
+            let content = new_padding_node_content(&coord);
+            Node { coord, content }
+        });
 
-    /// Hash map giving the x-coord that each entity is mapped to.
-    pub fn entity_mapping(&self) -> &HashMap<EntityId, u64> {
-        &self.entity_mapping
-    }
+        let path_siblings = PathSiblings::build_using_multi_threaded_algorithm(
+            &self.binary_tree,
+            &leaf_node,
+            new_padding_node_content,
+        )?;
 
-    #[doc = include_str!("../shared_docs/height.md")]
-    pub fn height(&self) -> &Height {
-        self.binary_tree.height()
+        Ok(InclusionProof::generate(
+            leaf_node,
+            path_siblings,
+            aggregation_factor,
+            upper_bound_bit_length,
+        )?)
     }
-}
 
-// -------------------------------------------------------------------------------------------------
-// Helper functions.
+    /// Generate inclusion proofs for the given `entity_id`, one for each of
+    /// the given aggregation factors.
+    ///
+    /// The path siblings are only built once and shared by all the proofs, so
+    /// this is cheaper than calling [NdmSmt::generate_inclusion_proof] once
+    /// for each aggregation factor. The proofs are returned in the same order
+    /// as `aggregation_factors`.
+    ///
+    /// Parameters:
+    /// - `master_secret`:
+    #[doc = include_str!("../shared_docs/master_secret.md")]
+    /// - `salt_b`:
+    #[doc = include_str!("../shared_docs/salt_b.md")]
+    /// - `salt_s`:
+    #[doc = include_str!("../shared_docs/salt_s.md")]
+    /// - `entity_id`: unique ID for the entity that the proofs will be
+    ///   generated for.
+    /// - `aggregation_factors`: the aggregation factor to use for each proof.
+    /// - `upper_bound_bit_length`:
+    #[doc = include_str!("../shared_docs/upper_bound_bit_length.md")]
+    pub fn generate_inclusion_proofs_for_aggregation_factors(
+        &self,
+        master_secret: &Secret,
+        salt_b: &Salt,
+        salt_s: &Salt,
+        entity_id: &EntityId,
+        aggregation_factors: &[AggregationFactor],
+        upper_bound_bit_length: u8,
+    ) -> Result<Vec<InclusionProof>, NdmSmtError> {
+        self.check_padding_fn()?;
 
-/// Create a new closure that generates padding node content using the secret
-/// values.
-fn new_padding_node_content_closure(
-    master_secret_bytes: [u8; 32],
-    salt_b_bytes: [u8; 32],
-    salt_s_bytes: [u8; 32],
-) -> impl Fn(&Coordinate) -> Content {
-    // closure that is used to create new padding nodes
-    move |coord: &Coordinate| {
-        // TODO unfortunately we copy data here, maybe there is a way to do without
-        // copying
-        let coord_bytes = coord.to_bytes();
-        // pad_secret is given as 'w' in the DAPOL+ paper
-        let pad_secret = kdf::generate_key(None, &master_secret_bytes, Some(&coord_bytes));
-        let pad_secret_bytes: [u8; 32] = pad_secret.into();
-        let blinding_factor = kdf::generate_key(Some(&salt_b_bytes), &pad_secret_bytes, None);
-        let salt = kdf::generate_key(Some(&salt_s_bytes), &pad_secret_bytes, None);
-        Content::new_pad(blinding_factor.into(), coord, salt.into())
-    }
-}
+        let new_padding_node_content = self.padding_closure(master_secret, salt_b, salt_s);
 
-// -------------------------------------------------------------------------------------------------
-// Errors.
+        let leaf_node = self.leaf_for(entity_id)?;
 
-/// Errors encountered when handling [NdmSmt].
-#[derive(thiserror::Error, Debug)]
-pub enum NdmSmtError {
-    #[error("Problem constructing the tree")]
-    TreeError(#[from] crate::binary_tree::TreeBuildError),
-    #[error("Number of entities cannot be bigger than 2^(height-1)")]
-    HeightTooSmall(#[from] x_coord_generator::OutOfBoundsError),
-    #[error("Inclusion proof generation failed when trying to build the path in the tree")]
-    InclusionProofPathSiblingsGenerationError(#[from] crate::binary_tree::PathSiblingsBuildError),
-    #[error("Inclusion proof generation failed")]
-    InclusionProofGenerationError(#[from] crate::inclusion_proof::InclusionProofError),
-    #[error("Entity ID {0:?} not found in the entity mapping")]
-    EntityIdNotFound(EntityId),
-    #[error("Entity ID {0:?} was duplicated")]
-    DuplicateEntityIds(EntityId),
-}
+        let path_siblings = PathSiblings::build_using_multi_threaded_algorithm(
+            &self.binary_tree,
+            &leaf_node,
+            new_padding_node_content,
+        )?;
 
-// -------------------------------------------------------------------------------------------------
-// Unit tests.
+        aggregation_factors
+            .iter()
+            .map(|aggregation_factor| {
+                Ok(InclusionProof::generate(
+                    leaf_node.clone(),
+                    path_siblings.clone(),
+                    aggregation_factor.clone(),
+                    upper_bound_bit_length,
+                )?)
+            })
+            .collect()
+    }
 
-// TODO test that the tree error propagates correctly (how do we mock in rust?)
-// TODO we should fuzz on these tests because the code utilizes a random number
-// generator
-// TODO test that duplicate entity IDs gives an error on NdmSmt::new
-// TODO test serialization & deserialization
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::secret::Secret;
-    use std::str::FromStr;
+    /// Generate a proof that the 2 given entities are in the tree.
+    ///
+    /// The paths of the 2 entities are shared from their lowest common
+    /// ancestor up to the root, so the proof is more compact than 2
+    /// independent inclusion proofs. There are no range proofs in the joint
+    /// proof, only the Merkle paths.
+    ///
+    /// Parameters:
+    /// - `master_secret`:
+    #[doc = include_str!("../shared_docs/master_secret.md")]
+    /// - `salt_b`:
+    #[doc = include_str!("../shared_docs/salt_b.md")]
+    /// - `salt_s`:
+    #[doc = include_str!("../shared_docs/salt_s.md")]
+    /// - `entity_id_a` & `entity_id_b`: unique IDs for the 2 entities that
+    ///   the proof will be generated for.
+    pub fn generate_joint_inclusion_proof(
+        &self,
+        master_secret: &Secret,
+        salt_b: &Salt,
+        salt_s: &Salt,
+        entity_id_a: &EntityId,
+        entity_id_b: &EntityId,
+    ) -> Result<JointInclusionProof, NdmSmtError> {
+        self.check_padding_fn()?;
 
-    #[test]
-    fn constructor_works() {
-        let master_secret: Secret = 1u64.into();
-        let salt_b: Salt = 2u64.into();
-        let salt_s: Salt = 3u64.into();
+        let path_siblings = |leaf_node: &Node<FullNodeContent>| {
+            PathSiblings::build_using_multi_threaded_algorithm(
+                &self.binary_tree,
+                leaf_node,
+                self.padding_closure(master_secret, salt_b, salt_s),
+            )
+        };
 
-        let height = Height::expect_from(4u8);
-        let max_thread_count = MaxThreadCount::default();
-        let entities = vec![Entity {
-            liability: 5u64,
-            id: EntityId::from_str("some entity").unwrap(),
-        }];
+        let leaf_node_a = self.leaf_for(entity_id_a)?;
+        let leaf_node_b = self.leaf_for(entity_id_b)?;
+        let path_siblings_a = path_siblings(&leaf_node_a)?;
+        let path_siblings_b = path_siblings(&leaf_node_b)?;
 
-        NdmSmt::new(
-            master_secret,
-            salt_b,
-            salt_s,
-            height,
-            max_thread_count,
-            entities,
-        )
-        .unwrap();
+        Ok(JointInclusionProof::generate(
+            leaf_node_a,
+            path_siblings_a,
+            leaf_node_b,
+            path_siblings_b,
+        )?)
+    }
+
+    /// Generate a proof that the given entity is in the tree, without any
+    /// range proofs.
+    ///
+    /// Only the path siblings are built, so this is much faster than
+    /// [NdmSmt::generate_inclusion_proof]. The proof gives no guarantee on
+    /// the liabilities in the tree, see [MembershipProof].
+    ///
+    /// Parameters:
+    /// - `master_secret`:
+    #[doc = include_str!("../shared_docs/master_secret.md")]
+    /// - `salt_b`:
+    #[doc = include_str!("../shared_docs/salt_b.md")]
+    /// - `salt_s`:
+    #[doc = include_str!("../shared_docs/salt_s.md")]
+    /// - `entity_id`: unique ID for the entity that the proof will be generated
+    ///   for.
+    pub fn generate_membership_proof(
+        &self,
+        master_secret: &Secret,
+        salt_b: &Salt,
+        salt_s: &Salt,
+        entity_id: &EntityId,
+    ) -> Result<MembershipProof, NdmSmtError> {
+        self.check_padding_fn()?;
+
+        let new_padding_node_content = self.padding_closure(master_secret, salt_b, salt_s);
+
+        let leaf_node = self.leaf_for(entity_id)?;
+
+        let path_siblings = PathSiblings::build_using_multi_threaded_algorithm(
+            &self.binary_tree,
+            &leaf_node,
+            new_padding_node_content,
+        )?;
+
+        Ok(MembershipProof::generate(leaf_node, path_siblings))
+    }
+
+    /// Generate an inclusion proof for the given `entity_id` using only the
+    /// nodes in the store.
+    ///
+    /// Unlike [NdmSmt::generate_inclusion_proof] no nodes are regenerated, so
+    /// the secrets are not needed. The tree store must be full (see
+    /// [NdmSmt::with_full_store]), otherwise an error is returned.
+    ///
+    /// Parameters:
+    /// - `entity_id`: unique ID for the entity that the proof will be generated
+    ///   for.
+    /// - `aggregation_factor` is used to determine how many of the range proofs
+    /// are aggregated.
+    /// - `upper_bound_bit_length`:
+    #[doc = include_str!("../shared_docs/upper_bound_bit_length.md")]
+    pub fn generate_inclusion_proof_from_store(
+        &self,
+        entity_id: &EntityId,
+        aggregation_factor: AggregationFactor,
+        upper_bound_bit_length: u8,
+    ) -> Result<InclusionProof, NdmSmtError> {
+        let leaf_node = self.leaf_for(entity_id)?;
+
+        let path_siblings = PathSiblings::build_from_store(&self.binary_tree, &leaf_node)?;
+
+        Ok(InclusionProof::generate(
+            leaf_node,
+            path_siblings,
+            aggregation_factor,
+            upper_bound_bit_length,
+        )?)
+    }
+
+    /// Rebuild the tree so that every node is in the store
+    /// (`store_depth == height`).
+    ///
+    /// The tree is rebuilt from the stored bottom-layer leaf nodes, so the
+    /// root node does not change. After this inclusion proofs can be
+    /// generated without the secrets using
+    /// [NdmSmt::generate_inclusion_proof_from_store].
+    ///
+    /// Note that a full store can use a lot of memory for large trees.
+    ///
+    /// Parameters:
+    /// - `master_secret`:
+    #[doc = include_str!("../shared_docs/master_secret.md")]
+    /// - `salt_b`:
+    #[doc = include_str!("../shared_docs/salt_b.md")]
+    /// - `salt_s`:
+    #[doc = include_str!("../shared_docs/salt_s.md")]
+    /// - `max_thread_count`:
+    #[doc = include_str!("../shared_docs/max_thread_count.md")]
+    pub fn with_full_store(
+        self,
+        master_secret: &Secret,
+        salt_b: &Salt,
+        salt_s: &Salt,
+        max_thread_count: MaxThreadCount,
+    ) -> Result<Self, NdmSmtError> {
+        self.check_padding_fn()?;
+
+        let height = *self.height();
+
+        let leaf_nodes = self
+            .entity_mapping
+            .values()
+            .filter_map(|x_coord| self.binary_tree.get_leaf_node(*x_coord))
+            .map(|node| InputLeafNode {
+                x_coord: node.coord.x,
+                content: node.content,
+            })
+            .collect::<Vec<InputLeafNode<Content>>>();
+
+        let new_padding_node_content = self.padding_closure(master_secret, salt_b, salt_s);
+
+        let tree = BinaryTreeBuilder::new()
+            .with_height(height)
+            .with_leaf_nodes(leaf_nodes)
+            .with_store_depth(height.as_u8())
+            .with_max_thread_count(max_thread_count)
+            .build_using_multi_threaded_algorithm(new_padding_node_content)?;
+
+        Ok(NdmSmt {
+            binary_tree: tree,
+            entity_mapping: self.entity_mapping,
+            domain_separator: self.domain_separator,
+            custom_padding: self.custom_padding,
+            padding_fn: self.padding_fn,
+        })
+    }
+
+    /// All the nodes of the tree, with their full content (including
+    /// liabilities & blinding factors), sorted by `y` and then `x`.
+    ///
+    /// A copy of the tree is rebuilt with a full store (see
+    /// [NdmSmt::with_full_store]) and all the stored nodes are returned. This
+    /// means the children of padding nodes are not included, just like they
+    /// are not included in a full store.
+    ///
+    /// WARNING: the returned nodes contain secret information, this should
+    /// only be used for testing.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn full_nodes(
+        &self,
+        master_secret: &Secret,
+        salt_b: &Salt,
+        salt_s: &Salt,
+        max_thread_count: MaxThreadCount,
+    ) -> Result<Vec<Node<FullNodeContent>>, NdmSmtError> {
+        let ndm_smt = self.clone();
+        let full_tree = ndm_smt.with_full_store(master_secret, salt_b, salt_s, max_thread_count)?;
+
+        Ok(full_tree.binary_tree.stored_nodes())
+    }
+
+    /// Copy of the tree with all the padding nodes removed from the store.
+    ///
+    /// A node is a padding node if there are no entities mapped to the
+    /// bottom-layer of its sub-tree. Padding nodes can be regenerated from
+    /// their coordinate and the secrets, which is done automatically when
+    /// they are needed for proof generation. Removing them reduces the size of
+    /// the serialized tree.
+    pub fn without_padding_nodes(&self) -> Self {
+        let mut x_coords = self.entity_mapping.values().copied().collect::<Vec<u64>>();
+        x_coords.sort_unstable();
+
+        let is_not_padding = |coord: &Coordinate| {
+            let (x_coord_min, x_coord_max) = coord.subtree_x_coord_bounds();
+            let i = x_coords.partition_point(|x| *x < x_coord_min);
+            i < x_coords.len() && x_coords[i] <= x_coord_max
+        };
+
+        NdmSmt {
+            binary_tree: self.binary_tree.with_filtered_store(is_not_padding),
+            entity_mapping: self.entity_mapping.clone(),
+            domain_separator: self.domain_separator.clone(),
+            custom_padding: self.custom_padding,
+            padding_fn: self.padding_fn.clone(),
+        }
+    }
+
+    /// Return the node at the given coordinate with its secret values removed.
+    ///
+    /// If the node is not in the store it is regenerated from the leaf nodes
+    /// of its sub-tree. Only the hash & commitment of the node are returned so
+    /// that the liabilities of other entities are not leaked.
+    ///
+    /// Parameters:
+    /// - `master_secret`:
+    #[doc = include_str!("../shared_docs/master_secret.md")]
+    /// - `salt_b`:
+    #[doc = include_str!("../shared_docs/salt_b.md")]
+    /// - `salt_s`:
+    #[doc = include_str!("../shared_docs/salt_s.md")]
+    /// - `coord`: coordinate of the node in the tree.
+    ///
+    /// `None` is returned if the coordinate is outside the bounds of the tree.
+    /// [NdmSmtError::PaddingFnNotSet] is returned if the tree was built with a
+    /// custom padding function that has not been set again after
+    /// deserialization.
+    pub fn node_at(
+        &self,
+        master_secret: &Secret,
+        salt_b: &Salt,
+        salt_s: &Salt,
+        coord: &Coordinate,
+    ) -> Result<Option<Node<HiddenNodeContent>>, NdmSmtError> {
+        self.check_padding_fn()?;
+
+        let new_padding_node_content = self.padding_closure(master_secret, salt_b, salt_s);
+
+        Ok(self
+            .binary_tree
+            .get_or_regenerate_node(coord, new_padding_node_content)
+            .map(|node| node.convert()))
+    }
+
+    /// Return the bottom-layer leaf node of the given entity, with the
+    /// liability hidden.
+    ///
+    /// `None` is returned if the entity is not in the tree.
+    pub fn leaf_node_for(&self, entity_id: &EntityId) -> Option<Node<HiddenNodeContent>> {
+        self.leaf_for(entity_id).ok().map(|node| node.convert())
+    }
+
+    /// Rebuild the root node from the stored bottom-layer leaf nodes.
+    ///
+    /// Only the leaf nodes of the entities in the entity mapping are used, and
+    /// the padding nodes are regenerated, so the result can be compared to the
+    /// stored root to detect corruption of the tree.
+    ///
+    /// Parameters:
+    /// - `master_secret`:
+    #[doc = include_str!("../shared_docs/master_secret.md")]
+    /// - `salt_b`:
+    #[doc = include_str!("../shared_docs/salt_b.md")]
+    /// - `salt_s`:
+    #[doc = include_str!("../shared_docs/salt_s.md")]
+    pub fn recompute_root(
+        &self,
+        master_secret: &Secret,
+        salt_b: &Salt,
+        salt_s: &Salt,
+    ) -> Result<Node<HiddenNodeContent>, NdmSmtError> {
+        self.check_padding_fn()?;
+
+        let new_padding_node_content = self.padding_closure(master_secret, salt_b, salt_s);
+
+        let mut x_coords = self.entity_mapping.values().copied().collect::<Vec<u64>>();
+        x_coords.sort_unstable();
+
+        Ok(self
+            .binary_tree
+            .recompute_root(&x_coords, new_padding_node_content)
+            .convert())
+    }
+
+    /// Change the liability of an existing entity.
+    ///
+    /// The leaf node of the entity is given the new liability (the blinding
+    /// factor & hash stay the same, see [Content::with_liability]), and the
+    /// nodes on the path from the leaf node to the root are recomputed. Only
+    /// the nodes on that path that are in the store are replaced.
+    ///
+    /// Inclusion proofs that were generated before the update will no longer
+    /// verify against the new root.
+    ///
+    /// Parameters:
+    /// - `master_secret`:
+    #[doc = include_str!("../shared_docs/master_secret.md")]
+    /// - `salt_b`:
+    #[doc = include_str!("../shared_docs/salt_b.md")]
+    /// - `salt_s`:
+    #[doc = include_str!("../shared_docs/salt_s.md")]
+    /// - `entity_id`: unique ID for the entity whose liability is changed.
+    /// - `new_liability`: the liability that replaces the current one.
+    ///
+    /// An error is returned if the entity is not in the tree.
+    pub fn update_liability(
+        &mut self,
+        master_secret: &Secret,
+        salt_b: &Salt,
+        salt_s: &Salt,
+        entity_id: &EntityId,
+        new_liability: u64,
+    ) -> Result<(), NdmSmtError> {
+        self.check_padding_fn()?;
+
+        let new_padding_node_content = self.padding_closure(master_secret, salt_b, salt_s);
+
+        let leaf_node = self.leaf_for(entity_id)?;
+
+        // The siblings do not depend on the content of the leaf node, so they
+        // can be taken from the tree before it is updated.
+        let path_siblings = PathSiblings::build_using_multi_threaded_algorithm(
+            &self.binary_tree,
+            &leaf_node,
+            new_padding_node_content,
+        )?;
+
+        let updated_leaf_node = Node {
+            content: leaf_node.content.with_liability(new_liability),
+            coord: leaf_node.coord,
+        };
+
+        let path = path_siblings.construct_path(updated_leaf_node)?;
+        self.binary_tree.replace_path(path);
+
+        Ok(())
+    }
+
+    /// Check that the bottom-layer leaf nodes were derived from the given
+    /// secrets.
+    ///
+    /// The blinding factor of each leaf node is recomputed from
+    /// `master_secret` & `salt_b` using the KDF (see [kdf]), and compared to
+    /// the stored one. Entities that were given their own blinding factor
+    /// (see [Entity]) will not match the derived value, so true is returned as
+    /// soon as one leaf node matches. False is returned if none of the leaf
+    /// nodes match, which includes the case where every entity was given its
+    /// own blinding factor.
+    ///
+    /// Parameters:
+    /// - `master_secret`:
+    #[doc = include_str!("../shared_docs/master_secret.md")]
+    /// - `salt_b`:
+    #[doc = include_str!("../shared_docs/salt_b.md")]
+    pub fn leaves_derived_from(&self, master_secret: &Secret, salt_b: &Salt) -> bool {
+        let master_secret_bytes = master_secret.as_bytes();
+        let salt_b_bytes = salt_b.as_bytes();
+
+        self.entity_mapping.par_iter().any(|(_, x_coord)| {
+            let leaf = match self.binary_tree.get_leaf_node(*x_coord) {
+                Some(leaf) => leaf,
+                None => return false,
+            };
+
+            let blinding_factor: [u8; 32] =
+                kdf::derive_entity_blinding_factor(master_secret_bytes, salt_b_bytes, *x_coord)
+                    .into();
+
+            leaf.content.blinding_factor == Scalar::from_bytes_mod_order(blinding_factor)
+        })
+    }
+
+    /// Iterator over the liabilities in the entities' bottom-layer leaf nodes,
+    /// in no particular order.
+    ///
+    /// Entities whose leaf node is not in the store are skipped.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn entity_liabilities(&self) -> impl Iterator<Item = u64> + '_ {
+        self.entity_mapping.values().filter_map(|x_coord| {
+            self.binary_tree
+                .get_leaf_node(*x_coord)
+                .map(|leaf| leaf.content.liability)
+        })
+    }
+
+    /// Recompute the content of the entity's bottom-layer leaf node from the
+    /// given secrets, and check that it matches the stored leaf node.
+    ///
+    /// The entity secret, blinding factor & salt are derived from the
+    /// entity's x-coord using the KDF (see [kdf]), and the content is rebuilt
+    /// with [Content::new_leaf]. The liability cannot be derived, so it is
+    /// taken from the stored leaf node. Entities that were given their own
+    /// salt or blinding factor (see [Entity]) will not match the derived
+    /// content.
+    ///
+    /// Parameters:
+    /// - `master_secret`:
+    #[doc = include_str!("../shared_docs/master_secret.md")]
+    /// - `salt_b`:
+    #[doc = include_str!("../shared_docs/salt_b.md")]
+    /// - `salt_s`:
+    #[doc = include_str!("../shared_docs/salt_s.md")]
+    /// - `entity_id`: unique ID for the entity whose leaf node is recomputed.
+    ///
+    /// An error is returned if:
+    /// 1. The entity is not in the tree.
+    /// 2. The recomputed content does not match the stored leaf node.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn recompute_leaf_content(
+        &self,
+        master_secret: &Secret,
+        salt_b: &Salt,
+        salt_s: &Salt,
+        entity_id: &EntityId,
+    ) -> Result<Content, NdmSmtError> {
+        let leaf = self.leaf_for(entity_id)?;
+        let x_coord = leaf.coord.x;
+
+        let (blinding_factor, entity_salt) = kdf::derive_entity_secrets(
+            master_secret.as_bytes(),
+            salt_b.as_bytes(),
+            salt_s.as_bytes(),
+            x_coord,
+        );
+
+        let content = Content::new_leaf_with_domain_separator(
+            leaf.content.liability,
+            blinding_factor.into(),
+            entity_id.clone(),
+            entity_salt.into(),
+            self.domain_separator.as_deref(),
+        );
+
+        if content != leaf.content {
+            return Err(NdmSmtError::LeafContentMismatch(entity_id.clone()));
+        }
+
+        Ok(content)
+    }
+
+    #[doc = include_str!("../shared_docs/root_hash.md")]
+    pub fn root_hash(&self) -> &H256 {
+        &self.binary_tree.root().content.hash
+    }
+
+    #[doc = include_str!("../shared_docs/root_hash.md")]
+    pub fn root_commitment(&self) -> &RistrettoPoint {
+        &self.binary_tree.root().content.commitment
+    }
+
+    #[doc = include_str!("../shared_docs/root_liability.md")]
+    pub fn root_liability(&self) -> u64 {
+        self.binary_tree.root().content.liability
+    }
+
+    #[doc = include_str!("../shared_docs/root_blinding_factor.md")]
+    pub fn root_blinding_factor(&self) -> &Scalar {
+        &self.binary_tree.root().content.blinding_factor
+    }
+
+    /// Hash map giving the x-coord that each entity is mapped to.
+    pub fn entity_mapping(&self) -> &HashMap<EntityId, u64> {
+        &self.entity_mapping
+    }
+
+    /// Domain separator used to seed the hasher for the leaf & padding nodes,
+    /// see [BuildOptions::with_domain_separator].
+    pub fn domain_separator(&self) -> Option<&str> {
+        self.domain_separator.as_deref()
+    }
+
+    /// Set the function used to generate padding node content, see
+    /// [BuildOptions::with_padding_fn].
+    ///
+    /// This does not change the tree, so it must be the same function that
+    /// the tree was built with, otherwise generated proofs will not verify.
+    /// It has to be called after deserializing a tree that was built with a
+    /// custom padding function, before generating proofs or regenerating
+    /// nodes.
+    pub fn set_padding_fn<F>(&mut self, padding_fn: F)
+    where
+        F: Fn(&Coordinate) -> Content + Send + Sync + 'static,
+    {
+        self.padding_fn = Some(PaddingFn(Arc::new(padding_fn)));
+    }
+
+    /// Closure that generates the content of padding nodes from the secrets,
+    /// or using the custom padding function if one is set.
+    fn padding_closure(
+        &self,
+        master_secret: &Secret,
+        salt_b: &Salt,
+        salt_s: &Salt,
+    ) -> impl Fn(&Coordinate) -> Content {
+        new_padding_node_content_closure(
+            self.padding_fn.clone(),
+            self.domain_separator.clone(),
+            *master_secret.as_bytes(),
+            *salt_b.as_bytes(),
+            *salt_s.as_bytes(),
+        )
+    }
+
+    /// Bottom-layer leaf node of the given entity, with its full content.
+    ///
+    /// An error is returned if the entity is not in the tree, or if its leaf
+    /// node is not in the store.
+    fn leaf_for(&self, entity_id: &EntityId) -> Result<Node<Content>, NdmSmtError> {
+        self.entity_mapping
+            .get(entity_id)
+            .and_then(|x_coord| self.binary_tree.get_leaf_node(*x_coord))
+            .ok_or(NdmSmtError::EntityIdNotFound(entity_id.clone()))
+    }
+
+    /// Check that the padding function is set if the tree was built with
+    /// one, otherwise padding nodes would be regenerated using the secrets
+    /// and would not match the tree.
+    fn check_padding_fn(&self) -> Result<(), NdmSmtError> {
+        if self.custom_padding && self.padding_fn.is_none() {
+            Err(NdmSmtError::PaddingFnNotSet)
+        } else {
+            Ok(())
+        }
+    }
+
+    #[doc = include_str!("../shared_docs/height.md")]
+    pub fn height(&self) -> &Height {
+        self.binary_tree.height()
+    }
+
+    /// Number of nodes in the tree's store.
+    pub fn stored_node_count(&self) -> usize {
+        self.binary_tree.stored_node_count()
+    }
+
+    /// Coordinates of the nodes in the tree's store, sorted by `y` and then
+    /// `x`.
+    pub fn stored_coordinates(&self) -> Vec<Coordinate> {
+        self.binary_tree.stored_coordinates()
+    }
+
+    /// Coordinate of the left-most bottom-layer leaf node that has an entity
+    /// mapped to it but is not in the store.
+    ///
+    /// `None` is returned if all the entities' leaf nodes are in the store,
+    /// which is required for inclusion proof generation.
+    pub fn first_missing_leaf_coord(&self) -> Option<Coordinate> {
+        let mut x_coords = self.entity_mapping.values().copied().collect::<Vec<u64>>();
+        x_coords.sort_unstable();
+
+        x_coords
+            .into_iter()
+            .map(|x| Coordinate { x, y: 0 })
+            .find(|coord| self.binary_tree.get_node(coord).is_none())
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Helper functions.
+
+/// Create a new closure that generates padding node content using the secret
+/// values.
+///
+/// If `padding_fn` is set then it is used instead, and the secret values are
+/// ignored.
+fn new_padding_node_content_closure(
+    padding_fn: Option<PaddingFn>,
+    domain_separator: Option<String>,
+    master_secret_bytes: [u8; 32],
+    salt_b_bytes: [u8; 32],
+    salt_s_bytes: [u8; 32],
+) -> impl Fn(&Coordinate) -> Content {
+    // closure that is used to create new padding nodes
+    move |coord: &Coordinate| {
+        if let Some(padding_fn) = &padding_fn {
+            return (padding_fn.0)(coord);
+        }
+
+        // TODO unfortunately we copy data here, maybe there is a way to do without
+        // copying
+        let coord_bytes = coord.to_bytes();
+        // pad_secret is given as 'w' in the DAPOL+ paper
+        let pad_secret = kdf::generate_key(None, &master_secret_bytes, Some(&coord_bytes));
+        let pad_secret_bytes: [u8; 32] = pad_secret.into();
+        let blinding_factor = kdf::generate_key(Some(&salt_b_bytes), &pad_secret_bytes, None);
+        let salt = kdf::generate_key(Some(&salt_s_bytes), &pad_secret_bytes, None);
+        Content::new_pad_with_domain_separator(
+            blinding_factor.into(),
+            coord,
+            salt.into(),
+            domain_separator.as_deref(),
+        )
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Errors.
+
+/// Errors encountered when handling [NdmSmt].
+#[derive(thiserror::Error, Debug)]
+pub enum NdmSmtError {
+    #[error("Problem constructing the tree")]
+    TreeError(#[from] crate::binary_tree::TreeBuildError),
+    #[error("Number of entities cannot be bigger than 2^(height-1)")]
+    HeightTooSmall(#[from] x_coord_generator::OutOfBoundsError),
+    #[error("Too many entities for the given height (given: {given:?}, max: {max:?})")]
+    TooManyEntities { given: u64, max: u64 },
+    #[error("Inclusion proof generation failed when trying to build the path in the tree")]
+    InclusionProofPathSiblingsGenerationError(#[from] crate::binary_tree::PathSiblingsBuildError),
+    #[error("Inclusion proof generation failed")]
+    InclusionProofGenerationError(#[from] crate::inclusion_proof::InclusionProofError),
+    #[error("Failed to construct the path from a leaf node to the root")]
+    PathConstructionError(#[from] crate::binary_tree::PathSiblingsError),
+    #[error("Joint inclusion proof generation failed")]
+    JointInclusionProofGenerationError(#[from] crate::inclusion_proof::JointInclusionProofError),
+    #[error("Entity ID {0:?} not found in the entity mapping")]
+    EntityIdNotFound(EntityId),
+    #[error("Entity ID {0:?} was duplicated")]
+    DuplicateEntityIds(EntityId),
+    #[error("Recomputed leaf node content for entity ID {0:?} does not match the stored one")]
+    LeafContentMismatch(EntityId),
+    #[error("x-coord {x_coord} is outside the bottom layer of the tree, which has {max_bottom_layer_nodes} nodes")]
+    XCoordOutOfBounds {
+        x_coord: u64,
+        max_bottom_layer_nodes: u64,
+    },
+    #[error("The tree was built with a custom padding function, which has to be set again after deserialization")]
+    PaddingFnNotSet,
+}
+
+// -------------------------------------------------------------------------------------------------
+// Unit tests.
+
+// TODO test that the tree error propagates correctly (how do we mock in rust?)
+// TODO we should fuzz on these tests because the code utilizes a random number
+// generator
+// TODO test that duplicate entity IDs gives an error on NdmSmt::new
+// TODO test serialization & deserialization
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secret::Secret;
+    use std::str::FromStr;
+
+    #[test]
+    fn constructor_works() {
+        let master_secret: Secret = 1u64.into();
+        let salt_b: Salt = 2u64.into();
+        let salt_s: Salt = 3u64.into();
+
+        let height = Height::expect_from(4u8);
+        let max_thread_count = MaxThreadCount::default();
+        let entities = vec![Entity {
+            liability: 5u64,
+            id: EntityId::from_str("some entity").unwrap(),
+            salt: None,
+            blinding_factor: None,
+        }];
+
+        NdmSmt::new(
+            master_secret,
+            salt_b,
+            salt_s,
+            height,
+            entities,
+            BuildOptions::default().with_max_thread_count(max_thread_count),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn constructor_err_when_too_many_entities_for_height() {
+        use crate::utils::test_utils::assert_err;
+
+        let height = Height::expect_from(4u8);
+        let max = height.max_bottom_layer_nodes();
+        let entities = (0..max + 1)
+            .map(|i| Entity {
+                liability: i,
+                id: EntityId::from_str(&i.to_string()).unwrap(),
+                salt: None,
+                blinding_factor: None,
+            })
+            .collect::<Vec<Entity>>();
+
+        let res = NdmSmt::new(
+            1u64.into(),
+            2u64.into(),
+            3u64.into(),
+            height,
+            entities,
+            BuildOptions::default(),
+        );
+
+        assert_err!(
+            res,
+            Err(NdmSmtError::TooManyEntities { given, max: m }) if given == max + 1 && m == max
+        );
+    }
+
+    #[test]
+    fn entity_salt_is_used_for_leaf_hash_when_set() {
+        let master_secret: Secret = 1u64.into();
+        let salt_b: Salt = 2u64.into();
+        let salt_s: Salt = 3u64.into();
+        let entity_salt = Salt::from_str("externally_managed_salt").unwrap();
+
+        let height = Height::expect_from(4u8);
+        let max_thread_count = MaxThreadCount::default();
+        let entity_id = EntityId::from_str("some entity").unwrap();
+        let entities = vec![Entity {
+            liability: 5u64,
+            id: entity_id.clone(),
+            salt: Some(entity_salt.clone()),
+            blinding_factor: None,
+        }];
+
+        let ndm_smt = NdmSmt::new(
+            master_secret,
+            salt_b,
+            salt_s,
+            height,
+            entities,
+            BuildOptions::default().with_max_thread_count(max_thread_count),
+        )
+        .unwrap();
+
+        let x_coord = ndm_smt.entity_mapping().get(&entity_id).unwrap();
+        let leaf = ndm_smt.binary_tree.get_leaf_node(*x_coord).unwrap();
+
+        let expected_hash = {
+            let entity_id_bytes: Vec<u8> = entity_id.into();
+            let mut hasher = crate::hasher::Hasher::new();
+            hasher.update("leaf".as_bytes());
+            hasher.update(&entity_id_bytes);
+            hasher.update(entity_salt.as_bytes());
+            hasher.finalize()
+        };
+
+        assert_eq!(leaf.content.hash, expected_hash);
+    }
+
+    #[test]
+    fn entity_blinding_factor_is_used_for_leaf_commitment_when_set() {
+        use bulletproofs::PedersenGens;
+
+        let master_secret: Secret = 1u64.into();
+        let salt_b: Salt = 2u64.into();
+        let salt_s: Salt = 3u64.into();
+        let blinding_factor = Scalar::from_bytes_mod_order(*b"11112222333344445555666677778888");
+
+        let height = Height::expect_from(4u8);
+        let max_thread_count = MaxThreadCount::default();
+        let entity_id = EntityId::from_str("some entity").unwrap();
+        let liability = 5u64;
+        let entities = vec![Entity {
+            liability,
+            id: entity_id.clone(),
+            salt: None,
+            blinding_factor: Some(blinding_factor),
+        }];
+
+        let ndm_smt = NdmSmt::new(
+            master_secret,
+            salt_b,
+            salt_s,
+            height,
+            entities,
+            BuildOptions::default().with_max_thread_count(max_thread_count),
+        )
+        .unwrap();
+
+        let x_coord = ndm_smt.entity_mapping().get(&entity_id).unwrap();
+        let leaf = ndm_smt.binary_tree.get_leaf_node(*x_coord).unwrap();
+
+        let expected_commitment =
+            PedersenGens::default().commit(Scalar::from(liability), blinding_factor);
+
+        assert_eq!(leaf.content.blinding_factor, blinding_factor);
+        assert_eq!(leaf.content.commitment, expected_commitment);
+    }
+
+    #[test]
+    fn leaf_secrets_can_be_recomputed_with_kdf() {
+        let master_secret: Secret = 1u64.into();
+        let salt_b: Salt = 2u64.into();
+        let salt_s: Salt = 3u64.into();
+
+        let height = Height::expect_from(4u8);
+        let max_thread_count = MaxThreadCount::default();
+        let entity_id = EntityId::from_str("some entity").unwrap();
+        let liability = 5u64;
+        let entities = vec![Entity {
+            liability,
+            id: entity_id.clone(),
+            salt: None,
+            blinding_factor: None,
+        }];
+
+        let ndm_smt = NdmSmt::new(
+            master_secret.clone(),
+            salt_b.clone(),
+            salt_s.clone(),
+            height,
+            entities,
+            BuildOptions::default().with_max_thread_count(max_thread_count),
+        )
+        .unwrap();
+
+        let x_coord = ndm_smt.entity_mapping().get(&entity_id).unwrap();
+        let leaf = ndm_smt.binary_tree.get_leaf_node(*x_coord).unwrap();
+
+        let entity_secret: [u8; 32] =
+            kdf::generate_key(None, master_secret.as_bytes(), Some(&x_coord.to_le_bytes())).into();
+        let blinding_factor = kdf::generate_key(Some(salt_b.as_bytes()), &entity_secret, None);
+        let entity_salt = kdf::generate_key(Some(salt_s.as_bytes()), &entity_secret, None);
+
+        let expected = Content::new_leaf(
+            liability,
+            blinding_factor.into(),
+            entity_id,
+            entity_salt.into(),
+        );
+
+        assert_eq!(leaf.content, expected);
+    }
+
+    #[test]
+    fn domain_separator_is_used_for_leaf_hash() {
+        let master_secret: Secret = 1u64.into();
+        let salt_b: Salt = 2u64.into();
+        let salt_s: Salt = 3u64.into();
+        let domain_separator = "some deployment";
+
+        let height = Height::expect_from(4u8);
+        let entity_id = EntityId::from_str("some entity").unwrap();
+        let liability = 5u64;
+        let entities = vec![Entity {
+            liability,
+            id: entity_id.clone(),
+            salt: None,
+            blinding_factor: None,
+        }];
+
+        let ndm_smt = NdmSmt::new(
+            master_secret.clone(),
+            salt_b.clone(),
+            salt_s.clone(),
+            height,
+            entities,
+            BuildOptions::default().with_domain_separator(domain_separator.to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(ndm_smt.domain_separator(), Some(domain_separator));
+
+        let x_coord = ndm_smt.entity_mapping().get(&entity_id).unwrap();
+        let leaf = ndm_smt.binary_tree.get_leaf_node(*x_coord).unwrap();
+
+        let entity_secret: [u8; 32] =
+            kdf::generate_key(None, master_secret.as_bytes(), Some(&x_coord.to_le_bytes())).into();
+        let blinding_factor: Secret =
+            kdf::generate_key(Some(salt_b.as_bytes()), &entity_secret, None).into();
+        let entity_salt: Secret =
+            kdf::generate_key(Some(salt_s.as_bytes()), &entity_secret, None).into();
+
+        let expected = Content::new_leaf_with_domain_separator(
+            liability,
+            blinding_factor.clone(),
+            entity_id.clone(),
+            entity_salt.clone(),
+            Some(domain_separator),
+        );
+        let without_domain_separator =
+            Content::new_leaf(liability, blinding_factor, entity_id.clone(), entity_salt);
+
+        assert_eq!(leaf.content, expected);
+        assert_ne!(leaf.content.hash, without_domain_separator.hash);
+
+        ndm_smt
+            .recompute_leaf_content(&master_secret, &salt_b, &salt_s, &entity_id)
+            .unwrap();
+    }
+
+    #[test]
+    fn leaves_derived_from_detects_wrong_master_secret() {
+        let master_secret: Secret = 1u64.into();
+        let salt_b: Salt = 2u64.into();
+        let salt_s: Salt = 3u64.into();
+
+        let height = Height::expect_from(4u8);
+        let max_thread_count = MaxThreadCount::default();
+        let entities = vec![
+            Entity {
+                liability: 5u64,
+                id: EntityId::from_str("overridden").unwrap(),
+                salt: None,
+                blinding_factor: Some(Scalar::from(7u64)),
+            },
+            Entity {
+                liability: 6u64,
+                id: EntityId::from_str("derived").unwrap(),
+                salt: None,
+                blinding_factor: None,
+            },
+        ];
+
+        let ndm_smt = NdmSmt::new(
+            master_secret.clone(),
+            salt_b.clone(),
+            salt_s,
+            height,
+            entities,
+            BuildOptions::default().with_max_thread_count(max_thread_count),
+        )
+        .unwrap();
+
+        assert!(ndm_smt.leaves_derived_from(&master_secret, &salt_b));
+        assert!(!ndm_smt.leaves_derived_from(&4u64.into(), &salt_b));
+        assert!(!ndm_smt.leaves_derived_from(&master_secret, &5u64.into()));
+    }
+
+    #[test]
+    fn removing_padding_nodes_does_not_change_path_siblings() {
+        let master_secret: Secret = 1u64.into();
+        let salt_b: Salt = 2u64.into();
+        let salt_s: Salt = 3u64.into();
+
+        let height = Height::expect_from(6u8);
+        let entities = (0..5u64)
+            .map(|i| Entity {
+                liability: i,
+                id: EntityId::from_str(&format!("entity {}", i)).unwrap(),
+                salt: None,
+                blinding_factor: None,
+            })
+            .collect();
+
+        let ndm_smt = NdmSmt::new(
+            master_secret.clone(),
+            salt_b.clone(),
+            salt_s.clone(),
+            height,
+            entities,
+            BuildOptions::default(),
+        )
+        .unwrap()
+        .with_full_store(&master_secret, &salt_b, &salt_s, MaxThreadCount::default())
+        .unwrap();
+
+        let without_padding = ndm_smt.without_padding_nodes();
+
+        assert!(without_padding.stored_node_count() < ndm_smt.stored_node_count());
+        assert_eq!(without_padding.root_hash(), ndm_smt.root_hash());
+
+        let padding = new_padding_node_content_closure(
+            None,
+            None,
+            *master_secret.as_bytes(),
+            *salt_b.as_bytes(),
+            *salt_s.as_bytes(),
+        );
+
+        for x_coord in ndm_smt.entity_mapping().values() {
+            let leaf = ndm_smt.binary_tree.get_leaf_node(*x_coord).unwrap();
+            let siblings = PathSiblings::build_from_store(&ndm_smt.binary_tree, &leaf).unwrap();
+            let regenerated_siblings = PathSiblings::build_using_single_threaded_algorithm(
+                &without_padding.binary_tree,
+                &leaf,
+                &padding,
+            )
+            .unwrap();
+
+            assert_eq!(siblings.0, regenerated_siblings.0);
+        }
+    }
+
+    #[test]
+    fn first_missing_leaf_coord_finds_removed_leaf() {
+        let master_secret: Secret = 1u64.into();
+        let salt_b: Salt = 2u64.into();
+        let salt_s: Salt = 3u64.into();
+
+        let height = Height::expect_from(6u8);
+        let entities = (0..5u64)
+            .map(|i| Entity {
+                liability: i,
+                id: EntityId::from_str(&format!("entity {}", i)).unwrap(),
+                salt: None,
+                blinding_factor: None,
+            })
+            .collect();
+
+        let ndm_smt = NdmSmt::new(
+            master_secret,
+            salt_b,
+            salt_s,
+            height,
+            entities,
+            BuildOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(ndm_smt.first_missing_leaf_coord(), None);
+
+        let mut x_coords = ndm_smt
+            .entity_mapping()
+            .values()
+            .copied()
+            .collect::<Vec<u64>>();
+        x_coords.sort_unstable();
+        let removed = Coordinate {
+            x: x_coords[2],
+            y: 0,
+        };
+
+        let ndm_smt = NdmSmt {
+            binary_tree: ndm_smt
+                .binary_tree
+                .with_filtered_store(|coord| *coord != removed),
+            entity_mapping: ndm_smt.entity_mapping.clone(),
+            domain_separator: None,
+            custom_padding: false,
+            padding_fn: None,
+        };
+
+        assert_eq!(ndm_smt.first_missing_leaf_coord(), Some(removed));
     }
 }
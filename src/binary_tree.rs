@@ -34,26 +34,40 @@
 
 use serde::{Deserialize, Serialize};
 use std::fmt::{self, Debug};
+#[cfg(feature = "full")]
+use std::sync::Arc;
 
 mod utils;
 
 mod node_content;
 pub use node_content::{FullNodeContent, HiddenNodeContent, Mergeable};
 
+#[cfg(feature = "full")]
+mod lru_store;
+#[cfg(feature = "full")]
+pub use lru_store::LruStore;
+
+#[cfg(feature = "full")]
 mod tree_builder;
+#[cfg(all(feature = "full", any(test, feature = "testing")))]
+pub use tree_builder::compute_root_only;
+#[cfg(feature = "full")]
 pub use tree_builder::multi_threaded;
+#[cfg(feature = "full")]
 pub use tree_builder::{
-    single_threaded, BinaryTreeBuilder, InputLeafNode, TreeBuildError, MIN_STORE_DEPTH,
+    expected_store_size, single_threaded, BinaryTreeBuilder, BuildStrategy, InputLeafNode,
+    TreeBuildError, MIN_STORE_DEPTH,
 };
 
 mod path_siblings;
-pub use path_siblings::{
-    PathSiblings, PathSiblingsBuildError, PathSiblingsError, PathSiblingsWriteError,
-};
+#[cfg(feature = "full")]
+pub use path_siblings::PathSiblingsBuildError;
+pub use path_siblings::{PathInfoFormat, PathSiblings, PathSiblingsError, PathSiblingsWriteError};
 
 mod height;
 pub use height::{Height, HeightError, MAX_HEIGHT, MIN_HEIGHT};
 
+#[cfg(feature = "full")]
 use crate::utils::ErrOnSome;
 
 /// Minimum recommended empty-space-to-leaf-node ratio.
@@ -70,6 +84,7 @@ use crate::utils::ErrOnSome;
 ///
 /// It is not recommended to have less sparsity than 2 because this means the
 /// upper bound is exactly double the actual number.
+#[cfg(feature = "full")]
 pub const MIN_RECOMMENDED_SPARSITY: u8 = 2;
 
 // -------------------------------------------------------------------------------------------------
@@ -84,10 +99,16 @@ pub const MIN_RECOMMENDED_SPARSITY: u8 = 2;
 /// according to logic in [tree_builder].
 ///
 /// The generic type `C` is for the content contained within each node.
-#[derive(Serialize, Deserialize)]
+///
+/// The store is wrapped in an [Arc] to make cloning the tree cheap. Clones
+/// share the same store until one of them is modified (see
+/// [BinaryTree::replace_path]), at which point the modified tree gets its own
+/// copy of the store.
+#[cfg(feature = "full")]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct BinaryTree<C: fmt::Display> {
     root: Node<C>,
-    store: Store<C>,
+    store: Arc<Store<C>>,
     height: Height,
 }
 
@@ -119,15 +140,21 @@ pub struct Coordinate {
 /// are [erased_serde] and [typetag] but none support deserialization of generic
 /// traits; for more details see
 /// [this issue](https://github.com/dtolnay/typetag/issues/1).
+///
+/// [Store::Lru] is not tied to a build algorithm, it is produced by
+/// either of them if a store capacity is given to [BinaryTreeBuilder].
+#[cfg(feature = "full")]
 #[derive(Serialize, Deserialize)]
 pub enum Store<C: fmt::Display> {
-    MultiThreadedStore(multi_threaded::DashMapStore<C>),
-    SingleThreadedStore(single_threaded::HashMapStore<C>),
+    MultiThreaded(multi_threaded::DashMapStore<C>),
+    SingleThreaded(single_threaded::HashMapStore<C>),
+    Lru(LruStore<C>),
 }
 
 // -------------------------------------------------------------------------------------------------
 // Accessor methods.
 
+#[cfg(feature = "full")]
 impl<C: Clone + fmt::Display> BinaryTree<C> {
     pub fn height(&self) -> &Height {
         &self.height
@@ -170,6 +197,139 @@ impl<C: Clone + fmt::Display> BinaryTree<C> {
         let coord = Coordinate { x: x_coord, y: 0 };
         self.get_node(&coord)
     }
+
+    /// Number of nodes in the store.
+    ///
+    /// This includes all non-padding bottom-layer leaf nodes, as well as the
+    /// other nodes that the builder decided to store (see [tree_builder]).
+    pub fn stored_node_count(&self) -> usize {
+        self.store.len()
+    }
+
+    /// Coordinates of the nodes in the store, sorted by `y` and then `x`.
+    ///
+    /// Nodes that are not in the store have to be regenerated when they are
+    /// needed (e.g. for an inclusion proof), so this shows which parts of the
+    /// tree are cheap to access.
+    pub fn stored_coordinates(&self) -> Vec<Coordinate> {
+        let mut coords = self.store.coordinates();
+        coords.sort_by_key(|coord| (coord.y, coord.x));
+        coords
+    }
+
+    /// All the nodes in the store, sorted by `y` and then `x`.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn stored_nodes(&self) -> Vec<Node<C>> {
+        self.stored_coordinates()
+            .iter()
+            .filter_map(|coord| self.store.get_node(coord))
+            .collect()
+    }
+
+    /// Replace the nodes on the path from a bottom-layer leaf node to the root.
+    ///
+    /// `path` is expected to start with the leaf node and end with the root
+    /// node, with each node being the parent of the previous one (as returned
+    /// by [PathSiblings::construct_path]). The leaf node is always put in the
+    /// store, but the other nodes only replace nodes that are already in the
+    /// store, so the store depth of the tree is preserved.
+    ///
+    /// If the store is shared with clones of this tree then it is copied
+    /// first, so that the clones are not affected.
+    pub fn replace_path(&mut self, path: Vec<Node<C>>) {
+        if Arc::get_mut(&mut self.store).is_none() {
+            self.store = Arc::new(self.store.filtered(|_| true));
+        }
+
+        let store = Arc::get_mut(&mut self.store)
+            .expect("[Bug in path replacement] store should not be shared after copying");
+
+        for node in path.into_iter() {
+            let is_leaf = node.coord.y == 0;
+            let is_root = node.coord == self.root.coord;
+
+            if is_root {
+                self.root = node.clone();
+            }
+
+            if is_leaf || store.get_node(&node.coord).is_some() {
+                store.insert_node(node);
+            }
+        }
+    }
+
+    /// Copy of the tree where the store only contains the nodes for which
+    /// `keep` returns true.
+    ///
+    /// The nodes that are left out are regenerated when they are needed (see
+    /// [BinaryTree::get_or_regenerate_node]), so as long as all the
+    /// non-padding bottom-layer leaf nodes are kept the tree is still valid.
+    /// Unlike [Clone], the returned tree does not share the store with `self`.
+    pub fn with_filtered_store<P: Fn(&Coordinate) -> bool>(&self, keep: P) -> Self {
+        BinaryTree {
+            root: self.root.clone(),
+            store: Arc::new(self.store.filtered(keep)),
+            height: self.height,
+        }
+    }
+}
+
+#[cfg(feature = "full")]
+impl<C: Debug + Clone + fmt::Display + Mergeable + Send + Sync + 'static> BinaryTree<C> {
+    /// Return the node with the given coordinate, regenerating it if it is not
+    /// in the store.
+    ///
+    /// Nodes that were left out of the store by the builder are rebuilt from
+    /// the bottom-layer leaf nodes of their sub-tree using the same algorithm
+    /// as [tree_builder][multi_threaded]. The regenerated node is not added to
+    /// the store.
+    ///
+    /// `new_padding_node_content` is needed to generate new nodes.
+    ///
+    /// `None` is returned if the coordinate is outside the bounds of the tree.
+    pub fn get_or_regenerate_node<F>(
+        &self,
+        coord: &Coordinate,
+        new_padding_node_content: F,
+    ) -> Option<Node<C>>
+    where
+        F: Fn(&Coordinate) -> C + Send + Sync + 'static,
+    {
+        use std::sync::Arc;
+
+        if !coord.is_within(&self.height) {
+            return None;
+        }
+
+        let node = self.get_node(coord).unwrap_or_else(|| {
+            let node =
+                multi_threaded::regenerate_node(self, coord, Arc::new(new_padding_node_content));
+            self.store.cache_node(&node);
+            node
+        });
+
+        Some(node)
+    }
+
+    /// Rebuild the root node from the bottom-layer leaf nodes in the store at
+    /// `leaf_x_coords`, ignoring all other stored nodes.
+    ///
+    /// `leaf_x_coords` must be sorted, and should hold the x-coords of all the
+    /// non-padding leaf nodes. Only these are looked up in the store, so the
+    /// time taken depends on the number of leaf nodes and not the height.
+    ///
+    /// This can be compared to [root] to check that the stored root is
+    /// consistent with the stored leaves.
+    ///
+    /// `new_padding_node_content` is needed to generate new nodes.
+    pub fn recompute_root<F>(&self, leaf_x_coords: &[u64], new_padding_node_content: F) -> Node<C>
+    where
+        F: Fn(&Coordinate) -> C + Send + Sync + 'static,
+    {
+        use std::sync::Arc;
+
+        multi_threaded::regenerate_root(self, leaf_x_coords, Arc::new(new_padding_node_content))
+    }
 }
 
 // -------------------------------------------------------------------------------------------------
@@ -195,6 +355,21 @@ impl Coordinate {
         c
     }
 
+    /// Inverse of [Coordinate::to_bytes].
+    ///
+    /// The y-coord is read from the 1st byte and the x-coord from the next 8
+    /// bytes, both in Little Endian byte order. The remaining bytes are not
+    /// used by [Coordinate::to_bytes] and so are ignored.
+    pub fn from_bytes(bytes: &[u8; 32]) -> Coordinate {
+        let mut x_bytes = [0u8; 8];
+        x_bytes.copy_from_slice(&bytes[1..9]);
+
+        Coordinate {
+            y: u8::from_le_bytes([bytes[0]]),
+            x: u64::from_le_bytes(x_bytes),
+        }
+    }
+
     /// Returns left if a node with this coord is a left sibling and vice versa
     /// for right.
     ///
@@ -212,6 +387,7 @@ impl Coordinate {
     /// Return the coordinates of the node that would be a sibling to the node
     /// with coordinates equal to `self`, whether that be a right or a left
     /// sibling.
+    #[cfg(feature = "full")]
     fn sibling_coord(&self) -> Coordinate {
         let x = match self.orientation() {
             NodeOrientation::Left => self.x + 1,
@@ -224,7 +400,8 @@ impl Coordinate {
     /// coordinate. The x-coord divide-by-2 works for both left _and_ right
     /// siblings because of truncation. Note that this function can be
     /// misused if tree height is not used to bound the y-coord from above.
-    fn parent_coord(&self) -> Coordinate {
+    #[cfg(feature = "full")]
+    pub(crate) fn parent_coord(&self) -> Coordinate {
         Coordinate {
             y: self.y + 1,
             x: self.x / 2,
@@ -241,7 +418,8 @@ impl Coordinate {
     /// the height of the main tree. This is due to the fact that we know the
     /// `x` value of the current coordinate. The `x` encodes for the main tree
     /// height.
-    fn subtree_x_coord_bounds(&self) -> (u64, u64) {
+    #[cfg(feature = "full")]
+    pub(crate) fn subtree_x_coord_bounds(&self) -> (u64, u64) {
         // This is essentially the number of bottom-layer leaf nodes for the
         // subtree, but shifted right to account for the subtree's position
         // in the main tree.
@@ -255,13 +433,24 @@ impl Coordinate {
 
     /// Return the height for the coordinate.
     /// Why the offset? `y` starts from 0 but height starts from 1.
+    #[cfg(feature = "full")]
     fn to_height(&self) -> Height {
         // Since a) y is a u8 and b) height is also:
         // there is a small chance this panics.
         Height::expect_from(self.y + 1)
     }
 
+    /// True if a node with this coordinate can exist in a tree of the given
+    /// height.
+    ///
+    /// The layer with y-coord `y` has `2^(height-1-y)` nodes.
+    #[cfg(feature = "full")]
+    pub(crate) fn is_within(&self, height: &Height) -> bool {
+        self.y < height.as_u8() && self.x < 2u64.pow((height.as_y_coord() - self.y) as u32)
+    }
+
     /// Generate a new bottom-layer leaf coordinate from the given x-coord.
+    #[cfg(feature = "full")]
     fn bottom_layer_leaf_from(x_coord: u64) -> Self {
         Coordinate { x: x_coord, y: 0 }
     }
@@ -307,6 +496,7 @@ impl<C: fmt::Display> Node<C> {
 
     /// Return the coordinates of this node's sibling, whether that be a right
     /// or a left sibling.
+    #[cfg(feature = "full")]
     fn sibling_coord(&self) -> Coordinate {
         self.coord.sibling_coord()
     }
@@ -315,7 +505,8 @@ impl<C: fmt::Display> Node<C> {
     /// The x-coord divide-by-2 works for both left _and_ right siblings because
     /// of truncation. Note that this function can be misused if tree height
     /// is not used to bound the y-coord from above.
-    fn parent_coord(&self) -> Coordinate {
+    #[cfg(feature = "full")]
+    pub(crate) fn parent_coord(&self) -> Coordinate {
         self.coord.parent_coord()
     }
 
@@ -328,26 +519,79 @@ impl<C: fmt::Display> Node<C> {
     }
 }
 
+#[cfg(feature = "full")]
 impl<C: Clone + fmt::Display> Store<C> {
     /// Simply delegate the call to the wrapped store.
     fn get_node(&self, coord: &Coordinate) -> Option<Node<C>> {
         match self {
-            Store::MultiThreadedStore(store) => store.get_node(coord),
-            Store::SingleThreadedStore(store) => store.get_node(coord),
+            Store::MultiThreaded(store) => store.get_node(coord),
+            Store::SingleThreaded(store) => store.get_node(coord),
+            Store::Lru(store) => store.get_node(coord),
         }
     }
 
     /// Simply delegate the call to the wrapped store.
     fn len(&self) -> usize {
         match self {
-            Store::MultiThreadedStore(store) => store.len(),
-            Store::SingleThreadedStore(store) => store.len(),
+            Store::MultiThreaded(store) => store.len(),
+            Store::SingleThreaded(store) => store.len(),
+            Store::Lru(store) => store.len(),
+        }
+    }
+
+    /// Simply delegate the call to the wrapped store.
+    fn coordinates(&self) -> Vec<Coordinate> {
+        match self {
+            Store::MultiThreaded(store) => store.coordinates(),
+            Store::SingleThreaded(store) => store.coordinates(),
+            Store::Lru(store) => store.coordinates(),
         }
     }
+
+    /// Put a regenerated node in the store, if the store supports it.
+    ///
+    /// Only [Store::Lru] keeps regenerated nodes, the other stores are
+    /// fixed after the tree is built.
+    fn cache_node(&self, node: &Node<C>) {
+        if let Store::Lru(store) = self {
+            store.cache_node(node.clone());
+        }
+    }
+
+    /// Copy of the store that only contains the nodes for which `keep` returns
+    /// true. The type of store is preserved.
+    fn filtered<P: Fn(&Coordinate) -> bool>(&self, keep: P) -> Self {
+        match self {
+            Store::MultiThreaded(store) => Store::MultiThreaded(store.filtered(keep)),
+            Store::SingleThreaded(store) => Store::SingleThreaded(store.filtered(keep)),
+            Store::Lru(store) => Store::Lru(store.filtered(keep)),
+        }
+    }
+
+    /// Simply delegate the call to the wrapped store.
+    fn insert_node(&mut self, node: Node<C>) {
+        match self {
+            Store::MultiThreaded(store) => store.insert_node(node),
+            Store::SingleThreaded(store) => store.insert_node(node),
+            Store::Lru(store) => store.insert_node(node),
+        }
+    }
+
+    /// Move all the nodes into a [Store::Lru] with the given capacity.
+    fn into_bounded_lru(self, capacity_bytes: usize) -> Self {
+        let store = match self {
+            Store::MultiThreaded(store) => LruStore::new(capacity_bytes, store.into_nodes()),
+            Store::SingleThreaded(store) => LruStore::new(capacity_bytes, store.into_nodes()),
+            Store::Lru(store) => store,
+        };
+
+        Store::Lru(store)
+    }
 }
 
 /// We can't use the default Debug implementation because it prints the whole
 /// store.
+#[cfg(feature = "full")]
 impl<C: fmt::Display + Clone> fmt::Debug for BinaryTree<C> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "root: {}, height: {:?}", self.root, self.height)
@@ -378,17 +622,20 @@ enum NodeOrientation {
 
 /// Used to orient nodes inside a sibling pair so that the compiler can
 /// guarantee a left node is actually a left node.
+#[cfg(feature = "full")]
 enum Sibling<C: fmt::Display> {
     Left(Node<C>),
     Right(Node<C>),
 }
 
 /// A pair of sibling nodes.
+#[cfg(feature = "full")]
 struct MatchedPair<C: fmt::Display> {
     left: Node<C>,
     right: Node<C>,
 }
 
+#[cfg(feature = "full")]
 impl<C: fmt::Display> From<Node<C>> for Sibling<C> {
     /// Move a generic node into the left/right sibling type.
     fn from(node: Node<C>) -> Self {
@@ -399,6 +646,7 @@ impl<C: fmt::Display> From<Node<C>> for Sibling<C> {
     }
 }
 
+#[cfg(feature = "full")]
 impl<C: Mergeable + fmt::Display> MatchedPair<C> {
     /// Create a parent node by merging the 2 nodes in the pair.
     fn merge(&self) -> Node<C> {
@@ -409,6 +657,7 @@ impl<C: Mergeable + fmt::Display> MatchedPair<C> {
     }
 }
 
+#[cfg(feature = "full")]
 impl<C: fmt::Display> From<(Node<C>, Node<C>)> for MatchedPair<C> {
     /// Construct a [MatchedPair] using the 2 given nodes.
     ///
@@ -440,10 +689,12 @@ impl<C: fmt::Display> From<(Node<C>, Node<C>)> for MatchedPair<C> {
 // -------------------------------------------------------------------------------------------------
 // Unit tests.
 
-#[cfg(test)]
+#[cfg(all(test, feature = "full"))]
 mod tests {
     use super::*;
-    use crate::binary_tree::utils::test_utils::single_leaf;
+    use crate::binary_tree::utils::test_utils::{
+        full_bottom_layer, generate_padding_closure, single_leaf, sparse_leaves,
+    };
 
     #[test]
     fn coord_byte_conversion_correct() {
@@ -477,6 +728,27 @@ mod tests {
         }
     }
 
+    #[test]
+    fn coord_from_bytes_reverses_to_bytes() {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(42);
+        let edge_cases = [
+            Coordinate { x: 0, y: 0 },
+            Coordinate {
+                x: u64::MAX,
+                y: u8::MAX,
+            },
+        ];
+
+        for coord in edge_cases.into_iter().chain((0..1000).map(|_| Coordinate {
+            x: rng.gen(),
+            y: rng.gen(),
+        })) {
+            assert_eq!(Coordinate::from_bytes(&coord.to_bytes()), coord);
+        }
+    }
+
     // TODO repeat for Coordinate::orientation
     #[test]
     fn node_orientation_correctly_determined() {
@@ -640,4 +912,191 @@ mod tests {
         assert_eq!(lower, 8, "Incorrect lower x-coord bound for subtree");
         assert_eq!(upper, 11, "Incorrect upper x-coord bound for subtree");
     }
+
+    #[test]
+    fn coord_within_tree_bounds_works() {
+        let height = Height::expect_from(4u8);
+
+        assert!(Coordinate { x: 7, y: 0 }.is_within(&height));
+        assert!(Coordinate { x: 0, y: 3 }.is_within(&height));
+        assert!(!Coordinate { x: 8, y: 0 }.is_within(&height));
+        assert!(!Coordinate { x: 1, y: 3 }.is_within(&height));
+        assert!(!Coordinate { x: 0, y: 4 }.is_within(&height));
+    }
+
+    #[test]
+    fn regenerated_nodes_match_nodes_in_full_store() {
+        let height = Height::expect_from(6u8);
+        let leaf_nodes = sparse_leaves(&height);
+
+        let full_tree = BinaryTreeBuilder::new()
+            .with_height(height)
+            .with_leaf_nodes(leaf_nodes.clone())
+            .with_store_depth(height.as_u8())
+            .build_using_multi_threaded_algorithm(generate_padding_closure())
+            .unwrap();
+
+        let sparse_tree = BinaryTreeBuilder::new()
+            .with_height(height)
+            .with_leaf_nodes(leaf_nodes)
+            .with_store_depth(MIN_STORE_DEPTH)
+            .build_using_multi_threaded_algorithm(generate_padding_closure())
+            .unwrap();
+
+        for y in 0..height.as_u8() {
+            for x in 0..2u64.pow((height.as_y_coord() - y) as u32) {
+                let coord = Coordinate { x, y };
+                // Padding nodes whose sibling is also a padding node are not
+                // stored, even for a full store.
+                if let Some(node) = full_tree.get_node(&coord) {
+                    assert_eq!(
+                        sparse_tree.get_or_regenerate_node(&coord, generate_padding_closure()),
+                        Some(node)
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn stored_coordinates_are_sorted_for_all_store_types() {
+        let height = Height::expect_from(6u8);
+        let leaf_nodes = sparse_leaves(&height);
+
+        let multi_threaded_tree = BinaryTreeBuilder::new()
+            .with_height(height)
+            .with_leaf_nodes(leaf_nodes.clone())
+            .build_using_multi_threaded_algorithm(generate_padding_closure())
+            .unwrap();
+
+        let single_threaded_tree = BinaryTreeBuilder::new()
+            .with_height(height)
+            .with_leaf_nodes(leaf_nodes.clone())
+            .build_using_single_threaded_algorithm(generate_padding_closure())
+            .unwrap();
+
+        let bounded_tree = BinaryTreeBuilder::new()
+            .with_height(height)
+            .with_leaf_nodes(leaf_nodes.clone())
+            .with_store_capacity_bytes(1024)
+            .build_using_multi_threaded_algorithm(generate_padding_closure())
+            .unwrap();
+
+        for tree in [multi_threaded_tree, single_threaded_tree, bounded_tree] {
+            let coords = tree.stored_coordinates();
+
+            assert_eq!(coords.len(), tree.stored_node_count());
+            assert!(coords
+                .windows(2)
+                .all(|pair| (pair[0].y, pair[0].x) < (pair[1].y, pair[1].x)));
+
+            for leaf in leaf_nodes.iter() {
+                assert!(coords.contains(&Coordinate {
+                    x: leaf.x_coord,
+                    y: 0
+                }));
+            }
+        }
+    }
+
+    #[test]
+    fn bounded_lru_store_gives_same_nodes_as_full_store() {
+        let height = Height::expect_from(6u8);
+        let leaf_nodes = sparse_leaves(&height);
+        let num_leaf_nodes = leaf_nodes.len();
+
+        let full_tree = BinaryTreeBuilder::new()
+            .with_height(height)
+            .with_leaf_nodes(leaf_nodes.clone())
+            .with_store_depth(height.as_u8())
+            .build_using_multi_threaded_algorithm(generate_padding_closure())
+            .unwrap();
+
+        // Only enough space for a handful of internal nodes.
+        let bounded_tree = BinaryTreeBuilder::new()
+            .with_height(height)
+            .with_leaf_nodes(leaf_nodes)
+            .with_store_depth(height.as_u8())
+            .with_store_capacity_bytes(1024)
+            .build_using_multi_threaded_algorithm(generate_padding_closure())
+            .unwrap();
+
+        assert!(bounded_tree.stored_node_count() < full_tree.stored_node_count());
+        assert!(bounded_tree.stored_node_count() >= num_leaf_nodes);
+
+        for y in 0..height.as_u8() {
+            for x in 0..2u64.pow((height.as_y_coord() - y) as u32) {
+                let coord = Coordinate { x, y };
+                if let Some(node) = full_tree.get_node(&coord) {
+                    assert_eq!(
+                        bounded_tree.get_or_regenerate_node(&coord, generate_padding_closure()),
+                        Some(node)
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn regenerated_node_is_cached_in_bounded_lru_store() {
+        let height = Height::expect_from(6u8);
+
+        let tree = BinaryTreeBuilder::new()
+            .with_height(height)
+            .with_leaf_nodes(sparse_leaves(&height))
+            .with_store_depth(MIN_STORE_DEPTH)
+            .with_store_capacity_bytes(1024 * 1024)
+            .build_using_single_threaded_algorithm(generate_padding_closure())
+            .unwrap();
+
+        let coord = Coordinate { x: 0, y: 1 };
+        assert!(tree.get_node(&coord).is_none());
+
+        let node = tree.get_or_regenerate_node(&coord, generate_padding_closure());
+
+        assert_eq!(tree.get_node(&coord), node);
+    }
+
+    #[test]
+    fn recomputed_root_matches_stored_root() {
+        let height = Height::expect_from(6u8);
+
+        for leaf_nodes in [sparse_leaves(&height), full_bottom_layer(&height)] {
+            let mut x_coords = leaf_nodes
+                .iter()
+                .map(|leaf| leaf.x_coord)
+                .collect::<Vec<u64>>();
+            x_coords.sort_unstable();
+
+            let tree = BinaryTreeBuilder::new()
+                .with_height(height)
+                .with_leaf_nodes(leaf_nodes)
+                .build_using_multi_threaded_algorithm(generate_padding_closure())
+                .unwrap();
+
+            assert_eq!(
+                &tree.recompute_root(&x_coords, generate_padding_closure()),
+                tree.root()
+            );
+        }
+    }
+
+    #[test]
+    fn regenerating_node_outside_tree_gives_none() {
+        let height = Height::expect_from(6u8);
+
+        let tree = BinaryTreeBuilder::new()
+            .with_height(height)
+            .with_leaf_nodes(sparse_leaves(&height))
+            .build_using_multi_threaded_algorithm(generate_padding_closure())
+            .unwrap();
+
+        let coord = Coordinate {
+            x: 0,
+            y: height.as_u8(),
+        };
+        assert!(tree
+            .get_or_regenerate_node(&coord, generate_padding_closure())
+            .is_none());
+    }
 }
@@ -18,9 +18,17 @@ pub const MIN_HEIGHT: Height = Height(2);
 /// it's just a soft limit that can be increased later if need be. If it is
 /// increased then we will need to change the type of the x-coord because it is
 /// currently u64, which gives a max tree height of 64.
+///
+/// The bottom layer of a tree with height 64 has `2^63` nodes, so all the
+/// x-coords, as well as the number of nodes in the bottom layer, fit in a u64
+/// with 1 bit to spare. This is checked at compile time.
 pub const MAX_HEIGHT: Height = Height(64);
 pub type XCoord = u64;
 
+// `2^(MAX_HEIGHT-1)` must fit in [XCoord] for the bottom-layer node count
+// (see [Height::max_bottom_layer_nodes]) not to overflow.
+const _: () = assert!((MAX_HEIGHT.0 as u32) - 1 < XCoord::BITS);
+
 /// 2^32 is about half the human population so it is a reasonable default height
 /// to have for any protocol involving people as the entities.
 pub const DEFAULT_HEIGHT: UnderlyingInt = 32;
@@ -30,13 +38,16 @@ pub const DEFAULT_HEIGHT: UnderlyingInt = 32;
 /// Example:
 /// ```
 /// use dapol::Height;
+/// use std::convert::TryFrom;
 /// use std::str::FromStr;
 ///
 /// let height = Height::default();
 /// let height = Height::expect_from(8u8);
 /// let height = Height::from_str("8");
+/// let height = Height::try_from(8u8);
+/// assert!(Height::try_from(1u8).is_err());
 /// ```
-#[derive(Copy, Clone, Debug, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 #[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
 pub struct Height(UnderlyingInt);
 
@@ -44,7 +55,8 @@ impl Height {
     /// Create a [Height] object from `int`.
     ///
     /// panics if `int` is greater than [MAX_HEIGHT] or less than
-    /// [MIN_HEIGHT].
+    /// [MIN_HEIGHT]. Use [Height::try_from] for a non-panicking version, for
+    /// example if `int` comes from untrusted user input.
     ///
     /// Note that if we try to implement the From trait then we have a
     /// collision.
@@ -105,8 +117,49 @@ impl Height {
     /// The maximum number of leaf nodes on the bottom layer of the binary tree.
     ///
     /// $$\text{max} = 2^{\text{height}-1}$$
+    ///
+    /// The computation is overflow-checked, rather than silently wrapping
+    /// around in release builds. It cannot overflow for heights up to
+    /// [MAX_HEIGHT], so this only panics if a [Height] greater than
+    /// [MAX_HEIGHT] has somehow been constructed.
     pub fn max_bottom_layer_nodes(&self) -> u64 {
-        2u64.pow(self.as_u32() - 1)
+        self.max_bottom_layer_nodes_checked().unwrap_or_else(|| {
+            panic!(
+                "[Bug in height bounds] 2^({} - 1) overflows u64, the height must not be greater than {:?}",
+                self.0, MAX_HEIGHT
+            )
+        })
+    }
+
+    /// Same as [Height::max_bottom_layer_nodes] but returns [None] instead of
+    /// overflowing.
+    ///
+    /// This cannot overflow for heights up to the current [MAX_HEIGHT], but
+    /// it may do if [MAX_HEIGHT] is increased.
+    pub fn max_bottom_layer_nodes_checked(&self) -> Option<u64> {
+        2u64.checked_pow(self.as_u32() - 1)
+    }
+
+    /// The smallest height for a tree that can hold `num_entities` with a
+    /// sparsity greater than `min_sparsity`.
+    ///
+    /// Sparsity is the ratio of max bottom-layer nodes to entities, rounded
+    /// down, which is the same check that the tree builder does before
+    /// warning about low sparsity (see [MIN_RECOMMENDED_SPARSITY]).
+    ///
+    /// The result is never smaller than [MIN_HEIGHT]. An error is returned if
+    /// the height would have to be greater than [MAX_HEIGHT].
+    ///
+    /// [MIN_RECOMMENDED_SPARSITY]: super::MIN_RECOMMENDED_SPARSITY
+    pub fn optimal_for(num_entities: u64, min_sparsity: u8) -> Result<Self, HeightError> {
+        if num_entities == 0 {
+            return Ok(MIN_HEIGHT);
+        }
+
+        (MIN_HEIGHT.0..=MAX_HEIGHT.0)
+            .map(Height)
+            .find(|height| height.max_bottom_layer_nodes() / num_entities > min_sparsity as u64)
+            .ok_or(HeightError::InputTooBig)
     }
 }
 
@@ -149,8 +202,10 @@ impl FromStr for Height {
 // -------------------------------------------------------------------------------------------------
 // From for OsStr (for the CLI).
 
+#[cfg(feature = "full")]
 use clap::builder::{OsStr, Str};
 
+#[cfg(feature = "full")]
 impl From<Height> for OsStr {
     fn from(height: Height) -> OsStr {
         OsStr::from(Str::from(height.as_u8().to_string()))
@@ -178,3 +233,93 @@ pub enum HeightError {
     #[error("Malformed string input for {UNDERLYING_INT_TYPE_STR:?} type")]
     MalformedString(#[from] std::num::ParseIntError),
 }
+
+// -------------------------------------------------------------------------------------------------
+// Unit tests.
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_utils::assert_err;
+
+    #[test]
+    fn try_from_works_for_bounds() {
+        assert_eq!(Height::try_from(MIN_HEIGHT.as_u8()).unwrap(), MIN_HEIGHT);
+        assert_eq!(Height::try_from(MAX_HEIGHT.as_u8()).unwrap(), MAX_HEIGHT);
+    }
+
+    #[test]
+    fn try_from_fails_for_out_of_bounds_input() {
+        assert_err!(
+            Height::try_from(MIN_HEIGHT.as_u8() - 1),
+            Err(HeightError::InputTooSmall)
+        );
+        assert_err!(
+            Height::try_from(MAX_HEIGHT.as_u8() + 1),
+            Err(HeightError::InputTooBig)
+        );
+    }
+
+    #[test]
+    fn optimal_for_gives_smallest_height_with_min_sparsity() {
+        assert_eq!(Height::optimal_for(0, 2).unwrap(), MIN_HEIGHT);
+        assert_eq!(Height::optimal_for(1, 1).unwrap(), MIN_HEIGHT);
+        assert_eq!(Height::optimal_for(1, 2).unwrap(), Height::expect_from(3));
+        assert_eq!(
+            Height::optimal_for(100, 2).unwrap(),
+            Height::expect_from(10)
+        );
+        assert_eq!(
+            Height::optimal_for(170, 2).unwrap(),
+            Height::expect_from(10)
+        );
+        assert_eq!(
+            Height::optimal_for(171, 2).unwrap(),
+            Height::expect_from(11)
+        );
+        assert_eq!(
+            Height::optimal_for(MAX_HEIGHT.max_bottom_layer_nodes(), 0).unwrap(),
+            MAX_HEIGHT
+        );
+    }
+
+    #[test]
+    fn optimal_for_fails_when_entities_do_not_fit() {
+        assert_err!(
+            Height::optimal_for(MAX_HEIGHT.max_bottom_layer_nodes(), 1),
+            Err(HeightError::InputTooBig)
+        );
+    }
+
+    #[test]
+    fn max_bottom_layer_nodes_does_not_overflow_at_max_height() {
+        assert_eq!(MAX_HEIGHT.max_bottom_layer_nodes(), 1u64 << 63);
+        assert_eq!(
+            MAX_HEIGHT.max_bottom_layer_nodes_checked(),
+            Some(1u64 << 63)
+        );
+    }
+
+    #[test]
+    fn max_bottom_layer_nodes_checked_gives_none_past_max_height() {
+        let height = Height(MAX_HEIGHT.as_u8() + 1);
+        assert_eq!(height.max_bottom_layer_nodes_checked(), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn max_bottom_layer_nodes_panics_past_max_height() {
+        Height(MAX_HEIGHT.as_u8() + 1).max_bottom_layer_nodes();
+    }
+
+    #[test]
+    fn max_bottom_layer_nodes_checked_matches_unchecked() {
+        for int in MIN_HEIGHT.as_u8()..=MAX_HEIGHT.as_u8() {
+            let height = Height::expect_from(int);
+            assert_eq!(
+                height.max_bottom_layer_nodes_checked(),
+                Some(height.max_bottom_layer_nodes())
+            );
+        }
+    }
+}
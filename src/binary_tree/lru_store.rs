@@ -0,0 +1,331 @@
+//! Store with a bounded memory footprint.
+//!
+//! All the non-padding bottom-layer leaf nodes are always retained because
+//! they are needed to regenerate any other node in the tree. The rest of the
+//! nodes are kept in a least-recently-used (LRU) cache with a byte budget.
+//! Once the budget is reached the least recently accessed nodes are evicted.
+//!
+//! Evicted nodes are not lost for good: [BinaryTree] and [PathSiblings]
+//! regenerate nodes that are not in the store, and the regenerated nodes are
+//! put back into the cache.
+//!
+//! The store is created from the store of a finished tree, so it bounds the
+//! memory the tree retains afterwards, not the memory used to build it.
+//!
+//! The byte budget is converted to a max number of cached nodes using the
+//! in-memory size of a cache entry, so it is only an approximation of the
+//! actual memory used: the per-entry overhead of the hash map & the leaf
+//! nodes are not counted. The node content types used in the tree do not hold
+//! any heap data, so the size of an entry does not depend on its content.
+//!
+//! [BinaryTree]: super::BinaryTree
+//! [PathSiblings]: super::PathSiblings
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::Mutex;
+
+use super::{Coordinate, Node};
+
+// -------------------------------------------------------------------------------------------------
+// Main struct.
+
+pub struct LruStore<C: fmt::Display> {
+    capacity_bytes: usize,
+    leaf_nodes: HashMap<Coordinate, Node<C>>,
+    cache: Mutex<LruCache<C>>,
+}
+
+/// Cached nodes, with a counter for each node that records when it was last
+/// accessed. `recency` is ordered by the counter so the least recently used
+/// node is the first entry.
+struct LruCache<C: fmt::Display> {
+    max_nodes: usize,
+    nodes: HashMap<Coordinate, (Node<C>, u64)>,
+    recency: BTreeMap<u64, Coordinate>,
+    counter: u64,
+}
+
+// -------------------------------------------------------------------------------------------------
+// Implementations.
+
+impl<C: fmt::Display> LruStore<C> {
+    /// Constructor.
+    ///
+    /// Bottom-layer nodes are kept out of the cache and never evicted. The
+    /// rest of the nodes are inserted into the cache layer by layer, from the
+    /// bottom up, so that if the budget is exceeded the nodes closest to the
+    /// root (which are shared by the most paths) are the ones that are kept.
+    pub fn new<I>(capacity_bytes: usize, nodes: I) -> Self
+    where
+        I: IntoIterator<Item = Node<C>>,
+    {
+        let mut leaf_nodes = HashMap::new();
+        let mut internal_nodes = Vec::new();
+
+        for node in nodes {
+            if node.coord.y == 0 {
+                leaf_nodes.insert(node.coord.clone(), node);
+            } else {
+                internal_nodes.push(node);
+            }
+        }
+
+        internal_nodes.sort_by_key(|node| node.coord.y);
+
+        let mut cache = LruCache::new(capacity_bytes);
+        for node in internal_nodes {
+            cache.insert(node);
+        }
+
+        LruStore {
+            capacity_bytes,
+            leaf_nodes,
+            cache: Mutex::new(cache),
+        }
+    }
+
+    /// Put a (regenerated) node into the cache, evicting the least recently
+    /// used node if the cache is full.
+    pub fn cache_node(&self, node: Node<C>) {
+        if !self.leaf_nodes.contains_key(&node.coord) {
+            self.cache().insert(node);
+        }
+    }
+
+    /// Insert the node, replacing any node with the same coordinate.
+    ///
+    /// Unlike [LruStore::cache_node], bottom-layer nodes are added to the
+    /// non-evictable leaf nodes.
+    pub fn insert_node(&mut self, node: Node<C>) {
+        if node.coord.y == 0 {
+            self.leaf_nodes.insert(node.coord.clone(), node);
+        } else {
+            self.cache().insert(node);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.leaf_nodes.len() + self.cache().nodes.len()
+    }
+
+    /// Coordinates of all the nodes currently in the store, in no particular
+    /// order. This does not affect the recency of the cached nodes.
+    pub fn coordinates(&self) -> Vec<Coordinate> {
+        self.leaf_nodes
+            .keys()
+            .chain(self.cache().nodes.keys())
+            .cloned()
+            .collect()
+    }
+
+    fn cache(&self) -> std::sync::MutexGuard<'_, LruCache<C>> {
+        // The cache is never left in an invalid state by a panicking thread,
+        // so it is safe to keep using it if the lock is poisoned.
+        self.cache
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl<C: Clone + fmt::Display> LruStore<C> {
+    /// Return the node if it is in the store, marking it as the most recently
+    /// used node.
+    pub fn get_node(&self, coord: &Coordinate) -> Option<Node<C>> {
+        if let Some(node) = self.leaf_nodes.get(coord) {
+            return Some(node.clone());
+        }
+
+        self.cache().get(coord)
+    }
+
+    /// Copy of the store that only contains the nodes for which `keep`
+    /// returns true. The recency information is not copied.
+    pub fn filtered<P: Fn(&Coordinate) -> bool>(&self, keep: P) -> Self {
+        let cache = self.cache();
+
+        let nodes = self
+            .leaf_nodes
+            .values()
+            .chain(cache.nodes.values().map(|(node, _)| node))
+            .filter(|node| keep(&node.coord))
+            .cloned()
+            .collect::<Vec<Node<C>>>();
+
+        LruStore::new(self.capacity_bytes, nodes)
+    }
+}
+
+impl<C: fmt::Display> LruCache<C> {
+    fn new(capacity_bytes: usize) -> Self {
+        let entry_size = std::mem::size_of::<(Coordinate, Node<C>, u64)>()
+            + std::mem::size_of::<(u64, Coordinate)>();
+
+        LruCache {
+            max_nodes: capacity_bytes / entry_size,
+            nodes: HashMap::new(),
+            recency: BTreeMap::new(),
+            counter: 0,
+        }
+    }
+
+    fn get(&mut self, coord: &Coordinate) -> Option<Node<C>>
+    where
+        C: Clone,
+    {
+        let counter = self.next_counter();
+        let (node, last_used) = self.nodes.get_mut(coord)?;
+
+        self.recency.remove(last_used);
+        self.recency.insert(counter, coord.clone());
+        *last_used = counter;
+
+        Some(node.clone())
+    }
+
+    fn insert(&mut self, node: Node<C>) {
+        if self.max_nodes == 0 {
+            return;
+        }
+
+        let counter = self.next_counter();
+        let coord = node.coord.clone();
+
+        if let Some((_, last_used)) = self.nodes.insert(coord.clone(), (node, counter)) {
+            self.recency.remove(&last_used);
+        }
+        self.recency.insert(counter, coord);
+
+        while self.nodes.len() > self.max_nodes {
+            match self.recency.pop_first() {
+                Some((_, lru_coord)) => {
+                    self.nodes.remove(&lru_coord);
+                }
+                None => break,
+            }
+        }
+    }
+
+    fn next_counter(&mut self) -> u64 {
+        self.counter += 1;
+        self.counter
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Serialization.
+
+// The recency information is not serialized, only the nodes. The 2 structs
+// below have the same serialized format.
+
+#[derive(Serialize)]
+struct SerializableLruStore<'a, C: fmt::Display> {
+    capacity_bytes: usize,
+    nodes: Vec<&'a Node<C>>,
+}
+
+#[derive(Deserialize)]
+struct DeserializableLruStore<C: fmt::Display> {
+    capacity_bytes: usize,
+    nodes: Vec<Node<C>>,
+}
+
+impl<C: fmt::Display + Serialize> Serialize for LruStore<C> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let cache = self.cache();
+
+        let nodes = self
+            .leaf_nodes
+            .values()
+            .chain(cache.nodes.values().map(|(node, _)| node))
+            .collect();
+
+        SerializableLruStore {
+            capacity_bytes: self.capacity_bytes,
+            nodes,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de, C: fmt::Display + Deserialize<'de>> Deserialize<'de> for LruStore<C> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let store = DeserializableLruStore::<C>::deserialize(deserializer)?;
+        Ok(LruStore::new(store.capacity_bytes, store.nodes))
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Unit tests.
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary_tree::utils::test_utils::TestContent;
+    use primitive_types::H256;
+
+    fn node(x: u64, y: u8) -> Node<TestContent> {
+        Node {
+            coord: Coordinate { x, y },
+            content: TestContent {
+                hash: H256::default(),
+                value: x as u32,
+            },
+        }
+    }
+
+    fn entry_size() -> usize {
+        std::mem::size_of::<(Coordinate, Node<TestContent>, u64)>()
+            + std::mem::size_of::<(u64, Coordinate)>()
+    }
+
+    #[test]
+    fn leaf_nodes_are_never_evicted() {
+        let store = LruStore::new(0, (0..10).map(|x| node(x, 0)));
+
+        for x in 0..10 {
+            assert!(store.get_node(&Coordinate { x, y: 0 }).is_some());
+        }
+        assert_eq!(store.len(), 10);
+    }
+
+    #[test]
+    fn nodes_closest_to_root_are_kept_on_construction() {
+        let nodes = vec![node(0, 1), node(1, 1), node(0, 2), node(0, 3)];
+        let store = LruStore::new(2 * entry_size(), nodes);
+
+        assert!(store.get_node(&Coordinate { x: 0, y: 3 }).is_some());
+        assert!(store.get_node(&Coordinate { x: 0, y: 2 }).is_some());
+        assert!(store.get_node(&Coordinate { x: 0, y: 1 }).is_none());
+        assert!(store.get_node(&Coordinate { x: 1, y: 1 }).is_none());
+    }
+
+    #[test]
+    fn least_recently_used_node_is_evicted() {
+        let store = LruStore::new(2 * entry_size(), vec![node(0, 1), node(1, 1)]);
+
+        // Access (0,1) so that (1,1) becomes the least recently used.
+        store.get_node(&Coordinate { x: 0, y: 1 });
+        store.cache_node(node(0, 2));
+
+        assert!(store.get_node(&Coordinate { x: 0, y: 1 }).is_some());
+        assert!(store.get_node(&Coordinate { x: 1, y: 1 }).is_none());
+        assert!(store.get_node(&Coordinate { x: 0, y: 2 }).is_some());
+    }
+
+    #[test]
+    fn serde_keeps_nodes() {
+        let store = LruStore::new(10 * entry_size(), vec![node(0, 0), node(0, 1)]);
+
+        let encoded = bincode::serialize(&store).unwrap();
+        let decoded: LruStore<TestContent> = bincode::deserialize(&encoded).unwrap();
+
+        assert_eq!(decoded.capacity_bytes, store.capacity_bytes);
+        assert_eq!(decoded.len(), 2);
+        assert_eq!(
+            decoded.get_node(&Coordinate { x: 0, y: 1 }),
+            Some(node(0, 1))
+        );
+    }
+}
@@ -80,6 +80,24 @@ impl FullNodeContent {
         blinding_factor: Secret,
         entity_id: EntityId,
         entity_salt: Secret,
+    ) -> FullNodeContent {
+        FullNodeContent::new_leaf_with_domain_separator(
+            liability,
+            blinding_factor,
+            entity_id,
+            entity_salt,
+            None,
+        )
+    }
+
+    /// Same as [FullNodeContent::new_leaf] but the hash is computed using
+    /// [Hasher::new_with_domain_separator] if `domain_separator` is set.
+    pub fn new_leaf_with_domain_separator(
+        liability: u64,
+        blinding_factor: Secret,
+        entity_id: EntityId,
+        entity_salt: Secret,
+        domain_separator: Option<&str>,
     ) -> FullNodeContent {
         // Scalar expects bytes to be in little-endian
         let blinding_factor_scalar = Scalar::from_bytes_mod_order(blinding_factor.into());
@@ -89,21 +107,53 @@ impl FullNodeContent {
         let commitment =
             PedersenGens::default().commit(Scalar::from(liability), blinding_factor_scalar);
 
+        let hash = FullNodeContent::leaf_hash(entity_id, entity_salt, domain_separator);
+
+        FullNodeContent {
+            liability,
+            blinding_factor: blinding_factor_scalar,
+            commitment,
+            hash,
+        }
+    }
+
+    /// Hash of a bottom-layer leaf node: `H("leaf" | entity_id | entity_salt)`.
+    ///
+    /// The hash is computed using [Hasher::new_with_domain_separator] if
+    /// `domain_separator` is set.
+    pub(crate) fn leaf_hash(
+        entity_id: EntityId,
+        entity_salt: Secret,
+        domain_separator: Option<&str>,
+    ) -> H256 {
         let entity_id_bytes: Vec<u8> = entity_id.into();
         let entity_salt_bytes: [u8; 32] = entity_salt.into();
 
-        // Compute the hash: `H("leaf" | entity_id | entity_salt)`
-        let mut hasher = Hasher::new();
+        let mut hasher =
+            domain_separator.map_or_else(Hasher::new, Hasher::new_with_domain_separator);
         hasher.update("leaf".as_bytes());
         hasher.update(&entity_id_bytes);
         hasher.update(&entity_salt_bytes);
-        let hash = hasher.finalize();
+        hasher.finalize()
+    }
+
+    /// Copy of a bottom-layer leaf node's content, with a different liability.
+    ///
+    /// The blinding factor and hash are kept, and the Pedersen commitment is
+    /// recomputed. The hash of a leaf node does not depend on the liability
+    /// (see [FullNodeContent::new_leaf]), so the result is the same as
+    /// constructing the leaf node again with the new liability and the
+    /// original secrets. This is not valid for non-leaf nodes because their
+    /// hash depends on their children's commitments.
+    pub fn with_liability(&self, liability: u64) -> FullNodeContent {
+        let commitment =
+            PedersenGens::default().commit(Scalar::from(liability), self.blinding_factor);
 
         FullNodeContent {
             liability,
-            blinding_factor: blinding_factor_scalar,
+            blinding_factor: self.blinding_factor,
             commitment,
-            hash,
+            hash: self.hash,
         }
     }
 
@@ -114,6 +164,17 @@ impl FullNodeContent {
     /// required for the Pedersen commitment.
     #[allow(dead_code)]
     pub fn new_pad(blinding_factor: Secret, coord: &Coordinate, salt: Secret) -> FullNodeContent {
+        FullNodeContent::new_pad_with_domain_separator(blinding_factor, coord, salt, None)
+    }
+
+    /// Same as [FullNodeContent::new_pad] but the hash is computed using
+    /// [Hasher::new_with_domain_separator] if `domain_separator` is set.
+    pub fn new_pad_with_domain_separator(
+        blinding_factor: Secret,
+        coord: &Coordinate,
+        salt: Secret,
+        domain_separator: Option<&str>,
+    ) -> FullNodeContent {
         let liability = 0u64;
         // TODO need to think about whether this is okay or if modulo is going to break
         // things. Maybe we should just have the kdf such that it outputs within the
@@ -129,7 +190,8 @@ impl FullNodeContent {
         let salt_bytes: [u8; 32] = salt.into();
 
         // Compute the hash: `H("pad" | coordinate | salt)`
-        let mut hasher = Hasher::new();
+        let mut hasher =
+            domain_separator.map_or_else(Hasher::new, Hasher::new_with_domain_separator);
         hasher.update("pad".as_bytes());
         hasher.update(&coord_bytes);
         hasher.update(&salt_bytes);
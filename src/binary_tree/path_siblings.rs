@@ -21,10 +21,12 @@
 //! [super][tree_builder][multi_threaded] and
 //! [super][tree_builder][single_threaded].
 
-use super::{BinaryTree, Coordinate, HiddenNodeContent, Mergeable, Node, MIN_STORE_DEPTH};
-use crate::{
-    binary_tree::multi_threaded::RecursionParamsBuilder, read_write_utils, utils::Consume,
-};
+#[cfg(feature = "full")]
+use super::{BinaryTree, MIN_STORE_DEPTH};
+use super::{Coordinate, HiddenNodeContent, Mergeable, Node};
+use crate::read_write_utils;
+#[cfg(feature = "full")]
+use crate::utils::Consume;
 
 use log::info;
 use serde::{Deserialize, Serialize};
@@ -44,9 +46,10 @@ use std::{
 /// node's path. The siblings are ordered from bottom layer (first) to root node
 /// (last, not included). The leaf node + the siblings can be used to
 /// reconstruct the actual nodes in the path as well as the root node.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PathSiblings<C: fmt::Display>(pub Vec<Node<C>>);
 
+#[cfg(feature = "full")]
 impl<C: fmt::Display> PathSiblings<C> {
     /// High performance build algorithm utilizing parallelization.
     /// Uses the same code in [super][tree_builder][multi_threaded].
@@ -58,7 +61,9 @@ impl<C: fmt::Display> PathSiblings<C> {
     /// `new_padding_node_content` is needed to generate new nodes.
     ///
     /// This function defines a closure for building nodes that are not found
-    /// in the store, which is then passed to [build].
+    /// in the store, which is then passed to [build_in_parallel]. The missing
+    /// siblings are independent of each other so they are regenerated
+    /// concurrently.
     pub fn build_using_multi_threaded_algorithm<F>(
         tree: &BinaryTree<C>,
         leaf_node: &Node<C>,
@@ -68,48 +73,16 @@ impl<C: fmt::Display> PathSiblings<C> {
         C: Debug + Clone + Mergeable + Send + Sync + 'static,
         F: Fn(&Coordinate) -> C + Send + Sync + 'static,
     {
-        use super::tree_builder::multi_threaded::{build_node, RecursionParams};
-        use dashmap::DashMap;
+        use super::tree_builder::multi_threaded::regenerate_node;
         use std::sync::Arc;
 
         let new_padding_node_content = Arc::new(new_padding_node_content);
 
         let node_builder = |coord: &Coordinate, tree: &BinaryTree<C>| {
-            let params = RecursionParamsBuilder::default()
-                // We don't want to store anything because the store already exists
-                // inside the binary tree struct.
-                .store_depth(MIN_STORE_DEPTH)
-                .height(tree.height)
-                .build_with_coord(coord);
-
-            // TODO This cloning can be optimized away by changing the
-            // build_node function to use a pre-populated map instead of the
-            // mutable leaves vector.
-            let mut leaf_nodes = Vec::<Node<C>>::new();
-            for x in params.x_coord_range() {
-                tree.get_node(&Coordinate { x, y: 0 }).consume(|node| {
-                    leaf_nodes.push(node);
-                });
-            }
-
-            // If the above vector is empty then we know this node needs to be a
-            // padding node.
-            if leaf_nodes.is_empty() {
-                return Node {
-                    coord: coord.clone(),
-                    content: new_padding_node_content(coord),
-                };
-            }
-
-            build_node(
-                params,
-                leaf_nodes,
-                Arc::clone(&new_padding_node_content),
-                Arc::new(DashMap::<Coordinate, Node<C>>::new()),
-            )
+            regenerate_node(tree, coord, Arc::clone(&new_padding_node_content))
         };
 
-        PathSiblings::build(tree, leaf_node, node_builder)
+        PathSiblings::build_in_parallel(tree, leaf_node, node_builder)
     }
 
     /// Sequential build algorithm.
@@ -161,12 +134,15 @@ impl<C: fmt::Display> PathSiblings<C> {
             // TODO The leaf nodes are cloned and put into a store that is
             // dropped. We should have an option to not put anything in the
             // store, maybe by changing store_depth to be an enum.
+            // There is no cancellation token so the build cannot fail.
             let (_, node) = build_node(
                 leaf_nodes,
                 &coord.to_height(),
                 store_depth,
                 &new_padding_node_content,
-            );
+                None,
+            )
+            .unwrap_or_else(|_| panic!("Regeneration without a cancellation token failed"));
 
             node
         };
@@ -174,6 +150,39 @@ impl<C: fmt::Display> PathSiblings<C> {
         PathSiblings::build(tree, leaf_node, node_builder)
     }
 
+    /// Build algorithm that only uses the nodes in the store.
+    ///
+    /// No nodes are regenerated, so no padding node generator (and hence no
+    /// secrets) is needed. This only works if every sibling node in the path
+    /// is in the store, which is the case if the tree was built with
+    /// `store_depth == height`.
+    ///
+    /// An error is returned if any of the sibling nodes are not in the store.
+    pub fn build_from_store(
+        tree: &BinaryTree<C>,
+        leaf_node: &Node<C>,
+    ) -> Result<PathSiblings<C>, PathSiblingsBuildError>
+    where
+        C: Debug + Clone,
+    {
+        let max_y_coord = tree.height().as_y_coord();
+        let mut siblings = Vec::with_capacity(tree.height().as_usize());
+        let mut current_coord = leaf_node.coord().clone();
+
+        for _y in 0..max_y_coord {
+            let coord = current_coord.sibling_coord();
+
+            let sibling = tree
+                .get_node(&coord)
+                .ok_or(PathSiblingsBuildError::NodeNotInStore { coord })?;
+
+            siblings.push(sibling);
+            current_coord = current_coord.parent_coord();
+        }
+
+        Ok(PathSiblings(siblings))
+    }
+
     /// Private build function that is to be called only by
     /// [build_using_multi_threaded_algorithm] or
     /// [build_using_single_threaded_algorithm].
@@ -201,9 +210,11 @@ impl<C: fmt::Display> PathSiblings<C> {
         for _y in 0..max_y_coord {
             let sibling_coord = current_coord.sibling_coord();
 
-            let sibling = tree
-                .get_node(&sibling_coord)
-                .unwrap_or_else(|| node_builder(&sibling_coord, tree));
+            let sibling = tree.get_node(&sibling_coord).unwrap_or_else(|| {
+                let node = node_builder(&sibling_coord, tree);
+                tree.store.cache_node(&node);
+                node
+            });
 
             siblings.push(sibling);
             current_coord = current_coord.parent_coord();
@@ -211,6 +222,49 @@ impl<C: fmt::Display> PathSiblings<C> {
 
         Ok(PathSiblings(siblings))
     }
+
+    /// Same as [build] but the siblings are grabbed from the store (or
+    /// generated if they are not in the store) concurrently, one task per
+    /// layer. To be called only by [build_using_multi_threaded_algorithm].
+    ///
+    /// Regenerating a sibling does not depend on any of the other siblings,
+    /// so this speeds up proof generation for trees with a small store depth,
+    /// where most of the siblings need to be regenerated. The siblings are
+    /// still ordered from bottom layer (first) to root node (last).
+    fn build_in_parallel<F>(
+        tree: &BinaryTree<C>,
+        leaf_node: &Node<C>,
+        node_builder: F,
+    ) -> Result<PathSiblings<C>, PathSiblingsBuildError>
+    where
+        C: Debug + Clone + Send + Sync,
+        F: Fn(&Coordinate, &BinaryTree<C>) -> Node<C> + Sync,
+    {
+        use rayon::prelude::*;
+
+        let max_y_coord = tree.height().as_y_coord();
+        let mut sibling_coords = Vec::with_capacity(tree.height().as_usize());
+        let mut current_coord = leaf_node.coord().clone();
+
+        for _y in 0..max_y_coord {
+            sibling_coords.push(current_coord.sibling_coord());
+            current_coord = current_coord.parent_coord();
+        }
+
+        // Collecting an indexed parallel iterator preserves the order.
+        let siblings = sibling_coords
+            .into_par_iter()
+            .map(|sibling_coord| {
+                tree.get_node(&sibling_coord).unwrap_or_else(|| {
+                    let node = node_builder(&sibling_coord, tree);
+                    tree.store.cache_node(&node);
+                    node
+                })
+            })
+            .collect();
+
+        Ok(PathSiblings(siblings))
+    }
 }
 
 // -------------------------------------------------------------------------------------------------
@@ -257,6 +311,25 @@ impl<C: Debug + fmt::Display + Clone + Mergeable + PartialEq> PathSiblings<C> {
         Ok(parent)
     }
 
+    /// Merge `node` with each of the siblings in turn, returning the ancestor
+    /// of `node` that is `self.len()` layers above it.
+    ///
+    /// Unlike [construct_root_node] the siblings do not have to reach the
+    /// root node, so there is no min number of siblings. If there are no
+    /// siblings then `node` is returned.
+    ///
+    /// An error is returned if the [PathSiblings] data is invalid.
+    pub fn construct_ancestor(&self, node: Node<C>) -> Result<Node<C>, PathSiblingsError> {
+        let mut ancestor = node;
+
+        for sibling in &self.0 {
+            let parent = MatchedPairRef::from(sibling, &ancestor)?.merge();
+            ancestor = parent;
+        }
+
+        Ok(ancestor)
+    }
+
     /// Return a vector containing only the nodes in the tree path.
     ///
     /// The path nodes have to be constructed using the leaf & sibling nodes in
@@ -270,6 +343,34 @@ impl<C: Debug + fmt::Display + Clone + Mergeable + PartialEq> PathSiblings<C> {
     /// 1. The number of siblings is less than the min amount.
     /// 2. The [PathSiblings] data is invalid.
     pub fn construct_path(&self, leaf: Node<C>) -> Result<Vec<Node<C>>, PathSiblingsError> {
+        self.construct_path_using(leaf, |left, right| {
+            MatchedPairRef {
+                left: LeftSiblingRef(left),
+                right: RightSiblingRef(right),
+            }
+            .merge()
+        })
+    }
+
+    /// Same as [construct_path] but the parent of each pair of nodes is
+    /// computed by `merge`, which is given the left & right sibling (in that
+    /// order).
+    ///
+    /// This allows the calling code to avoid recomputing parent nodes that
+    /// it has already computed, e.g. when constructing many paths from the
+    /// same tree.
+    ///
+    /// An error is returned if
+    /// 1. The number of siblings is less than the min amount.
+    /// 2. The [PathSiblings] data is invalid.
+    pub fn construct_path_using<F>(
+        &self,
+        leaf: Node<C>,
+        mut merge: F,
+    ) -> Result<Vec<Node<C>>, PathSiblingsError>
+    where
+        F: FnMut(&Node<C>, &Node<C>) -> Node<C>,
+    {
         use super::MIN_HEIGHT;
 
         if self.len() < MIN_HEIGHT.as_usize() {
@@ -287,7 +388,8 @@ impl<C: Debug + fmt::Display + Clone + Mergeable + PartialEq> PathSiblings<C> {
                 .last()
                 .expect("[Bug in path generation] Empty node vector");
             let pair = MatchedPairRef::from(node, parent)?;
-            nodes.push(pair.merge());
+            let merged = merge(pair.left.0, pair.right.0);
+            nodes.push(merged);
         }
 
         Ok(nodes)
@@ -341,6 +443,20 @@ struct PathWithSiblings {
     path_siblings: Vec<PrettyNode>,
 }
 
+/// Encoding of the hash & commitment bytes in the path information json
+/// file.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "full", derive(clap::ValueEnum))]
+pub enum PathInfoFormat {
+    /// Hexadecimal string with a `0x` prefix.
+    #[default]
+    Hex,
+    /// Standard base64 string, with padding.
+    Base64,
+    /// Array of the raw byte values.
+    Bytes,
+}
+
 /// This is basically a PrettyNode<HiddenNodeContent>.
 ///
 /// This is used to write out path information to a neat json file.
@@ -348,36 +464,47 @@ struct PathWithSiblings {
 // Note that we could have just serialized Node<C>, but the commitment and
 // hash values serialize to different formats. The commitment serializes to
 // [u8; 32], and the hash to a hex String. This is a pain to ingest with other
-// software. One way to get both commitment & hash to be hex Strings is to
-// simply do it manually, but that means knowing the specific type of C. So
+// software. One way to get both commitment & hash to be in the same format is
+// to simply do it manually, but that means knowing the specific type of C. So
 // we cannot make this generic for all types of C.
 #[derive(Debug, Serialize)]
 struct PrettyNode {
     coord: Coordinate,
-    hash: String,
-    commitment: String,
+    hash: EncodedBytes,
+    commitment: EncodedBytes,
 }
 
-impl From<Node<HiddenNodeContent>> for PrettyNode {
-    /// Convert from a Node type to a PrettyNode.
-    ///
-    /// The hash & commitment string fields are populated with hexadecimal
-    /// format of the underlying data.
-    fn from(node: Node<HiddenNodeContent>) -> Self {
-        use primitive_types::H256;
-        use std::fmt::Write as _;
+/// Bytes encoded according to [PathInfoFormat].
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(untagged)]
+enum EncodedBytes {
+    Text(String),
+    Raw([u8; 32]),
+}
 
-        let com_bytes = H256::from_slice(node.content.commitment.compress().as_bytes());
-        let mut com_str = String::new();
-        write!(&mut com_str, "{:x?}", com_bytes).expect("Cannot write to string object");
+impl EncodedBytes {
+    fn new(bytes: [u8; 32], format: PathInfoFormat) -> Self {
+        use base64::{engine::general_purpose::STANDARD, Engine as _};
+        use primitive_types::H256;
 
-        let mut hash_str = String::new();
-        write!(&mut hash_str, "{:x?}", node.content.hash).expect("Cannot write to string object");
+        match format {
+            PathInfoFormat::Hex => EncodedBytes::Text(format!("{:x?}", H256::from(bytes))),
+            PathInfoFormat::Base64 => EncodedBytes::Text(STANDARD.encode(bytes)),
+            PathInfoFormat::Bytes => EncodedBytes::Raw(bytes),
+        }
+    }
+}
 
+impl PrettyNode {
+    /// Convert from a Node type to a PrettyNode.
+    ///
+    /// The hash & commitment fields are populated with the underlying bytes,
+    /// encoded according to `format`.
+    fn new(node: Node<HiddenNodeContent>, format: PathInfoFormat) -> Self {
         PrettyNode {
             coord: node.coord,
-            hash: hash_str,
-            commitment: com_str,
+            hash: EncodedBytes::new(node.content.hash.to_fixed_bytes(), format),
+            commitment: EncodedBytes::new(node.content.commitment.compress().to_bytes(), format),
         }
     }
 }
@@ -389,6 +516,8 @@ impl PathSiblings<HiddenNodeContent> {
     /// recompute in here if they have been computed elsewhere). The path nodes
     /// can be generated using [construct_nodes].
     ///
+    /// The hash & commitment of each node are encoded according to `format`.
+    ///
     /// Returns an error if the provided directory is invalid, or if the
     /// serialization process fails.
     pub fn write_path_to_json(
@@ -396,6 +525,7 @@ impl PathSiblings<HiddenNodeContent> {
         path_nodes: Vec<Node<HiddenNodeContent>>,
         dir: PathBuf,
         mut file_name: OsString,
+        format: PathInfoFormat,
     ) -> Result<(), PathSiblingsWriteError> {
         if !dir.is_dir() {
             return Err(PathSiblingsWriteError::InvalidDirectory(
@@ -406,8 +536,15 @@ impl PathSiblings<HiddenNodeContent> {
         file_name.push(".path_information.json");
         let file_path = dir.join(file_name);
 
-        let siblings = self.0.into_iter().map(PrettyNode::from).collect();
-        let nodes = path_nodes.into_iter().map(PrettyNode::from).collect();
+        let siblings = self
+            .0
+            .into_iter()
+            .map(|node| PrettyNode::new(node, format))
+            .collect();
+        let nodes = path_nodes
+            .into_iter()
+            .map(|node| PrettyNode::new(node, format))
+            .collect();
 
         let path_with_siblings = PathWithSiblings {
             path_nodes: nodes,
@@ -449,6 +586,7 @@ impl<C: fmt::Display> fmt::Display for PathSiblings<C> {
 // -------------------------------------------------------------------------------------------------
 // Errors.
 
+#[cfg(feature = "full")]
 #[derive(thiserror::Error, Debug)]
 pub enum PathSiblingsBuildError {
     #[error("The builder must be given a padding node generator function before building")]
@@ -459,6 +597,8 @@ pub enum PathSiblingsBuildError {
     NoLeafProvided,
     #[error("Leaf node not found in the tree ({coord:?})")]
     LeafNodeNotFound { coord: Coordinate },
+    #[error("Node not found in the store ({coord:?}), the tree store is not full")]
+    NodeNotInStore { coord: Coordinate },
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -558,7 +698,7 @@ impl<'a, C: Mergeable + fmt::Display> MatchedPairRef<'a, C> {
 
 // TODO tests for multi tree build then single path build, and vice versa.
 
-#[cfg(test)]
+#[cfg(all(test, feature = "full"))]
 mod tests {
     use super::super::*;
     use super::*;
@@ -761,4 +901,115 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn multi_threaded_siblings_are_in_same_order_as_single_threaded() {
+        let height = Height::expect_from(16u8);
+
+        let leaf_nodes = sparse_leaves(&height);
+
+        // Separate trees are used so that the nodes cached by the one build
+        // are not used by the other.
+        let build_tree = || {
+            BinaryTreeBuilder::new()
+                .with_height(height)
+                .with_leaf_nodes(leaf_nodes.clone())
+                .with_store_depth(MIN_STORE_DEPTH)
+                .build_using_multi_threaded_algorithm(generate_padding_closure())
+                .unwrap()
+        };
+        let tree_single_threaded = build_tree();
+        let tree_multi_threaded = build_tree();
+
+        let leaf_node = tree_single_threaded.get_leaf_node(6).unwrap();
+
+        let single_threaded = PathSiblings::build_using_single_threaded_algorithm(
+            &tree_single_threaded,
+            &leaf_node,
+            generate_padding_closure(),
+        )
+        .unwrap();
+        let multi_threaded = PathSiblings::build_using_multi_threaded_algorithm(
+            &tree_multi_threaded,
+            &leaf_node,
+            generate_padding_closure(),
+        )
+        .unwrap();
+
+        assert_eq!(single_threaded.0, multi_threaded.0);
+    }
+
+    #[test]
+    fn path_from_store_works_for_full_store() {
+        let height = Height::expect_from(8u8);
+
+        let leaf_nodes = sparse_leaves(&height);
+
+        let tree = BinaryTreeBuilder::new()
+            .with_height(height)
+            .with_leaf_nodes(leaf_nodes)
+            .with_store_depth(height.as_u8())
+            .build_using_multi_threaded_algorithm(generate_padding_closure())
+            .unwrap();
+
+        let leaf_node = tree.get_leaf_node(6).unwrap();
+
+        let siblings = PathSiblings::build_from_store(&tree, &leaf_node)
+            .expect("PathSiblings build should have been successful");
+
+        assert_eq!(
+            &siblings.construct_root_node(&leaf_node).unwrap(),
+            tree.root()
+        );
+    }
+
+    #[test]
+    fn path_from_store_fails_for_partial_store() {
+        let height = Height::expect_from(8u8);
+
+        let leaf_nodes = sparse_leaves(&height);
+
+        let tree = BinaryTreeBuilder::new()
+            .with_height(height)
+            .with_leaf_nodes(leaf_nodes)
+            .with_store_depth(MIN_STORE_DEPTH)
+            .build_using_multi_threaded_algorithm(generate_padding_closure())
+            .unwrap();
+
+        let leaf_node = tree.get_leaf_node(6).unwrap();
+
+        let res = PathSiblings::build_from_store(&tree, &leaf_node);
+        assert!(matches!(
+            res,
+            Err(PathSiblingsBuildError::NodeNotInStore { .. })
+        ));
+    }
+
+    #[test]
+    fn pretty_node_is_encoded_in_requested_format() {
+        use bulletproofs::PedersenGens;
+        use curve25519_dalek_ng::scalar::Scalar;
+        use primitive_types::H256;
+
+        let commitment = PedersenGens::default().commit(Scalar::from(3u64), Scalar::from(7u64));
+        let node = Node {
+            coord: Coordinate { x: 1, y: 0 },
+            content: HiddenNodeContent::new(commitment, H256::repeat_byte(0xab)),
+        };
+        let commitment_bytes = commitment.compress().to_bytes();
+
+        let hex = serde_json::to_value(PrettyNode::new(node.clone(), PathInfoFormat::Hex)).unwrap();
+        assert_eq!(hex["hash"], format!("0x{}", "ab".repeat(32)));
+
+        let base64 =
+            serde_json::to_value(PrettyNode::new(node.clone(), PathInfoFormat::Base64)).unwrap();
+        assert_eq!(
+            base64["hash"],
+            "q6urq6urq6urq6urq6urq6urq6urq6urq6urq6urq6s="
+        );
+
+        let bytes = serde_json::to_value(PrettyNode::new(node, PathInfoFormat::Bytes)).unwrap();
+        assert_eq!(bytes["hash"], serde_json::json!([0xabu8; 32].to_vec()));
+        assert_eq!(bytes["commitment"], serde_json::json!(commitment_bytes));
+    }
 }
@@ -8,12 +8,15 @@
 //! type for the content of the node, which means the tree builder also has this
 //! generic type, `C`.
 
-use serde::Serialize;
+use clap::ValueEnum;
+use log::warn;
+use serde::{Deserialize, Serialize};
 use std::fmt::{self, Debug};
+use std::sync::Arc;
 
-use crate::MaxThreadCount;
+use crate::{CancellationToken, MaxThreadCount};
 
-use super::{BinaryTree, Coordinate, Height, Mergeable, Node};
+use super::{BinaryTree, Coordinate, Height, Mergeable, Node, MIN_RECOMMENDED_SPARSITY};
 
 pub mod multi_threaded;
 pub mod single_threaded;
@@ -45,13 +48,38 @@ pub const MIN_STORE_DEPTH: u8 = 1;
 /// root node down are stored. So if `store_depth == height` then all the nodes
 /// are stored.
 ///
+/// The memory retained by the store once the tree is built can be bounded
+/// using `store_capacity_bytes`, see
+/// [BinaryTreeBuilder::with_store_capacity_bytes].
+///
 /// [binary tree]: super::BinaryTree
 #[derive(Debug)]
 pub struct BinaryTreeBuilder<C> {
     height: Option<Height>,
     leaf_nodes: Option<Vec<InputLeafNode<C>>>,
     store_depth: Option<u8>,
+    store_capacity_bytes: Option<usize>,
     max_thread_count: Option<MaxThreadCount>,
+    cancellation_token: Option<CancellationToken>,
+    build_strategy: Option<BuildStrategy>,
+    min_sparsity_warning: Option<u64>,
+}
+
+/// Algorithm used to build the tree, see [BinaryTreeBuilder::build].
+///
+/// Both algorithms produce exactly the same tree (and root node) for the same
+/// input. The multi-threaded algorithm is much faster for large trees, but the
+/// single-threaded one can be useful for debugging, or on machines where
+/// thread contention hurts performance.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, ValueEnum, PartialEq)]
+pub enum BuildStrategy {
+    #[serde(rename = "single")]
+    #[value(name = "single")]
+    SingleThreaded,
+    #[default]
+    #[serde(rename = "multi")]
+    #[value(name = "multi")]
+    MultiThreaded,
 }
 
 /// A simpler version of the [super][Node] struct that is used as input to
@@ -77,7 +105,11 @@ where
             height: None,
             leaf_nodes: None,
             store_depth: None,
+            store_capacity_bytes: None,
             max_thread_count: None,
+            cancellation_token: None,
+            build_strategy: None,
+            min_sparsity_warning: None,
         }
     }
 
@@ -114,11 +146,35 @@ where
     /// proofs since more nodes may have to be built from scratch.
     ///
     /// This value is not required, and will be given a default if not provided.
+    /// It must be in the range [MIN_STORE_DEPTH, height], otherwise the build
+    /// fails with [TreeBuildError::InvalidStoreDepth] before any nodes are
+    /// built. It is not clamped because a value outside the range is most
+    /// likely a configuration mistake.
     pub fn with_store_depth(mut self, store_depth: u8) -> Self {
         self.store_depth = Some(store_depth);
         self
     }
 
+    /// Bound the memory retained by the store once the tree is built.
+    ///
+    /// If set, the store of the built tree becomes a bounded
+    /// least-recently-used cache (see [LruStore](super::LruStore)). All the
+    /// non-padding leaf nodes are always kept, but the rest of the stored
+    /// nodes are evicted once the byte budget is reached. Evicted nodes are
+    /// regenerated when they are needed, and put back into the cache.
+    ///
+    /// `store_depth` still determines which nodes are placed in the store
+    /// during the build; if they do not fit in the budget then the nodes
+    /// closest to the root are kept. The budget is not applied during the
+    /// build, so it does not bound the peak memory used by the build.
+    ///
+    /// This value is not required, and the store is not bounded if it is not
+    /// provided.
+    pub fn with_store_capacity_bytes(mut self, store_capacity_bytes: usize) -> Self {
+        self.store_capacity_bytes = Some(store_capacity_bytes);
+        self
+    }
+
     /// Set the max number of threads that will be spawned.
     ///
     /// This value is not required, and will be given a default if not provided.
@@ -127,11 +183,82 @@ where
         self
     }
 
+    /// Set a token that can be used to cancel the build while it is running.
+    ///
+    /// Both build algorithms check the token. If the token is cancelled then
+    /// the build stops and [TreeBuildError::Cancelled] is returned.
+    ///
+    /// This value is not required, and the build cannot be cancelled if it is
+    /// not provided.
+    pub fn with_cancellation_token_opt(
+        mut self,
+        cancellation_token: Option<CancellationToken>,
+    ) -> Self {
+        self.cancellation_token = cancellation_token;
+        self
+    }
+
+    /// Set a token that can be used to cancel the build while it is running.
+    ///
+    /// See [BinaryTreeBuilder::with_cancellation_token_opt] for more details.
+    pub fn with_cancellation_token(self, cancellation_token: CancellationToken) -> Self {
+        self.with_cancellation_token_opt(Some(cancellation_token))
+    }
+
+    /// Set the algorithm used by [BinaryTreeBuilder::build].
+    ///
+    /// This value is not required, and the multi-threaded algorithm is used if
+    /// it is not provided.
+    pub fn with_build_strategy_opt(mut self, build_strategy: Option<BuildStrategy>) -> Self {
+        self.build_strategy = build_strategy;
+        self
+    }
+
+    /// Set the algorithm used by [BinaryTreeBuilder::build].
+    ///
+    /// See [BinaryTreeBuilder::with_build_strategy_opt] for more details.
+    pub fn with_build_strategy(self, build_strategy: BuildStrategy) -> Self {
+        self.with_build_strategy_opt(Some(build_strategy))
+    }
+
+    /// Set the sparsity at or below which a warning is logged during the
+    /// build (see [MIN_RECOMMENDED_SPARSITY]).
+    ///
+    /// A value of 0 disables the warning. This is useful for trees that are
+    /// intentionally dense, e.g. in tests.
+    ///
+    /// This value is not required, and [MIN_RECOMMENDED_SPARSITY] is used if it
+    /// is not provided.
+    pub fn with_min_sparsity_warning(mut self, min_sparsity_warning: u64) -> Self {
+        self.min_sparsity_warning = Some(min_sparsity_warning);
+        self
+    }
+
+    /// Build the tree using the algorithm set by the build strategy.
+    ///
+    /// See [build_using_multi_threaded_algorithm] and
+    /// [build_using_single_threaded_algorithm] for when an error is returned.
+    pub fn build<F>(self, new_padding_node_content: F) -> Result<BinaryTree<C>, TreeBuildError>
+    where
+        C: Debug + Serialize + Send + Sync + 'static,
+        F: Fn(&Coordinate) -> C + Send + Sync + 'static,
+    {
+        match self.build_strategy.unwrap_or_default() {
+            BuildStrategy::SingleThreaded => {
+                self.build_using_single_threaded_algorithm(new_padding_node_content)
+            }
+            BuildStrategy::MultiThreaded => {
+                self.build_using_multi_threaded_algorithm(new_padding_node_content)
+            }
+        }
+    }
+
     /// High performance build algorithm utilizing parallelization.
     ///
     /// Will return an error if:
     /// 1. `height` not set or is <= the min allowed height.
     /// 2. `leaf_nodes` is not set or is empty.
+    /// 3. the cancellation token is cancelled before the build is done.
     pub fn build_using_multi_threaded_algorithm<F>(
         self,
         new_padding_node_content: F,
@@ -143,15 +270,22 @@ where
         let height = self.height()?;
         let max_thread_count = self.max_thread_count.unwrap_or_default();
         let store_depth = self.store_depth(height)?;
+        let store_capacity_bytes = self.store_capacity_bytes;
+        let cancellation_token = self.cancellation_token.clone();
+        let min_sparsity_warning = self.min_sparsity_warning();
         let input_leaf_nodes = self.leaf_nodes(&height)?;
 
-        multi_threaded::build_tree(
+        let tree = multi_threaded::build_tree(
             height,
             store_depth,
             input_leaf_nodes,
             new_padding_node_content,
             max_thread_count,
-        )
+            cancellation_token,
+            min_sparsity_warning,
+        )?;
+
+        bound_store(tree, store_capacity_bytes)
     }
 
     /// Regular build algorithm.
@@ -159,6 +293,7 @@ where
     /// Will return an error if:
     /// 1. `height` not set or is <= the min allowed height.
     /// 2. `leaf_nodes` is not set or is empty.
+    /// 3. the cancellation token is cancelled before the build is done.
     pub fn build_using_single_threaded_algorithm<F>(
         self,
         new_padding_node_content: F,
@@ -169,14 +304,21 @@ where
     {
         let height = self.height()?;
         let store_depth = self.store_depth(height)?;
+        let store_capacity_bytes = self.store_capacity_bytes;
+        let cancellation_token = self.cancellation_token.clone();
+        let min_sparsity_warning = self.min_sparsity_warning();
         let input_leaf_nodes = self.leaf_nodes(&height)?;
 
-        single_threaded::build_tree(
+        let tree = single_threaded::build_tree(
             height,
             store_depth,
             input_leaf_nodes,
             new_padding_node_content,
-        )
+            cancellation_token,
+            min_sparsity_warning,
+        )?;
+
+        bound_store(tree, store_capacity_bytes)
     }
 
     /// Private function used internally to retrieve store depth for building.
@@ -198,6 +340,15 @@ where
         }
     }
 
+    /// Private function used internally to retrieve the sparsity warning
+    /// threshold for building.
+    ///
+    /// Default value: [MIN_RECOMMENDED_SPARSITY].
+    fn min_sparsity_warning(&self) -> u64 {
+        self.min_sparsity_warning
+            .unwrap_or(MIN_RECOMMENDED_SPARSITY as u64)
+    }
+
     /// Private function used internally to retrieve height for building.
     /// No default value, returns an error if not set.
     fn height(&self) -> Result<Height, TreeBuildError> {
@@ -257,6 +408,115 @@ impl<C: fmt::Display> InputLeafNode<C> {
 // -------------------------------------------------------------------------------------------------
 // Helper functions.
 
+/// Upper bound on the number of nodes that are placed in the store when
+/// building a tree with `num_leaf_nodes` bottom-layer leaf nodes.
+///
+/// The store contains the root node, the bottom layer (the leaf nodes and,
+/// depending on the build algorithm & store depth, their padding siblings)
+/// and the `store_depth - 1` layers directly below the root. A node in one of
+/// these layers is only stored if its parent has at least 1 leaf node in its
+/// sub-tree, so layer `y` holds at most `min(2n, 2^(h-1-y))` nodes, where `n`
+/// is the number of leaf nodes and `h` the height. The bound is the sum of
+/// these maximums over the stored layers.
+///
+/// The bound is reached when the leaf nodes are spread out evenly across the
+/// bottom layer. Clustered leaf nodes share more of their ancestors, so fewer
+/// nodes are stored. The multi-threaded algorithm only stores the padding
+/// siblings of the leaf nodes if `store_depth == height`, in which case it
+/// stores up to `n` fewer nodes than the bound.
+///
+/// `store_depth` is clamped to the range [MIN_STORE_DEPTH, height].
+pub fn expected_store_size(height: &Height, num_leaf_nodes: u64, store_depth: u8) -> u64 {
+    let h = height.as_u8();
+    let store_depth = store_depth.clamp(MIN_STORE_DEPTH, h);
+    let n = num_leaf_nodes as u128;
+
+    let max_stored_in_layer = |y: u8| (2 * n).min(1u128 << (h - 1 - y));
+
+    let root = 1u128;
+    let bottom_layer = max_stored_in_layer(0);
+    let internal_layers: u128 = ((h - store_depth).max(1)..h - 1)
+        .map(max_stored_in_layer)
+        .sum();
+
+    u64::try_from(root + bottom_layer + internal_layers).unwrap_or(u64::MAX)
+}
+
+/// Compute the root node of the tree that would be built from `leaf_nodes`,
+/// without building the tree.
+///
+/// The merge recursion of the multi-threaded algorithm is run with
+/// `store_depth == MIN_STORE_DEPTH` and the nodes it produces along the way
+/// are dropped, so only the root node is returned. This is useful when only
+/// the root hash & commitment are needed, for example to check a root that
+/// was published by someone else.
+///
+/// The same checks are done on `leaf_nodes` as when building a tree, and the
+/// same errors are returned if they fail.
+#[cfg(any(test, feature = "testing"))]
+pub fn compute_root_only<C, F>(
+    height: Height,
+    mut leaf_nodes: Vec<InputLeafNode<C>>,
+    new_padding_node_content: F,
+) -> Result<Node<C>, TreeBuildError>
+where
+    C: fmt::Display + Debug + Clone + Mergeable + Send + Sync + 'static,
+    F: Fn(&Coordinate) -> C + Send + Sync + 'static,
+{
+    leaf_nodes.sort_by_key(|node| node.x_coord);
+
+    let leaf_nodes = BinaryTreeBuilder::new()
+        .with_height(height)
+        .with_leaf_nodes(leaf_nodes)
+        .leaf_nodes(&height)?;
+
+    verify_no_duplicate_leaves(&leaf_nodes)?;
+
+    let leaf_nodes = leaf_nodes
+        .into_iter()
+        .map(InputLeafNode::into_node)
+        .collect::<Vec<Node<C>>>();
+
+    let params = multi_threaded::RecursionParamsBuilder::default()
+        .height(height)
+        .store_depth(MIN_STORE_DEPTH)
+        .build();
+
+    multi_threaded::build_node(
+        params,
+        leaf_nodes,
+        Arc::new(new_padding_node_content),
+        Arc::new(dashmap::DashMap::new()),
+    )
+}
+
+/// Swap the tree's store out for a bounded LRU store, if a capacity is given.
+fn bound_store<C: Clone + fmt::Display>(
+    mut tree: BinaryTree<C>,
+    store_capacity_bytes: Option<usize>,
+) -> Result<BinaryTree<C>, TreeBuildError> {
+    if let Some(capacity_bytes) = store_capacity_bytes {
+        let store = Arc::into_inner(tree.store).ok_or(TreeBuildError::StoreOwnershipFailure)?;
+        tree.store = Arc::new(store.into_bounded_lru(capacity_bytes));
+    }
+
+    Ok(tree)
+}
+
+/// Log a warning if the sparsity of the tree is at or below
+/// `min_sparsity_warning` (see [MIN_RECOMMENDED_SPARSITY]).
+///
+/// The sparsity is always at least 1, so a `min_sparsity_warning` of 0 means
+/// the warning is never logged.
+fn warn_on_low_sparsity(height: &Height, num_leaf_nodes: u64, min_sparsity_warning: u64) {
+    if height.max_bottom_layer_nodes() / num_leaf_nodes <= min_sparsity_warning {
+        warn!(
+            "Minimum tree sparsity of {} reached, consider increasing tree height",
+            min_sparsity_warning
+        );
+    }
+}
+
 /// Check that no 2 leaf nodes share the same x-coord.
 /// `leaf_nodes` is expected to be sorted by x-coord.
 /// An error is returned if a duplicate is found.
@@ -300,6 +560,8 @@ pub enum TreeBuildError {
     StoreOwnershipFailure,
     #[error("Store depth ({store_depth:?}) out of bounds [{MIN_STORE_DEPTH:?}, {height:?}]")]
     InvalidStoreDepth { height: Height, store_depth: u8 },
+    #[error("The tree build was cancelled")]
+    Cancelled,
 }
 
 // -------------------------------------------------------------------------------------------------
@@ -318,6 +580,137 @@ mod tests {
     use primitive_types::H256;
     use rand::{thread_rng, Rng};
 
+    // =========================================================================
+    // Expected store size.
+
+    #[test]
+    fn expected_store_size_is_total_node_count_for_full_tree() {
+        let height = Height::expect_from(6u8);
+        let num_leaf_nodes = height.max_bottom_layer_nodes();
+
+        let expected = expected_store_size(&height, num_leaf_nodes, height.as_u8());
+
+        assert_eq!(expected, 2u64.pow(height.as_u32()) - 1);
+
+        let tree = BinaryTreeBuilder::new()
+            .with_height(height)
+            .with_leaf_nodes(full_bottom_layer(&height))
+            .with_store_depth(height.as_u8())
+            .build_using_single_threaded_algorithm(generate_padding_closure())
+            .unwrap();
+
+        assert_eq!(tree.store.len() as u64 + 1, expected);
+    }
+
+    #[test]
+    fn expected_store_size_bounds_store_for_all_store_depths() {
+        use crate::binary_tree::utils::test_utils::random_leaf_nodes;
+
+        let height = Height::expect_from(10u8);
+        let num_leaf_nodes = 50u64;
+
+        for store_depth in MIN_STORE_DEPTH..=height.as_u8() {
+            let expected = expected_store_size(&height, num_leaf_nodes, store_depth);
+
+            for strategy in [BuildStrategy::SingleThreaded, BuildStrategy::MultiThreaded] {
+                let tree = BinaryTreeBuilder::new()
+                    .with_height(height)
+                    .with_leaf_nodes(random_leaf_nodes(num_leaf_nodes, &height, 7))
+                    .with_store_depth(store_depth)
+                    .with_build_strategy(strategy)
+                    .build(generate_padding_closure())
+                    .unwrap();
+
+                // The root is counted in the bound but may not be in the store.
+                assert!(
+                    tree.store.len() as u64 <= expected,
+                    "store_depth {} strategy {:?}: {} > {}",
+                    store_depth,
+                    strategy,
+                    tree.store.len(),
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn expected_store_size_grows_with_store_depth() {
+        let height = Height::expect_from(32u8);
+        let num_leaf_nodes = 1000u64;
+
+        let sizes: Vec<u64> = (MIN_STORE_DEPTH..=height.as_u8())
+            .map(|store_depth| expected_store_size(&height, num_leaf_nodes, store_depth))
+            .collect();
+
+        assert_eq!(sizes[0], 1 + 2 * num_leaf_nodes);
+        assert!(sizes.windows(2).all(|w| w[0] <= w[1]));
+    }
+
+    // =========================================================================
+    // Compute root only.
+
+    #[test]
+    fn compute_root_only_matches_root_of_built_tree() {
+        use crate::binary_tree::utils::test_utils::random_leaf_nodes;
+
+        let height = Height::expect_from(10u8);
+
+        for leaf_nodes in [
+            random_leaf_nodes(50, &height, 7),
+            sparse_leaves(&height),
+            full_bottom_layer(&height),
+            vec![single_leaf(0)],
+            vec![single_leaf(height.max_bottom_layer_nodes() - 1)],
+        ] {
+            let tree = BinaryTreeBuilder::new()
+                .with_height(height)
+                .with_leaf_nodes(leaf_nodes.clone())
+                .build_using_single_threaded_algorithm(generate_padding_closure())
+                .unwrap();
+
+            let root = compute_root_only(height, leaf_nodes, generate_padding_closure()).unwrap();
+
+            assert_eq!(&root, tree.root());
+        }
+    }
+
+    #[test]
+    fn compute_root_only_does_not_depend_on_leaf_order() {
+        let height = Height::expect_from(8u8);
+        let leaf_nodes = sparse_leaves(&height);
+        let mut reversed = leaf_nodes.clone();
+        reversed.reverse();
+
+        let root = compute_root_only(height, leaf_nodes, generate_padding_closure()).unwrap();
+        let root_reversed =
+            compute_root_only(height, reversed, generate_padding_closure()).unwrap();
+
+        assert_eq!(root, root_reversed);
+    }
+
+    #[test]
+    fn compute_root_only_err_for_invalid_leaves() {
+        let height = Height::expect_from(4u8);
+
+        let res = compute_root_only(height, Vec::new(), generate_padding_closure());
+        assert_err!(res, Err(TreeBuildError::EmptyLeaves));
+
+        let res = compute_root_only(
+            height,
+            vec![single_leaf(1), single_leaf(1)],
+            generate_padding_closure(),
+        );
+        assert_err!(res, Err(TreeBuildError::DuplicateLeaves));
+
+        let res = compute_root_only(
+            height,
+            vec![single_leaf(height.max_bottom_layer_nodes()), single_leaf(0)],
+            generate_padding_closure(),
+        );
+        assert_err!(res, Err(TreeBuildError::InvalidXCoord));
+    }
+
     // =========================================================================
     // Happy cases for both single- and multi-threaded builders.
     // All tests here compare the trees from the 2 build algorithms, which gives
@@ -399,6 +792,29 @@ mod tests {
         }
     }
 
+    #[test]
+    fn build_strategies_give_same_root() {
+        let height = Height::expect_from(8u8);
+
+        let leaf_nodes = sparse_leaves(&height);
+
+        let single_threaded = BinaryTreeBuilder::new()
+            .with_height(height)
+            .with_leaf_nodes(leaf_nodes.clone())
+            .with_build_strategy(BuildStrategy::SingleThreaded)
+            .build(generate_padding_closure())
+            .unwrap();
+
+        let multi_threaded = BinaryTreeBuilder::new()
+            .with_height(height)
+            .with_leaf_nodes(leaf_nodes)
+            .with_build_strategy(BuildStrategy::MultiThreaded)
+            .build(generate_padding_closure())
+            .unwrap();
+
+        assert_eq!(single_threaded.root, multi_threaded.root);
+    }
+
     // =========================================================================
 
     #[test]
@@ -462,6 +878,57 @@ mod tests {
         );
     }
 
+    #[test]
+    fn err_for_store_depth_greater_than_height() {
+        let height = Height::expect_from(4);
+        let res = BinaryTreeBuilder::<TestContent>::new()
+            .with_height(height)
+            .with_store_depth(height.as_u8() + 1)
+            .store_depth(height);
+
+        assert_err!(
+            res,
+            Err(TreeBuildError::InvalidStoreDepth {
+                height: _,
+                store_depth: 5,
+            })
+        );
+    }
+
+    #[test]
+    fn err_for_store_depth_less_than_min() {
+        let height = Height::expect_from(4);
+        let res = BinaryTreeBuilder::<TestContent>::new()
+            .with_height(height)
+            .with_store_depth(MIN_STORE_DEPTH - 1)
+            .store_depth(height);
+
+        assert_err!(
+            res,
+            Err(TreeBuildError::InvalidStoreDepth {
+                height: _,
+                store_depth: 0,
+            })
+        );
+    }
+
+    #[test]
+    fn store_depth_bounds_and_default_are_valid() {
+        use crate::binary_tree::MIN_HEIGHT;
+
+        let height = Height::expect_from(4);
+        for store_depth in [MIN_STORE_DEPTH, height.as_u8()] {
+            let res = BinaryTreeBuilder::<TestContent>::new()
+                .with_store_depth(store_depth)
+                .store_depth(height);
+            assert_eq!(res.unwrap(), store_depth);
+        }
+
+        BinaryTreeBuilder::<TestContent>::new()
+            .store_depth(MIN_HEIGHT)
+            .unwrap();
+    }
+
     #[test]
     fn err_for_duplicate_leaves() {
         let height = Height::expect_from(4);
@@ -38,7 +38,6 @@ use core::fmt;
 use std::fmt::Debug;
 use std::ops::Range;
 
-use log::warn;
 use logging_timer::stime;
 
 use dashmap::DashMap;
@@ -50,11 +49,11 @@ use serde::{Deserialize, Serialize};
 
 use derive_builder::Builder;
 
-use crate::{MaxThreadCount, MAX_HEIGHT};
+use crate::{CancellationToken, MaxThreadCount, MAX_HEIGHT};
 
 use super::super::{
     Coordinate, Height, InputLeafNode, MatchedPair, Mergeable, Node, Sibling, Store,
-    MIN_RECOMMENDED_SPARSITY, MIN_STORE_DEPTH,
+    MIN_STORE_DEPTH,
 };
 use super::{BinaryTree, TreeBuildError};
 
@@ -76,12 +75,14 @@ pub fn build_tree<C: fmt::Display, F>(
     mut input_leaf_nodes: Vec<InputLeafNode<C>>,
     new_padding_node_content: F,
     max_thread_count: MaxThreadCount,
+    cancellation_token: Option<CancellationToken>,
+    min_sparsity_warning: u64,
 ) -> Result<BinaryTree<C>, TreeBuildError>
 where
     C: Debug + Clone + Mergeable + Send + Sync + 'static,
     F: Fn(&Coordinate) -> C + Send + Sync + 'static,
 {
-    use super::verify_no_duplicate_leaves;
+    use super::{verify_no_duplicate_leaves, warn_on_low_sparsity};
 
     let leaf_nodes = {
         // Sort by x-coord ascending.
@@ -104,23 +105,20 @@ where
         .height(height)
         .store_depth(store_depth)
         .max_thread_count(max_thread_count.as_u8())
+        .cancellation_token(cancellation_token)
         .build();
 
-    if height.max_bottom_layer_nodes() / leaf_nodes.len() as u64 <= MIN_RECOMMENDED_SPARSITY as u64
-    {
-        warn!(
-            "Minimum recommended tree sparsity of {} reached, consider increasing tree height",
-            MIN_RECOMMENDED_SPARSITY
-        );
-    }
+    warn_on_low_sparsity(&height, leaf_nodes.len() as u64, min_sparsity_warning);
 
     // Parallelized build algorithm.
+    // If the build is cancelled then the partially filled store is dropped
+    // along with the error.
     let root = build_node(
         params,
         leaf_nodes,
         Arc::new(new_padding_node_content),
         Arc::clone(&store),
-    );
+    )?;
 
     store.insert(root.coord.clone(), root.clone());
     store.shrink_to_fit();
@@ -131,7 +129,7 @@ where
 
     Ok(BinaryTree {
         root,
-        store: Store::MultiThreadedStore(store),
+        store: Arc::new(Store::MultiThreaded(store)),
         height,
     })
 }
@@ -154,6 +152,33 @@ impl<C: Clone + fmt::Display> DashMapStore<C> {
     pub fn len(&self) -> usize {
         self.map.len()
     }
+
+    pub fn into_nodes(self) -> impl Iterator<Item = Node<C>> {
+        self.map.into_iter().map(|(_, node)| node)
+    }
+
+    /// Coordinates of all the nodes in the store, in no particular order.
+    pub fn coordinates(&self) -> Vec<Coordinate> {
+        self.map.iter().map(|entry| entry.key().clone()).collect()
+    }
+
+    /// Copy of the store that only contains the nodes for which `keep`
+    /// returns true.
+    pub fn filtered<P: Fn(&Coordinate) -> bool>(&self, keep: P) -> Self {
+        let map = self
+            .map
+            .iter()
+            .filter(|entry| keep(entry.key()))
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect();
+
+        DashMapStore { map }
+    }
+
+    /// Insert the node, replacing any node with the same coordinate.
+    pub fn insert_node(&mut self, node: Node<C>) {
+        self.map.insert(node.coord.clone(), node);
+    }
 }
 
 // -------------------------------------------------------------------------------------------------
@@ -164,20 +189,25 @@ impl<C: Clone + fmt::Display> DashMapStore<C> {
 /// Requires `nodes` to be sorted according to the x-coord field.
 /// If all nodes satisfy `node.coord.x <= mid` then `Full` is returned.
 /// If no nodes satisfy `node.coord.x <= mid` then `Empty` is returned.
-// TODO can be optimized using a binary search
+///
+/// A binary search is used, which relies on the x-coords being unique (which
+/// is checked by the builder).
 fn num_nodes_left_of<C: fmt::Display>(x_coord_mid: u64, nodes: &Vec<Node<C>>) -> NumNodes {
-    nodes
-        .iter()
-        .rposition(|leaf| leaf.coord.x <= x_coord_mid)
-        .map_or(NumNodes::Empty, |index| {
-            if index == nodes.len() - 1 {
-                NumNodes::Full
-            } else {
-                NumNodes::Partial(index)
-            }
-        })
+    let num_left = match nodes.binary_search_by(|node| node.coord.x.cmp(&x_coord_mid)) {
+        Ok(index) => index + 1,
+        Err(index) => index,
+    };
+
+    if num_left == 0 {
+        NumNodes::Empty
+    } else if num_left == nodes.len() {
+        NumNodes::Full
+    } else {
+        NumNodes::Partial(num_left - 1)
+    }
 }
 
+#[derive(Debug, PartialEq)]
 enum NumNodes {
     Full,
     Empty,
@@ -261,6 +291,7 @@ pub struct RecursionParams {
     max_thread_count: u8,
     store_depth: u8,
     height: Height,
+    cancellation_token: Option<CancellationToken>,
 }
 
 impl RecursionParamsBuilder {
@@ -283,6 +314,7 @@ impl RecursionParamsBuilder {
             thread_count: Arc::new(Mutex::new(1)),
             max_thread_count: self.max_thread_count.unwrap_or(1),
             store_depth: self.store_depth.unwrap_or(MIN_STORE_DEPTH),
+            cancellation_token: self.cancellation_token.clone().flatten(),
         }
     }
 
@@ -299,6 +331,7 @@ impl RecursionParamsBuilder {
             height: self.height.unwrap_or(MAX_HEIGHT),
             max_thread_count: self.max_thread_count.unwrap_or(1),
             store_depth: self.store_depth.unwrap_or(MIN_STORE_DEPTH),
+            cancellation_token: self.cancellation_token.clone().flatten(),
         }
     }
 }
@@ -354,12 +387,19 @@ impl RecursionParams {
             max_thread_count: 1,
             store_depth: MIN_STORE_DEPTH,
             height,
+            cancellation_token: None,
         }
     }
 
     pub fn x_coord_range(&self) -> Range<u64> {
         self.x_coord_min..self.x_coord_max + 1
     }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancellation_token
+            .as_ref()
+            .is_some_and(|token| token.is_cancelled())
+    }
 }
 
 /// Recursive, multi-threaded function for building a node by exploring the tree
@@ -389,16 +429,25 @@ impl RecursionParams {
 /// function anyway. If either case is reached then either there is a bug in the
 /// original calling code or there is a bug in the splitting algorithm in this
 /// function. There is no recovery from these 2 states so we panic.
+///
+/// If the cancellation token in `params` is cancelled then
+/// [TreeBuildError::Cancelled] is returned. The token is checked on every
+/// call, and spawned threads are always joined before returning, so no
+/// threads are left running after cancellation.
 pub fn build_node<C: fmt::Display, F>(
     params: RecursionParams,
     mut leaves: Vec<Node<C>>,
     new_padding_node_content: Arc<F>,
     map: Arc<Map<C>>,
-) -> Node<C>
+) -> Result<Node<C>, TreeBuildError>
 where
     C: Debug + Clone + Mergeable + Send + Sync + 'static,
     F: Fn(&Coordinate) -> C + Send + Sync + 'static,
 {
+    if params.is_cancelled() {
+        return Err(TreeBuildError::Cancelled);
+    }
+
     {
         let max_nodes = Height::from_y_coord(params.y_coord).max_bottom_layer_nodes();
         assert!(
@@ -459,7 +508,7 @@ where
             MatchedPair::from((node, sibling))
         };
 
-        return pair.merge();
+        return Ok(pair.merge());
     }
 
     // NOTE this includes the root node.
@@ -491,7 +540,7 @@ where
                 let params_clone = params.clone();
                 let map_ref = Arc::clone(&map);
 
-                let right_handler = thread::spawn(move || -> Node<C> {
+                let right_handler = thread::spawn(move || -> Result<Node<C>, TreeBuildError> {
                     build_node(
                         params_clone.into_right_child(),
                         right_leaves,
@@ -521,21 +570,22 @@ where
                     }
                 }
 
-                MatchedPair::from((left, right))
+                // Only return an error once the spawned thread has been joined.
+                MatchedPair::from((left?, right?))
             } else {
                 let right = build_node(
                     params.clone().into_right_child(),
                     right_leaves,
                     new_padding_node_content_ref,
                     Arc::clone(&map),
-                );
+                )?;
 
                 let left = build_node(
                     params.into_left_child(),
                     left_leaves,
                     new_padding_node_content,
                     Arc::clone(&map),
-                );
+                )?;
 
                 MatchedPair::from((left, right))
             }
@@ -547,7 +597,7 @@ where
                 leaves,
                 new_padding_node_content.clone(),
                 Arc::clone(&map),
-            );
+            )?;
             let right = left.new_sibling_padding_node_arc(new_padding_node_content);
             MatchedPair::from((left, right))
         }
@@ -558,7 +608,7 @@ where
                 leaves,
                 new_padding_node_content.clone(),
                 Arc::clone(&map),
-            );
+            )?;
             let left = right.new_sibling_padding_node_arc(new_padding_node_content);
             MatchedPair::from((left, right))
         }
@@ -569,7 +619,110 @@ where
         map.insert(pair.right.coord.clone(), pair.right.clone());
     }
 
-    pair.merge()
+    Ok(pair.merge())
+}
+
+/// Regenerate the node at `coord` using the bottom-layer leaf nodes of its
+/// sub-tree that are in the store of `tree`.
+///
+/// Nothing is added to the store of `tree` during the regeneration. If no
+/// leaf nodes are found for the sub-tree then the node is a padding node.
+///
+/// Non-padding bottom-layer leaf nodes are always in the store so if `coord`
+/// is on the bottom layer it is expected to point to a padding node.
+pub fn regenerate_node<C, F>(
+    tree: &BinaryTree<C>,
+    coord: &Coordinate,
+    new_padding_node_content: Arc<F>,
+) -> Node<C>
+where
+    C: fmt::Display + Debug + Clone + Mergeable + Send + Sync + 'static,
+    F: Fn(&Coordinate) -> C + Send + Sync + 'static,
+{
+    let params = RecursionParamsBuilder::default()
+        // We don't want to store anything because the store already exists
+        // inside the binary tree struct.
+        .store_depth(MIN_STORE_DEPTH)
+        .height(*tree.height())
+        .build_with_coord(coord);
+
+    // TODO This cloning can be optimized away by changing the
+    // build_node function to use a pre-populated map instead of the
+    // mutable leaves vector.
+    let mut leaf_nodes = Vec::<Node<C>>::new();
+    for x in params.x_coord_range() {
+        if let Some(node) = tree.get_leaf_node(x) {
+            leaf_nodes.push(node);
+        }
+    }
+
+    regenerate_node_from_leaves(params, coord, leaf_nodes, new_padding_node_content)
+}
+
+/// Regenerate the root node of `tree` using only the bottom-layer leaf nodes
+/// at `leaf_x_coords`, which must be sorted.
+///
+/// Unlike [regenerate_node], the bottom layer is not scanned: only the given
+/// x-coords are looked up in the store, and the rest of the tree is built
+/// with the same sparse merge algorithm as the tree builder. So the time
+/// taken depends on the number of leaf nodes, not on the height of the tree.
+/// Any x-coord that has no leaf node in the store is skipped.
+pub fn regenerate_root<C, F>(
+    tree: &BinaryTree<C>,
+    leaf_x_coords: &[u64],
+    new_padding_node_content: Arc<F>,
+) -> Node<C>
+where
+    C: fmt::Display + Debug + Clone + Mergeable + Send + Sync + 'static,
+    F: Fn(&Coordinate) -> C + Send + Sync + 'static,
+{
+    let params = RecursionParamsBuilder::default()
+        .store_depth(MIN_STORE_DEPTH)
+        .height(*tree.height())
+        .build();
+
+    let leaf_nodes = leaf_x_coords
+        .iter()
+        .filter_map(|x| tree.get_leaf_node(*x))
+        .collect::<Vec<Node<C>>>();
+
+    regenerate_node_from_leaves(
+        params,
+        &tree.root.coord,
+        leaf_nodes,
+        new_padding_node_content,
+    )
+}
+
+/// Build the node at `coord` from the bottom-layer `leaf_nodes` of its
+/// sub-tree, or generate a padding node if there are none.
+fn regenerate_node_from_leaves<C, F>(
+    params: RecursionParams,
+    coord: &Coordinate,
+    leaf_nodes: Vec<Node<C>>,
+    new_padding_node_content: Arc<F>,
+) -> Node<C>
+where
+    C: fmt::Display + Debug + Clone + Mergeable + Send + Sync + 'static,
+    F: Fn(&Coordinate) -> C + Send + Sync + 'static,
+{
+    // If there are no leaf nodes then we know this node needs to be a
+    // padding node.
+    if leaf_nodes.is_empty() {
+        return Node {
+            coord: coord.clone(),
+            content: new_padding_node_content(coord),
+        };
+    }
+
+    // There is no cancellation token so the build cannot fail.
+    build_node(
+        params,
+        leaf_nodes,
+        new_padding_node_content,
+        Arc::new(DashMap::<Coordinate, Node<C>>::new()),
+    )
+    .unwrap_or_else(|_| panic!("{} Regeneration without a cancellation token failed", BUG))
 }
 
 // TODO this does not work if store depth is not 100%
@@ -650,6 +803,23 @@ pub(crate) mod tests {
         assert_err!(res, Err(TreeBuildError::EmptyLeaves));
     }
 
+    #[test]
+    fn err_when_cancellation_token_is_cancelled() {
+        let height = Height::expect_from(8);
+        let leaf_nodes = full_bottom_layer(&height);
+        let cancellation_token = CancellationToken::new();
+        cancellation_token.cancel();
+
+        let res = BinaryTreeBuilder::new()
+            .with_height(height)
+            .with_leaf_nodes(leaf_nodes)
+            .with_cancellation_token(cancellation_token)
+            .build_using_multi_threaded_algorithm(generate_padding_closure());
+
+        // cannot use assert_err because it requires Func to have the Debug trait
+        assert_err_simple!(res, Err(TreeBuildError::Cancelled));
+    }
+
     #[test]
     fn err_for_too_many_leaves_with_height_first() {
         let height = Height::expect_from(8u8);
@@ -859,6 +1029,33 @@ pub(crate) mod tests {
         assert!(tree.store.len() < max_nodes as usize);
     }
 
+    #[test]
+    fn num_nodes_left_of_gives_correct_boundaries() {
+        let nodes: Vec<Node<TestContent>> = [1u64, 3, 4, 8]
+            .into_iter()
+            .map(|x| Node {
+                coord: Coordinate { x, y: 0 },
+                content: TestContent {
+                    hash: H256::random(),
+                    value: x as u32,
+                },
+            })
+            .collect();
+
+        assert_eq!(num_nodes_left_of(0, &nodes), NumNodes::Empty);
+        assert_eq!(num_nodes_left_of(1, &nodes), NumNodes::Partial(0));
+        assert_eq!(num_nodes_left_of(2, &nodes), NumNodes::Partial(0));
+        assert_eq!(num_nodes_left_of(3, &nodes), NumNodes::Partial(1));
+        assert_eq!(num_nodes_left_of(5, &nodes), NumNodes::Partial(2));
+        assert_eq!(num_nodes_left_of(7, &nodes), NumNodes::Partial(2));
+        assert_eq!(num_nodes_left_of(8, &nodes), NumNodes::Full);
+        assert_eq!(num_nodes_left_of(9, &nodes), NumNodes::Full);
+        assert_eq!(
+            num_nodes_left_of::<TestContent>(0, &Vec::new()),
+            NumNodes::Empty
+        );
+    }
+
     #[test]
     fn max_nodes_to_store_equality() {
         // Got this by using the fuzzer and setting fuzz_max_nodes_to_store to
@@ -17,16 +17,16 @@
 
 use std::collections::HashMap;
 use std::fmt::{self, Debug};
+use std::sync::Arc;
 
-use log::warn;
 use logging_timer::stime;
 use serde::{Deserialize, Serialize};
 
 use super::super::{
     BinaryTree, Coordinate, Height, InputLeafNode, MatchedPair, Mergeable, Node, Sibling, Store,
-    MIN_RECOMMENDED_SPARSITY,
 };
 use super::TreeBuildError;
+use crate::CancellationToken;
 
 const BUG: &str = "[Bug in single-threaded builder]";
 
@@ -40,19 +40,24 @@ const BUG: &str = "[Bug in single-threaded builder]";
 ///
 /// The leaf nodes are sorted by x-coord, checked for duplicates, and
 /// converted to the right type.
+///
+/// If `cancellation_token` is cancelled then the build stops before the next
+/// layer of the tree is built, and [TreeBuildError::Cancelled] is returned.
 #[stime("info", "SingleThreadedBuilder::{}")]
 pub fn build_tree<C: fmt::Display, F>(
     height: Height,
     store_depth: u8,
     mut input_leaf_nodes: Vec<InputLeafNode<C>>,
     new_padding_node_content: F,
+    cancellation_token: Option<CancellationToken>,
+    min_sparsity_warning: u64,
 ) -> Result<BinaryTree<C>, TreeBuildError>
 where
     C: Debug + Clone + Mergeable + 'static, /* This static is needed for the boxed
                                              * hashmap. */
     F: Fn(&Coordinate) -> C,
 {
-    use super::verify_no_duplicate_leaves;
+    use super::{verify_no_duplicate_leaves, warn_on_low_sparsity};
 
     let leaf_nodes = {
         // Sort by x-coord ascending.
@@ -67,19 +72,19 @@ where
             .collect::<Vec<Node<C>>>()
     };
 
-    if height.max_bottom_layer_nodes() / leaf_nodes.len() as u64 <= MIN_RECOMMENDED_SPARSITY as u64
-    {
-        warn!(
-            "Minimum recommended tree sparsity of {} reached, consider increasing tree height",
-            MIN_RECOMMENDED_SPARSITY
-        );
-    }
+    warn_on_low_sparsity(&height, leaf_nodes.len() as u64, min_sparsity_warning);
 
-    let (map, root) = build_node(leaf_nodes, &height, store_depth, &new_padding_node_content);
+    let (map, root) = build_node(
+        leaf_nodes,
+        &height,
+        store_depth,
+        &new_padding_node_content,
+        cancellation_token.as_ref(),
+    )?;
 
     Ok(BinaryTree {
         root,
-        store: Store::SingleThreadedStore(HashMapStore { map }),
+        store: Arc::new(Store::SingleThreaded(HashMapStore { map })),
         height,
     })
 }
@@ -100,6 +105,33 @@ impl<C: Clone + fmt::Display> HashMapStore<C> {
     pub fn len(&self) -> usize {
         self.map.len()
     }
+
+    pub fn into_nodes(self) -> impl Iterator<Item = Node<C>> {
+        self.map.into_values()
+    }
+
+    /// Coordinates of all the nodes in the store, in no particular order.
+    pub fn coordinates(&self) -> Vec<Coordinate> {
+        self.map.keys().cloned().collect()
+    }
+
+    /// Copy of the store that only contains the nodes for which `keep`
+    /// returns true.
+    pub fn filtered<P: Fn(&Coordinate) -> bool>(&self, keep: P) -> Self {
+        let map = self
+            .map
+            .iter()
+            .filter(|(coord, _)| keep(coord))
+            .map(|(coord, node)| (coord.clone(), node.clone()))
+            .collect();
+
+        HashMapStore { map }
+    }
+
+    /// Insert the node, replacing any node with the same coordinate.
+    pub fn insert_node(&mut self, node: Node<C>) {
+        self.map.insert(node.coord.clone(), node);
+    }
 }
 
 // -------------------------------------------------------------------------------------------------
@@ -184,12 +216,16 @@ type RootNode<C> = Node<C>;
 ///
 /// Note that all bottom layer nodes are stored, both the inputted leaf
 /// nodes and their accompanying padding nodes.
+///
+/// `cancellation_token` is checked before each layer is built, and
+/// [TreeBuildError::Cancelled] is returned if it has been cancelled.
 pub fn build_node<C: fmt::Display, F>(
     leaf_nodes: Vec<Node<C>>,
     height: &Height,
     store_depth: u8,
     new_padding_node_content: &F,
-) -> (Map<C>, RootNode<C>)
+    cancellation_token: Option<&CancellationToken>,
+) -> Result<(Map<C>, RootNode<C>), TreeBuildError>
 where
     C: Debug + Clone + Mergeable,
     F: Fn(&Coordinate) -> C,
@@ -235,6 +271,10 @@ where
     // Repeat for each layer of the tree, except the root node layer.
     let max_y_coord = height.as_y_coord();
     for y in 0..max_y_coord {
+        if cancellation_token.is_some_and(|token| token.is_cancelled()) {
+            return Err(TreeBuildError::Cancelled);
+        }
+
         // Create the next layer up of nodes from the current layer of nodes.
         nodes = nodes
             .into_iter()
@@ -304,7 +344,7 @@ where
         BUG
     );
 
-    (map, root)
+    Ok((map, root))
 }
 
 // -------------------------------------------------------------------------------------------------
@@ -347,6 +387,23 @@ mod tests {
         assert_err_simple!(res, Err(TreeBuildError::NoLeafNodesProvided));
     }
 
+    #[test]
+    fn err_when_cancellation_token_is_cancelled() {
+        let height = Height::expect_from(8);
+        let leaf_nodes = full_bottom_layer(&height);
+        let cancellation_token = CancellationToken::new();
+        cancellation_token.cancel();
+
+        let res = BinaryTreeBuilder::new()
+            .with_height(height)
+            .with_leaf_nodes(leaf_nodes)
+            .with_cancellation_token(cancellation_token)
+            .build_using_single_threaded_algorithm(generate_padding_closure());
+
+        // cannot use assert_err because it requires Func to have the Debug trait
+        assert_err_simple!(res, Err(TreeBuildError::Cancelled));
+    }
+
     #[test]
     fn err_for_empty_leaves() {
         let height = Height::expect_from(5);
@@ -3,13 +3,13 @@
 // -------------------------------------------------------------------------------------------------
 // Test utils for sub-modules.
 
-#[cfg(any(test, feature = "fuzzing"))]
+#[cfg(all(feature = "full", any(test, feature = "fuzzing")))]
 pub mod test_utils {
     use super::super::*;
     use crate::hasher::Hasher;
     use primitive_types::H256;
 
-    #[derive(Clone, Debug, PartialEq, Serialize)]
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
     pub struct TestContent {
         pub value: u32,
         pub hash: H256,
@@ -0,0 +1,246 @@
+use std::fmt;
+use std::sync::Arc;
+
+use crate::{
+    BuildStrategy, CancellationToken, Coordinate, FullNodeContent, MaxLiability, MaxThreadCount,
+};
+
+/// Optional parameters for building a [DapolTree](crate::DapolTree), see
+/// [DapolTree::new_with_options](crate::DapolTree::new_with_options).
+///
+/// All the options that are set are applied together, and the ones that are
+/// not set are given a default value. None of the options are serialized
+/// with the tree, except for the max liability and the domain separator.
+///
+/// Example:
+/// ```
+/// use dapol::{BuildOptions, BuildStrategy, CancellationToken, MaxLiability, MaxThreadCount};
+///
+/// let options = BuildOptions::default()
+///     .with_max_liability(MaxLiability::from(10_000_000))
+///     .with_max_thread_count(MaxThreadCount::from(8))
+///     .with_build_strategy(BuildStrategy::SingleThreaded)
+///     .with_cancellation_token(CancellationToken::new())
+///     .with_min_sparsity_warning(0);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct BuildOptions {
+    pub(crate) max_liability: MaxLiability,
+    pub(crate) max_thread_count: MaxThreadCount,
+    pub(crate) build_strategy: BuildStrategy,
+    pub(crate) cancellation_token: Option<CancellationToken>,
+    pub(crate) leaf_mapping_chunk_size: Option<usize>,
+    pub(crate) min_sparsity_warning: Option<u64>,
+    pub(crate) padding_fn: Option<PaddingFn>,
+    pub(crate) domain_separator: Option<String>,
+    pub(crate) store_capacity_bytes: Option<usize>,
+    #[cfg(any(test, feature = "testing"))]
+    pub(crate) random_seed: Option<u64>,
+}
+
+/// Custom generator for padding node content, see
+/// [BuildOptions::with_padding_fn].
+#[derive(Clone)]
+pub(crate) struct PaddingFn(pub(crate) Arc<dyn Fn(&Coordinate) -> FullNodeContent + Send + Sync>);
+
+impl fmt::Debug for PaddingFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PaddingFn")
+    }
+}
+
+impl BuildOptions {
+    /// Set the max liability of the tree.
+    #[doc = include_str!("./shared_docs/max_liability.md")]
+    ///
+    /// If not set then [DEFAULT_MAX_LIABILITY](crate::DEFAULT_MAX_LIABILITY)
+    /// is used.
+    pub fn with_max_liability(mut self, max_liability: MaxLiability) -> Self {
+        self.max_liability = max_liability;
+        self
+    }
+
+    /// Set the max number of threads used for the build.
+    #[doc = include_str!("./shared_docs/max_thread_count.md")]
+    ///
+    /// If not set the max parallelism of the underlying machine will be used.
+    pub fn with_max_thread_count(mut self, max_thread_count: MaxThreadCount) -> Self {
+        self.max_thread_count = max_thread_count;
+        self
+    }
+
+    /// Set the algorithm used to build the tree, see [BuildStrategy].
+    ///
+    /// Both algorithms produce the same tree, so the single-threaded one is
+    /// mostly useful for debugging or benchmarking on machines where thread
+    /// contention hurts performance. If not set then the multi-threaded
+    /// algorithm is used.
+    pub fn with_build_strategy(mut self, build_strategy: BuildStrategy) -> Self {
+        self.build_strategy = build_strategy;
+        self
+    }
+
+    /// Set a token that can be used to stop the build before it is done.
+    ///
+    /// The token can be cancelled from another thread, or be given a deadline
+    /// (see [CancellationToken::with_timeout]). When the build is cancelled
+    /// the partially built tree is dropped and
+    /// [DapolTreeError::BuildCancelled](crate::DapolTreeError::BuildCancelled)
+    /// is returned. If not set then the build cannot be cancelled.
+    pub fn with_cancellation_token_opt(
+        mut self,
+        cancellation_token: Option<CancellationToken>,
+    ) -> Self {
+        self.cancellation_token = cancellation_token;
+        self
+    }
+
+    /// Set a token that can be used to stop the build before it is done.
+    ///
+    /// See [BuildOptions::with_cancellation_token_opt] for more details.
+    pub fn with_cancellation_token(self, cancellation_token: CancellationToken) -> Self {
+        self.with_cancellation_token_opt(Some(cancellation_token))
+    }
+
+    /// Set the minimum number of entities handled by each rayon task when
+    /// mapping the entities to leaf nodes.
+    ///
+    /// The mapping phase is where the leaf secrets are derived using the KDF;
+    /// tuning the chunk size can reduce scheduling overhead for very large
+    /// entity sets. The chunk size does not affect the resulting tree. If not
+    /// set then rayon decides how to split up the work.
+    pub fn with_leaf_mapping_chunk_size_opt(
+        mut self,
+        leaf_mapping_chunk_size: Option<usize>,
+    ) -> Self {
+        self.leaf_mapping_chunk_size = leaf_mapping_chunk_size;
+        self
+    }
+
+    /// Set the minimum number of entities handled by each rayon task when
+    /// mapping the entities to leaf nodes.
+    ///
+    /// See [BuildOptions::with_leaf_mapping_chunk_size_opt] for more details.
+    pub fn with_leaf_mapping_chunk_size(self, leaf_mapping_chunk_size: usize) -> Self {
+        self.with_leaf_mapping_chunk_size_opt(Some(leaf_mapping_chunk_size))
+    }
+
+    /// Set the sparsity at or below which a warning is logged during the
+    /// build.
+    ///
+    /// Some trees are intentionally dense (e.g. in tests), in which case the
+    /// warning is just noise; a value of 0 disables it. The threshold does
+    /// not affect the resulting tree. If not set then the minimum recommended
+    /// sparsity is used.
+    pub fn with_min_sparsity_warning_opt(mut self, min_sparsity_warning: Option<u64>) -> Self {
+        self.min_sparsity_warning = min_sparsity_warning;
+        self
+    }
+
+    /// Set the sparsity at or below which a warning is logged during the
+    /// build.
+    ///
+    /// See [BuildOptions::with_min_sparsity_warning_opt] for more details.
+    pub fn with_min_sparsity_warning(self, min_sparsity_warning: u64) -> Self {
+        self.with_min_sparsity_warning_opt(Some(min_sparsity_warning))
+    }
+
+    /// Set the function used to generate the content of the padding nodes,
+    /// instead of deriving it from the secrets.
+    ///
+    /// `padding_fn` is given the coordinate of the padding node, and could,
+    /// for example, give padding nodes realistic-looking liabilities.
+    ///
+    /// Changing the padding function changes the root of the tree. The same
+    /// function is used to regenerate padding nodes when generating
+    /// inclusion proofs, so it must be deterministic for the proofs to
+    /// verify against the root. The function is not serialized, so it has to
+    /// be set again with [DapolTree::set_padding_fn](crate::DapolTree::set_padding_fn)
+    /// after deserialization; until then proof generation gives an error.
+    pub fn with_padding_fn<F>(mut self, padding_fn: F) -> Self
+    where
+        F: Fn(&Coordinate) -> FullNodeContent + Send + Sync + 'static,
+    {
+        self.padding_fn = Some(PaddingFn(Arc::new(padding_fn)));
+        self
+    }
+
+    /// Set a context string used to domain-separate the hashes of the
+    /// bottom-layer leaf & padding nodes, e.g. a per-deployment context
+    /// string.
+    ///
+    /// Only the hasher for the leaf & padding nodes is seeded with
+    /// `domain_separator` (see [Hasher::new_with_domain_separator]); the
+    /// hasher that merges 2 sibling nodes into their parent is the same for
+    /// all trees. Every other hash in the tree is derived from the leaf &
+    /// padding hashes, so changing the domain separator still changes all the
+    /// hashes in the tree, including the root hash.
+    ///
+    /// The domain separator is not part of inclusion proofs, so proof
+    /// verification does not check it; an entity can check that its leaf
+    /// node was hashed with a given domain separator using
+    /// [InclusionProof::verify_entity_with_domain_separator]. The domain
+    /// separator is serialized with the tree. If not set then the leaf &
+    /// padding nodes are hashed without one.
+    ///
+    /// [Hasher::new_with_domain_separator]: crate::Hasher::new_with_domain_separator
+    /// [InclusionProof::verify_entity_with_domain_separator]: crate::InclusionProof::verify_entity_with_domain_separator
+    pub fn with_domain_separator_opt(mut self, domain_separator: Option<String>) -> Self {
+        self.domain_separator = domain_separator;
+        self
+    }
+
+    /// Set a context string used to domain-separate the hashes of the
+    /// bottom-layer leaf & padding nodes.
+    ///
+    /// See [BuildOptions::with_domain_separator_opt] for more details.
+    pub fn with_domain_separator(self, domain_separator: String) -> Self {
+        self.with_domain_separator_opt(Some(domain_separator))
+    }
+
+    /// Bound the memory retained by the tree's node store after the build.
+    ///
+    /// Once the tree is built its store becomes a bounded least-recently-used
+    /// cache: all the non-padding leaf nodes are always kept, but the rest of
+    /// the stored nodes are evicted once the byte budget is reached, and
+    /// regenerated when they are needed.
+    ///
+    /// This does not bound the memory used while building the tree: the build
+    /// stores all the nodes selected by the store depth, and the budget is
+    /// only applied to the finished tree. The budget is converted to a max
+    /// number of cached nodes using the in-memory size of a cache entry, which
+    /// does not include the overhead of the cache's hash map, nor the leaf
+    /// nodes, so it is only an approximation of the memory used. If not set
+    /// then the store is not bounded.
+    pub fn with_store_capacity_bytes_opt(mut self, store_capacity_bytes: Option<usize>) -> Self {
+        self.store_capacity_bytes = store_capacity_bytes;
+        self
+    }
+
+    /// Bound the memory retained by the tree's node store after the build.
+    ///
+    /// See [BuildOptions::with_store_capacity_bytes_opt] for more details.
+    pub fn with_store_capacity_bytes(self, store_capacity_bytes: usize) -> Self {
+        self.with_store_capacity_bytes_opt(Some(store_capacity_bytes))
+    }
+
+    /// Set the seed for the PRNG that maps the entities to bottom-layer leaf
+    /// nodes.
+    ///
+    /// Note: This is **not** cryptographically secure and should only be used
+    /// for testing.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn with_random_seed_opt(mut self, random_seed: Option<u64>) -> Self {
+        self.random_seed = random_seed;
+        self
+    }
+
+    /// Set the seed for the PRNG that maps the entities to bottom-layer leaf
+    /// nodes.
+    ///
+    /// See [BuildOptions::with_random_seed_opt] for more details.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn with_random_seed(self, random_seed: u64) -> Self {
+        self.with_random_seed_opt(Some(random_seed))
+    }
+}
@@ -0,0 +1,124 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Token for cancelling a tree build that is in progress.
+///
+/// The multi-threaded tree builder checks the token every time it visits a
+/// node, and the single-threaded one checks it before building each layer of
+/// the tree. Both stop with an error as soon as they see that the token is
+/// cancelled. All the spawned threads are joined before the error is
+/// returned, and the partially built tree is dropped.
+///
+/// The token is cheap to clone and all clones share the same flag, so one
+/// clone can be given to the build while another is kept to cancel it from a
+/// different thread. A deadline can also be set, after which the token counts
+/// as cancelled, which gives a time-bounded build.
+///
+/// Example:
+/// ```
+/// use dapol::CancellationToken;
+/// use std::time::Duration;
+///
+/// let token = CancellationToken::new();
+/// let token_for_build = token.clone();
+/// token.cancel();
+/// assert!(token_for_build.is_cancelled());
+///
+/// let token = CancellationToken::with_timeout(Duration::from_secs(60));
+/// assert!(!token.is_cancelled());
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+    deadline: Option<Instant>,
+}
+
+impl CancellationToken {
+    /// Constructor. The token is not cancelled until
+    /// [CancellationToken::cancel] is called.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Token that is cancelled automatically once `timeout` has passed, or
+    /// earlier if [CancellationToken::cancel] is called.
+    pub fn with_timeout(timeout: Duration) -> Self {
+        CancellationToken {
+            cancelled: Arc::new(AtomicBool::new(false)),
+            deadline: Instant::now().checked_add(timeout),
+        }
+    }
+
+    /// Cancel the token, and all of its clones.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// True if [CancellationToken::cancel] has been called on this token (or
+    /// any of its clones), or if the deadline has passed.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+            || self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// From for Arc<AtomicBool>.
+
+impl From<Arc<AtomicBool>> for CancellationToken {
+    /// Use an existing flag as the token, cancelling the build when the flag
+    /// is set to true.
+    fn from(cancelled: Arc<AtomicBool>) -> Self {
+        CancellationToken {
+            cancelled,
+            deadline: None,
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// PartialEq.
+
+impl PartialEq for CancellationToken {
+    /// 2 tokens are equal if they share the same flag and deadline.
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.cancelled, &other.cancelled) && self.deadline == other.deadline
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Unit tests.
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cancelling_a_clone_cancels_the_original() {
+        let token = CancellationToken::new();
+        token.clone().cancel();
+        assert!(token.is_cancelled());
+    }
+
+    #[test]
+    fn setting_the_flag_cancels_the_token() {
+        let flag = Arc::new(AtomicBool::new(false));
+        let token = CancellationToken::from(Arc::clone(&flag));
+
+        assert!(!token.is_cancelled());
+        flag.store(true, Ordering::Relaxed);
+        assert!(token.is_cancelled());
+    }
+
+    #[test]
+    fn token_is_cancelled_after_timeout() {
+        let token = CancellationToken::with_timeout(Duration::ZERO);
+        assert!(token.is_cancelled());
+
+        let token = CancellationToken::with_timeout(Duration::from_secs(600));
+        assert!(!token.is_cancelled());
+    }
+}
@@ -7,14 +7,14 @@ use clap_verbosity_flag::{InfoLevel, Verbosity};
 use patharg::{InputArg, OutputArg};
 use primitive_types::H256;
 
-use std::str::FromStr;
+use std::{path::PathBuf, str::FromStr};
 
 use crate::{
     accumulators::AccumulatorType,
-    binary_tree::Height,
+    binary_tree::{BuildStrategy, Height, PathInfoFormat},
     inclusion_proof,
     percentage::{Percentage, ONE_HUNDRED_PERCENT},
-    InclusionProofFileType, MaxLiability, MaxThreadCount, Salt,
+    InclusionProofFileType, MaxLiability, MaxThreadCount, RootPublicData, Salt,
 };
 
 // -------------------------------------------------------------------------------------------------
@@ -60,6 +60,11 @@ pub enum Command {
         /// one for the secret data.
         #[arg(short, long, value_name = "DIR", global = true)]
         root_serialize: Option<OutputArg>,
+
+        /// Directory to write the inclusion proofs to, if `gen-proofs` is
+        /// set. The directory is created if it does not exist.
+        #[arg(long, value_name = "DIR", global = true, default_value = DEFAULT_PROOFS_DIR)]
+        output_dir: PathBuf,
     },
 
     /// Generate inclusion proofs for entities.
@@ -89,9 +94,14 @@ pub enum Command {
         #[arg(short, long, value_parser = Percentage::from_str, default_value = ONE_HUNDRED_PERCENT, value_name = "PERCENTAGE")]
         range_proof_aggregation: Percentage,
 
-        /// File type for proofs (supported types: binary, json).
+        /// File type for proofs (supported types: binary, json, msgpack).
         #[arg(short, long, value_parser = InclusionProofFileType::from_str, default_value = InclusionProofFileType::default())]
         file_type: inclusion_proof::InclusionProofFileType,
+
+        /// Directory to write the inclusion proofs to. The directory is
+        /// created if it does not exist.
+        #[arg(long, value_name = "DIR", default_value = DEFAULT_PROOFS_DIR)]
+        output_dir: PathBuf,
     },
 
     /// Verify an inclusion proof.
@@ -104,13 +114,42 @@ pub enum Command {
         file_path: InputArg,
 
         /// Hash digest/bytes for the root node of the tree.
-        #[arg(short, long, value_parser = H256::from_str, value_name = "BYTES")]
+        ///
+        /// Expected format is 32 bytes of hex, with an optional 0x prefix
+        /// (case-insensitive). This is the same format as the root hash that
+        /// is logged on tree creation, so it can be copy-pasted from there.
+        #[arg(short, long, value_parser = RootPublicData::hash_from_hex, value_name = "HEX")]
         root_hash: H256,
 
         /// Create a json file containing all the path information, and print
         /// the same path information to stdout.
         #[arg(long, short, action)]
         show_path: bool,
+
+        /// Encoding of the hashes & commitments in the path information json
+        /// file, if `show-path` is set.
+        #[arg(long, value_enum, default_value = "hex")]
+        path_format: PathInfoFormat,
+    },
+
+    /// Convert an inclusion proof file to a different file format.
+    ///
+    /// The format of the input file is determined by its extension.
+    ConvertProof {
+        /// File path for the serialized inclusion proof file.
+        #[arg(short, long = "in", value_name = "FILE_PATH")]
+        input: InputArg,
+
+        /// File type to convert the proof to (supported types: binary, json,
+        /// msgpack).
+        #[arg(short = 't', long, value_parser = InclusionProofFileType::from_str)]
+        out_type: inclusion_proof::InclusionProofFileType,
+
+        /// File path for the converted proof. The extension must match the
+        /// file type. If not given then the input path is used, with the
+        /// extension changed.
+        #[arg(short, long, value_name = "FILE_PATH")]
+        out: Option<OutputArg>,
     },
 
     /// Verify the root node of a DAPOL tree.
@@ -125,6 +164,28 @@ pub enum Command {
         #[arg(short, long)]
         root_pvt: InputArg,
     },
+
+    /// Extract the root data from an existing tree file.
+    ///
+    /// The public & secret data of the root node are written to 2 json files
+    /// in the output directory, the same files that `build-tree
+    /// --root-serialize` writes. This is useful if the root files were not
+    /// written when the tree was built.
+    ExtractRoot {
+        /// Path to the tree file that will be deserialized.
+        #[arg(short, long, value_name = "FILE_PATH")]
+        tree_file: InputArg,
+
+        /// Directory to write the root data files to. The directory is
+        /// created if it does not exist.
+        #[arg(short, long, value_name = "DIR")]
+        out_dir: PathBuf,
+
+        /// Only write the public root data, so that the output directory can
+        /// be distributed without leaking the total liability.
+        #[arg(long, action)]
+        public_only: bool,
+    },
 }
 
 #[derive(Debug, Subcommand)]
@@ -144,8 +205,8 @@ pub enum BuildKindCommand {
         #[arg(long, value_parser = Salt::from_str, help = include_str!("./shared_docs/salt_s.md"))]
         salt_s: Option<Salt>,
 
-        #[arg(long, value_parser = Height::from_str, default_value = Height::default(), value_name = "U8_INT", help = include_str!("./shared_docs/height.md"))]
-        height: Height,
+        #[arg(long, value_parser = Height::from_str, value_name = "U8_INT", help = concat!(include_str!("./shared_docs/height.md"), "If not set then the default height is used, or a bigger height derived from the number of entities if they do not fit."))]
+        height: Option<Height>,
 
         #[arg(long, value_parser = MaxLiability::from_str, default_value = MaxLiability::default(), value_name = "U64_INT", help = include_str!("./shared_docs/max_liability.md"))]
         max_liability: MaxLiability,
@@ -153,11 +214,25 @@ pub enum BuildKindCommand {
         #[arg(long, value_parser = MaxThreadCount::from_str, default_value = MaxThreadCount::default(), value_name = "U8_INT", help = include_str!("./shared_docs/max_thread_count.md"))]
         max_thread_count: MaxThreadCount,
 
+        #[arg(long, value_enum, default_value = "multi", long_help = BUILD_ALGORITHM_HELP)]
+        build_algorithm: BuildStrategy,
+
         #[arg(short, long, value_name = "FILE_PATH", long_help = SECRETS_HELP)]
         secrets_file: InputArg,
 
         #[command(flatten)]
         entity_source: EntitySource,
+
+        /// Seed for the PRNG that maps entities to bottom-layer leaf nodes
+        /// (and samples entities from the entities file). Together with
+        /// fixed salts & entities this gives a deterministic tree root.
+        ///
+        /// Warning: this is NOT secure, the mapping of entities to leaf nodes
+        /// can be recovered from the seed. Only use it for testing, debugging
+        /// & demos.
+        #[cfg(feature = "testing")]
+        #[arg(long, value_name = "U64_INT")]
+        random_seed: Option<u64>,
     },
 
     #[command(about = COMMAND_CONFIG_FILE_ABOUT, long_about = COMMAND_CONFIG_FILE_LONG_ABOUT)]
@@ -176,6 +251,11 @@ pub struct EntitySource {
     #[arg(short, long, value_name = "FILE_PATH", long_help = ENTITIES_FILE_HELP)]
     pub entities_file: Option<InputArg>,
 
+    /// Read entity ID & liability entries from stdin (CSV format, same as for
+    /// the entities file).
+    #[arg(long, action)]
+    pub entities_stdin: bool,
+
     /// Randomly generate a number of entities.
     #[arg(short, long, value_name = "NUM_ENTITIES")]
     pub random_entities: Option<u64>,
@@ -196,6 +276,9 @@ Top-level doc for the project: https://hackmd.io/p0dy3R0RS5qpm3sX-_zreA
 
 Source code: https://github.com/silversixpence-crypto/dapol/";
 
+/// Directory that inclusion proofs are written to if no other is given.
+const DEFAULT_PROOFS_DIR: &str = "./inclusion_proofs/";
+
 const GEN_PROOFS_HELP: &str = "
 Generate inclusion proofs for the provided entity IDs, after building the tree.
 The entity IDs file is expected to be a list of entity IDs, each on a new line.
@@ -206,6 +289,12 @@ liabilities file that is accepted by the `entity-source` option in the
 Custom configuration of the proofs is not supported here. The `gen-proofs`
 command offers more options.";
 
+const BUILD_ALGORITHM_HELP: &str = "
+Algorithm used to build the tree. The multi-threaded algorithm is the fastest
+for large trees, but the single-threaded algorithm can be useful for debugging,
+or for benchmarking on machines where thread contention hurts performance. Both
+algorithms produce identical roots.";
+
 const SERIALIZE_HELP: &str = "
 Serialize the tree to a file. If the path given is a directory then a default
 file name will be given. If the path given is a file then that file will be
@@ -227,7 +316,11 @@ Path to file containing entity ID & liability entries (supported file
 types: CSV).
 
 CSV file format:
-entity_id,liability";
+entity_id,liability
+
+An optional 3rd column can be used to set the salt for each entity, which
+replaces the salt that is derived from the master secret:
+entity_id,liability,salt";
 
 const COMMAND_CONFIG_FILE_ABOUT: &str =
     "Read tree configuration from a file. Supported file formats: TOML.";
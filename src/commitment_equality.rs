@@ -0,0 +1,230 @@
+//! Proof that 2 root commitments commit to the same total liability, or to
+//! totals that differ by a known amount.
+//!
+//! See [EqualityProof] for the exact statement that is proved.
+
+use bulletproofs::PedersenGens;
+use curve25519_dalek_ng::{ristretto::RistrettoPoint, scalar::Scalar};
+use merlin::Transcript;
+use serde::{Deserialize, Serialize};
+
+// -------------------------------------------------------------------------------------------------
+// Main struct & implementation.
+
+/// Non-interactive proof that 2 Pedersen commitments commit to values that
+/// differ by `difference`.
+///
+/// The root commitment of a tree is a Pedersen commitment
+/// `C = v*G + r*H` to the total liability `v` using blinding factor `r`, where
+/// `G` & `H` are the bases from [PedersenGens::default] (`B` & `B_blinding`).
+///
+/// Given 2 published commitments `C_a = v_a*G + r_a*H` (the prover's) and
+/// `C_b = v_b*G + r_b*H` (the other party's), and a public difference `d`,
+/// the statement proved is:
+///
+/// "The prover knows a scalar `x` such that `C_a - C_b - d*G = x*H`."
+///
+/// Since Pedersen commitments are binding, this is only possible if
+/// `v_a = v_b + d` (modulo the group order), in which case `x = r_a - r_b`.
+/// The proof is a Schnorr proof of knowledge of `x`, made non-interactive
+/// using the Fiat-Shamir transform with a [merlin] transcript. Neither `v_a`,
+/// `v_b` nor the blinding factors are revealed.
+///
+/// Note that the prover needs to know `r_b`, so the other party has to share
+/// their root blinding factor with the prover. Together with `C_b` this can
+/// be used to brute-force `v_b`, so it should only be shared between parties
+/// that already know each other's total (e.g. 2 organizations proving a
+/// shared reserve). The verifier only needs the 2 commitments and the proof.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct EqualityProof {
+    /// Public difference `d` between the committed values: `v_a = v_b + d`.
+    pub difference: u64,
+    /// Schnorr nonce commitment `R = k*H`.
+    nonce_commitment: RistrettoPoint,
+    /// Schnorr response `s = k + c*x`.
+    response: Scalar,
+}
+
+fn new_transcript(
+    my_commitment: &RistrettoPoint,
+    other_commitment: &RistrettoPoint,
+    difference: u64,
+    nonce_commitment: &RistrettoPoint,
+) -> Transcript {
+    let mut transcript = Transcript::new(b"CommitmentEqualityProof");
+    transcript.append_message(b"C_a", my_commitment.compress().as_bytes());
+    transcript.append_message(b"C_b", other_commitment.compress().as_bytes());
+    transcript.append_message(b"d", &difference.to_le_bytes());
+    transcript.append_message(b"R", nonce_commitment.compress().as_bytes());
+    transcript
+}
+
+fn challenge(transcript: &mut Transcript) -> Scalar {
+    let mut bytes = [0u8; 64];
+    transcript.challenge_bytes(b"c", &mut bytes);
+    Scalar::from_bytes_mod_order_wide(&bytes)
+}
+
+/// The point `C_a - C_b - d*G`, which is `x*H` if the statement is true.
+fn commitment_difference(
+    my_commitment: &RistrettoPoint,
+    other_commitment: &RistrettoPoint,
+    difference: u64,
+) -> RistrettoPoint {
+    my_commitment - other_commitment - PedersenGens::default().B * Scalar::from(difference)
+}
+
+impl EqualityProof {
+    /// Generate the proof for commitments `my_commitment` (`C_a`) and
+    /// `other_commitment` (`C_b`), using the witness
+    /// `blinding_factor_difference` (`x = r_a - r_b`).
+    ///
+    /// An error is returned if the witness does not satisfy the statement,
+    /// i.e. if the committed values do not differ by `difference` or the
+    /// blinding factors are wrong.
+    #[cfg(feature = "full")]
+    pub(crate) fn generate(
+        my_commitment: &RistrettoPoint,
+        other_commitment: &RistrettoPoint,
+        difference: u64,
+        blinding_factor_difference: &Scalar,
+    ) -> Result<Self, EqualityProofError> {
+        let h = PedersenGens::default().B_blinding;
+
+        if commitment_difference(my_commitment, other_commitment, difference)
+            != h * blinding_factor_difference
+        {
+            return Err(EqualityProofError::StatementNotSatisfied);
+        }
+
+        let nonce = Scalar::random(&mut rand::thread_rng());
+        let nonce_commitment = h * nonce;
+
+        let mut transcript = new_transcript(
+            my_commitment,
+            other_commitment,
+            difference,
+            &nonce_commitment,
+        );
+        let c = challenge(&mut transcript);
+
+        Ok(EqualityProof {
+            difference,
+            nonce_commitment,
+            response: nonce + c * blinding_factor_difference,
+        })
+    }
+
+    /// Verify that the value committed to in `my_commitment` (`C_a`) is equal
+    /// to the value committed to in `other_commitment` (`C_b`) plus
+    /// `difference`.
+    ///
+    /// The commitments must be given in the same order as when the proof was
+    /// generated. An error is returned if the proof is invalid.
+    pub fn verify(
+        &self,
+        my_commitment: &RistrettoPoint,
+        other_commitment: &RistrettoPoint,
+    ) -> Result<(), EqualityProofError> {
+        let h = PedersenGens::default().B_blinding;
+
+        let mut transcript = new_transcript(
+            my_commitment,
+            other_commitment,
+            self.difference,
+            &self.nonce_commitment,
+        );
+        let c = challenge(&mut transcript);
+
+        // s*H == R + c*(C_a - C_b - d*G)
+        let lhs = h * self.response;
+        let rhs = self.nonce_commitment
+            + commitment_difference(my_commitment, other_commitment, self.difference) * c;
+
+        if lhs == rhs {
+            Ok(())
+        } else {
+            Err(EqualityProofError::VerificationFailed)
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Errors.
+
+#[derive(thiserror::Error, Debug)]
+pub enum EqualityProofError {
+    #[error("Committed values do not differ by the given amount, or a blinding factor is wrong")]
+    StatementNotSatisfied,
+    #[error("Commitment equality proof verification failed")]
+    VerificationFailed,
+}
+
+// -------------------------------------------------------------------------------------------------
+// Unit tests.
+
+#[cfg(all(test, feature = "full"))]
+mod tests {
+    use super::*;
+    use crate::utils::test_utils::assert_err;
+
+    fn commit(value: u64, blinding_factor: Scalar) -> RistrettoPoint {
+        PedersenGens::default().commit(Scalar::from(value), blinding_factor)
+    }
+
+    #[test]
+    fn proof_verifies_for_equal_values() {
+        let (r_a, r_b) = (Scalar::from(11u64), Scalar::from(22u64));
+        let (c_a, c_b) = (commit(1000, r_a), commit(1000, r_b));
+
+        let proof = EqualityProof::generate(&c_a, &c_b, 0, &(r_a - r_b)).unwrap();
+        proof.verify(&c_a, &c_b).unwrap();
+    }
+
+    #[test]
+    fn proof_verifies_for_known_difference() {
+        let (r_a, r_b) = (Scalar::from(11u64), Scalar::from(22u64));
+        let (c_a, c_b) = (commit(1500, r_a), commit(1000, r_b));
+
+        let proof = EqualityProof::generate(&c_a, &c_b, 500, &(r_a - r_b)).unwrap();
+        proof.verify(&c_a, &c_b).unwrap();
+    }
+
+    #[test]
+    fn generate_fails_for_different_values() {
+        let (r_a, r_b) = (Scalar::from(11u64), Scalar::from(22u64));
+        let (c_a, c_b) = (commit(1001, r_a), commit(1000, r_b));
+
+        assert_err!(
+            EqualityProof::generate(&c_a, &c_b, 0, &(r_a - r_b)),
+            Err(EqualityProofError::StatementNotSatisfied)
+        );
+    }
+
+    #[test]
+    fn verify_fails_for_swapped_commitments() {
+        let (r_a, r_b) = (Scalar::from(11u64), Scalar::from(22u64));
+        let (c_a, c_b) = (commit(1500, r_a), commit(1000, r_b));
+
+        let proof = EqualityProof::generate(&c_a, &c_b, 500, &(r_a - r_b)).unwrap();
+
+        assert_err!(
+            proof.verify(&c_b, &c_a),
+            Err(EqualityProofError::VerificationFailed)
+        );
+    }
+
+    #[test]
+    fn verify_fails_for_tampered_difference() {
+        let (r_a, r_b) = (Scalar::from(11u64), Scalar::from(22u64));
+        let (c_a, c_b) = (commit(1000, r_a), commit(1000, r_b));
+
+        let mut proof = EqualityProof::generate(&c_a, &c_b, 0, &(r_a - r_b)).unwrap();
+        proof.difference = 1;
+
+        assert_err!(
+            proof.verify(&c_a, &c_b),
+            Err(EqualityProofError::VerificationFailed)
+        );
+    }
+}
@@ -0,0 +1,153 @@
+//! Read-only store for serving inclusion proofs.
+//!
+//! A [DapolTree] carries state that is only needed while the tree is being
+//! built or updated (the format version, the update epoch & root history, and
+//! the padding nodes in the store). A [CompactProofStore] keeps only what is
+//! needed to generate inclusion proofs: the tree store without its padding
+//! nodes, the entity mapping, the secrets & salts, the height and the max
+//! liability. Padding nodes are regenerated from the secrets when they are
+//! needed, so the proofs are the same as the ones generated by the original
+//! tree.
+//!
+//! Unlike a [ProofServer] the secrets are kept, but the store does not have
+//! to be filled so the serialized file is smaller than the tree's.
+//!
+//! Example:
+//! ```ignore
+//! let proof_store = dapol_tree.to_compact_proof_store();
+//! let path = proof_store.serialize(dir)?;
+//!
+//! let proof_store = CompactProofStore::deserialize(path)?;
+//! let proof = proof_store.generate_inclusion_proof(&entity_id)?;
+//! ```
+//!
+//! [DapolTree]: crate::DapolTree
+//! [ProofServer]: crate::ProofServer
+
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+
+use std::path::PathBuf;
+
+use crate::{
+    accumulators::{Accumulator, NdmSmtError},
+    read_write_utils::{self, ReadWriteError},
+    AggregationFactor, EntityId, InclusionProof, MaxLiability, Salt, Secret,
+};
+
+/// The file extension used when writing serialized binary files.
+pub const SERIALIZED_COMPACT_PROOF_STORE_EXTENSION: &str = "dapolproofstore";
+
+/// The default file name prefix used when writing serialized binary files.
+const SERIALIZED_COMPACT_PROOF_STORE_FILE_PREFIX: &str = "proof_store_";
+
+/// Inclusion proof generator with none of the tree's builder state.
+///
+/// Created using [DapolTree::to_compact_proof_store]. The only thing that can
+/// be done with it is generating inclusion proofs.
+///
+/// [DapolTree::to_compact_proof_store]: crate::DapolTree::to_compact_proof_store
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CompactProofStore {
+    accumulator: Accumulator,
+    master_secret: Secret,
+    salt_b: Salt,
+    salt_s: Salt,
+    max_liability: MaxLiability,
+}
+
+impl CompactProofStore {
+    /// Constructor.
+    ///
+    /// The padding nodes are removed from the store of the tree in
+    /// `accumulator`.
+    pub(crate) fn new(
+        accumulator: &Accumulator,
+        master_secret: Secret,
+        salt_b: Salt,
+        salt_s: Salt,
+        max_liability: MaxLiability,
+    ) -> Self {
+        let accumulator = match accumulator {
+            Accumulator::NdmSmt(ndm_smt) => Accumulator::NdmSmt(ndm_smt.without_padding_nodes()),
+        };
+
+        CompactProofStore {
+            accumulator,
+            master_secret,
+            salt_b,
+            salt_s,
+            max_liability,
+        }
+    }
+
+    /// Generate an inclusion proof for the given `entity_id`, using the
+    /// default aggregation factor.
+    ///
+    /// Parameters:
+    /// - `entity_id`: unique ID for the entity that the proof will be generated
+    ///   for.
+    pub fn generate_inclusion_proof(
+        &self,
+        entity_id: &EntityId,
+    ) -> Result<InclusionProof, NdmSmtError> {
+        match &self.accumulator {
+            Accumulator::NdmSmt(ndm_smt) => ndm_smt.generate_inclusion_proof(
+                &self.master_secret,
+                &self.salt_b,
+                &self.salt_s,
+                entity_id,
+                AggregationFactor::default(),
+                self.max_liability.as_range_proof_upper_bound_bit_length(),
+            ),
+        }
+    }
+
+    /// Serialize the store to a binary file.
+    ///
+    /// `path` is parsed in the same way as for
+    /// [DapolTree::serialize](crate::DapolTree::serialize), but the extension
+    /// is expected to be [SERIALIZED_COMPACT_PROOF_STORE_EXTENSION]. The path
+    /// of the written file is returned.
+    ///
+    /// **DANGER**: the file contains the master secret & salts, so it must be
+    /// kept as safe as the serialized tree.
+    pub fn serialize(&self, path: PathBuf) -> Result<PathBuf, ReadWriteError> {
+        let path = read_write_utils::parse_serialization_path(
+            path,
+            SERIALIZED_COMPACT_PROOF_STORE_EXTENSION,
+            SERIALIZED_COMPACT_PROOF_STORE_FILE_PREFIX,
+        )?;
+
+        info!(
+            "Serializing compact proof store to file {:?}",
+            path.clone().into_os_string()
+        );
+
+        read_write_utils::serialize_to_bin_file(self, path.clone())?;
+
+        Ok(path)
+    }
+
+    /// Deserialize the store from a file written by
+    /// [CompactProofStore::serialize].
+    ///
+    /// An error is returned if
+    /// 1. The file cannot be opened.
+    /// 2. The file extension is not
+    ///    [SERIALIZED_COMPACT_PROOF_STORE_EXTENSION].
+    /// 3. The [bincode] deserializer fails.
+    pub fn deserialize(path: PathBuf) -> Result<CompactProofStore, ReadWriteError> {
+        debug!(
+            "Deserializing CompactProofStore from file {:?}",
+            path.clone().into_os_string()
+        );
+
+        read_write_utils::check_deserialization_path(
+            &path,
+            SERIALIZED_COMPACT_PROOF_STORE_EXTENSION,
+        )?;
+
+        read_write_utils::deserialize_from_bin_file(path)
+    }
+}
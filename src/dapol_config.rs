@@ -1,13 +1,15 @@
 use derive_builder::Builder;
-use log::debug;
+use log::{debug, info};
 use serde::Deserialize;
 use std::{ffi::OsString, fs::File, io::Read, path::PathBuf, str::FromStr};
 
 use crate::{
     accumulators::AccumulatorType,
-    entity::{self, EntitiesParser},
+    binary_tree::MIN_RECOMMENDED_SPARSITY,
+    entity::{self, DuplicatePolicy, EntitiesParser, Entity},
     utils::LogOnErr,
-    DapolTree, DapolTreeError, Height, MaxLiability, MaxThreadCount, Salt, Secret,
+    BuildOptions, BuildStrategy, CancellationToken, DapolTree, DapolTreeError, Height,
+    MaxLiability, MaxThreadCount, Salt, Secret,
 };
 use crate::{salt, secret};
 
@@ -75,23 +77,111 @@ pub struct DapolConfig {
     accumulator_type: AccumulatorType,
 
     #[doc = include_str!("./shared_docs/salt_b.md")]
+    #[serde(default)]
     salt_b: Salt,
 
     #[doc = include_str!("./shared_docs/salt_s.md")]
+    #[serde(default)]
     salt_s: Salt,
 
+    /// Path to a file containing both `salt_b` & `salt_s`.
+    ///
+    /// If set then the salts in the file are used, and `salt_b` & `salt_s`
+    /// are ignored.
+    #[builder(setter(strip_option))]
+    #[serde(default)]
+    salts_file_path: Option<PathBuf>,
+
     #[doc = include_str!("./shared_docs/max_liability.md")]
     max_liability: MaxLiability,
 
+    /// Derive the max liability from the entities after they are parsed (see
+    /// [MaxLiability::from_entities]), instead of using `max_liability`.
+    ///
+    /// Note that this leaks a coarse upper bound on the entities'
+    /// liabilities, since the range proof bit length is public.
+    #[builder(setter(custom))]
+    #[serde(default)]
+    auto_max_liability: bool,
+
     #[doc = include_str!("./shared_docs/height.md")]
-    height: Height,
+    ///
+    /// If not set then the default height is used, or the smallest height
+    /// that gives the minimum recommended sparsity for the number of entities
+    /// (see [Height::optimal_for]) if that is bigger. The height is public in
+    /// every inclusion proof, so it is not made smaller than the default:
+    /// a height derived from the number of entities would reveal roughly how
+    /// many entities are in the tree.
+    #[builder(setter(strip_option))]
+    #[serde(default)]
+    height: Option<Height>,
 
     #[doc = include_str!("./shared_docs/max_thread_count.md")]
     max_thread_count: MaxThreadCount,
 
+    /// Algorithm used to build the tree, see [BuildStrategy].
+    ///
+    /// If not set then the multi-threaded algorithm is used.
+    #[serde(default)]
+    build_strategy: BuildStrategy,
+
+    /// Minimum number of entities handled by each rayon task when mapping
+    /// the entities to leaf nodes (which is where the KDF is used to derive
+    /// the leaf secrets).
+    ///
+    /// If not set then rayon decides how to split up the work. The chunk size
+    /// does not affect the resulting tree.
+    #[builder(setter(strip_option))]
+    #[serde(default)]
+    leaf_mapping_chunk_size: Option<usize>,
+
+    /// Sparsity at or below which a warning is logged during the build, see
+    /// [BuildOptions::with_min_sparsity_warning]. A value of 0 disables the
+    /// warning.
+    ///
+    /// If not set then [MIN_RECOMMENDED_SPARSITY] is used. The threshold does
+    /// not affect the resulting tree.
+    #[builder(setter(strip_option))]
+    #[serde(default)]
+    min_sparsity_warning: Option<u64>,
+
+    /// Bound on the memory retained by the tree's node store after the build,
+    /// see [BuildOptions::with_store_capacity_bytes].
+    ///
+    /// If not set then the store is not bounded. The bound is not applied
+    /// during the build, so it does not limit the peak memory of the build.
+    #[builder(setter(strip_option))]
+    #[serde(default)]
+    store_capacity_bytes: Option<usize>,
+
+    /// Max number of previous roots kept by the tree when it is updated, see
+    /// [DapolTree::root_history].
+    ///
+    /// If not set then no previous roots are kept.
+    #[serde(default)]
+    max_root_history: usize,
+
+    /// Context string used to domain-separate the hashes of the bottom-layer
+    /// leaf & padding nodes, see [BuildOptions::with_domain_separator].
+    ///
+    /// The rest of the hashes are derived from these, so changing the domain
+    /// separator changes all the hashes in the tree, including the root hash.
+    /// If not set then the leaf & padding nodes are hashed without one.
+    #[builder(setter(custom))]
+    #[serde(default)]
+    domain_separator: Option<String>,
+
     #[builder(setter(custom))]
     random_seed: Option<u64>,
 
+    /// Token for cancelling the tree build, see [CancellationToken]. This
+    /// cannot be set via a config file.
+    ///
+    /// If not set then the build cannot be cancelled.
+    #[builder(setter(strip_option))]
+    #[serde(skip)]
+    cancellation_token: Option<CancellationToken>,
+
     #[builder(private)]
     entities: EntityConfig,
 
@@ -112,26 +202,35 @@ pub struct SecretsConfig {
 pub struct EntityConfig {
     file_path: Option<PathBuf>,
     num_random_entities: Option<u64>,
+    sample_fraction: Option<f64>,
+    #[serde(default)]
+    duplicate_policy: DuplicatePolicy,
+    #[serde(default, deserialize_with = "deserialize_csv_delimiter")]
+    csv_delimiter: Option<u8>,
+    csv_has_headers: Option<bool>,
+    #[serde(default)]
+    combine_file_and_random: bool,
+    /// Entity records that have already been parsed (e.g. from stdin). These
+    /// cannot be set via a config file.
+    #[serde(skip)]
+    records: Option<Vec<Entity>>,
 }
 
 // -------------------------------------------------------------------------------------------------
 // Builder.
 
 impl DapolConfigBuilder {
+    /// Return the entity config, setting it to the default if it is not set.
+    fn entities_mut(&mut self) -> &mut EntityConfig {
+        self.entities.get_or_insert_with(EntityConfig::default)
+    }
+
     /// Set the path for the file containing the entity data.
     ///
     /// Wrapped in an option to provide ease of use if the PathBuf is already
     /// an option.
     pub fn entities_file_path_opt(&mut self, path: Option<PathBuf>) -> &mut Self {
-        match &mut self.entities {
-            None => {
-                self.entities = Some(EntityConfig {
-                    file_path: path,
-                    num_random_entities: None,
-                })
-            }
-            Some(entities) => entities.file_path = path,
-        }
+        self.entities_mut().file_path = path;
         self
     }
 
@@ -143,31 +242,99 @@ impl DapolConfigBuilder {
     /// Set the number of entities that will be generated randomly.
     ///
     /// If a path is also given for the entities then that is used instead,
-    /// i.e. they are not combined.
+    /// i.e. they are not combined, unless [combine_file_and_random] is set.
     ///
     /// Wrapped in an option to provide ease of use if the PathBuf is already
     /// an option.
     pub fn num_random_entities_opt(&mut self, num_entities: Option<u64>) -> &mut Self {
-        match &mut self.entities {
-            None => {
-                self.entities = Some(EntityConfig {
-                    file_path: None,
-                    num_random_entities: num_entities,
-                })
-            }
-            Some(entities) => entities.num_random_entities = num_entities,
-        }
+        self.entities_mut().num_random_entities = num_entities;
         self
     }
 
     /// Set the number of entities that will be generated randomly.
     ///
     /// If a path is also given for the entities then that is used instead,
-    /// i.e. they are not combined.
+    /// i.e. they are not combined, unless [combine_file_and_random] is set.
     pub fn num_random_entities(&mut self, num_entities: u64) -> &mut Self {
         self.num_random_entities_opt(Some(num_entities))
     }
 
+    /// Set entity records that have already been parsed, for example from
+    /// stdin using [EntitiesParser::from_reader].
+    ///
+    /// These take priority over both the entities file path and the number of
+    /// random entities. Sampling is not applied to these records.
+    ///
+    /// Wrapped in an option to provide ease of use if the records are already
+    /// an option.
+    pub fn entity_records_opt(&mut self, records: Option<Vec<Entity>>) -> &mut Self {
+        self.entities_mut().records = records;
+        self
+    }
+
+    /// Set entity records that have already been parsed, for example from
+    /// stdin using [EntitiesParser::from_reader].
+    ///
+    /// These take priority over both the entities file path and the number of
+    /// random entities. Sampling is not applied to these records.
+    pub fn entity_records(&mut self, records: Vec<Entity>) -> &mut Self {
+        self.entity_records_opt(Some(records))
+    }
+
+    /// Only use a random sample of the entities in the entities file.
+    ///
+    /// Each entity is kept with probability `sample_fraction`, which must be
+    /// in the range `(0, 1]`. This has no effect on randomly generated
+    /// entities.
+    ///
+    /// Note that the sample is not stable across runs unless a seed is set
+    /// using [random_seed], which is only available with the `testing`
+    /// feature.
+    pub fn entity_sample_fraction(&mut self, sample_fraction: f64) -> &mut Self {
+        self.entities_mut().sample_fraction = Some(sample_fraction);
+        self
+    }
+
+    /// Set what to do with entities in the entities file that have the same
+    /// ID.
+    ///
+    /// Defaults to [DuplicatePolicy::Error]. This has no effect on randomly
+    /// generated entities or on records set using [entity_records].
+    pub fn duplicate_policy(&mut self, duplicate_policy: DuplicatePolicy) -> &mut Self {
+        self.entities_mut().duplicate_policy = duplicate_policy;
+        self
+    }
+
+    /// Set the field delimiter for the entities file, e.g. `b'\t'` for
+    /// tab-separated values.
+    ///
+    /// Defaults to a comma. This has no effect on randomly generated entities
+    /// or on records set using [entity_records].
+    pub fn entities_csv_delimiter(&mut self, delimiter: u8) -> &mut Self {
+        self.entities_mut().csv_delimiter = Some(delimiter);
+        self
+    }
+
+    /// Set whether the first row of the entities file is a header row.
+    ///
+    /// Defaults to true. If false then the columns are expected to be in the
+    /// order `id,liability,salt`, with the salt column being optional.
+    pub fn entities_csv_has_headers(&mut self, has_headers: bool) -> &mut Self {
+        self.entities_mut().csv_has_headers = Some(has_headers);
+        self
+    }
+
+    /// Set whether the randomly generated entities are appended to the ones
+    /// in the entities file, rather than being ignored.
+    ///
+    /// Defaults to false. The random entities are given IDs that do not
+    /// collide with the ones in the file. This has no effect on records set
+    /// using [entity_records].
+    pub fn combine_file_and_random(&mut self, combine: bool) -> &mut Self {
+        self.entities_mut().combine_file_and_random = combine;
+        self
+    }
+
     /// Set the path for the file containing the secrets.
     ///
     /// Wrapped in an option to provide ease of use if the PathBuf is already
@@ -205,6 +372,16 @@ impl DapolConfigBuilder {
         self
     }
 
+    #[doc = include_str!("./shared_docs/height.md")]
+    ///
+    /// Wrapped in an option to provide ease of use if the value is already
+    /// an option. If None then the default height is used, or a bigger
+    /// height derived from the number of entities.
+    pub fn height_opt(&mut self, height: Option<Height>) -> &mut Self {
+        self.height = Some(height);
+        self
+    }
+
     #[doc = include_str!("./shared_docs/salt_b.md")]
     ///
     /// Wrapped in an option to provide ease of use if the value is already
@@ -223,6 +400,30 @@ impl DapolConfigBuilder {
         self
     }
 
+    /// Set the path for the file containing the salts.
+    ///
+    /// Wrapped in an option to provide ease of use if the PathBuf is already
+    /// an option.
+    pub fn salts_file_path_opt(&mut self, path: Option<PathBuf>) -> &mut Self {
+        self.salts_file_path = Some(path);
+        self
+    }
+
+    /// Derive the max liability from the entities after they are parsed,
+    /// ignoring the `max_liability` value (see [MaxLiability::from_entities]).
+    pub fn auto_max_liability(&mut self) -> &mut Self {
+        self.auto_max_liability = Some(true);
+        self
+    }
+
+    /// Set the context string used to domain-separate the hashes of the
+    /// bottom-layer leaf & padding nodes, see
+    /// [BuildOptions::with_domain_separator].
+    pub fn domain_separator(&mut self, domain_separator: &str) -> &mut Self {
+        self.domain_separator = Some(Some(domain_separator.to_string()));
+        self
+    }
+
     /// For seeding any PRNG to have deterministic output.
     ///
     /// Note: This is **not** cryptographically secure and should only be used
@@ -259,9 +460,29 @@ impl DapolConfigBuilder {
                 .clone()
                 .and_then(|e| e.num_random_entities)
                 .or(None),
+            sample_fraction: self
+                .entities
+                .clone()
+                .and_then(|e| e.sample_fraction)
+                .or(None),
+            duplicate_policy: self
+                .entities
+                .clone()
+                .map(|e| e.duplicate_policy)
+                .unwrap_or_default(),
+            csv_delimiter: self.entities.clone().and_then(|e| e.csv_delimiter),
+            csv_has_headers: self.entities.clone().and_then(|e| e.csv_has_headers),
+            records: self.entities.clone().and_then(|e| e.records).or(None),
+            combine_file_and_random: self
+                .entities
+                .clone()
+                .is_some_and(|e| e.combine_file_and_random),
         };
 
-        if entities.file_path.is_none() && entities.num_random_entities.is_none() {
+        if entities.file_path.is_none()
+            && entities.num_random_entities.is_none()
+            && entities.records.is_none()
+        {
             return Err(DapolConfigBuilderError::UninitializedField("entities"));
         }
 
@@ -276,21 +497,39 @@ impl DapolConfigBuilder {
 
         let salt_b = self.salt_b.clone().unwrap_or_default();
         let salt_s = self.salt_s.clone().unwrap_or_default();
-        let height = self.height.unwrap_or_default();
+        let salts_file_path = self.salts_file_path.clone().flatten();
+        let height = self.height.flatten();
         let max_thread_count = self.max_thread_count.unwrap_or_default();
+        let build_strategy = self.build_strategy.unwrap_or_default();
+        let leaf_mapping_chunk_size = self.leaf_mapping_chunk_size.flatten();
+        let min_sparsity_warning = self.min_sparsity_warning.flatten();
+        let store_capacity_bytes = self.store_capacity_bytes.flatten();
+        let max_root_history = self.max_root_history.unwrap_or_default();
+        let domain_separator = self.domain_separator.clone().flatten();
         let max_liability = self.max_liability.unwrap_or_default();
+        let auto_max_liability = self.auto_max_liability.unwrap_or_default();
         let random_seed = self.get_random_seed();
+        let cancellation_token = self.cancellation_token.clone().flatten();
 
         Ok(DapolConfig {
             accumulator_type,
             salt_b,
             salt_s,
+            salts_file_path,
             max_liability,
+            auto_max_liability,
             height,
             max_thread_count,
+            build_strategy,
+            leaf_mapping_chunk_size,
+            min_sparsity_warning,
+            store_capacity_bytes,
+            max_root_history,
+            domain_separator,
             entities,
             secrets,
             random_seed,
+            cancellation_token,
         })
     }
 }
@@ -339,7 +578,8 @@ impl DapolConfig {
         config.entities.file_path =
             extend_path_if_relative(config_file_path.clone(), config.entities.file_path);
         config.secrets.file_path =
-            extend_path_if_relative(config_file_path, config.secrets.file_path);
+            extend_path_if_relative(config_file_path.clone(), config.secrets.file_path);
+        config.salts_file_path = extend_path_if_relative(config_file_path, config.salts_file_path);
 
         debug!("Successfully deserialized DAPOL config file");
 
@@ -352,48 +592,40 @@ impl DapolConfig {
     pub fn parse(self) -> Result<DapolTree, DapolConfigError> {
         debug!("Parsing config to create a new DAPOL tree: {:?}", self);
 
-        let salt_b = self.salt_b;
-        let salt_s = self.salt_s;
+        let options = self.build_options();
+        let (salt_b, salt_s) = self.resolve_salts()?;
+
+        let entities = match self.entities.records {
+            Some(records) => records,
+            None => EntitiesParser::new()
+                .with_path_opt(self.entities.file_path)
+                .with_num_entities_opt(self.entities.num_random_entities)
+                .with_sample_fraction_opt(self.entities.sample_fraction)
+                .with_duplicate_policy(self.entities.duplicate_policy)
+                .with_delimiter_opt(self.entities.csv_delimiter)
+                .with_has_headers_opt(self.entities.csv_has_headers)
+                .with_combine(self.entities.combine_file_and_random)
+                .with_random_seed_opt(self.random_seed)
+                .parse_file_and_maybe_generate_random()?,
+        };
 
-        let entities = EntitiesParser::new()
-            .with_path_opt(self.entities.file_path)
-            .with_num_entities_opt(self.entities.num_random_entities)
-            .parse_file_or_generate_random()?;
+        let height = Self::resolve_height(self.height, entities.len() as u64)?;
+        let master_secret = self.secrets.resolve_master_secret()?;
+        let max_liability =
+            Self::resolve_max_liability(self.max_liability, self.auto_max_liability, &entities);
 
-        let master_secret = if let Some(path) = self.secrets.file_path {
-            Ok(DapolConfig::parse_secrets_file(path)?)
-        } else if let Some(master_secret) = self.secrets.master_secret {
-            Ok(master_secret)
-        } else {
-            Err(DapolConfigError::CannotFindMasterSecret)
-        }?;
-
-        let dapol_tree = if let Some(random_seed) = self.random_seed {
-            DapolTree::new_with_random_seed(
-                self.accumulator_type,
-                master_secret,
-                salt_b,
-                salt_s,
-                self.max_liability,
-                self.max_thread_count,
-                self.height,
-                entities,
-                random_seed,
-            )
-            .log_on_err()?
-        } else {
-            DapolTree::new(
-                self.accumulator_type,
-                master_secret,
-                salt_b,
-                salt_s,
-                self.max_liability,
-                self.max_thread_count,
-                self.height,
-                entities,
-            )
-            .log_on_err()?
-        };
+        let mut dapol_tree = DapolTree::new_with_options(
+            self.accumulator_type,
+            master_secret,
+            salt_b,
+            salt_s,
+            height,
+            entities,
+            options.with_max_liability(max_liability),
+        )
+        .log_on_err()?;
+
+        dapol_tree.set_max_root_history(self.max_root_history);
 
         Ok(dapol_tree)
     }
@@ -404,33 +636,173 @@ impl DapolConfig {
     pub fn parse(self) -> Result<DapolTree, DapolConfigError> {
         debug!("Parsing config to create a new DAPOL tree: {:?}", self);
 
-        let salt_b = self.salt_b;
-        let salt_s = self.salt_s;
-
-        let entities = EntitiesParser::new()
-            .with_path_opt(self.entities.file_path)
-            .with_num_entities_opt(self.entities.num_random_entities)
-            .parse_file_or_generate_random()?;
+        let options = self.build_options();
+        let (salt_b, salt_s) = self.resolve_salts()?;
+
+        let entities = match self.entities.records {
+            Some(records) => records,
+            None => EntitiesParser::new()
+                .with_path_opt(self.entities.file_path)
+                .with_num_entities_opt(self.entities.num_random_entities)
+                .with_sample_fraction_opt(self.entities.sample_fraction)
+                .with_duplicate_policy(self.entities.duplicate_policy)
+                .with_delimiter_opt(self.entities.csv_delimiter)
+                .with_has_headers_opt(self.entities.csv_has_headers)
+                .with_combine(self.entities.combine_file_and_random)
+                .parse_file_and_maybe_generate_random()?,
+        };
 
-        let master_secret = if let Some(path) = self.secrets.file_path {
-            Ok(DapolConfig::parse_secrets_file(path)?)
-        } else if let Some(master_secret) = self.secrets.master_secret {
-            Ok(master_secret)
-        } else {
-            Err(DapolConfigError::CannotFindMasterSecret)
-        }?;
+        let height = Self::resolve_height(self.height, entities.len() as u64)?;
+        let master_secret = self.secrets.resolve_master_secret()?;
+        let max_liability =
+            Self::resolve_max_liability(self.max_liability, self.auto_max_liability, &entities);
 
-        Ok(DapolTree::new(
+        let mut dapol_tree = DapolTree::new_with_options(
             self.accumulator_type,
             master_secret,
             salt_b,
             salt_s,
-            self.max_liability,
-            self.max_thread_count,
-            self.height,
+            height,
             entities,
+            options.with_max_liability(max_liability),
         )
-        .log_on_err()?)
+        .log_on_err()?;
+
+        dapol_tree.set_max_root_history(self.max_root_history);
+
+        Ok(dapol_tree)
+    }
+
+    /// Collect all the optional build parameters that are set in the config.
+    ///
+    /// All of them are applied together when building the tree, none take
+    /// precedence over the others.
+    fn build_options(&self) -> BuildOptions {
+        let options = BuildOptions::default()
+            .with_max_thread_count(self.max_thread_count)
+            .with_build_strategy(self.build_strategy)
+            .with_cancellation_token_opt(self.cancellation_token.clone())
+            .with_leaf_mapping_chunk_size_opt(self.leaf_mapping_chunk_size)
+            .with_min_sparsity_warning_opt(self.min_sparsity_warning)
+            .with_store_capacity_bytes_opt(self.store_capacity_bytes)
+            .with_domain_separator_opt(self.domain_separator.clone());
+
+        #[cfg(any(test, feature = "testing"))]
+        let options = options.with_random_seed_opt(self.random_seed);
+
+        options
+    }
+
+    /// Check that the config can be used to construct a [DapolTree], without
+    /// actually constructing the tree.
+    ///
+    /// The following checks are done:
+    /// 1. The entities file (if set) can be parsed, and duplicate entity IDs
+    ///    are handled according to the duplicate policy.
+    /// 2. The master secret can be found, either in the secrets file or set
+    ///    directly.
+    /// 3. The salts file (if set) can be parsed.
+    /// 4. The number of entities fits on the bottom layer of a tree with the
+    ///    configured height, or a height can be derived from the number of
+    ///    entities if it is not set.
+    ///
+    /// The first check that fails is returned as an error. Note that passing
+    /// these checks does not guarantee that the tree build will succeed (e.g.
+    /// duplicate entity IDs in entity records that were set directly are only
+    /// detected during the build), but it gives quick feedback on a bad config
+    /// before starting a lengthy build.
+    pub fn validate(&self) -> Result<(), DapolConfigError> {
+        debug!("Validating DAPOL config: {:?}", self);
+
+        let num_entities = match (
+            &self.entities.records,
+            &self.entities.file_path,
+            self.entities.num_random_entities,
+        ) {
+            (Some(records), _, _) => records.len() as u64,
+            (None, Some(path), num_random_entities) => {
+                let num_file_entities = EntitiesParser::new()
+                    .with_path(path.clone())
+                    .with_duplicate_policy(self.entities.duplicate_policy)
+                    .with_delimiter_opt(self.entities.csv_delimiter)
+                    .with_has_headers_opt(self.entities.csv_has_headers)
+                    .parse_file()?
+                    .len() as u64;
+
+                if self.entities.combine_file_and_random {
+                    num_file_entities + num_random_entities.unwrap_or(0)
+                } else {
+                    num_file_entities
+                }
+            }
+            (None, None, Some(num_entities)) => num_entities,
+            (None, None, None) => return Err(entity::EntitiesParserError::NumEntitiesNotSet.into()),
+        };
+
+        self.secrets.resolve_master_secret()?;
+        self.resolve_salts()?;
+
+        let height = Self::resolve_height(self.height, num_entities)?;
+        let max_entities = height.max_bottom_layer_nodes();
+        if num_entities > max_entities {
+            return Err(DapolConfigError::TooManyEntities {
+                num_entities,
+                max_entities,
+                height,
+            });
+        }
+
+        debug!("DAPOL config is valid");
+
+        Ok(())
+    }
+
+    /// Return the max liability derived from `entities` if
+    /// `auto_max_liability` is set, otherwise `max_liability`.
+    fn resolve_max_liability(
+        max_liability: MaxLiability,
+        auto_max_liability: bool,
+        entities: &[Entity],
+    ) -> MaxLiability {
+        if auto_max_liability {
+            let derived = MaxLiability::from_entities(entities);
+            info!(
+                "Using max liability {} derived from the entities",
+                derived.as_u64()
+            );
+            derived
+        } else {
+            max_liability
+        }
+    }
+
+    /// Return `height` if it is set, otherwise the bigger of the default
+    /// height and the smallest height that gives more than
+    /// [MIN_RECOMMENDED_SPARSITY] for `num_entities`.
+    ///
+    /// The default height is a lower bound so that the height does not leak
+    /// the number of entities.
+    ///
+    /// An error is returned if the height is not set and there is no height
+    /// that can fit `num_entities` with the recommended sparsity.
+    fn resolve_height(
+        height: Option<Height>,
+        num_entities: u64,
+    ) -> Result<Height, DapolConfigError> {
+        match height {
+            Some(height) => Ok(height),
+            None => {
+                let height = Height::optimal_for(num_entities, MIN_RECOMMENDED_SPARSITY)
+                    .map_err(|_| DapolConfigError::CannotDeriveHeight { num_entities })?
+                    .max(Height::default());
+                info!(
+                    "Tree height not set, using height {} for {} entities",
+                    height.as_u8(),
+                    num_entities
+                );
+                Ok(height)
+            }
+        }
     }
 
     /// Open and parse the secrets file, returning a [Secret].
@@ -440,6 +812,7 @@ impl DapolConfig {
     /// 2. The file cannot be opened.
     /// 3. The file cannot be read.
     /// 4. The file type is not supported.
+    /// 5. The file does not contain the `master_secret` field.
     fn parse_secrets_file(path: PathBuf) -> Result<Secret, SecretsParserError> {
         debug!(
             "Attempting to parse {:?} as a file containing secrets",
@@ -453,9 +826,13 @@ impl DapolConfig {
         let master_secret = match FileType::from_str(ext)? {
             FileType::Toml => {
                 let mut buf = String::new();
-                File::open(path)?.read_to_string(&mut buf)?;
+                File::open(path.clone())?.read_to_string(&mut buf)?;
                 let secrets: DapolSecrets = toml::from_str(&buf)?;
-                secrets.master_secret
+                secrets
+                    .master_secret
+                    .ok_or(SecretsParserError::MissingMasterSecretField(
+                        path.into_os_string(),
+                    ))?
             }
         };
 
@@ -463,6 +840,63 @@ impl DapolConfig {
 
         Ok(master_secret)
     }
+
+    /// Return `salt_b` & `salt_s`, giving preference to the salts file over
+    /// the directly-set values.
+    ///
+    /// An error is returned if the salts file cannot be parsed.
+    fn resolve_salts(&self) -> Result<(Salt, Salt), DapolConfigError> {
+        match &self.salts_file_path {
+            Some(path) => {
+                Self::parse_salts_file(path.clone()).map_err(DapolConfigError::SaltsFileParseError)
+            }
+            None => Ok((self.salt_b.clone(), self.salt_s.clone())),
+        }
+    }
+
+    /// Open and parse the salts file, returning `salt_b` & `salt_s`.
+    ///
+    /// An error is returned if:
+    /// 1. The file cannot be opened.
+    /// 2. The file cannot be read.
+    /// 3. The file type is not supported.
+    fn parse_salts_file(path: PathBuf) -> Result<(Salt, Salt), SecretsParserError> {
+        debug!("Attempting to parse {:?} as a file containing salts", path);
+
+        let ext = path.extension().and_then(|s| s.to_str()).ok_or(
+            SecretsParserError::UnknownFileType(path.clone().into_os_string()),
+        )?;
+
+        let salts = match FileType::from_str(ext)? {
+            FileType::Toml => {
+                let mut buf = String::new();
+                File::open(path)?.read_to_string(&mut buf)?;
+                let salts: DapolSalts = toml::from_str(&buf)?;
+                (salts.salt_b, salts.salt_s)
+            }
+        };
+
+        debug!("Successfully parsed DAPOL salts file");
+
+        Ok(salts)
+    }
+}
+
+impl SecretsConfig {
+    /// Return the master secret, giving preference to the secrets file over
+    /// the directly-set value.
+    ///
+    /// An error is returned if neither are set, or if the secrets file cannot
+    /// be parsed.
+    fn resolve_master_secret(&self) -> Result<Secret, DapolConfigError> {
+        if let Some(path) = &self.file_path {
+            Ok(DapolConfig::parse_secrets_file(path.clone())?)
+        } else if let Some(master_secret) = &self.master_secret {
+            Ok(master_secret.clone())
+        } else {
+            Err(DapolConfigError::CannotFindMasterSecret)
+        }
+    }
 }
 
 fn extend_path_if_relative(
@@ -481,6 +915,25 @@ fn extend_path_if_relative(
     }
 }
 
+/// The CSV delimiter is given as a single-character string in the config
+/// file, e.g. `"\t"`, but the CSV parser takes a byte.
+fn deserialize_csv_delimiter<'de, D>(deserializer: D) -> Result<Option<u8>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let delimiter = Option::<String>::deserialize(deserializer)?;
+
+    delimiter
+        .map(|delimiter| match delimiter.as_bytes() {
+            [byte] => Ok(*byte),
+            _ => Err(serde::de::Error::custom(format!(
+                "expected the CSV delimiter to be a single ASCII character, found {:?}",
+                delimiter
+            ))),
+        })
+        .transpose()
+}
+
 /// Supported file types for deserialization.
 enum FileType {
     Toml,
@@ -499,7 +952,15 @@ impl FromStr for FileType {
 
 #[derive(Deserialize, Debug)]
 struct DapolSecrets {
-    master_secret: Secret,
+    // Optional so that a missing field can be reported with a specific error,
+    // rather than a generic deserialization one.
+    master_secret: Option<Secret>,
+}
+
+#[derive(Deserialize, Debug)]
+struct DapolSalts {
+    salt_b: Salt,
+    salt_s: Salt,
 }
 
 // -------------------------------------------------------------------------------------------------
@@ -518,6 +979,16 @@ pub enum DapolConfigError {
     CannotFindMasterSecret,
     #[error("Error parsing the salt string")]
     SaltParseError(#[from] salt::SaltParserError),
+    #[error("Error parsing the salts file")]
+    SaltsFileParseError(#[source] SecretsParserError),
+    #[error("Number of entities ({num_entities}) exceeds the max ({max_entities}) for a tree with height {height:?}")]
+    TooManyEntities {
+        num_entities: u64,
+        max_entities: u64,
+        height: Height,
+    },
+    #[error("Unable to derive a tree height for {num_entities} entities, try setting the height explicitly")]
+    CannotDeriveHeight { num_entities: u64 },
     #[error("Tree construction failed after parsing DAPOL config")]
     BuildError(#[from] DapolTreeError),
     #[error("Unable to find file extension for path {0:?}")]
@@ -540,6 +1011,8 @@ pub enum SecretsParserError {
     FileReadError(#[from] std::io::Error),
     #[error("Deserialization process failed")]
     DeserializationError(#[from] toml::de::Error),
+    #[error("The secrets file {0:?} does not contain the 'master_secret' field")]
+    MissingMasterSecretField(OsString),
 }
 
 // -------------------------------------------------------------------------------------------------
@@ -616,7 +1089,7 @@ mod tests {
             assert_eq!(dapol_config.entities.num_random_entities, None);
             assert_eq!(dapol_config.secrets.master_secret, None);
             assert_eq!(dapol_config.max_thread_count, MaxThreadCount::default());
-            assert_eq!(dapol_config.height, Height::default());
+            assert_eq!(dapol_config.height, None);
             assert_eq!(dapol_config.max_liability, MaxLiability::default());
 
             // Salts should be random bytes. Check that at least one byte is non-zero.
@@ -653,7 +1126,7 @@ mod tests {
             assert_eq!(dapol_config.secrets.master_secret, Some(master_secret));
             assert_eq!(dapol_config.max_thread_count, max_thread_count);
             assert_eq!(dapol_config.max_liability, max_liability);
-            assert_eq!(dapol_config.height, height);
+            assert_eq!(dapol_config.height, Some(height));
             assert_eq!(dapol_config.salt_b, salt_b);
             assert_eq!(dapol_config.salt_s, salt_s);
         }
@@ -764,82 +1237,679 @@ mod tests {
                 ))
             );
         }
-    }
 
-    // TODO these are actually integration tests, so move them to tests dir
-    mod config_to_tree {
-        use super::*;
+        #[test]
+        fn fail_when_secrets_file_has_no_master_secret_field() {
+            let path =
+                PathBuf::from("./examples/my_secrets_without_master_secret_for_testing.toml");
+            std::fs::write(&path, "mastr_secret = \"master_secret\"\n").unwrap();
+
+            let res = DapolConfigBuilder::default()
+                .accumulator_type(AccumulatorType::NdmSmt)
+                .num_random_entities(100)
+                .secrets_file_path(path.clone())
+                .build()
+                .unwrap()
+                .parse();
+
+            std::fs::remove_file(path).unwrap();
+
+            assert_err!(
+                res,
+                Err(DapolConfigError::MasterSecretFileParseError(
+                    SecretsParserError::MissingMasterSecretField(_)
+                ))
+            );
+        }
 
         #[test]
-        fn parsing_config_gives_correct_tree() {
+        fn entities_file_and_random_entities_are_combined() {
             let src_dir = env!("CARGO_MANIFEST_DIR");
             let resources_dir = Path::new(&src_dir).join("examples");
             let entities_file_path = resources_dir.join("entities_example.csv");
 
-            let entities_file = File::open(entities_file_path.clone()).unwrap();
-            // "-1" because we don't include the top line of the csv which defines
-            // the column headings.
-            let num_entities = BufReader::new(entities_file).lines().count() - 1;
+            let dapol_config = DapolConfigBuilder::default()
+                .accumulator_type(AccumulatorType::NdmSmt)
+                .master_secret(Secret::from_str("master_secret").unwrap())
+                .entities_file_path(entities_file_path)
+                .num_random_entities(20)
+                .combine_file_and_random(true)
+                .height(Height::expect_from(8u8))
+                .build()
+                .unwrap();
 
-            let height = Height::expect_from(8u8);
-            let master_secret = Secret::from_str("master_secret").unwrap();
-            let salt_b = Salt::from_str("salt_b").unwrap();
-            let salt_s = Salt::from_str("salt_s").unwrap();
+            dapol_config.validate().unwrap();
+            let dapol_tree = dapol_config.parse().unwrap();
 
-            let dapol_tree = DapolConfigBuilder::default()
-                .accumulator_type(AccumulatorType::NdmSmt)
-                .height(height.clone())
-                .salt_b(salt_b.clone())
-                .salt_s(salt_s.clone())
-                .master_secret(master_secret.clone())
-                .entities_file_path(entities_file_path.clone())
+            assert_eq!(dapol_tree.entity_mapping().unwrap().len(), 120);
+        }
+
+        #[test]
+        fn csv_delimiter_is_read_from_single_character_string() {
+            let entities: EntityConfig = toml::from_str("csv_delimiter = \"\\t\"").unwrap();
+            assert_eq!(entities.csv_delimiter, Some(b'\t'));
+
+            let res = toml::from_str::<EntityConfig>("csv_delimiter = \"ab\"");
+            assert!(res.is_err());
+        }
+    }
+
+    mod validation {
+        use super::*;
+
+        #[test]
+        fn valid_config_passes_validation() {
+            let dapol_config = dapol_config_builder_matching_example_file()
+                .height(Height::expect_from(8u8))
                 .build()
-                .unwrap()
-                .parse()
                 .unwrap();
 
-            assert_eq!(
-                dapol_tree.entity_mapping().unwrap().len(),
-                num_entities as usize
-            );
-            assert_eq!(dapol_tree.accumulator_type(), AccumulatorType::NdmSmt);
-            assert_eq!(*dapol_tree.height(), height);
-            assert_eq!(*dapol_tree.master_secret(), master_secret);
-            assert_eq!(dapol_tree.max_liability(), &MaxLiability::default());
-            assert_eq!(*dapol_tree.salt_b(), salt_b);
-            assert_eq!(*dapol_tree.salt_s(), salt_s);
+            dapol_config.validate().unwrap();
         }
 
         #[test]
-        fn config_with_random_entities_gives_correct_tree() {
-            let height = Height::expect_from(8);
-            let num_random_entities = 10;
+        fn too_many_entities_for_height_fails_validation() {
             let master_secret = Secret::from_str("master_secret").unwrap();
+            let height = Height::expect_from(4u8);
 
-            let dapol_tree = DapolConfigBuilder::default()
+            let res = DapolConfigBuilder::default()
                 .accumulator_type(AccumulatorType::NdmSmt)
                 .height(height)
                 .master_secret(master_secret)
-                .num_random_entities(num_random_entities)
+                .num_random_entities(height.max_bottom_layer_nodes() + 1)
                 .build()
                 .unwrap()
-                .parse()
-                .unwrap();
+                .validate();
 
-            assert_eq!(
-                dapol_tree.entity_mapping().unwrap().len(),
-                num_random_entities as usize
+            assert_err!(
+                res,
+                Err(DapolConfigError::TooManyEntities {
+                    num_entities: 9,
+                    max_entities: 8,
+                    height: _,
+                })
             );
         }
 
         #[test]
-        fn secrets_file_gives_same_master_secret_as_setting_directly() {
-            let src_dir = env!("CARGO_MANIFEST_DIR");
-            let resources_dir = Path::new(&src_dir).join("examples");
-            let secrets_file_path = resources_dir.join("dapol_secrets_example.toml");
-            let entities_file_path = resources_dir.join("entities_example.csv");
+        fn too_many_entities_for_derived_height_fails_validation() {
             let master_secret = Secret::from_str("master_secret").unwrap();
-            let height = Height::expect_from(8u8);
+
+            let res = DapolConfigBuilder::default()
+                .accumulator_type(AccumulatorType::NdmSmt)
+                .master_secret(master_secret)
+                .num_random_entities(u64::MAX)
+                .build()
+                .unwrap()
+                .validate();
+
+            assert_err!(
+                res,
+                Err(DapolConfigError::CannotDeriveHeight {
+                    num_entities: u64::MAX
+                })
+            );
+        }
+
+        #[test]
+        fn missing_secrets_file_fails_validation() {
+            let res = DapolConfigBuilder::default()
+                .accumulator_type(AccumulatorType::NdmSmt)
+                .num_random_entities(10)
+                .secrets_file_path(PathBuf::from("./nonexistent_secrets.toml"))
+                .build()
+                .unwrap()
+                .validate();
+
+            assert_err!(
+                res,
+                Err(DapolConfigError::MasterSecretFileParseError(
+                    SecretsParserError::FileReadError(_)
+                ))
+            );
+        }
+
+        #[test]
+        fn missing_salts_file_fails_validation() {
+            let res = dapol_config_builder_matching_example_file()
+                .height(Height::expect_from(8u8))
+                .salts_file_path(PathBuf::from("./nonexistent_salts.toml"))
+                .build()
+                .unwrap()
+                .validate();
+
+            assert_err!(
+                res,
+                Err(DapolConfigError::SaltsFileParseError(
+                    SecretsParserError::FileReadError(_)
+                ))
+            );
+        }
+
+        #[test]
+        fn unparsable_entities_file_fails_validation() {
+            let master_secret = Secret::from_str("master_secret").unwrap();
+
+            let res = DapolConfigBuilder::default()
+                .accumulator_type(AccumulatorType::NdmSmt)
+                .master_secret(master_secret)
+                .entities_file_path(PathBuf::from("./nonexistent_entities.csv"))
+                .build()
+                .unwrap()
+                .validate();
+
+            assert_err!(res, Err(DapolConfigError::EntitiesError(_)));
+        }
+    }
+
+    // TODO these are actually integration tests, so move them to tests dir
+    mod config_to_tree {
+        use super::*;
+        use crate::EntityId;
+
+        #[test]
+        fn parsing_config_gives_correct_tree() {
+            let src_dir = env!("CARGO_MANIFEST_DIR");
+            let resources_dir = Path::new(&src_dir).join("examples");
+            let entities_file_path = resources_dir.join("entities_example.csv");
+
+            let entities_file = File::open(entities_file_path.clone()).unwrap();
+            // "-1" because we don't include the top line of the csv which defines
+            // the column headings.
+            let num_entities = BufReader::new(entities_file).lines().count() - 1;
+
+            let height = Height::expect_from(8u8);
+            let master_secret = Secret::from_str("master_secret").unwrap();
+            let salt_b = Salt::from_str("salt_b").unwrap();
+            let salt_s = Salt::from_str("salt_s").unwrap();
+
+            let dapol_tree = DapolConfigBuilder::default()
+                .accumulator_type(AccumulatorType::NdmSmt)
+                .height(height.clone())
+                .salt_b(salt_b.clone())
+                .salt_s(salt_s.clone())
+                .master_secret(master_secret.clone())
+                .entities_file_path(entities_file_path.clone())
+                .build()
+                .unwrap()
+                .parse()
+                .unwrap();
+
+            assert_eq!(
+                dapol_tree.entity_mapping().unwrap().len(),
+                num_entities as usize
+            );
+            assert_eq!(dapol_tree.accumulator_type(), AccumulatorType::NdmSmt);
+            assert_eq!(*dapol_tree.height(), height);
+            assert_eq!(*dapol_tree.master_secret(), master_secret);
+            assert_eq!(dapol_tree.max_liability(), &MaxLiability::default());
+            assert_eq!(*dapol_tree.salt_b(), salt_b);
+            assert_eq!(*dapol_tree.salt_s(), salt_s);
+        }
+
+        #[test]
+        fn config_with_random_entities_gives_correct_tree() {
+            let height = Height::expect_from(8);
+            let num_random_entities = 10;
+            let master_secret = Secret::from_str("master_secret").unwrap();
+
+            let dapol_tree = DapolConfigBuilder::default()
+                .accumulator_type(AccumulatorType::NdmSmt)
+                .height(height)
+                .master_secret(master_secret)
+                .num_random_entities(num_random_entities)
+                .build()
+                .unwrap()
+                .parse()
+                .unwrap();
+
+            assert_eq!(
+                dapol_tree.entity_mapping().unwrap().len(),
+                num_random_entities as usize
+            );
+        }
+
+        #[test]
+        fn config_with_entity_sample_fraction_gives_sampled_tree() {
+            let src_dir = env!("CARGO_MANIFEST_DIR");
+            let resources_dir = Path::new(&src_dir).join("examples");
+            let entities_file_path = resources_dir.join("entities_example.csv");
+            let master_secret = Secret::from_str("master_secret").unwrap();
+            let height = Height::expect_from(8u8);
+
+            let dapol_tree = DapolConfigBuilder::default()
+                .accumulator_type(AccumulatorType::NdmSmt)
+                .height(height)
+                .master_secret(master_secret)
+                .entities_file_path(entities_file_path)
+                .entity_sample_fraction(0.5)
+                .random_seed(1)
+                .build()
+                .unwrap()
+                .parse()
+                .unwrap();
+
+            let num_entities = dapol_tree.entity_mapping().unwrap().len();
+            assert!(num_entities > 0 && num_entities < 100);
+        }
+
+        #[test]
+        fn unset_height_is_not_smaller_than_default() {
+            let master_secret = Secret::from_str("master_secret").unwrap();
+            let num_random_entities = 100;
+
+            let dapol_tree = DapolConfigBuilder::default()
+                .accumulator_type(AccumulatorType::NdmSmt)
+                .master_secret(master_secret)
+                .num_random_entities(num_random_entities)
+                .build()
+                .unwrap()
+                .parse()
+                .unwrap();
+
+            assert!(
+                Height::optimal_for(num_random_entities, MIN_RECOMMENDED_SPARSITY).unwrap()
+                    < Height::default()
+            );
+            assert_eq!(*dapol_tree.height(), Height::default());
+        }
+
+        #[test]
+        fn config_with_duplicate_policy_merges_entities_in_file() {
+            let src_dir = env!("CARGO_MANIFEST_DIR");
+            let resources_dir = Path::new(&src_dir).join("examples");
+            let entities_file_path = resources_dir.join("duplicate_entities_for_testing.csv");
+            let master_secret = Secret::from_str("master_secret").unwrap();
+            let height = Height::expect_from(8u8);
+
+            std::fs::write(
+                &entities_file_path,
+                "id,liability\njohn.doe@example.com,10\njohn.doe@example.com,12\n",
+            )
+            .unwrap();
+
+            let mut builder = DapolConfigBuilder::default();
+            builder
+                .accumulator_type(AccumulatorType::NdmSmt)
+                .height(height)
+                .master_secret(master_secret)
+                .entities_file_path(entities_file_path.clone());
+
+            let res = builder.build().unwrap().parse();
+            assert_err!(
+                res,
+                Err(DapolConfigError::EntitiesError(
+                    entity::EntitiesParserError::DuplicateEntityId(_)
+                ))
+            );
+
+            let dapol_tree = builder
+                .duplicate_policy(DuplicatePolicy::Sum)
+                .build()
+                .unwrap()
+                .parse();
+            std::fs::remove_file(entities_file_path).unwrap();
+
+            assert_eq!(dapol_tree.unwrap().entity_mapping().unwrap().len(), 1);
+        }
+
+        #[test]
+        fn config_with_cancelled_token_gives_error() {
+            let master_secret = Secret::from_str("master_secret").unwrap();
+            let cancellation_token = CancellationToken::with_timeout(std::time::Duration::ZERO);
+
+            let res = DapolConfigBuilder::default()
+                .accumulator_type(AccumulatorType::NdmSmt)
+                .master_secret(master_secret)
+                .num_random_entities(100)
+                .cancellation_token(cancellation_token)
+                .build()
+                .unwrap()
+                .parse();
+
+            assert_err!(
+                res,
+                Err(DapolConfigError::BuildError(DapolTreeError::BuildCancelled))
+            );
+        }
+
+        #[test]
+        fn config_with_domain_separator_and_cancelled_token_gives_error() {
+            let master_secret = Secret::from_str("master_secret").unwrap();
+            let cancellation_token = CancellationToken::with_timeout(std::time::Duration::ZERO);
+
+            let res = DapolConfigBuilder::default()
+                .accumulator_type(AccumulatorType::NdmSmt)
+                .master_secret(master_secret)
+                .num_random_entities(100)
+                .domain_separator("some deployment")
+                .cancellation_token(cancellation_token)
+                .build()
+                .unwrap()
+                .parse();
+
+            assert_err!(
+                res,
+                Err(DapolConfigError::BuildError(DapolTreeError::BuildCancelled))
+            );
+        }
+
+        #[test]
+        fn config_with_random_seed_and_cancelled_token_gives_error() {
+            let master_secret = Secret::from_str("master_secret").unwrap();
+            let cancellation_token = CancellationToken::with_timeout(std::time::Duration::ZERO);
+
+            let res = DapolConfigBuilder::default()
+                .accumulator_type(AccumulatorType::NdmSmt)
+                .master_secret(master_secret)
+                .num_random_entities(100)
+                .random_seed(1)
+                .cancellation_token(cancellation_token)
+                .build()
+                .unwrap()
+                .parse();
+
+            assert_err!(
+                res,
+                Err(DapolConfigError::BuildError(DapolTreeError::BuildCancelled))
+            );
+        }
+
+        #[test]
+        fn all_set_build_options_are_passed_to_tree() {
+            let config = DapolConfigBuilder::default()
+                .accumulator_type(AccumulatorType::NdmSmt)
+                .master_secret(Secret::from_str("master_secret").unwrap())
+                .num_random_entities(100)
+                .domain_separator("some deployment")
+                .random_seed(1)
+                .build_strategy(BuildStrategy::SingleThreaded)
+                .cancellation_token(CancellationToken::new())
+                .leaf_mapping_chunk_size(8)
+                .min_sparsity_warning(0)
+                .build()
+                .unwrap();
+
+            let options = config.build_options();
+
+            assert_eq!(options.domain_separator.as_deref(), Some("some deployment"));
+            assert_eq!(options.random_seed, Some(1));
+            assert_eq!(options.build_strategy, BuildStrategy::SingleThreaded);
+            assert!(options.cancellation_token.is_some());
+            assert_eq!(options.leaf_mapping_chunk_size, Some(8));
+            assert_eq!(options.min_sparsity_warning, Some(0));
+
+            let dapol_tree = config.parse().unwrap();
+
+            assert_eq!(dapol_tree.domain_separator(), Some("some deployment"));
+            assert_eq!(dapol_tree.entity_mapping().unwrap().len(), 100);
+        }
+
+        #[test]
+        fn config_with_single_threaded_build_strategy_and_cancelled_token_gives_error() {
+            let master_secret = Secret::from_str("master_secret").unwrap();
+            let cancellation_token = CancellationToken::with_timeout(std::time::Duration::ZERO);
+
+            let res = DapolConfigBuilder::default()
+                .accumulator_type(AccumulatorType::NdmSmt)
+                .master_secret(master_secret)
+                .num_random_entities(100)
+                .build_strategy(BuildStrategy::SingleThreaded)
+                .cancellation_token(cancellation_token)
+                .build()
+                .unwrap()
+                .parse();
+
+            assert_err!(
+                res,
+                Err(DapolConfigError::BuildError(DapolTreeError::BuildCancelled))
+            );
+        }
+
+        #[test]
+        fn config_with_single_threaded_build_strategy_builds_tree() {
+            let master_secret = Secret::from_str("master_secret").unwrap();
+
+            let dapol_tree = DapolConfigBuilder::default()
+                .accumulator_type(AccumulatorType::NdmSmt)
+                .master_secret(master_secret)
+                .num_random_entities(100)
+                .build_strategy(BuildStrategy::SingleThreaded)
+                .build()
+                .unwrap()
+                .parse()
+                .unwrap();
+
+            assert_eq!(dapol_tree.entity_mapping().unwrap().len(), 100);
+        }
+
+        #[test]
+        fn config_with_max_root_history_bounds_history() {
+            let master_secret = Secret::from_str("master_secret").unwrap();
+            let entities = vec![Entity {
+                liability: 1u64,
+                id: EntityId::from_str("id").unwrap(),
+                salt: None,
+                blinding_factor: None,
+            }];
+
+            let mut dapol_tree = DapolConfigBuilder::default()
+                .accumulator_type(AccumulatorType::NdmSmt)
+                .master_secret(master_secret)
+                .entity_records(entities)
+                .max_root_history(2)
+                .build()
+                .unwrap()
+                .parse()
+                .unwrap();
+
+            let entity_id = EntityId::from_str("id").unwrap();
+            for liability in 2..6 {
+                dapol_tree.update_liability(&entity_id, liability).unwrap();
+            }
+
+            assert_eq!(dapol_tree.epoch(), 4);
+            let epochs: Vec<u64> = dapol_tree.root_history().iter().map(|(e, _)| *e).collect();
+            assert_eq!(epochs, vec![2, 3]);
+        }
+
+        #[test]
+        fn config_with_leaf_mapping_chunk_size_builds_tree() {
+            let master_secret = Secret::from_str("master_secret").unwrap();
+
+            let dapol_tree = DapolConfigBuilder::default()
+                .accumulator_type(AccumulatorType::NdmSmt)
+                .master_secret(master_secret)
+                .num_random_entities(100)
+                .leaf_mapping_chunk_size(8)
+                .build()
+                .unwrap()
+                .parse()
+                .unwrap();
+
+            assert_eq!(dapol_tree.entity_mapping().unwrap().len(), 100);
+        }
+
+        #[test]
+        fn config_with_min_sparsity_warning_builds_dense_tree() {
+            let master_secret = Secret::from_str("master_secret").unwrap();
+
+            let dapol_tree = DapolConfigBuilder::default()
+                .accumulator_type(AccumulatorType::NdmSmt)
+                .master_secret(master_secret)
+                .num_random_entities(100)
+                .height(Height::expect_from(8u8))
+                .min_sparsity_warning(0)
+                .build()
+                .unwrap()
+                .parse()
+                .unwrap();
+
+            assert_eq!(dapol_tree.entity_mapping().unwrap().len(), 100);
+        }
+
+        #[test]
+        fn config_with_min_sparsity_warning_and_other_options_builds_dense_tree() {
+            let config = DapolConfigBuilder::default()
+                .accumulator_type(AccumulatorType::NdmSmt)
+                .master_secret(Secret::from_str("master_secret").unwrap())
+                .num_random_entities(100)
+                .height(Height::expect_from(8u8))
+                .random_seed(1)
+                .build_strategy(BuildStrategy::SingleThreaded)
+                .cancellation_token(CancellationToken::new())
+                .leaf_mapping_chunk_size(8)
+                .min_sparsity_warning(0)
+                .build()
+                .unwrap();
+
+            assert_eq!(config.build_options().min_sparsity_warning, Some(0));
+
+            let dapol_tree = config.parse().unwrap();
+
+            assert_eq!(dapol_tree.entity_mapping().unwrap().len(), 100);
+        }
+
+        #[test]
+        fn config_with_store_capacity_bytes_bounds_store() {
+            let config = DapolConfigBuilder::default()
+                .accumulator_type(AccumulatorType::NdmSmt)
+                .master_secret(Secret::from_str("master_secret").unwrap())
+                .num_random_entities(100)
+                .store_capacity_bytes(0)
+                .build()
+                .unwrap();
+
+            assert_eq!(config.build_options().store_capacity_bytes, Some(0));
+
+            let bounded_tree = config.parse().unwrap();
+            let tree = DapolConfigBuilder::default()
+                .accumulator_type(AccumulatorType::NdmSmt)
+                .master_secret(Secret::from_str("master_secret").unwrap())
+                .num_random_entities(100)
+                .build()
+                .unwrap()
+                .parse()
+                .unwrap();
+
+            assert!(bounded_tree.stored_node_count() < tree.stored_node_count());
+        }
+
+        #[test]
+        fn config_with_csv_options_parses_tab_separated_file() {
+            let src_dir = env!("CARGO_MANIFEST_DIR");
+            let resources_dir = Path::new(&src_dir).join("examples");
+            let entities_file_path = resources_dir.join("tab_separated_entities_for_testing.csv");
+            let master_secret = Secret::from_str("master_secret").unwrap();
+            let height = Height::expect_from(8u8);
+
+            std::fs::write(
+                &entities_file_path,
+                "john.doe@example.com\t10\njane.doe@example.com\t12\n",
+            )
+            .unwrap();
+
+            let dapol_tree = DapolConfigBuilder::default()
+                .accumulator_type(AccumulatorType::NdmSmt)
+                .height(height)
+                .master_secret(master_secret)
+                .entities_file_path(entities_file_path.clone())
+                .entities_csv_delimiter(b'\t')
+                .entities_csv_has_headers(false)
+                .build()
+                .unwrap()
+                .parse();
+            std::fs::remove_file(entities_file_path).unwrap();
+
+            let dapol_tree = dapol_tree.unwrap();
+            assert_eq!(dapol_tree.entity_mapping().unwrap().len(), 2);
+            assert_eq!(dapol_tree.root_liability(), 22);
+        }
+
+        #[test]
+        fn entity_records_are_used_instead_of_entities_file() {
+            let src_dir = env!("CARGO_MANIFEST_DIR");
+            let resources_dir = Path::new(&src_dir).join("examples");
+            let entities_file_path = resources_dir.join("entities_example.csv");
+            let master_secret = Secret::from_str("master_secret").unwrap();
+            let height = Height::expect_from(8u8);
+
+            let csv = "id,liability\njohn.doe@example.com,893267\njane.doe@example.com,12\n";
+            let records = EntitiesParser::from_reader(std::io::Cursor::new(csv))
+                .parse_reader()
+                .unwrap();
+
+            let dapol_tree = DapolConfigBuilder::default()
+                .accumulator_type(AccumulatorType::NdmSmt)
+                .height(height)
+                .master_secret(master_secret)
+                .entities_file_path(entities_file_path)
+                .entity_records(records)
+                .build()
+                .unwrap()
+                .parse()
+                .unwrap();
+
+            assert_eq!(dapol_tree.entity_mapping().unwrap().len(), 2);
+        }
+
+        #[test]
+        fn auto_max_liability_is_derived_from_entities() {
+            let master_secret = Secret::from_str("master_secret").unwrap();
+            let height = Height::expect_from(8u8);
+
+            let csv = "id,liability\njohn.doe@example.com,893267\njane.doe@example.com,12\n";
+            let records = EntitiesParser::from_reader(std::io::Cursor::new(csv))
+                .parse_reader()
+                .unwrap();
+
+            let dapol_tree = DapolConfigBuilder::default()
+                .accumulator_type(AccumulatorType::NdmSmt)
+                .height(height)
+                .master_secret(master_secret)
+                .max_liability(MaxLiability::from(10u64))
+                .auto_max_liability()
+                .entity_records(records)
+                .build()
+                .unwrap()
+                .parse()
+                .unwrap();
+
+            assert_eq!(dapol_tree.max_liability().as_u64(), u32::MAX as u64);
+
+            let entity_id = EntityId::from_str("john.doe@example.com").unwrap();
+            let proof = dapol_tree.generate_inclusion_proof(&entity_id).unwrap();
+            proof.verify(*dapol_tree.root_hash()).unwrap();
+        }
+
+        #[test]
+        fn domain_separator_is_passed_to_tree() {
+            let src_dir = env!("CARGO_MANIFEST_DIR");
+            let resources_dir = Path::new(&src_dir).join("examples");
+            let entities_file_path = resources_dir.join("entities_example.csv");
+
+            let dapol_tree = DapolConfigBuilder::default()
+                .accumulator_type(AccumulatorType::NdmSmt)
+                .height(Height::expect_from(8u8))
+                .master_secret(Secret::from_str("master_secret").unwrap())
+                .entities_file_path(entities_file_path)
+                .domain_separator("some deployment")
+                .build()
+                .unwrap()
+                .parse()
+                .unwrap();
+
+            assert_eq!(dapol_tree.domain_separator(), Some("some deployment"));
+        }
+
+        #[test]
+        fn secrets_file_gives_same_master_secret_as_setting_directly() {
+            let src_dir = env!("CARGO_MANIFEST_DIR");
+            let resources_dir = Path::new(&src_dir).join("examples");
+            let secrets_file_path = resources_dir.join("dapol_secrets_example.toml");
+            let entities_file_path = resources_dir.join("entities_example.csv");
+            let master_secret = Secret::from_str("master_secret").unwrap();
+            let height = Height::expect_from(8u8);
 
             let tree_from_secrets_file = DapolConfigBuilder::default()
                 .accumulator_type(AccumulatorType::NdmSmt)
@@ -892,5 +1962,72 @@ mod tests {
                 &Secret::from_str("master_secret").unwrap()
             );
         }
+
+        #[test]
+        fn salts_file_gives_same_salts_as_setting_directly() {
+            let src_dir = env!("CARGO_MANIFEST_DIR");
+            let resources_dir = Path::new(&src_dir).join("examples");
+            let salts_file_path = resources_dir.join("dapol_salts_example.toml");
+            let entities_file_path = resources_dir.join("entities_example.csv");
+            let master_secret = Secret::from_str("master_secret").unwrap();
+            let height = Height::expect_from(8u8);
+
+            let tree_from_salts_file = DapolConfigBuilder::default()
+                .accumulator_type(AccumulatorType::NdmSmt)
+                .height(height)
+                .master_secret(master_secret.clone())
+                .salts_file_path(salts_file_path)
+                .entities_file_path(entities_file_path.clone())
+                .build()
+                .unwrap()
+                .parse()
+                .unwrap();
+
+            let tree_from_direct_salts = DapolConfigBuilder::default()
+                .accumulator_type(AccumulatorType::NdmSmt)
+                .height(height)
+                .master_secret(master_secret)
+                .salt_b(Salt::from_str("salt_b").unwrap())
+                .salt_s(Salt::from_str("salt_s").unwrap())
+                .entities_file_path(entities_file_path)
+                .build()
+                .unwrap()
+                .parse()
+                .unwrap();
+
+            assert_eq!(
+                tree_from_direct_salts.salt_b(),
+                tree_from_salts_file.salt_b()
+            );
+            assert_eq!(
+                tree_from_direct_salts.salt_s(),
+                tree_from_salts_file.salt_s()
+            );
+        }
+
+        #[test]
+        fn salts_file_preferred_over_setting_directly() {
+            let src_dir = env!("CARGO_MANIFEST_DIR");
+            let resources_dir = Path::new(&src_dir).join("examples");
+            let salts_file_path = resources_dir.join("dapol_salts_example.toml");
+            let master_secret = Secret::from_str("master_secret").unwrap();
+            let height = Height::expect_from(8u8);
+
+            let dapol_tree = DapolConfigBuilder::default()
+                .accumulator_type(AccumulatorType::NdmSmt)
+                .height(height)
+                .master_secret(master_secret)
+                .salt_b(Salt::from_str("garbage_b").unwrap())
+                .salt_s(Salt::from_str("garbage_s").unwrap())
+                .salts_file_path(salts_file_path)
+                .num_random_entities(10)
+                .build()
+                .unwrap()
+                .parse()
+                .unwrap();
+
+            assert_eq!(*dapol_tree.salt_b(), Salt::from_str("salt_b").unwrap());
+            assert_eq!(*dapol_tree.salt_s(), Salt::from_str("salt_s").unwrap());
+        }
     }
 }
@@ -1,16 +1,25 @@
 use bulletproofs::PedersenGens;
-use curve25519_dalek_ng::{ristretto::RistrettoPoint, scalar::Scalar};
+use curve25519_dalek_ng::{
+    ristretto::{CompressedRistretto, RistrettoPoint},
+    scalar::Scalar,
+};
 use log::{debug, info};
 use primitive_types::H256;
 use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DurationMilliSeconds, TimestampSeconds};
+use std::ops::RangeInclusive;
 use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+use zeroize::{Zeroize, ZeroizeOnDrop};
 
 use crate::{
     accumulators::{Accumulator, AccumulatorType, NdmSmt, NdmSmtError},
+    binary_tree::{Coordinate, FullNodeContent, HiddenNodeContent, Node, TreeBuildError},
     read_write_utils::{self},
     utils::LogOnErr,
-    AggregationFactor, Entity, EntityId, Height, InclusionProof, MaxLiability, MaxThreadCount,
-    Salt, Secret,
+    AggregationFactor, BuildOptions, CompactProofStore, Entity, EntityId, EqualityProof,
+    EqualityProofError, Height, InclusionProof, JointInclusionProof, MaxLiability, MaxThreadCount,
+    MembershipProof, OptimizationTarget, ProofServer, Salt, Secret,
 };
 
 pub const SERIALIZED_TREE_EXTENSION: &str = "dapoltree";
@@ -18,6 +27,14 @@ pub const SERIALIZED_TREE_FILE_PREFIX: &str = "proof_of_liabilities_merkle_sum_t
 
 pub const SERIALIZED_ROOT_PUB_FILE_PREFIX: &str = "public_root_data_";
 pub const SERIALIZED_ROOT_PVT_FILE_PREFIX: &str = "secret_root_data_";
+pub const SERIALIZED_ENTITY_MAPPING_FILE_PREFIX: &str = "entity_mapping_";
+
+/// Version of the serialized [DapolTree] format.
+///
+/// This is written as the first field of a serialized tree, and checked on
+/// deserialization. It must be bumped whenever the layout of [DapolTree] (or
+/// any of the types it contains) changes.
+pub const SERIALIZED_TREE_FORMAT_VERSION: u16 = 5;
 
 // -------------------------------------------------------------------------------------------------
 // Main struct.
@@ -29,15 +46,49 @@ pub const SERIALIZED_ROOT_PVT_FILE_PREFIX: &str = "secret_root_data_";
 /// It is recommended that one use [DapolConfig](crate::DapolConfig) to construct the
 /// tree, which has extra sanity checks on the inputs and more ways to set
 /// the parameters. But there is also a `new` function for direct construction.
-#[derive(Debug, Serialize, Deserialize)]
+///
+/// Cloning the tree is cheap because the node store of the underlying binary
+/// tree is shared between clones.
+///
+/// The master secret is zeroized when the tree is dropped, whether the tree
+/// was built or deserialized. Serialization is not affected since the secret
+/// is only zeroized once the tree is no longer alive.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DapolTree {
+    // This must stay the first field so that it can be read without
+    // deserializing the rest of the tree.
+    format_version: u16,
     accumulator: Accumulator,
     master_secret: Secret,
     salt_s: Salt,
     salt_b: Salt,
     max_liability: MaxLiability,
+    /// Number of updates that have been made to the tree since it was built.
+    epoch: u64,
+    /// Public data of previous roots, oldest first, with the epoch at which
+    /// each was the root. At most `max_root_history` entries are kept.
+    root_history: Vec<(u64, RootPublicData)>,
+    max_root_history: usize,
+    /// Time at which the current root was computed (when the tree was built
+    /// or last updated), in seconds since the Unix epoch.
+    root_generated_at: Option<u64>,
+}
+
+/// Only the master secret is zeroized, the rest of the tree does not contain
+/// key material. The master secret is not checked before it is used, so a
+/// zeroized tree must not be used to generate inclusion proofs or regenerate
+/// nodes: the secret values would be derived from an all-zero master secret,
+/// and the resulting proofs would not verify against the root.
+impl Zeroize for DapolTree {
+    fn zeroize(&mut self) {
+        self.master_secret.zeroize();
+    }
 }
 
+// The master secret field is [ZeroizeOnDrop] so it is zeroized when the tree
+// is dropped.
+impl ZeroizeOnDrop for DapolTree {}
+
 // -------------------------------------------------------------------------------------------------
 // Periphery structs.
 
@@ -47,10 +98,36 @@ pub struct DapolTree {
 /// to legitimize the proof of liabilities. Without doing this there is no
 /// guarantee to the user that their inclusion proof is checked against the same
 /// data as other users' inclusion proofs.
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+///
+/// `generated_at` & `crate_version` are metadata for auditing published roots.
+/// They are not part of the root node, so they are ignored by verification
+/// and by the [PartialEq] implementation. `generated_at` is the time at which
+/// the root was computed, which is recorded on the tree, so the root data of
+/// a tree is the same every time it is requested. Files written before these
+/// fields were added deserialize with `generated_at` as `None` and an empty
+/// `crate_version`.
+///
+/// More metadata may be added in the future, so the struct cannot be
+/// constructed with a literal outside of this crate, see
+/// [RootPublicData::new].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[non_exhaustive]
 pub struct RootPublicData {
     pub hash: H256,
     pub commitment: RistrettoPoint,
+    /// Time at which the root was computed, in seconds since the Unix epoch.
+    #[serde(default)]
+    pub generated_at: Option<u64>,
+    /// Version of this crate that produced the root data.
+    #[serde(default)]
+    pub crate_version: String,
+}
+
+/// Only the root node values are compared, not the metadata.
+impl PartialEq for RootPublicData {
+    fn eq(&self, other: &Self) -> bool {
+        self.hash == other.hash && self.commitment == other.commitment
+    }
 }
 
 /// The secret values of the root node.
@@ -64,6 +141,182 @@ pub struct RootSecretData {
     pub blinding_factor: Scalar,
 }
 
+/// Public summary of a [DapolTree], for appending to an audit trail.
+///
+/// The summary never contains the master secret or any of the entities'
+/// liabilities, only data that is safe to publish.
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AuditSummary {
+    pub accumulator_type: AccumulatorType,
+    pub height: Height,
+    /// Hex encoding of `salt_b`, with `0x` prefix.
+    pub salt_b: String,
+    /// Hex encoding of `salt_s`, with `0x` prefix.
+    pub salt_s: String,
+    pub root_hash: H256,
+    pub root_commitment: RistrettoPoint,
+    pub entity_count: u64,
+    /// Time at which the summary was created, in seconds since the Unix
+    /// epoch.
+    #[serde_as(as = "TimestampSeconds<i64>")]
+    pub timestamp: SystemTime,
+}
+
+/// Metrics gathered during the construction of a [DapolTree].
+///
+/// The report contains no secret information so it can be logged or written
+/// to a json file (e.g. for capturing metrics in CI).
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BuildReport {
+    /// Wall-clock time taken to build the tree, in milliseconds.
+    #[serde_as(as = "DurationMilliSeconds<u64>")]
+    pub build_time: Duration,
+    pub height: Height,
+    pub entity_count: u64,
+    /// Number of nodes kept in the tree's store after construction.
+    pub stored_node_count: u64,
+    /// Number of bottom-layer nodes that are padding nodes, i.e. not mapped
+    /// to an entity.
+    pub padding_leaf_node_count: u64,
+}
+
+/// Public description of a shard of the tree, see [DapolTree::shard_manifest].
+///
+/// A shard is the sub-tree whose bottom-layer leaf nodes have x-coords in
+/// `x_range`. The values contain no secret information, apart from the
+/// number of entities in the shard.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ShardInfo {
+    /// Inclusive range of the bottom-layer x-coords covered by the shard.
+    pub x_range: RangeInclusive<u64>,
+    pub subtree_root_hash: H256,
+    pub subtree_root_commitment: RistrettoPoint,
+    pub entity_count: u64,
+}
+
+/// Aggregate statistics over the liabilities of the entities in a
+/// [DapolTree], see [DapolTree::liability_stats].
+///
+/// These values are computed from the secret leaf node data so they must not
+/// be published. This is only available with the `testing` feature.
+#[cfg(any(test, feature = "testing"))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct LiabilityStats {
+    /// Number of entity leaf nodes in the tree.
+    pub count: u64,
+    pub sum: u64,
+    /// Smallest liability, or 0 if there are no entities.
+    pub min: u64,
+    /// Largest liability, or 0 if there are no entities.
+    pub max: u64,
+    /// Average liability, or 0 if there are no entities.
+    pub mean: f64,
+}
+
+impl RootPublicData {
+    /// Root data for the given root node values, with `crate_version` set to
+    /// the version of this crate and no `generated_at` time.
+    pub fn new(hash: H256, commitment: RistrettoPoint) -> Self {
+        RootPublicData {
+            hash,
+            commitment,
+            generated_at: None,
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+
+    /// Set the time at which the root was computed, in seconds since the
+    /// Unix epoch.
+    pub(crate) fn with_generated_at(mut self, generated_at: Option<u64>) -> Self {
+        self.generated_at = generated_at;
+        self
+    }
+
+    /// Canonical hex encoding of the root hash: `0x` prefix followed by 64
+    /// lower-case hex characters.
+    ///
+    /// This is the format that is logged on tree creation, and that is
+    /// accepted by [RootPublicData::hash_from_hex].
+    pub fn hash_hex(&self) -> String {
+        format!("0x{}", bytes_to_hex(self.hash.as_bytes()))
+    }
+
+    /// Canonical hex encoding of the root commitment: `0x` prefix followed by
+    /// the 32 bytes of the compressed Ristretto point as lower-case hex.
+    ///
+    /// Two equal commitments always have the same encoding, so published
+    /// commitments can be compared as strings.
+    pub fn commitment_hex(&self) -> String {
+        format!("0x{}", bytes_to_hex(self.commitment.compress().as_bytes()))
+    }
+
+    /// Parse a root hash from a hex string.
+    ///
+    /// The `0x` prefix is optional and the hex characters are
+    /// case-insensitive, so the canonical encoding given by
+    /// [RootPublicData::hash_hex] is accepted. The string must contain exactly
+    /// 64 hex characters (32 bytes).
+    pub fn hash_from_hex(hex: &str) -> Result<H256, RootHashParserError> {
+        let digits = hex
+            .strip_prefix("0x")
+            .or_else(|| hex.strip_prefix("0X"))
+            .unwrap_or(hex);
+
+        if digits.len() != 2 * H256::len_bytes() {
+            return Err(RootHashParserError::InvalidLength {
+                found: digits.len(),
+                expected: 2 * H256::len_bytes(),
+            });
+        }
+
+        let mut bytes = [0u8; 32];
+        for (i, c) in digits.chars().enumerate() {
+            let nibble = c
+                .to_digit(16)
+                .ok_or(RootHashParserError::InvalidCharacter {
+                    character: c,
+                    index: i,
+                })? as u8;
+            bytes[i / 2] |= nibble << (4 * (1 - i % 2));
+        }
+
+        Ok(H256(bytes))
+    }
+
+    /// Construct the root data from the compact encoding used on-chain: the
+    /// 32-byte root hash and the 32-byte compressed Ristretto commitment.
+    ///
+    /// An error is returned if `commitment` is not the encoding of a valid
+    /// Ristretto point. The metadata is not part of the on-chain encoding, so
+    /// it is left empty.
+    pub fn from_onchain_bytes(
+        hash: [u8; 32],
+        commitment: [u8; 32],
+    ) -> Result<RootPublicData, DapolTreeError> {
+        let commitment = CompressedRistretto(commitment)
+            .decompress()
+            .ok_or(DapolTreeError::InvalidRootCommitment)?;
+
+        Ok(RootPublicData {
+            hash: H256(hash),
+            commitment,
+            generated_at: None,
+            crate_version: String::new(),
+        })
+    }
+
+    /// Compact encoding of the root data for publishing on-chain, see
+    /// [RootPublicData::from_onchain_bytes].
+    ///
+    /// Returns the root hash bytes and the compressed commitment bytes, in
+    /// that order.
+    pub fn to_onchain_bytes(&self) -> ([u8; 32], [u8; 32]) {
+        (self.hash.0, self.commitment.compress().to_bytes())
+    }
+}
+
 // -------------------------------------------------------------------------------------------------
 // Construction & proof generation.
 
@@ -117,6 +370,8 @@ impl DapolTree {
     /// let entity = Entity {
     ///     liability: 1u64,
     ///     id: EntityId::from_str("id").unwrap(),
+    ///     salt: None,
+    ///     blinding_factor: None,
     /// };
     /// let entities = vec![entity];
     ///
@@ -143,6 +398,82 @@ impl DapolTree {
         height: Height,
         entities: Vec<Entity>,
     ) -> Result<Self, DapolTreeError> {
+        DapolTree::new_with_options(
+            accumulator_type,
+            master_secret,
+            salt_b,
+            salt_s,
+            height,
+            entities,
+            BuildOptions::default()
+                .with_max_liability(max_liability)
+                .with_max_thread_count(max_thread_count),
+        )
+    }
+
+    /// Construct a new tree with the optional build parameters given by
+    /// `options`, see [BuildOptions].
+    ///
+    /// The rest of the parameters are the same as for [DapolTree::new], except
+    /// for the max liability & max thread count which are set in `options`. All
+    /// the options that are set are applied together, for example a tree can
+    /// be built with a domain separator using the single-threaded algorithm,
+    /// and still be cancelled using a cancellation token.
+    ///
+    /// If the cancellation token in `options` is cancelled before the build is
+    /// done then all the threads used for the build are joined, the partially
+    /// built tree is dropped, and [DapolTreeError::BuildCancelled] is
+    /// returned.
+    ///
+    /// Example:
+    /// ```
+    /// use std::str::FromStr;
+    /// use dapol::{
+    ///     AccumulatorType, BuildOptions, BuildStrategy, DapolTree, Entity, EntityId,
+    ///     Height, MaxLiability, Salt, Secret,
+    /// };
+    ///
+    /// let entities = vec![Entity {
+    ///     liability: 1u64,
+    ///     id: EntityId::from_str("id").unwrap(),
+    ///     salt: None,
+    ///     blinding_factor: None,
+    /// }];
+    ///
+    /// let options = BuildOptions::default()
+    ///     .with_max_liability(MaxLiability::from(10_000_000))
+    ///     .with_build_strategy(BuildStrategy::SingleThreaded)
+    ///     .with_domain_separator("deployment 1".to_string());
+    ///
+    /// let dapol_tree = DapolTree::new_with_options(
+    ///     AccumulatorType::NdmSmt,
+    ///     Secret::from_str("master_secret").unwrap(),
+    ///     Salt::from_str("salt_b").unwrap(),
+    ///     Salt::from_str("salt_s").unwrap(),
+    ///     Height::expect_from(8),
+    ///     entities,
+    ///     options,
+    /// ).unwrap();
+    /// ```
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "DapolTree::new_with_options",
+            skip_all,
+            fields(%accumulator_type, height = height.as_u32(), num_entities = entities.len())
+        )
+    )]
+    pub fn new_with_options(
+        accumulator_type: AccumulatorType,
+        master_secret: Secret,
+        salt_b: Salt,
+        salt_s: Salt,
+        height: Height,
+        entities: Vec<Entity>,
+        options: BuildOptions,
+    ) -> Result<Self, DapolTreeError> {
+        let max_liability = options.max_liability;
+
         let accumulator = match accumulator_type {
             AccumulatorType::NdmSmt => {
                 let ndm_smt = NdmSmt::new(
@@ -150,19 +481,30 @@ impl DapolTree {
                     salt_b.clone(),
                     salt_s.clone(),
                     height,
-                    max_thread_count,
                     entities,
-                )?;
+                    options,
+                )
+                .map_err(|err| match err {
+                    NdmSmtError::TreeError(TreeBuildError::Cancelled) => {
+                        DapolTreeError::BuildCancelled
+                    }
+                    err => err.into(),
+                })?;
                 Accumulator::NdmSmt(ndm_smt)
             }
         };
 
         let tree = DapolTree {
+            format_version: SERIALIZED_TREE_FORMAT_VERSION,
             accumulator,
             master_secret,
             salt_b: salt_b.clone(),
             salt_s: salt_s.clone(),
             max_liability,
+            epoch: 0,
+            root_history: Vec::new(),
+            max_root_history: 0,
+            root_generated_at: unix_time_now(),
         };
 
         tree.log_successful_tree_creation();
@@ -170,76 +512,38 @@ impl DapolTree {
         Ok(tree)
     }
 
-    /// Constructor for testing purposes.
+    /// Construct a new tree, also returning a [BuildReport] containing
+    /// metrics gathered during construction.
     ///
-    /// Note: This is **not** cryptographically secure and should only be used
-    /// for testing.
+    /// The parameters are the same as for [DapolTree::new_with_options].
     ///
     /// An error is returned if the underlying accumulator type construction
     /// fails.
-    ///
-    /// - `accumulator_type`: This value must be set.
-    #[doc = include_str!("./shared_docs/accumulator_type.md")]
-    /// - `master_secret`: This value is known only to the tree generator, and
-    ///   is used to determine all other secret values needed in the tree. This
-    ///   value must be set.
-    /// - `salt_b`: If not set then it will be randomly generated.
-    #[doc = include_str!("./shared_docs/salt_b.md")]
-    /// - `salt_s`: If not set then it will be randomly generated.
-    #[doc = include_str!("./shared_docs/salt_s.md")]
-    /// - `max_liability`: If not set then a default value is used.
-    #[doc = include_str!("./shared_docs/max_liability.md")]
-    /// - `height`: If not set the [default height] will be used
-    ///   [crate][Height].
-    #[doc = include_str!("./shared_docs/height.md")]
-    /// - `max_thread_count`: If not set the max parallelism of the underlying
-    ///   machine will be used.
-    #[doc = include_str!("./shared_docs/max_thread_count.md")]
-    /// - `secrets_file_path`: Path to the secrets file. If not present the
-    ///   secrets will be generated randomly.
-    /// - `entities`:
-    #[doc = include_str!("./shared_docs/entities_vector.md")]
-    /// - `seed`: random seed for any PRNG used.
-    ///
-    /// [default height]: crate::Height::default
-    #[cfg(any(test, feature = "testing"))]
-    pub fn new_with_random_seed(
+    pub fn new_with_report(
         accumulator_type: AccumulatorType,
         master_secret: Secret,
         salt_b: Salt,
         salt_s: Salt,
-        max_liability: MaxLiability,
-        max_thread_count: MaxThreadCount,
         height: Height,
         entities: Vec<Entity>,
-        seed: u64,
-    ) -> Result<Self, DapolTreeError> {
-        let accumulator = match accumulator_type {
-            AccumulatorType::NdmSmt => {
-                let ndm_smt = NdmSmt::new_with_random_seed(
-                    master_secret.clone(),
-                    salt_b.clone(),
-                    salt_s.clone(),
-                    height,
-                    max_thread_count,
-                    entities,
-                    seed,
-                )?;
-                Accumulator::NdmSmt(ndm_smt)
-            }
-        };
+        options: BuildOptions,
+    ) -> Result<(Self, BuildReport), DapolTreeError> {
+        let entity_count = entities.len() as u64;
+        let start = Instant::now();
 
-        let tree = DapolTree {
-            accumulator,
+        let tree = DapolTree::new_with_options(
+            accumulator_type,
             master_secret,
-            salt_b: salt_b.clone(),
-            salt_s: salt_s.clone(),
-            max_liability,
-        };
+            salt_b,
+            salt_s,
+            height,
+            entities,
+            options,
+        )?;
 
-        tree.log_successful_tree_creation();
+        let report = tree.build_report(start.elapsed(), entity_count);
 
-        Ok(tree)
+        Ok((tree, report))
     }
 
     /// Generate an inclusion proof for the given `entity_id`.
@@ -287,613 +591,4361 @@ impl DapolTree {
         }
     }
 
-    /// Check that the public Pedersen commitment corresponds to the secret
-    /// values of the root.
+    /// Generate an inclusion proof for the bottom-layer leaf node at
+    /// `x_coord`, using the default aggregation factor.
     ///
-    /// If the secret data does not match the commitment then false is returned,
-    /// otherwise true.
-    pub fn verify_root_commitment(
-        public_commitment: &RistrettoPoint,
-        secret_root_data: &RootSecretData,
-    ) -> Result<(), DapolTreeError> {
-        let commitment = PedersenGens::default().commit(
-            Scalar::from(secret_root_data.liability),
-            secret_root_data.blinding_factor,
-        );
-
-        if commitment == *public_commitment {
-            Ok(())
-        } else {
-            Err(DapolTreeError::RootVerificationError)
+    /// The node can either belong to an entity or be a padding node, so this
+    /// can be used to spot-check specific positions in the tree without
+    /// knowing the entity IDs. It complements
+    /// [DapolTree::generate_inclusion_proof], which finds the x-coord using
+    /// the entity mapping.
+    ///
+    /// An error is returned if `x_coord` is not less than the number of nodes
+    /// in the bottom layer (see [Height::max_bottom_layer_nodes]).
+    pub fn generate_inclusion_proof_by_coord(
+        &self,
+        x_coord: u64,
+    ) -> Result<InclusionProof, NdmSmtError> {
+        match &self.accumulator {
+            Accumulator::NdmSmt(ndm_smt) => ndm_smt.generate_inclusion_proof_by_coord(
+                &self.master_secret,
+                &self.salt_b,
+                &self.salt_s,
+                x_coord,
+                AggregationFactor::default(),
+                self.max_liability.as_range_proof_upper_bound_bit_length(),
+            ),
         }
     }
-}
 
-// -------------------------------------------------------------------------------------------------
-// Accessor methods.
-
-impl DapolTree {
-    #[doc = include_str!("./shared_docs/accumulator_type.md")]
-    pub fn accumulator_type(&self) -> AccumulatorType {
-        self.accumulator.get_type()
+    /// Lazily generate inclusion proofs for the given entity IDs, in order.
+    ///
+    /// Each proof is only generated when the iterator is advanced, using
+    /// [DapolTree::generate_inclusion_proof]. This means the caller can write
+    /// each proof to disk and drop it before the next one is generated, so
+    /// that the proofs for millions of entities never have to be held in
+    /// memory at the same time. A failed proof does not stop the iteration.
+    pub fn inclusion_proofs_iter<'a>(
+        &'a self,
+        entity_ids: &'a [EntityId],
+    ) -> impl Iterator<Item = (EntityId, Result<InclusionProof, NdmSmtError>)> + 'a {
+        entity_ids
+            .iter()
+            .map(|entity_id| (entity_id.clone(), self.generate_inclusion_proof(entity_id)))
     }
 
-    #[doc = include_str!("./shared_docs/master_secret.md")]
-    pub fn master_secret(&self) -> &Secret {
-        &self.master_secret
+    /// Generate an inclusion proof for the given `entity_id`, with both the
+    /// aggregation factor and the range proof upper bound set explicitly.
+    ///
+    /// An existing proof cannot be converted to a different aggregation factor
+    /// because that requires the secret liabilities & blinding factors of
+    /// the nodes in the path, which the proof does not contain. Instead the
+    /// proof is regenerated from the tree using this function.
+    ///
+    /// Parameters:
+    /// - `entity_id`: unique ID for the entity that the proof will be generated
+    ///   for.
+    /// - `aggregation_factor`:
+    #[doc = include_str!("./shared_docs/aggregation_factor.md")]
+    /// - `upper_bound_bit_length`:
+    #[doc = include_str!("./shared_docs/upper_bound_bit_length.md")]
+    pub fn regenerate_proof_with(
+        &self,
+        entity_id: &EntityId,
+        aggregation_factor: AggregationFactor,
+        upper_bound_bit_length: u8,
+    ) -> Result<InclusionProof, NdmSmtError> {
+        match &self.accumulator {
+            Accumulator::NdmSmt(ndm_smt) => ndm_smt.generate_inclusion_proof(
+                &self.master_secret,
+                &self.salt_b,
+                &self.salt_s,
+                entity_id,
+                aggregation_factor,
+                upper_bound_bit_length,
+            ),
+        }
     }
 
-    #[doc = include_str!("./shared_docs/salt_b.md")]
-    pub fn salt_b(&self) -> &Salt {
-        &self.salt_b
+    /// Generate inclusion proofs for the given `entity_id`, one for each of
+    /// the given aggregation factors.
+    ///
+    /// This is useful for picking the aggregation factor that gives the best
+    /// trade-off between proof size and verification time (see
+    /// [InclusionProof::serialized_size]). The path in the tree is only built
+    /// once, so this is cheaper than generating each proof separately. The
+    /// proofs are returned in the same order as `aggregation_factors`.
+    pub fn generate_inclusion_proofs_for_aggregation_factors(
+        &self,
+        entity_id: &EntityId,
+        aggregation_factors: &[AggregationFactor],
+    ) -> Result<Vec<InclusionProof>, NdmSmtError> {
+        match &self.accumulator {
+            Accumulator::NdmSmt(ndm_smt) => ndm_smt
+                .generate_inclusion_proofs_for_aggregation_factors(
+                    &self.master_secret,
+                    &self.salt_b,
+                    &self.salt_s,
+                    entity_id,
+                    aggregation_factors,
+                    self.max_liability.as_range_proof_upper_bound_bit_length(),
+                ),
+        }
     }
 
-    #[doc = include_str!("./shared_docs/salt_s.md")]
-    pub fn salt_s(&self) -> &Salt {
-        &self.salt_s
-    }
+    /// Recommend an aggregation factor by generating sample proofs for
+    /// `sample_entity` and measuring them.
+    ///
+    /// A proof is generated for each of a few candidate aggregation factors
+    /// (0%, 25%, 50%, 75% & 100% of the path aggregated). The generation time
+    /// and the size of the binary serialization of each proof are measured,
+    /// and the candidate that best matches `target` is returned.
+    ///
+    /// Note that this does real work: 5 inclusion proofs are generated, so
+    /// expect this to take about 5 times as long as
+    /// [DapolTree::generate_inclusion_proof]. The timings are taken on this
+    /// machine for a single proof, so the result for
+    /// [OptimizationTarget::MinGenerationTime] and
+    /// [OptimizationTarget::Balanced] can vary between calls.
+    ///
+    /// An error is returned if a proof cannot be generated for
+    /// `sample_entity`, e.g. if it is not in the tree.
+    pub fn recommend_aggregation_factor(
+        &self,
+        sample_entity: &EntityId,
+        target: OptimizationTarget,
+    ) -> Result<AggregationFactor, NdmSmtError> {
+        use crate::{percentage::Percentage, InclusionProofFileType};
 
-    #[doc = include_str!("./shared_docs/max_liability.md")]
-    pub fn max_liability(&self) -> &MaxLiability {
-        &self.max_liability
-    }
+        let candidates: Vec<AggregationFactor> = [0, 25, 50, 75, 100]
+            .into_iter()
+            .map(|percent| AggregationFactor::Percent(Percentage::expect_from(percent)))
+            .collect();
 
-    #[doc = include_str!("./shared_docs/height.md")]
-    pub fn height(&self) -> &Height {
-        self.accumulator.height()
+        let mut measurements = Vec::with_capacity(candidates.len());
+        for aggregation_factor in candidates.iter() {
+            let start = Instant::now();
+            let proof =
+                self.generate_inclusion_proof_with(sample_entity, aggregation_factor.clone())?;
+            let generation_time = start.elapsed();
+            let size = proof.serialized_size(InclusionProofFileType::Binary)?;
+
+            debug!(
+                "Sample proof with aggregation factor {} took {:?} to generate and is {} bytes",
+                aggregation_factor, generation_time, size
+            );
+
+            measurements.push((generation_time, size));
+        }
+
+        let best = target
+            .select(&measurements)
+            .expect("[Bug in recommend_aggregation_factor] there should be at least 1 candidate");
+
+        Ok(candidates[best].clone())
     }
 
-    /// Mapping of [EntityId](crate::EntityId) to x-coord on the bottom layer of the tree.
+    /// Generate a proof that the 2 given entities are in the tree.
     ///
-    /// If the underlying accumulator is an NDM-SMT then a hashmap is returned
-    /// otherwise None is returned.
-    pub fn entity_mapping(&self) -> Option<&std::collections::HashMap<EntityId, u64>> {
+    /// The 2 paths share the nodes from the entities' lowest common ancestor
+    /// up to the root, so the proof is more compact than 2 independent
+    /// inclusion proofs when the entities are near each other in the tree.
+    /// The proof only contains the Merkle paths, not range proofs, and it
+    /// reveals the liabilities of both entities.
+    ///
+    /// An error is returned if either entity is not in the tree, or if the 2
+    /// entity IDs are the same.
+    pub fn generate_joint_inclusion_proof(
+        &self,
+        entity_id_a: &EntityId,
+        entity_id_b: &EntityId,
+    ) -> Result<JointInclusionProof, NdmSmtError> {
         match &self.accumulator {
-            Accumulator::NdmSmt(ndm_smt) => Some(ndm_smt.entity_mapping()),
-            _ => None,
+            Accumulator::NdmSmt(ndm_smt) => ndm_smt.generate_joint_inclusion_proof(
+                &self.master_secret,
+                &self.salt_b,
+                &self.salt_s,
+                entity_id_a,
+                entity_id_b,
+            ),
         }
     }
 
-    /// Hash & Pedersen commitment for the root node of the Merkle Sum Tree.
+    /// Generate a proof that the given entity is in the tree, without any
+    /// range proofs.
     ///
-    /// These values can be made public and do not disclose secret information
-    /// about the tree such as the number of leaf nodes or their liabilities.
-    pub fn public_root_data(&self) -> RootPublicData {
-        RootPublicData {
-            hash: self.root_hash().clone(),
-            commitment: self.root_commitment().clone(),
+    /// This is much faster to generate than [DapolTree::generate_inclusion_proof]
+    /// and the proof is smaller, but it provides NO guarantee that the
+    /// liabilities in the tree are within range, only that the entity's leaf
+    /// node is in the tree. It should only be used when liability bounds are
+    /// not required. The proof does not reveal the entity's liability.
+    ///
+    /// An error is returned if the entity is not in the tree.
+    pub fn generate_membership_proof(
+        &self,
+        entity_id: &EntityId,
+    ) -> Result<MembershipProof, NdmSmtError> {
+        match &self.accumulator {
+            Accumulator::NdmSmt(ndm_smt) => ndm_smt.generate_membership_proof(
+                &self.master_secret,
+                &self.salt_b,
+                &self.salt_s,
+                entity_id,
+            ),
         }
     }
 
-    /// Liability & blinding factor that make up the Pederesen commitment of
-    /// the Merkle Sum Tree.
+    /// Generate inclusion proofs for all the entities that are mapped to a
+    /// bottom-layer leaf node in the sub-tree rooted at `coord`.
     ///
-    /// Neither of these values should be made public if the owner of the tree
-    /// does not want to disclose the total liability sum of their users.
-    pub fn secret_root_data(&self) -> RootSecretData {
-        RootSecretData {
-            liability: self.root_liability(),
-            blinding_factor: self.root_blinding_factor().clone(),
+    /// This can be used to partition an audit between verifiers, each
+    /// verifier getting the proofs for a different sub-tree. The proofs are
+    /// returned in order of the entities' leaf node x-coords, and use the
+    /// default [AggregationFactor]. If the sub-tree contains only padding
+    /// nodes then the returned vector is empty.
+    ///
+    /// An error is returned if the coordinate is outside the bounds of the
+    /// tree, or if any of the proofs fail to be generated.
+    pub fn generate_inclusion_proofs_for_subtree(
+        &self,
+        coord: Coordinate,
+    ) -> Result<Vec<(EntityId, InclusionProof)>, DapolTreeError> {
+        if !coord.is_within(self.height()) {
+            return Err(DapolTreeError::CoordinateOutOfBounds {
+                coord,
+                height: *self.height(),
+            });
         }
-    }
 
-    #[doc = include_str!("./shared_docs/root_hash.md")]
-    pub fn root_hash(&self) -> &H256 {
-        self.accumulator.root_hash()
-    }
+        let (x_coord_min, x_coord_max) = coord.subtree_x_coord_bounds();
 
-    #[doc = include_str!("./shared_docs/root_commitment.md")]
-    pub fn root_commitment(&self) -> &RistrettoPoint {
-        self.accumulator.root_commitment()
-    }
+        let mut entities: Vec<(&EntityId, u64)> = self
+            .entity_mapping()
+            .map(|mapping| {
+                mapping
+                    .iter()
+                    .filter(|(_, x)| (x_coord_min..=x_coord_max).contains(*x))
+                    .map(|(id, x)| (id, *x))
+                    .collect()
+            })
+            .unwrap_or_default();
 
-    #[doc = include_str!("./shared_docs/root_liability.md")]
-    pub fn root_liability(&self) -> u64 {
-        self.accumulator.root_liability()
-    }
+        entities.sort_by_key(|(_, x)| *x);
 
-    #[doc = include_str!("./shared_docs/root_blinding_factor.md")]
-    pub fn root_blinding_factor(&self) -> &Scalar {
-        self.accumulator.root_blinding_factor()
+        entities
+            .into_iter()
+            .map(|(id, _)| {
+                let proof = self.generate_inclusion_proof(id)?;
+                Ok((id.clone(), proof))
+            })
+            .collect()
     }
-}
 
-// -------------------------------------------------------------------------------------------------
-// Serialization & deserialization.
+    /// Convert the tree into a [ProofServer], which can generate inclusion
+    /// proofs without the master secret & salts.
+    ///
+    /// The tree is rebuilt with all nodes in the store
+    /// (`store_depth == height`) so that no nodes ever need to be regenerated,
+    /// and the secrets are then dropped. Rebuilding takes about as long as the
+    /// original build, and a full store can use a lot of memory for large
+    /// trees.
+    ///
+    /// An error is returned if the rebuild fails.
+    pub fn into_proof_server(self) -> Result<ProofServer, DapolTreeError> {
+        let accumulator = match self.accumulator {
+            Accumulator::NdmSmt(ndm_smt) => Accumulator::NdmSmt(ndm_smt.with_full_store(
+                &self.master_secret,
+                &self.salt_b,
+                &self.salt_s,
+                MaxThreadCount::default(),
+            )?),
+        };
 
-impl DapolTree {
-    fn log_successful_tree_creation(&self) {
-        info!(
-            "\nDAPOL tree has been constructed. Public data:\n \
-             - accumulator type: {}\n \
-             - height: {}\n \
-             - salt_b: 0x{}\n \
-             - salt_s: 0x{}\n \
-             - root hash: 0x{}\n \
-             - root commitment: {:?}",
-            self.accumulator_type(),
-            self.height().as_u32(),
-            self.salt_b
-                .as_bytes()
-                .iter()
-                .map(|b| format!("{:02x}", b))
-                .collect::<String>(),
-            self.salt_s
-                .as_bytes()
-                .iter()
-                .map(|b| format!("{:02x}", b))
-                .collect::<String>(),
-            self.root_hash()
-                .as_bytes()
-                .iter()
-                .map(|b| format!("{:02x}", b))
-                .collect::<String>(),
-            self.root_commitment().compress()
-        );
+        Ok(ProofServer::new(
+            accumulator,
+            self.max_liability,
+            self.root_generated_at,
+        ))
     }
 
-    /// Parse `path` as one that points to a serialized dapol tree file.
-    ///
-    /// `path` can be either of the following:
-    /// 1. Existing directory: in this case a default file name is appended to
-    /// `path`. 2. Non-existing directory: in this case all dirs in the path
-    /// are created, and a default file name is appended.
-    /// 3. File in existing dir: in this case the extension is checked to be
-    /// [SERIALIZED_TREE_EXTENSION], then `path` is returned.
-    /// 4. File in non-existing dir: dirs in the path are created and the file
-    /// extension is checked.
+    /// Copy the parts of the tree that are needed for inclusion proof
+    /// generation into a [CompactProofStore].
     ///
-    /// The file prefix is [SERIALIZED_TREE_FILE_PREFIX].
-    pub fn parse_tree_serialization_path(
-        path: PathBuf,
-    ) -> Result<PathBuf, read_write_utils::ReadWriteError> {
-        read_write_utils::parse_serialization_path(
-            path,
-            SERIALIZED_TREE_EXTENSION,
-            SERIALIZED_TREE_FILE_PREFIX,
+    /// The padding nodes are left out of the store (they are regenerated when
+    /// needed), and the update epoch & root history are dropped. Proofs
+    /// generated by the returned store verify against the root hash of this
+    /// tree.
+    pub fn to_compact_proof_store(&self) -> CompactProofStore {
+        CompactProofStore::new(
+            &self.accumulator,
+            self.master_secret.clone(),
+            self.salt_b.clone(),
+            self.salt_s.clone(),
+            self.max_liability,
         )
     }
 
-    /// Parse `path` as one that points to a json file containing the public
-    /// data of the root node.
+    /// Return all the nodes of the tree with their full content, sorted by `y`
+    /// and then `x`.
     ///
-    /// `path` can be either of the following:
-    /// 1. Existing directory: in this case a default file name is appended to
-    /// `path`. 2. Non-existing directory: in this case all dirs in the path
-    /// are created, and a default file name is appended.
-    /// 3. File in existing dir: in this case the extension is checked to be
-    /// ".json", then `path` is returned.
-    /// 4. File in non-existing dir: dirs in the path are created and the file
-    /// extension is checked.
+    /// **DANGER**: the returned nodes contain every entity's liability and
+    /// every node's blinding factor. Anyone with this data can learn the total
+    /// liability sum and the liabilities of individual entities, which breaks
+    /// the privacy guarantees of the protocol. This is only available with
+    /// the `testing` feature and is meant for offline analysis and test
+    /// assertions (e.g. checking that each parent's liability is the sum of
+    /// its children's).
     ///
-    /// The file prefix is [SERIALIZED_ROOT_PUB_FILE_PREFIX].
-    pub fn parse_public_root_data_serialization_path(
-        path: PathBuf,
-    ) -> Result<PathBuf, read_write_utils::ReadWriteError> {
-        read_write_utils::parse_serialization_path(path, "json", SERIALIZED_ROOT_PUB_FILE_PREFIX)
+    /// The tree is rebuilt with all nodes in the store
+    /// (`store_depth == height`) in order to get the nodes, so this takes
+    /// about as long as the original build. The nodes returned are all the
+    /// nodes that have at least 1 entity in their sub-tree, plus their
+    /// siblings. Padding nodes are generated from their coordinate rather than
+    /// from their children, so the children of padding nodes are not
+    /// included.
+    ///
+    /// An error is returned if the rebuild fails.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn dump_full_tree(&self) -> Result<Vec<Node<FullNodeContent>>, DapolTreeError> {
+        let nodes = match &self.accumulator {
+            Accumulator::NdmSmt(ndm_smt) => ndm_smt.full_nodes(
+                &self.master_secret,
+                &self.salt_b,
+                &self.salt_s,
+                MaxThreadCount::default(),
+            )?,
+        };
+
+        Ok(nodes)
     }
 
-    /// Parse `path` as one that points to a json file containing the secret
-    /// data of the root node.
+    /// Compute [LiabilityStats] over the liabilities in the entities' leaf
+    /// nodes.
     ///
-    /// `path` can be either of the following:
-    /// 1. Existing directory: in this case a default file name is appended to
-    /// `path`. 2. Non-existing directory: in this case all dirs in the path
-    /// are created, and a default file name is appended.
-    /// 3. File in existing dir: in this case the extension is checked to be
-    /// ".json", then `path` is returned.
-    /// 4. File in non-existing dir: dirs in the path are created and the file
-    /// extension is checked.
+    /// Padding nodes are not included. This is useful for checking in tests
+    /// that entity ingestion preserved the liabilities, and that the root
+    /// liability is the expected total (`sum`). The stats reveal information
+    /// about the secret liabilities, so this is only available with the
+    /// `testing` feature.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn liability_stats(&self) -> LiabilityStats {
+        let liabilities: Vec<u64> = match &self.accumulator {
+            Accumulator::NdmSmt(ndm_smt) => ndm_smt.entity_liabilities().collect(),
+        };
+
+        let count = liabilities.len() as u64;
+        let sum: u64 = liabilities.iter().sum();
+        let mean = if count == 0 {
+            0.0
+        } else {
+            sum as f64 / count as f64
+        };
+
+        LiabilityStats {
+            count,
+            sum,
+            min: liabilities.iter().copied().min().unwrap_or(0),
+            max: liabilities.iter().copied().max().unwrap_or(0),
+            mean,
+        }
+    }
+
+    /// Return the node at the given coordinate.
     ///
-    /// The file prefix is [SERIALIZED_ROOT_PVT_FILE_PREFIX].
-    pub fn parse_secret_root_data_serialization_path(
-        path: PathBuf,
-    ) -> Result<PathBuf, read_write_utils::ReadWriteError> {
-        read_write_utils::parse_serialization_path(path, "json", SERIALIZED_ROOT_PVT_FILE_PREFIX)
+    /// If the node is not in the tree's store then it is regenerated from the
+    /// bottom-layer leaf nodes of its sub-tree. Only the hash & commitment of
+    /// the node are returned, so that the liabilities of the entities in the
+    /// sub-tree are not leaked.
+    ///
+    /// An error is returned if the coordinate is outside the bounds of the
+    /// tree, or if the tree was built with a custom padding function that has
+    /// not been set again after deserialization (see
+    /// [DapolTree::set_padding_fn]).
+    pub fn node_at(&self, coord: Coordinate) -> Result<Node<HiddenNodeContent>, DapolTreeError> {
+        let node = match &self.accumulator {
+            Accumulator::NdmSmt(ndm_smt) => {
+                ndm_smt.node_at(&self.master_secret, &self.salt_b, &self.salt_s, &coord)?
+            }
+        };
+
+        node.ok_or(DapolTreeError::CoordinateOutOfBounds {
+            coord,
+            height: *self.height(),
+        })
     }
 
-    /// Serialize the whole tree to a file.
+    /// Return the bottom-layer leaf node of the given entity.
     ///
-    /// Serialization is done using [bincode].
+    /// Only the hash & commitment of the leaf are returned, so that the
+    /// entity's liability is not leaked. `None` is returned if the entity is
+    /// not in the tree.
+    pub fn leaf_node_for(&self, entity_id: &EntityId) -> Option<Node<HiddenNodeContent>> {
+        match &self.accumulator {
+            Accumulator::NdmSmt(ndm_smt) => ndm_smt.leaf_node_for(entity_id),
+        }
+    }
+
+    /// Number of nodes in the tree's store.
     ///
-    /// An error is returned if
-    /// 1. [bincode] fails to serialize the file.
-    /// 2. There is an issue opening or writing the file.
+    /// This is the same as the length of [DapolTree::stored_coordinates], but
+    /// does not allocate. It is independent of the store type used by the
+    /// tree builder.
+    pub fn stored_node_count(&self) -> usize {
+        self.accumulator.stored_node_count()
+    }
+
+    /// Coordinates of the nodes in the tree's store, sorted by `y` and then
+    /// `x`.
     ///
-    /// `path` can be either of the following:
-    /// 1. Existing directory: in this case a default file name is appended to
-    /// `path`. 2. Non-existing directory: in this case all dirs in the path
-    /// are created, and a default file name is appended.
-    /// 3. File in existing dir: in this case the extension is checked to be
-    /// [SERIALIZED_TREE_EXTENSION], then `path` is returned.
-    /// 4. File in non-existing dir: dirs in the path are created and the file
-    /// extension is checked.
+    /// All the non-padding bottom-layer leaf nodes are in the store, as well
+    /// as the nodes that the builder decided to store (which depends on the
+    /// store depth). Any node not in the store has to be regenerated when it
+    /// is needed for an inclusion proof, so this is useful for understanding
+    /// the cost of proof generation.
+    pub fn stored_coordinates(&self) -> Vec<Coordinate> {
+        self.accumulator.stored_coordinates()
+    }
+
+    /// Number of nodes in the tree's store for each layer, as `(y, count)`
+    /// pairs ordered from the bottom layer (`y == 0`) up to the root.
     ///
-    /// The file prefix is [SERIALIZED_TREE_FILE_PREFIX].
-    pub fn serialize(&self, path: PathBuf) -> Result<PathBuf, DapolTreeError> {
-        let path = DapolTree::parse_tree_serialization_path(path)?;
+    /// Every layer is included, even if none of its nodes are stored. With
+    /// the default store depth the top layers are fully stored, the middle
+    /// layers are empty and the bottom layer holds the leaf nodes, so this is
+    /// useful for seeing the effect of the store depth on memory usage.
+    pub fn nodes_per_level(&self) -> Vec<(u8, usize)> {
+        let mut counts: Vec<(u8, usize)> =
+            (0..=self.height().as_y_coord()).map(|y| (y, 0)).collect();
 
-        info!(
-            "Serializing accumulator to file {:?}",
-            path.clone().into_os_string()
-        );
+        for coord in self.stored_coordinates() {
+            counts[coord.y as usize].1 += 1;
+        }
 
-        read_write_utils::serialize_to_bin_file(&self, path.clone()).log_on_err()?;
+        counts
+    }
 
-        Ok(path)
+    /// Sorted x-coords of the bottom-layer leaf nodes that belong to an
+    /// entity (i.e. the non-padding leaf nodes).
+    ///
+    /// This is a sparse version of the bottom-layer occupancy bitmap; a dense
+    /// bitmap has `2^(height-1)` bits, which is too big for large trees. It
+    /// can be used to check how uniformly the entities are spread across the
+    /// bottom layer.
+    pub fn occupied_leaf_x_coords(&self) -> Vec<u64> {
+        let mut x_coords: Vec<u64> = self
+            .entity_mapping()
+            .map(|mapping| mapping.values().copied().collect())
+            .unwrap_or_default();
+        x_coords.sort_unstable();
+        x_coords
     }
 
-    /// Serialize the public root node data to a file.
+    /// Upper bound on the number of nodes that would be stored when building
+    /// a tree with the given parameters.
     ///
-    /// The data that will be serialized to a json file:
-    /// - Pedersen commitment
-    /// - hash
+    /// This can be used for capacity planning, e.g. to estimate memory usage
+    /// before building a tree. Nodes in layers below the store depth are not
+    /// stored (apart from the bottom layer), so lowering the store depth
+    /// reduces memory usage at the cost of having to regenerate nodes when
+    /// generating inclusion proofs. See
+    /// [expected_store_size][crate::binary_tree::expected_store_size] for how
+    /// the bound is computed, and when it is reached.
+    pub fn expected_store_size(height: Height, num_entities: u64, store_depth: u8) -> u64 {
+        crate::binary_tree::expected_store_size(&height, num_entities, store_depth)
+    }
+
+    /// Graphviz DOT representation of the nodes in the tree's store.
     ///
-    /// An error is returned if
-    /// 1. [serde_json] fails to serialize the file.
-    /// 2. There is an issue opening or writing to the file.
+    /// Each node is labeled with its coordinate and the first 4 bytes of its
+    /// hash. Nodes with at least 1 entity in their sub-tree are drawn with a
+    /// solid outline (bottom-layer entity leaf nodes are filled), and padding
+    /// nodes are drawn with a dashed outline. Edges are drawn between stored
+    /// parent & child nodes.
     ///
-    /// `path` can be either of the following:
-    /// 1. Existing directory: in this case a default file name is appended to
-    /// `path`. 2. Non-existing directory: in this case all dirs in the path
-    /// are created, and a default file name is appended.
-    /// 3. File in existing dir: in this case the extension is checked to be
-    /// ".json", then `path` is returned.
-    /// 4. File in non-existing dir: dirs in the path are created and the file
-    /// extension is checked.
+    /// At most `max_nodes` nodes are included, starting from the root and
+    /// going down the tree layer by layer. This is only practical for small
+    /// trees (e.g. height < 10) since larger trees have too many nodes to be
+    /// visualized in a useful way.
     ///
-    /// The file prefix is [SERIALIZED_ROOT_PUB_FILE_PREFIX].
-    pub fn serialize_public_root_data(&self, path: PathBuf) -> Result<PathBuf, DapolTreeError> {
-        let public_root_data: RootPublicData = self.public_root_data();
-        let path = DapolTree::parse_public_root_data_serialization_path(path.clone())?;
-        read_write_utils::serialize_to_json_file(&public_root_data, path.clone())?;
+    /// The output can be rendered with `dot -Tsvg tree.dot -o tree.svg`.
+    pub fn to_dot(&self, max_nodes: usize) -> String {
+        use std::collections::HashSet;
+        use std::fmt::Write;
 
-        Ok(path)
+        let entity_x_coords = self.occupied_leaf_x_coords();
+
+        let has_entity_in_subtree = |coord: &Coordinate| {
+            let (x_coord_min, x_coord_max) = coord.subtree_x_coord_bounds();
+            let i = entity_x_coords.partition_point(|x| *x < x_coord_min);
+            i < entity_x_coords.len() && entity_x_coords[i] <= x_coord_max
+        };
+
+        let mut coords = self.stored_coordinates();
+        coords.sort_by_key(|coord| (std::cmp::Reverse(coord.y), coord.x));
+        coords.truncate(max_nodes);
+
+        let included: HashSet<Coordinate> = coords.iter().cloned().collect();
+        let node_id = |coord: &Coordinate| format!("n_{}_{}", coord.y, coord.x);
+
+        let mut dot = String::from("digraph dapol_tree {\n    node [shape=box];\n");
+
+        for coord in coords.iter() {
+            let hash = match self.node_at(coord.clone()) {
+                Ok(node) => bytes_to_hex(&node.content.hash.as_bytes()[..4]),
+                Err(_) => continue,
+            };
+
+            let style = match (coord.y == 0, has_entity_in_subtree(coord)) {
+                (true, true) => "style=filled, fillcolor=lightblue",
+                (false, true) => "style=solid",
+                (_, false) => "style=dashed, color=gray",
+            };
+
+            writeln!(
+                dot,
+                "    {} [label=\"({}, {})\\n0x{}..\", {}];",
+                node_id(coord),
+                coord.x,
+                coord.y,
+                hash,
+                style
+            )
+            .expect("Writing to a String cannot fail");
+        }
+
+        for coord in coords.iter().filter(|coord| coord.y > 0) {
+            for x in [2 * coord.x, 2 * coord.x + 1] {
+                let child = Coordinate { x, y: coord.y - 1 };
+                if included.contains(&child) {
+                    writeln!(dot, "    {} -> {};", node_id(coord), node_id(&child))
+                        .expect("Writing to a String cannot fail");
+                }
+            }
+        }
+
+        dot.push_str("}\n");
+        dot
     }
 
-    /// Serialize the public root node data to a file.
-    ///
-    /// The data that will be serialized to a json file:
-    /// - Pedersen commitment
-    /// - hash
-    /// - secret data (liability & blinding factor for Pedersen commitment)
+    /// Return the hash & commitment of the root of the sub-tree at the given
+    /// coordinate.
     ///
-    /// An error is returned if
-    /// 1. [serde_json] fails to serialize any of the files.
-    /// 2. There is an issue opening or writing to any of the files.
-    ///
-    /// `path` can be either of the following:
-    /// 1. Existing directory: in this case a default file name is appended to
-    /// `path`. 2. Non-existing directory: in this case all dirs in the path
-    /// are created, and a default file name is appended.
-    /// 3. File in existing dir: in this case the extension is checked to be
-    /// ".json", then `path` is returned.
-    /// 4. File in non-existing dir: dirs in the path are created and the file
-    /// extension is checked.
+    /// The node is regenerated if it is not in the tree's store. This is
+    /// useful for sharded verification, where different parties each verify
+    /// a sub-tree against a published sub-tree root.
     ///
-    /// The file prefix is [SERIALIZED_ROOT_PVT_FILE_PREFIX].
-    pub fn serialize_secret_root_data(&self, dir: PathBuf) -> Result<PathBuf, DapolTreeError> {
-        let secret_root_data: RootSecretData = self.secret_root_data();
-        let path = DapolTree::parse_secret_root_data_serialization_path(dir.clone())?;
-        read_write_utils::serialize_to_json_file(&secret_root_data, path.clone())?;
-
-        Ok(path)
+    /// An error is returned if the coordinate is outside the bounds of the
+    /// tree.
+    pub fn subtree_root(
+        &self,
+        coord: Coordinate,
+    ) -> Result<(H256, RistrettoPoint), DapolTreeError> {
+        let node = self.node_at(coord)?;
+        Ok((node.content.hash, node.content.commitment))
     }
 
-    /// Deserialize the tree from the given file path.
+    /// Split the bottom layer of the tree into `k` equal coordinate ranges,
+    /// and describe the sub-tree of each range.
     ///
-    /// The file is assumed to be in [bincode] format.
+    /// This can be used to distribute proof serving across `k` servers, each
+    /// serving the proofs for the entities in 1 shard. The shard roots can be
+    /// checked against the tree's root using
+    /// [DapolTree::verify_shard_manifest], so the manifest can be published
+    /// alongside the root.
     ///
-    /// An error is logged and returned if
-    /// 1. The file cannot be opened.
-    /// 2. The [bincode] deserializer fails.
-    /// 3. The file extension is not [SERIALIZED_TREE_EXTENSION]
-    pub fn deserialize(path: PathBuf) -> Result<DapolTree, DapolTreeError> {
-        debug!(
-            "Deserializing DapolTree from file {:?}",
-            path.clone().into_os_string()
-        );
+    /// A node whose sub-tree contains no entities is a padding node, which is
+    /// not the merge of its children. So adjacent shards that contain no
+    /// entities are replaced by the largest sub-tree around them that also
+    /// contains no entities, which means there can be fewer than `k` shards
+    /// in the manifest. The shards are returned in order of their x-coords.
+    ///
+    /// An error is returned if `k` is not a power of 2, or is larger than the
+    /// number of bottom-layer nodes.
+    pub fn shard_manifest(&self, k: usize) -> Result<Vec<ShardInfo>, DapolTreeError> {
+        let max_bottom_layer_nodes = self.height().max_bottom_layer_nodes();
 
-        read_write_utils::check_deserialization_path(&path, SERIALIZED_TREE_EXTENSION)?;
+        if !k.is_power_of_two() || k as u64 > max_bottom_layer_nodes {
+            return Err(DapolTreeError::InvalidShardCount {
+                k,
+                max_bottom_layer_nodes,
+            })
+            .log_on_err();
+        }
 
-        let dapol_tree: DapolTree =
-            read_write_utils::deserialize_from_bin_file(path.clone()).log_on_err()?;
+        let root_y = self.height().as_y_coord();
+        let shard_y = root_y - k.trailing_zeros() as u8;
+        let entity_x_coords = self.occupied_leaf_x_coords();
 
-        dapol_tree.log_successful_tree_creation();
+        let entity_count = |coord: &Coordinate| {
+            let (x_coord_min, x_coord_max) = coord.subtree_x_coord_bounds();
+            let start = entity_x_coords.partition_point(|x| *x < x_coord_min);
+            let end = entity_x_coords.partition_point(|x| *x <= x_coord_max);
+            (end - start) as u64
+        };
 
-        Ok(dapol_tree)
+        let mut shards: Vec<ShardInfo> = Vec::new();
+
+        for x in 0..k as u64 {
+            let mut coord = Coordinate { x, y: shard_y };
+
+            if shards
+                .last()
+                .is_some_and(|shard| *shard.x_range.end() >= coord.subtree_x_coord_bounds().0)
+            {
+                // Already covered by an empty sub-tree around a previous shard.
+                continue;
+            }
+
+            if entity_count(&coord) == 0 {
+                while coord.y < root_y && entity_count(&coord.parent_coord()) == 0 {
+                    coord = coord.parent_coord();
+                }
+            }
+
+            let (hash, commitment) = self.subtree_root(coord.clone())?;
+            let (x_coord_min, x_coord_max) = coord.subtree_x_coord_bounds();
+
+            shards.push(ShardInfo {
+                x_range: x_coord_min..=x_coord_max,
+                subtree_root_hash: hash,
+                subtree_root_commitment: commitment,
+                entity_count: entity_count(&coord),
+            });
+        }
+
+        Ok(shards)
     }
 
-    /// Deserialize the public root data from the given file path.
+    /// Rebuild the root node from the stored leaf nodes and check that it
+    /// matches the stored root hash & commitment.
     ///
-    /// The file is assumed to be in json format.
+    /// Nodes that are not in the store are regenerated using the master
+    /// secret & salts. This can be used as an integrity check after
+    /// deserialization, before trusting the tree for proof generation.
     ///
-    /// An error is logged and returned if
-    /// 1. The file cannot be opened.
-    /// 2. The [serde_json] deserializer fails.
-    /// 3. The file extension is not [SERIALIZED_ROOT_PUB_FILE_PREFIX]
-    pub fn deserialize_public_root_data(path: PathBuf) -> Result<RootPublicData, DapolTreeError> {
-        read_write_utils::check_deserialization_path(&path, "json")?;
+    /// [DapolTreeError::IntegrityCheckFailed] is returned if the recomputed
+    /// root does not match.
+    pub fn verify_integrity(&self) -> Result<(), DapolTreeError> {
+        let recomputed_root = match &self.accumulator {
+            Accumulator::NdmSmt(ndm_smt) => {
+                ndm_smt.recompute_root(&self.master_secret, &self.salt_b, &self.salt_s)?
+            }
+        };
 
-        let public_root_data: RootPublicData =
-            read_write_utils::deserialize_from_json_file(path.clone()).log_on_err()?;
+        if recomputed_root.content.hash == *self.root_hash()
+            && recomputed_root.content.commitment == *self.root_commitment()
+        {
+            Ok(())
+        } else {
+            Err(DapolTreeError::IntegrityCheckFailed).log_on_err()
+        }
+    }
 
-        Ok(public_root_data)
+    /// Check that the bottom-layer leaf node of every entity in
+    /// [DapolTree::entity_mapping] is in the tree's store.
+    ///
+    /// Inclusion proof generation relies on the leaf nodes being in the
+    /// store, so this is a cheap way to catch a broken store (e.g. after
+    /// deserialization) before any proofs are requested.
+    ///
+    /// [DapolTreeError::MissingLeafNode] is returned for the left-most leaf
+    /// node that is missing.
+    pub fn verify_leaves_present(&self) -> Result<(), DapolTreeError> {
+        let missing = match &self.accumulator {
+            Accumulator::NdmSmt(ndm_smt) => ndm_smt.first_missing_leaf_coord(),
+        };
+
+        match missing {
+            Some(coord) => Err(DapolTreeError::MissingLeafNode { coord }).log_on_err(),
+            None => Ok(()),
+        }
     }
 
-    /// Deserialize the secret root data from the given file path.
+    /// Change the liability of an existing entity.
     ///
-    /// The file is assumed to be in json format.
+    /// Only the nodes on the path from the entity's leaf node to the root are
+    /// recomputed, which is much cheaper than rebuilding the tree. The
+    /// blinding factor and hash of the leaf node do not change, only its
+    /// Pedersen commitment. The root hash & commitment change, so the public
+    /// root data needs to be published again, and inclusion proofs that were
+    /// generated before the update will no longer verify against the new
+    /// root.
     ///
-    /// An error is logged and returned if
-    /// 1. The file cannot be opened.
-    /// 2. The [serde_json] deserializer fails.
-    /// 3. The file extension is not [SERIALIZED_ROOT_PUB_FILE_PREFIX]
-    pub fn deserialize_secret_root_data(path: PathBuf) -> Result<RootSecretData, DapolTreeError> {
-        read_write_utils::check_deserialization_path(&path, "json")?;
+    /// Each successful update increments the tree's epoch. If the root
+    /// history is enabled (see [DapolTree::set_max_root_history]) then the
+    /// previous root is added to it, so that old proofs can still be checked
+    /// against it.
+    ///
+    /// An error is returned if
+    /// 1. The entity is not in the tree.
+    /// 2. The new liability is greater than the max liability of the tree.
+    pub fn update_liability(
+        &mut self,
+        entity_id: &EntityId,
+        new_liability: u64,
+    ) -> Result<(), DapolTreeError> {
+        if new_liability > self.max_liability.as_u64() {
+            return Err(DapolTreeError::LiabilityExceedsMax {
+                liability: new_liability,
+                max_liability: self.max_liability.as_u64(),
+            })
+            .log_on_err();
+        }
 
-        let secret_root_data: RootSecretData =
-            read_write_utils::deserialize_from_json_file(path.clone()).log_on_err()?;
+        let previous_root = self.public_root_data();
 
-        Ok(secret_root_data)
+        match &mut self.accumulator {
+            Accumulator::NdmSmt(ndm_smt) => ndm_smt.update_liability(
+                &self.master_secret,
+                &self.salt_b,
+                &self.salt_s,
+                entity_id,
+                new_liability,
+            )?,
+        }
+
+        self.push_root_history(previous_root);
+        self.root_generated_at = unix_time_now();
+
+        info!(
+            "Updated liability of entity {:?}, new root hash is {}",
+            entity_id,
+            self.public_root_data().hash_hex()
+        );
+
+        Ok(())
+    }
+
+    /// Previous roots of the tree, oldest first, each with the epoch at which
+    /// it was the root.
+    ///
+    /// The current root is not included. A root is added to the history when
+    /// the tree is updated (e.g. by [DapolTree::update_liability]), and the
+    /// oldest root is dropped once there are more than the max (see
+    /// [DapolTree::set_max_root_history]). A verifier can check an inclusion
+    /// proof that was generated before an update against any of these roots.
+    pub fn root_history(&self) -> &[(u64, RootPublicData)] {
+        &self.root_history
+    }
+
+    /// Number of updates that have been made to the tree since it was built.
+    ///
+    /// The root at epoch 0 is the root of the tree as it was built.
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Set the max number of previous roots that are kept in the root
+    /// history.
+    ///
+    /// The history is disabled by default (max of 0). If the history already
+    /// has more roots than `max_root_history` then the oldest ones are
+    /// dropped.
+    pub fn set_max_root_history(&mut self, max_root_history: usize) {
+        self.max_root_history = max_root_history;
+        let excess = self.root_history.len().saturating_sub(max_root_history);
+        self.root_history.drain(..excess);
+    }
+
+    /// Set the function used to generate padding node content, see
+    /// [BuildOptions::with_padding_fn].
+    ///
+    /// The tree is not changed, so this must be the function the tree was
+    /// built with, otherwise the generated inclusion proofs will not verify.
+    /// The function is not serialized, so after deserializing a tree that was
+    /// built with one, proof generation & node regeneration give an error
+    /// until this has been called.
+    pub fn set_padding_fn<F>(&mut self, padding_fn: F)
+    where
+        F: Fn(&Coordinate) -> FullNodeContent + Send + Sync + 'static,
+    {
+        match &mut self.accumulator {
+            Accumulator::NdmSmt(ndm_smt) => ndm_smt.set_padding_fn(padding_fn),
+        }
+    }
+
+    fn push_root_history(&mut self, previous_root: RootPublicData) {
+        if self.max_root_history > 0 {
+            if self.root_history.len() == self.max_root_history {
+                self.root_history.remove(0);
+            }
+            self.root_history.push((self.epoch, previous_root));
+        }
+        self.epoch += 1;
+    }
+
+    /// Check that the tree was built using the `expected` master secret.
+    ///
+    /// This is useful when loading a serialized tree, to make sure it belongs
+    /// to the holder of the master secret before serving proofs from it.
+    ///
+    /// The stored master secret is compared to `expected` in constant time.
+    /// The stored secret could have been changed without rebuilding the tree,
+    /// so the blinding factors of the bottom-layer leaf nodes are also
+    /// recomputed from `expected` and compared to the stored ones (see
+    /// [crate::kdf]). Note that if every entity was given its own blinding
+    /// factor (see [Entity]) then none of the leaf nodes are derived from the
+    /// master secret, and false is returned.
+    pub fn verify_master_secret(&self, expected: &Secret) -> bool {
+        if !self.master_secret.constant_time_eq(expected) {
+            return false;
+        }
+
+        match &self.accumulator {
+            Accumulator::NdmSmt(ndm_smt) => ndm_smt.leaves_derived_from(expected, &self.salt_b),
+        }
+    }
+
+    /// Recompute the content of the entity's bottom-layer leaf node from the
+    /// master secret & salts, and check that it matches the stored leaf node.
+    ///
+    /// This shows that the leaf secrets can be reproduced using the KDF (see
+    /// [crate::kdf]). The returned content contains the entity's liability, so
+    /// it should only be given to the entity or the tree owner.
+    ///
+    /// An error is returned if the entity is not in the tree, or if the
+    /// recomputed content does not match the stored leaf node. The latter is
+    /// expected for entities that were given their own salt or blinding
+    /// factor (see [Entity]).
+    #[cfg(any(test, feature = "testing"))]
+    pub fn recompute_leaf_content(
+        &self,
+        entity_id: &EntityId,
+    ) -> Result<FullNodeContent, DapolTreeError> {
+        let content = match &self.accumulator {
+            Accumulator::NdmSmt(ndm_smt) => ndm_smt.recompute_leaf_content(
+                &self.master_secret,
+                &self.salt_b,
+                &self.salt_s,
+                entity_id,
+            )?,
+        };
+
+        Ok(content)
+    }
+
+    /// Check that the public Pedersen commitment corresponds to the secret
+    /// values of the root.
+    ///
+    /// If the secret data does not match the commitment then false is returned,
+    /// otherwise true.
+    pub fn verify_root_commitment(
+        public_commitment: &RistrettoPoint,
+        secret_root_data: &RootSecretData,
+    ) -> Result<(), DapolTreeError> {
+        let commitment = PedersenGens::default().commit(
+            Scalar::from(secret_root_data.liability),
+            secret_root_data.blinding_factor,
+        );
+
+        if commitment == *public_commitment {
+            Ok(())
+        } else {
+            Err(DapolTreeError::RootVerificationError)
+        }
+    }
+
+    /// Prove that the total liability committed to by `my_secret` is equal
+    /// to the total committed to by `other_commitment`, plus `difference`,
+    /// without revealing either total.
+    ///
+    /// This is useful for 2 organizations proving a shared total (e.g. a
+    /// joint reserve). The exact statement proved is documented in
+    /// [EqualityProof]. The proof can be checked against the 2 public
+    /// root commitments with [EqualityProof::verify], where `my_commitment` is
+    /// the commitment for `my_secret`.
+    ///
+    /// `other_blinding_factor` is the root blinding factor of the other tree,
+    /// which has to be shared with the prover. Pass a `difference` of 0 to
+    /// prove that the totals are equal. If the other total is the larger one
+    /// then the roles should be swapped, so that the other party generates
+    /// the proof.
+    ///
+    /// An error is returned if the totals do not differ by `difference`, or
+    /// if `other_blinding_factor` is not the one used for `other_commitment`.
+    pub fn prove_commitment_equality(
+        other_commitment: &RistrettoPoint,
+        my_secret: &RootSecretData,
+        other_blinding_factor: &Scalar,
+        difference: u64,
+    ) -> Result<EqualityProof, EqualityProofError> {
+        let my_commitment = PedersenGens::default()
+            .commit(Scalar::from(my_secret.liability), my_secret.blinding_factor);
+
+        EqualityProof::generate(
+            &my_commitment,
+            other_commitment,
+            difference,
+            &(my_secret.blinding_factor - other_blinding_factor),
+        )
+    }
+
+    /// Check that each public Pedersen commitment corresponds to its secret
+    /// root values, for many roots at once.
+    ///
+    /// This is the batched version of [DapolTree::verify_root_commitment],
+    /// useful when reconciling a long history of published roots. Instead of
+    /// computing each commitment separately, a random linear combination of
+    /// all the checks is computed using a single multi-scalar multiplication:
+    ///
+    /// `sum_i r_i * (liability_i * G + blinding_factor_i * H - C_i) == 0`
+    ///
+    /// where `r_i` are random scalars, `G` & `H` are the Pedersen generators
+    /// and `C_i` are the public commitments. The random weights make it
+    /// infeasible for mismatches to cancel each other out.
+    ///
+    /// If the batch check fails then the commitments are checked one at a
+    /// time, and [DapolTreeError::BatchRootVerificationError] is returned with
+    /// the index of the first mismatch.
+    pub fn verify_root_commitments_batch(
+        pairs: &[(RistrettoPoint, RootSecretData)],
+    ) -> Result<(), DapolTreeError> {
+        use curve25519_dalek_ng::traits::{IsIdentity, VartimeMultiscalarMul};
+
+        let mut rng = rand::thread_rng();
+        let weights: Vec<Scalar> = pairs.iter().map(|_| Scalar::random(&mut rng)).collect();
+
+        let liability_scalar: Scalar = pairs
+            .iter()
+            .zip(weights.iter())
+            .map(|((_, secret_root_data), weight)| {
+                weight * Scalar::from(secret_root_data.liability)
+            })
+            .sum();
+
+        let blinding_factor_scalar: Scalar = pairs
+            .iter()
+            .zip(weights.iter())
+            .map(|((_, secret_root_data), weight)| weight * secret_root_data.blinding_factor)
+            .sum();
+
+        let pedersen_gens = PedersenGens::default();
+
+        let scalars = [liability_scalar, blinding_factor_scalar]
+            .into_iter()
+            .chain(weights.iter().map(|weight| -weight));
+        let points = [pedersen_gens.B, pedersen_gens.B_blinding]
+            .into_iter()
+            .chain(pairs.iter().map(|(commitment, _)| *commitment));
+
+        if RistrettoPoint::vartime_multiscalar_mul(scalars, points).is_identity() {
+            return Ok(());
+        }
+
+        let index = pairs
+            .iter()
+            .position(|(commitment, secret_root_data)| {
+                DapolTree::verify_root_commitment(commitment, secret_root_data).is_err()
+            })
+            .expect("[Bug in batch root verification] at least 1 commitment should not match");
+
+        Err(DapolTreeError::BatchRootVerificationError { index }).log_on_err()
+    }
+
+    /// Check that the shard roots of a manifest (see
+    /// [DapolTree::shard_manifest]) combine to the published root.
+    ///
+    /// The shards must be in order of their x-coords, and together they must
+    /// cover exactly the bottom layer of a tree with the given `height`, i.e.
+    /// the x-coords `0..2^(height-1)`. Each shard's x-coord range must be the
+    /// range of a sub-tree i.e. its length is a power of 2 and it starts at a
+    /// multiple of its length. Sibling sub-tree roots are merged until only
+    /// the root is left, which must be at `y == height - 1`, and the root's
+    /// hash & commitment are compared to `root_public_data`.
+    ///
+    /// [DapolTreeError::ShardManifestVerificationError] is returned if the
+    /// shards are not valid or do not combine to the published root.
+    pub fn verify_shard_manifest(
+        shards: &[ShardInfo],
+        height: Height,
+        root_public_data: &RootPublicData,
+    ) -> Result<(), DapolTreeError> {
+        use crate::binary_tree::Mergeable;
+
+        let max_bottom_layer_nodes = height.max_bottom_layer_nodes();
+        let mut stack: Vec<Node<HiddenNodeContent>> = Vec::with_capacity(shards.len());
+        let mut next_x_coord = 0u64;
+
+        for shard in shards {
+            let (start, end) = (*shard.x_range.start(), *shard.x_range.end());
+            let len = end.wrapping_sub(start).wrapping_add(1);
+
+            if start != next_x_coord
+                || end < start
+                || end >= max_bottom_layer_nodes
+                || !len.is_power_of_two()
+                || !start.is_multiple_of(len)
+            {
+                return Err(DapolTreeError::ShardManifestVerificationError).log_on_err();
+            }
+            next_x_coord = end.wrapping_add(1);
+
+            stack.push(Node {
+                coord: Coordinate {
+                    x: start / len,
+                    y: len.trailing_zeros() as u8,
+                },
+                content: HiddenNodeContent::new(
+                    shard.subtree_root_commitment,
+                    shard.subtree_root_hash,
+                ),
+            });
+
+            // Merge the top 2 nodes while they are siblings.
+            while stack.len() >= 2 {
+                let right = &stack[stack.len() - 1];
+                let left = &stack[stack.len() - 2];
+
+                if left.coord.y != right.coord.y || !left.coord.x.is_multiple_of(2) {
+                    break;
+                }
+
+                let parent = Node {
+                    coord: left.coord.parent_coord(),
+                    content: HiddenNodeContent::merge(&left.content, &right.content),
+                };
+                stack.truncate(stack.len() - 2);
+                stack.push(parent);
+            }
+        }
+
+        match stack.as_slice() {
+            [root]
+                if next_x_coord == max_bottom_layer_nodes
+                    && root.coord
+                        == (Coordinate {
+                            x: 0,
+                            y: height.as_y_coord(),
+                        })
+                    && root.content.hash == root_public_data.hash
+                    && root.content.commitment == root_public_data.commitment =>
+            {
+                Ok(())
+            }
+            _ => Err(DapolTreeError::ShardManifestVerificationError).log_on_err(),
+        }
     }
 }
 
 // -------------------------------------------------------------------------------------------------
-// Errors.
+// Accessor methods.
 
-/// Errors encountered when handling an [Accumulator].
-#[derive(thiserror::Error, Debug)]
-pub enum DapolTreeError {
-    #[error("Error serializing/deserializing file")]
-    SerdeError(#[from] read_write_utils::ReadWriteError),
-    #[error("Error constructing a new NDM-SMT")]
-    NdmSmtConstructionError(#[from] NdmSmtError),
-    #[error("Verification of root data failed")]
-    RootVerificationError,
+impl DapolTree {
+    #[doc = include_str!("./shared_docs/accumulator_type.md")]
+    pub fn accumulator_type(&self) -> AccumulatorType {
+        self.accumulator.get_type()
+    }
+
+    #[doc = include_str!("./shared_docs/master_secret.md")]
+    pub fn master_secret(&self) -> &Secret {
+        &self.master_secret
+    }
+
+    #[doc = include_str!("./shared_docs/salt_b.md")]
+    pub fn salt_b(&self) -> &Salt {
+        &self.salt_b
+    }
+
+    #[doc = include_str!("./shared_docs/salt_s.md")]
+    pub fn salt_s(&self) -> &Salt {
+        &self.salt_s
+    }
+
+    #[doc = include_str!("./shared_docs/max_liability.md")]
+    pub fn max_liability(&self) -> &MaxLiability {
+        &self.max_liability
+    }
+
+    #[doc = include_str!("./shared_docs/height.md")]
+    pub fn height(&self) -> &Height {
+        self.accumulator.height()
+    }
+
+    /// Mapping of [EntityId](crate::EntityId) to x-coord on the bottom layer of the tree.
+    ///
+    /// If the underlying accumulator is an NDM-SMT then a hashmap is returned
+    /// otherwise None is returned.
+    pub fn entity_mapping(&self) -> Option<&std::collections::HashMap<EntityId, u64>> {
+        match &self.accumulator {
+            Accumulator::NdmSmt(ndm_smt) => Some(ndm_smt.entity_mapping()),
+            _ => None,
+        }
+    }
+
+    /// Domain separator used for the hashes of the bottom-layer leaf &
+    /// padding nodes, if one was given (see
+    /// [BuildOptions::with_domain_separator]).
+    pub fn domain_separator(&self) -> Option<&str> {
+        match &self.accumulator {
+            Accumulator::NdmSmt(ndm_smt) => ndm_smt.domain_separator(),
+        }
+    }
+
+    /// Hash & Pedersen commitment for the root node of the Merkle Sum Tree.
+    ///
+    /// These values can be made public and do not disclose secret information
+    /// about the tree such as the number of leaf nodes or their liabilities.
+    /// The time at which the root was computed & the crate version are
+    /// included as metadata.
+    pub fn public_root_data(&self) -> RootPublicData {
+        RootPublicData::new(*self.root_hash(), *self.root_commitment())
+            .with_generated_at(self.root_generated_at)
+    }
+
+    /// True if both the hash & the commitment of the root node match the
+    /// `published` data.
+    pub fn matches_public_root(&self, published: &RootPublicData) -> bool {
+        self.public_root_data() == *published
+    }
+
+    /// Liability & blinding factor that make up the Pederesen commitment of
+    /// the Merkle Sum Tree.
+    ///
+    /// Neither of these values should be made public if the owner of the tree
+    /// does not want to disclose the total liability sum of their users.
+    pub fn secret_root_data(&self) -> RootSecretData {
+        RootSecretData {
+            liability: self.root_liability(),
+            blinding_factor: self.root_blinding_factor().clone(),
+        }
+    }
+
+    #[doc = include_str!("./shared_docs/root_hash.md")]
+    pub fn root_hash(&self) -> &H256 {
+        self.accumulator.root_hash()
+    }
+
+    #[doc = include_str!("./shared_docs/root_commitment.md")]
+    pub fn root_commitment(&self) -> &RistrettoPoint {
+        self.accumulator.root_commitment()
+    }
+
+    #[doc = include_str!("./shared_docs/root_liability.md")]
+    pub fn root_liability(&self) -> u64 {
+        self.accumulator.root_liability()
+    }
+
+    #[doc = include_str!("./shared_docs/root_blinding_factor.md")]
+    pub fn root_blinding_factor(&self) -> &Scalar {
+        self.accumulator.root_blinding_factor()
+    }
 }
 
-// -------------------------------------------------------------------------------------------------
+// -------------------------------------------------------------------------------------------------
+// Serialization & deserialization.
+
+impl DapolTree {
+    fn build_report(&self, build_time: Duration, entity_count: u64) -> BuildReport {
+        let report = BuildReport {
+            build_time,
+            height: *self.height(),
+            entity_count,
+            stored_node_count: self.stored_node_count() as u64,
+            padding_leaf_node_count: self.height().max_bottom_layer_nodes() - entity_count,
+        };
+
+        debug!("Build report: {:?}", report);
+
+        report
+    }
+
+    /// Create an [AuditSummary] of the tree's public data, timestamped with
+    /// the current time.
+    ///
+    /// Unlike [log_successful_tree_creation] this has no side effects, so the
+    /// summary can be stored wherever is needed (e.g. an append-only log).
+    pub fn audit_summary(&self) -> AuditSummary {
+        AuditSummary {
+            accumulator_type: self.accumulator_type(),
+            height: *self.height(),
+            salt_b: format!("0x{}", bytes_to_hex(self.salt_b.as_bytes())),
+            salt_s: format!("0x{}", bytes_to_hex(self.salt_s.as_bytes())),
+            root_hash: *self.root_hash(),
+            root_commitment: *self.root_commitment(),
+            entity_count: self
+                .entity_mapping()
+                .map_or(0, |mapping| mapping.len() as u64),
+            timestamp: SystemTime::now(),
+        }
+    }
+
+    fn log_successful_tree_creation(&self) {
+        info!(
+            "\nDAPOL tree has been constructed. Public data:\n \
+             - accumulator type: {}\n \
+             - height: {}\n \
+             - salt_b: 0x{}\n \
+             - salt_s: 0x{}\n \
+             - root hash: {}\n \
+             - root commitment: {:?}",
+            self.accumulator_type(),
+            self.height().as_u32(),
+            bytes_to_hex(self.salt_b.as_bytes()),
+            bytes_to_hex(self.salt_s.as_bytes()),
+            self.public_root_data().hash_hex(),
+            self.root_commitment().compress()
+        );
+    }
+
+    /// Parse `path` as one that points to a serialized dapol tree file.
+    ///
+    /// `path` can be either of the following:
+    /// 1. Existing directory: in this case a default file name is appended to
+    /// `path`. 2. Non-existing directory: in this case all dirs in the path
+    /// are created, and a default file name is appended.
+    /// 3. File in existing dir: in this case the extension is checked to be
+    /// [SERIALIZED_TREE_EXTENSION], then `path` is returned.
+    /// 4. File in non-existing dir: dirs in the path are created and the file
+    /// extension is checked.
+    ///
+    /// The file prefix is [SERIALIZED_TREE_FILE_PREFIX].
+    pub fn parse_tree_serialization_path(
+        path: PathBuf,
+    ) -> Result<PathBuf, read_write_utils::ReadWriteError> {
+        read_write_utils::parse_serialization_path(
+            path,
+            SERIALIZED_TREE_EXTENSION,
+            SERIALIZED_TREE_FILE_PREFIX,
+        )
+    }
+
+    /// Parse `path` as one that points to a json file containing the public
+    /// data of the root node.
+    ///
+    /// `path` can be either of the following:
+    /// 1. Existing directory: in this case a default file name is appended to
+    /// `path`. 2. Non-existing directory: in this case all dirs in the path
+    /// are created, and a default file name is appended.
+    /// 3. File in existing dir: in this case the extension is checked to be
+    /// ".json", then `path` is returned.
+    /// 4. File in non-existing dir: dirs in the path are created and the file
+    /// extension is checked.
+    ///
+    /// The file prefix is [SERIALIZED_ROOT_PUB_FILE_PREFIX].
+    pub fn parse_public_root_data_serialization_path(
+        path: PathBuf,
+    ) -> Result<PathBuf, read_write_utils::ReadWriteError> {
+        read_write_utils::parse_serialization_path(path, "json", SERIALIZED_ROOT_PUB_FILE_PREFIX)
+    }
+
+    /// Parse `path` as one that points to a json file containing the secret
+    /// data of the root node.
+    ///
+    /// `path` can be either of the following:
+    /// 1. Existing directory: in this case a default file name is appended to
+    /// `path`. 2. Non-existing directory: in this case all dirs in the path
+    /// are created, and a default file name is appended.
+    /// 3. File in existing dir: in this case the extension is checked to be
+    /// ".json", then `path` is returned.
+    /// 4. File in non-existing dir: dirs in the path are created and the file
+    /// extension is checked.
+    ///
+    /// The file prefix is [SERIALIZED_ROOT_PVT_FILE_PREFIX].
+    pub fn parse_secret_root_data_serialization_path(
+        path: PathBuf,
+    ) -> Result<PathBuf, read_write_utils::ReadWriteError> {
+        read_write_utils::parse_serialization_path(path, "json", SERIALIZED_ROOT_PVT_FILE_PREFIX)
+    }
+
+    /// Parse `path` as one that points to a json file containing the entity
+    /// mapping.
+    ///
+    /// `path` can be either of the following:
+    /// 1. Existing directory: in this case a default file name is appended to
+    ///    `path`.
+    /// 2. Non-existing directory: in this case all dirs in the path are
+    ///    created, and a default file name is appended.
+    /// 3. File in existing dir: in this case the extension is checked to be
+    ///    ".json", then `path` is returned.
+    /// 4. File in non-existing dir: dirs in the path are created and the file
+    ///    extension is checked.
+    ///
+    /// The file prefix is [SERIALIZED_ENTITY_MAPPING_FILE_PREFIX].
+    pub fn parse_entity_mapping_serialization_path(
+        path: PathBuf,
+    ) -> Result<PathBuf, read_write_utils::ReadWriteError> {
+        read_write_utils::parse_serialization_path(
+            path,
+            "json",
+            SERIALIZED_ENTITY_MAPPING_FILE_PREFIX,
+        )
+    }
+
+    /// Serialize the whole tree to a file.
+    ///
+    /// Serialization is done using [bincode].
+    ///
+    /// An error is returned if
+    /// 1. [bincode] fails to serialize the file.
+    /// 2. There is an issue opening or writing the file.
+    ///
+    /// `path` can be either of the following:
+    /// 1. Existing directory: in this case a default file name is appended to
+    /// `path`. 2. Non-existing directory: in this case all dirs in the path
+    /// are created, and a default file name is appended.
+    /// 3. File in existing dir: in this case the extension is checked to be
+    /// [SERIALIZED_TREE_EXTENSION], then `path` is returned.
+    /// 4. File in non-existing dir: dirs in the path are created and the file
+    /// extension is checked.
+    ///
+    /// The file prefix is [SERIALIZED_TREE_FILE_PREFIX].
+    pub fn serialize(&self, path: PathBuf) -> Result<PathBuf, DapolTreeError> {
+        let path = DapolTree::parse_tree_serialization_path(path)?;
+
+        info!(
+            "Serializing accumulator to file {:?}",
+            path.clone().into_os_string()
+        );
+
+        read_write_utils::serialize_to_bin_file(&self, path.clone()).log_on_err()?;
+
+        Ok(path)
+    }
+
+    /// Serialize the whole tree to a file, leaving out the padding nodes.
+    ///
+    /// Padding nodes are deterministically generated from their coordinate
+    /// and the secrets, so they do not need to be serialized. This can make
+    /// the file substantially smaller for sparse trees, at the cost of having
+    /// to regenerate the padding nodes when they are needed for inclusion
+    /// proofs. The generated proofs are the same as for the original tree.
+    ///
+    /// The file can be deserialized using [DapolTree::deserialize]. Note
+    /// that [DapolTree::into_proof_server] has to be used to get a full store
+    /// again.
+    ///
+    /// The path & error behaviour is the same as for [DapolTree::serialize].
+    pub fn serialize_without_padding(&self, path: PathBuf) -> Result<PathBuf, DapolTreeError> {
+        let accumulator = match &self.accumulator {
+            Accumulator::NdmSmt(ndm_smt) => Accumulator::NdmSmt(ndm_smt.without_padding_nodes()),
+        };
+
+        let tree = DapolTree {
+            accumulator,
+            ..self.clone()
+        };
+
+        tree.serialize(path)
+    }
+
+    /// Serialize the public root node data to a file.
+    ///
+    /// The data that will be serialized to a json file:
+    /// - Pedersen commitment
+    /// - hash
+    ///
+    /// An error is returned if
+    /// 1. [serde_json] fails to serialize the file.
+    /// 2. There is an issue opening or writing to the file.
+    ///
+    /// `path` can be either of the following:
+    /// 1. Existing directory: in this case a default file name is appended to
+    /// `path`. 2. Non-existing directory: in this case all dirs in the path
+    /// are created, and a default file name is appended.
+    /// 3. File in existing dir: in this case the extension is checked to be
+    /// ".json", then `path` is returned.
+    /// 4. File in non-existing dir: dirs in the path are created and the file
+    /// extension is checked.
+    ///
+    /// The file prefix is [SERIALIZED_ROOT_PUB_FILE_PREFIX].
+    pub fn serialize_public_root_data(&self, path: PathBuf) -> Result<PathBuf, DapolTreeError> {
+        let public_root_data: RootPublicData = self.public_root_data();
+        let path = DapolTree::parse_public_root_data_serialization_path(path.clone())?;
+        read_write_utils::serialize_to_json_file(&public_root_data, path.clone())?;
+
+        Ok(path)
+    }
+
+    /// Serialize the public root node data to a file.
+    ///
+    /// The data that will be serialized to a json file:
+    /// - Pedersen commitment
+    /// - hash
+    /// - secret data (liability & blinding factor for Pedersen commitment)
+    ///
+    /// An error is returned if
+    /// 1. [serde_json] fails to serialize any of the files.
+    /// 2. There is an issue opening or writing to any of the files.
+    ///
+    /// `path` can be either of the following:
+    /// 1. Existing directory: in this case a default file name is appended to
+    /// `path`. 2. Non-existing directory: in this case all dirs in the path
+    /// are created, and a default file name is appended.
+    /// 3. File in existing dir: in this case the extension is checked to be
+    /// ".json", then `path` is returned.
+    /// 4. File in non-existing dir: dirs in the path are created and the file
+    /// extension is checked.
+    ///
+    /// The file prefix is [SERIALIZED_ROOT_PVT_FILE_PREFIX].
+    pub fn serialize_secret_root_data(&self, dir: PathBuf) -> Result<PathBuf, DapolTreeError> {
+        let secret_root_data: RootSecretData = self.secret_root_data();
+        let path = DapolTree::parse_secret_root_data_serialization_path(dir.clone())?;
+        read_write_utils::serialize_to_json_file(&secret_root_data, path.clone())?;
+
+        Ok(path)
+    }
+
+    /// Deserialize the tree from the given file path.
+    ///
+    /// The file is assumed to be in [bincode] format.
+    ///
+    /// An error is logged and returned if
+    /// 1. The file cannot be opened.
+    /// 2. The [bincode] deserializer fails.
+    /// 3. The file extension is not [SERIALIZED_TREE_EXTENSION]
+    /// 4. The format version is not [SERIALIZED_TREE_FORMAT_VERSION].
+    ///
+    /// Trees serialized with a different format version need to be rebuilt.
+    pub fn deserialize(path: PathBuf) -> Result<DapolTree, DapolTreeError> {
+        debug!(
+            "Deserializing DapolTree from file {:?}",
+            path.clone().into_os_string()
+        );
+
+        read_write_utils::check_deserialization_path(&path, SERIALIZED_TREE_EXTENSION)?;
+
+        // The format version is the first field of the struct so only the
+        // first few bytes of the file are read here.
+        let format_version: u16 =
+            read_write_utils::deserialize_from_bin_file(path.clone()).log_on_err()?;
+
+        if format_version != SERIALIZED_TREE_FORMAT_VERSION {
+            return Err(DapolTreeError::IncompatibleFormatVersion {
+                found: format_version,
+                supported: SERIALIZED_TREE_FORMAT_VERSION,
+            })
+            .log_on_err();
+        }
+
+        let dapol_tree: DapolTree =
+            read_write_utils::deserialize_from_bin_file(path.clone()).log_on_err()?;
+
+        dapol_tree.log_successful_tree_creation();
+
+        Ok(dapol_tree)
+    }
+
+    /// Serialize the entity mapping (see [DapolTree::entity_mapping]) to a
+    /// json file, as a map of entity ID to bottom-layer x-coord.
+    ///
+    /// The file can be read with [DapolTree::load_entity_mapping], so that
+    /// the entities keep the same positions when the tree is rebuilt. Note
+    /// that the mapping reveals which leaf node belongs to which entity, so
+    /// the file should be kept as secret as the tree itself.
+    ///
+    /// The path & error behaviour is the same as for
+    /// [DapolTree::serialize_public_root_data], and the file prefix is
+    /// [SERIALIZED_ENTITY_MAPPING_FILE_PREFIX].
+    pub fn serialize_entity_mapping(&self, path: PathBuf) -> Result<PathBuf, DapolTreeError> {
+        let entity_mapping = match &self.accumulator {
+            Accumulator::NdmSmt(ndm_smt) => ndm_smt.entity_mapping(),
+        };
+        let path = DapolTree::parse_entity_mapping_serialization_path(path)?;
+        read_write_utils::serialize_to_json_file(entity_mapping, path.clone())?;
+
+        Ok(path)
+    }
+
+    /// Deserialize an entity mapping from the given json file, as written by
+    /// [DapolTree::serialize_entity_mapping].
+    ///
+    /// The mapping is checked against `height`, which should be the height of
+    /// the tree that the mapping will be used for.
+    ///
+    /// An error is logged and returned if
+    /// 1. The file cannot be opened.
+    /// 2. The [serde_json] deserializer fails.
+    /// 3. The file extension is not ".json".
+    /// 4. 2 entities are mapped to the same x-coord.
+    /// 5. An x-coord is outside the bottom layer of a tree with `height`.
+    pub fn load_entity_mapping(
+        path: PathBuf,
+        height: &Height,
+    ) -> Result<std::collections::HashMap<EntityId, u64>, DapolTreeError> {
+        use std::collections::{HashMap, HashSet};
+
+        read_write_utils::check_deserialization_path(&path, "json")?;
+
+        let entity_mapping: HashMap<EntityId, u64> =
+            read_write_utils::deserialize_from_json_file(path).log_on_err()?;
+
+        let mut x_coords = HashSet::with_capacity(entity_mapping.len());
+        for x_coord in entity_mapping.values() {
+            let coord = Coordinate { x: *x_coord, y: 0 };
+
+            if !coord.is_within(height) {
+                return Err(DapolTreeError::CoordinateOutOfBounds {
+                    coord,
+                    height: *height,
+                })
+                .log_on_err();
+            }
+
+            if !x_coords.insert(*x_coord) {
+                return Err(DapolTreeError::DuplicateMappedXCoord { x_coord: *x_coord })
+                    .log_on_err();
+            }
+        }
+
+        Ok(entity_mapping)
+    }
+
+    /// Deserialize the public root data from the given file path.
+    ///
+    /// The file is assumed to be in json format.
+    ///
+    /// An error is logged and returned if
+    /// 1. The file cannot be opened.
+    /// 2. The [serde_json] deserializer fails.
+    /// 3. The file extension is not [SERIALIZED_ROOT_PUB_FILE_PREFIX]
+    pub fn deserialize_public_root_data(path: PathBuf) -> Result<RootPublicData, DapolTreeError> {
+        read_write_utils::check_deserialization_path(&path, "json")?;
+
+        let public_root_data: RootPublicData =
+            read_write_utils::deserialize_from_json_file(path.clone()).log_on_err()?;
+
+        Ok(public_root_data)
+    }
+
+    /// Deserialize the secret root data from the given file path.
+    ///
+    /// The file is assumed to be in json format.
+    ///
+    /// An error is logged and returned if
+    /// 1. The file cannot be opened.
+    /// 2. The [serde_json] deserializer fails.
+    /// 3. The file extension is not [SERIALIZED_ROOT_PUB_FILE_PREFIX]
+    pub fn deserialize_secret_root_data(path: PathBuf) -> Result<RootSecretData, DapolTreeError> {
+        read_write_utils::check_deserialization_path(&path, "json")?;
+
+        let secret_root_data: RootSecretData =
+            read_write_utils::deserialize_from_json_file(path.clone()).log_on_err()?;
+
+        Ok(secret_root_data)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Async wrappers.
+
+#[cfg(feature = "async")]
+impl DapolTree {
+    /// Async version of [DapolTree::new].
+    ///
+    /// The build is run on tokio's blocking thread pool (see
+    /// [tokio::task::spawn_blocking]) so that it does not block the async
+    /// runtime. The build itself is the same multi-threaded algorithm as for
+    /// [DapolTree::new]. Must be called from within a tokio runtime.
+    ///
+    /// The function will panic if the build panics, or if the blocking task
+    /// is cancelled because the runtime is shutting down.
+    pub async fn new_async(
+        accumulator_type: AccumulatorType,
+        master_secret: Secret,
+        salt_b: Salt,
+        salt_s: Salt,
+        max_liability: MaxLiability,
+        max_thread_count: MaxThreadCount,
+        height: Height,
+        entities: Vec<Entity>,
+    ) -> Result<Self, DapolTreeError> {
+        spawn_blocking_and_join(move || {
+            DapolTree::new(
+                accumulator_type,
+                master_secret,
+                salt_b,
+                salt_s,
+                max_liability,
+                max_thread_count,
+                height,
+                entities,
+            )
+        })
+        .await
+    }
+
+    /// Async version of [DapolTree::deserialize].
+    ///
+    /// Reading & deserializing the file is run on tokio's blocking thread
+    /// pool, see [DapolTree::new_async].
+    pub async fn deserialize_async(path: PathBuf) -> Result<DapolTree, DapolTreeError> {
+        spawn_blocking_and_join(move || DapolTree::deserialize(path)).await
+    }
+}
+
+/// Run `f` on tokio's blocking thread pool and wait for it to finish.
+///
+/// If `f` panics then the panic is propagated to the caller.
+#[cfg(feature = "async")]
+async fn spawn_blocking_and_join<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    match tokio::task::spawn_blocking(f).await {
+        Ok(res) => res,
+        Err(err) => match err.try_into_panic() {
+            Ok(panic) => std::panic::resume_unwind(panic),
+            Err(err) => panic!("Blocking task for the tree did not complete: {}", err),
+        },
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Helpers.
+
+/// Lower-case hex encoding of the bytes, without a `0x` prefix.
+fn bytes_to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Current time in seconds since the Unix epoch, or `None` if the system
+/// clock is set to before the epoch.
+fn unix_time_now() -> Option<u64> {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .ok()
+        .map(|duration| duration.as_secs())
+}
+
+// -------------------------------------------------------------------------------------------------
+// Errors.
+
+/// Errors encountered when handling an [Accumulator].
+#[derive(thiserror::Error, Debug)]
+pub enum DapolTreeError {
+    #[error("Error serializing/deserializing file")]
+    SerdeError(#[from] read_write_utils::ReadWriteError),
+    #[error("Error constructing a new NDM-SMT")]
+    NdmSmtConstructionError(#[from] NdmSmtError),
+    #[error("The tree build was cancelled")]
+    BuildCancelled,
+    #[error("Verification of root data failed")]
+    RootVerificationError,
+    #[error("Verification of root data failed for the root at index {index}")]
+    BatchRootVerificationError { index: usize },
+    #[error("Shard count {k} must be a power of 2 that is at most {max_bottom_layer_nodes}")]
+    InvalidShardCount {
+        k: usize,
+        max_bottom_layer_nodes: u64,
+    },
+    #[error("Shard manifest does not combine to the root")]
+    ShardManifestVerificationError,
+    #[error("Root commitment bytes are not a valid compressed Ristretto point")]
+    InvalidRootCommitment,
+    #[error("Liability {liability} is greater than the max liability {max_liability}")]
+    LiabilityExceedsMax { liability: u64, max_liability: u64 },
+    #[error("Coordinate {coord:?} is outside the bounds of a tree with height {height:?}")]
+    CoordinateOutOfBounds { coord: Coordinate, height: Height },
+    #[error("Root recomputed from the stored leaf nodes does not match the stored root")]
+    IntegrityCheckFailed,
+    #[error("More than 1 entity is mapped to the x-coord {x_coord}")]
+    DuplicateMappedXCoord { x_coord: u64 },
+    #[error("Leaf node at {coord:?} is mapped to an entity but is not in the store")]
+    MissingLeafNode { coord: Coordinate },
+    #[error("The serialized tree has format version {found} but only version {supported} is supported, the tree needs to be rebuilt")]
+    IncompatibleFormatVersion { found: u16, supported: u16 },
+}
+
+/// Errors encountered when parsing a hex-encoded root hash.
+#[derive(thiserror::Error, Debug)]
+pub enum RootHashParserError {
+    #[error(
+        "Expected {expected} hex characters (excluding the optional 0x prefix) but found {found}"
+    )]
+    InvalidLength { found: usize, expected: usize },
+    #[error("Invalid hex character '{character}' at position {index}")]
+    InvalidCharacter { character: char, index: usize },
+}
+
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_utils::{assert_err, new_test_tree, test_entities};
+    use crate::{
+        AccumulatorType, BuildStrategy, CancellationToken, DapolTree, Entity, EntityId, Height,
+        MaxLiability, MaxThreadCount, Salt, Secret,
+    };
+    use std::path::{Path, PathBuf};
+    use std::str::FromStr;
+
+    fn new_tree() -> DapolTree {
+        let accumulator_type = AccumulatorType::NdmSmt;
+        let height = Height::expect_from(8);
+        let salt_b = Salt::from_str("salt_b").unwrap();
+        let salt_s = Salt::from_str("salt_s").unwrap();
+        let master_secret = Secret::from_str("master_secret").unwrap();
+        let max_liability = MaxLiability::from(10_000_000);
+        let max_thread_count = MaxThreadCount::from(8);
+        let random_seed = 1;
+
+        let entity = Entity {
+            liability: 1u64,
+            id: EntityId::from_str("id").unwrap(),
+            salt: None,
+            blinding_factor: None,
+        };
+        let entities = vec![entity.clone()];
+
+        DapolTree::new_with_options(
+            accumulator_type.clone(),
+            master_secret.clone(),
+            salt_b.clone(),
+            salt_s.clone(),
+            height.clone(),
+            entities,
+            BuildOptions::default()
+                .with_max_liability(max_liability)
+                .with_max_thread_count(max_thread_count.clone())
+                .with_random_seed(random_seed),
+        )
+        .unwrap()
+    }
+
+    mod construction {
+        use super::*;
+
+        #[test]
+        fn constructor_and_getters_work() {
+            let accumulator_type = AccumulatorType::NdmSmt;
+            let height = Height::expect_from(8);
+            let salt_b = Salt::from_str("salt_b").unwrap();
+            let salt_s = Salt::from_str("salt_s").unwrap();
+            let master_secret = Secret::from_str("master_secret").unwrap();
+            let max_liability = MaxLiability::from(10_000_000);
+            let max_thread_count = MaxThreadCount::from(8);
+            let random_seed = 1u64;
+
+            let entity = Entity {
+                liability: 1u64,
+                id: EntityId::from_str("id").unwrap(),
+                salt: None,
+                blinding_factor: None,
+            };
+            let entities = vec![entity.clone()];
+
+            let tree = DapolTree::new_with_options(
+                accumulator_type.clone(),
+                master_secret.clone(),
+                salt_b.clone(),
+                salt_s.clone(),
+                height.clone(),
+                entities,
+                BuildOptions::default()
+                    .with_max_liability(max_liability)
+                    .with_max_thread_count(max_thread_count.clone())
+                    .with_random_seed(random_seed),
+            )
+            .unwrap();
+
+            assert_eq!(tree.master_secret(), &master_secret);
+            assert_eq!(tree.height(), &height);
+            assert_eq!(tree.max_liability(), &max_liability);
+            assert_eq!(tree.salt_b(), &salt_b);
+            assert_eq!(tree.salt_s(), &salt_s);
+            assert_eq!(tree.accumulator_type(), accumulator_type);
+
+            assert!(tree.entity_mapping().is_some());
+            assert!(tree.entity_mapping().unwrap().get(&entity.id).is_some());
+        }
+
+        fn new_tree_with_cancellation_token(
+            cancellation_token: CancellationToken,
+        ) -> Result<DapolTree, DapolTreeError> {
+            let entities = (0..100)
+                .map(|i| Entity {
+                    liability: i,
+                    id: EntityId::from_str(&format!("id{}", i)).unwrap(),
+                    salt: None,
+                    blinding_factor: None,
+                })
+                .collect();
+
+            DapolTree::new_with_options(
+                AccumulatorType::NdmSmt,
+                Secret::from_str("master_secret").unwrap(),
+                Salt::from_str("salt_b").unwrap(),
+                Salt::from_str("salt_s").unwrap(),
+                Height::expect_from(16),
+                entities,
+                BuildOptions::default()
+                    .with_max_liability(MaxLiability::from(10_000_000))
+                    .with_cancellation_token(cancellation_token),
+            )
+        }
+
+        #[test]
+        fn cancelled_token_stops_the_build() {
+            let cancellation_token = CancellationToken::new();
+            cancellation_token.cancel();
+
+            let res = new_tree_with_cancellation_token(cancellation_token);
+
+            assert_err!(res, Err(DapolTreeError::BuildCancelled));
+        }
+
+        #[test]
+        fn build_completes_if_token_is_not_cancelled() {
+            let cancellation_token = CancellationToken::new();
+
+            let tree = new_tree_with_cancellation_token(cancellation_token).unwrap();
+
+            assert_eq!(tree.entity_mapping().unwrap().len(), 100);
+        }
+
+        #[test]
+        fn single_threaded_build_strategy_gives_verifiable_tree() {
+            let entities: Vec<Entity> = (0..100)
+                .map(|i| Entity {
+                    liability: i,
+                    id: EntityId::from_str(&format!("id{}", i)).unwrap(),
+                    salt: None,
+                    blinding_factor: None,
+                })
+                .collect();
+            let entity_id = entities[7].id.clone();
+
+            let tree = DapolTree::new_with_options(
+                AccumulatorType::NdmSmt,
+                Secret::from_str("master_secret").unwrap(),
+                Salt::from_str("salt_b").unwrap(),
+                Salt::from_str("salt_s").unwrap(),
+                Height::expect_from(16),
+                entities,
+                BuildOptions::default()
+                    .with_max_liability(MaxLiability::from(10_000_000))
+                    .with_build_strategy(BuildStrategy::SingleThreaded),
+            )
+            .unwrap();
+
+            assert_eq!(tree.root_liability(), (0..100).sum::<u64>());
+
+            let proof = tree.generate_inclusion_proof(&entity_id).unwrap();
+            proof.verify(*tree.root_hash()).unwrap();
+        }
+
+        #[test]
+        fn leaf_mapping_chunk_size_combined_with_other_options_gives_same_tree() {
+            let entities: Vec<Entity> = (0..100)
+                .map(|i| Entity {
+                    liability: i,
+                    id: EntityId::from_str(&format!("id{}", i)).unwrap(),
+                    salt: None,
+                    blinding_factor: None,
+                })
+                .collect();
+            let entity_id = entities[7].id.clone();
+
+            let options = BuildOptions::default()
+                .with_random_seed(1)
+                .with_domain_separator("some deployment".to_string())
+                .with_build_strategy(BuildStrategy::SingleThreaded)
+                .with_cancellation_token(CancellationToken::new());
+
+            let build = |options: BuildOptions| {
+                DapolTree::new_with_options(
+                    AccumulatorType::NdmSmt,
+                    Secret::from_str("master_secret").unwrap(),
+                    Salt::from_str("salt_b").unwrap(),
+                    Salt::from_str("salt_s").unwrap(),
+                    Height::expect_from(16),
+                    entities.clone(),
+                    options.with_max_liability(MaxLiability::from(10_000_000)),
+                )
+                .unwrap()
+            };
+
+            let tree = build(options.clone());
+            let chunked_tree = build(options.with_leaf_mapping_chunk_size(16));
+
+            assert_eq!(chunked_tree.root_hash(), tree.root_hash());
+            assert_eq!(chunked_tree.domain_separator(), Some("some deployment"));
+
+            let proof = chunked_tree.generate_inclusion_proof(&entity_id).unwrap();
+            proof.verify(*chunked_tree.root_hash()).unwrap();
+        }
+
+        #[test]
+        fn store_capacity_bytes_bounds_store_of_built_tree() {
+            let entities: Vec<Entity> = (0..100)
+                .map(|i| Entity {
+                    liability: i,
+                    id: EntityId::from_str(&format!("id{}", i)).unwrap(),
+                    salt: None,
+                    blinding_factor: None,
+                })
+                .collect();
+            let entity_id = entities[7].id.clone();
+
+            let build = |options: BuildOptions| {
+                DapolTree::new_with_options(
+                    AccumulatorType::NdmSmt,
+                    Secret::from_str("master_secret").unwrap(),
+                    Salt::from_str("salt_b").unwrap(),
+                    Salt::from_str("salt_s").unwrap(),
+                    Height::expect_from(16),
+                    entities.clone(),
+                    options
+                        .with_random_seed(1)
+                        .with_max_liability(MaxLiability::from(10_000_000)),
+                )
+                .unwrap()
+            };
+
+            let tree = build(BuildOptions::default());
+            let bounded_tree = build(BuildOptions::default().with_store_capacity_bytes(0));
+
+            assert_eq!(bounded_tree.root_hash(), tree.root_hash());
+            assert!(bounded_tree.stored_node_count() < tree.stored_node_count());
+
+            let proof = bounded_tree.generate_inclusion_proof(&entity_id).unwrap();
+            proof.verify(*bounded_tree.root_hash()).unwrap();
+        }
+
+        #[test]
+        fn leaf_mapping_chunk_size_gives_verifiable_tree() {
+            let entities: Vec<Entity> = (0..100)
+                .map(|i| Entity {
+                    liability: i,
+                    id: EntityId::from_str(&format!("id{}", i)).unwrap(),
+                    salt: None,
+                    blinding_factor: None,
+                })
+                .collect();
+            let entity_id = entities[7].id.clone();
+
+            let tree = DapolTree::new_with_options(
+                AccumulatorType::NdmSmt,
+                Secret::from_str("master_secret").unwrap(),
+                Salt::from_str("salt_b").unwrap(),
+                Salt::from_str("salt_s").unwrap(),
+                Height::expect_from(16),
+                entities,
+                BuildOptions::default()
+                    .with_max_liability(MaxLiability::from(10_000_000))
+                    .with_leaf_mapping_chunk_size(16),
+            )
+            .unwrap();
+
+            assert_eq!(tree.root_liability(), (0..100).sum::<u64>());
+            assert!(tree.verify_master_secret(&Secret::from_str("master_secret").unwrap()));
+
+            let proof = tree.generate_inclusion_proof(&entity_id).unwrap();
+            proof.verify(*tree.root_hash()).unwrap();
+        }
+    }
+
+    mod build_report {
+        use super::*;
+
+        #[test]
+        fn build_report_gives_correct_counts() {
+            let height = Height::expect_from(8);
+            let entities = vec![
+                Entity {
+                    liability: 1u64,
+                    id: EntityId::from_str("id1").unwrap(),
+                    salt: None,
+                    blinding_factor: None,
+                },
+                Entity {
+                    liability: 2u64,
+                    id: EntityId::from_str("id2").unwrap(),
+                    salt: None,
+                    blinding_factor: None,
+                },
+            ];
+
+            let (tree, report) = DapolTree::new_with_report(
+                AccumulatorType::NdmSmt,
+                Secret::from_str("master_secret").unwrap(),
+                Salt::from_str("salt_b").unwrap(),
+                Salt::from_str("salt_s").unwrap(),
+                height,
+                entities,
+                BuildOptions::default().with_max_liability(MaxLiability::from(10_000_000)),
+            )
+            .unwrap();
+
+            assert_eq!(report.height, height);
+            assert_eq!(report.entity_count, 2);
+            assert_eq!(report.padding_leaf_node_count, 126);
+            assert_eq!(
+                report.stored_node_count,
+                tree.accumulator.stored_node_count() as u64
+            );
+            // Both leaf nodes plus the root node must be stored.
+            assert!(report.stored_node_count >= 3);
+        }
+
+        #[test]
+        fn build_report_serializes_to_json() {
+            let report = BuildReport {
+                build_time: Duration::from_millis(1500),
+                height: Height::expect_from(8),
+                entity_count: 2,
+                stored_node_count: 10,
+                padding_leaf_node_count: 126,
+            };
+
+            let json = serde_json::to_value(&report).unwrap();
+            assert_eq!(json["build_time"], 1500);
+            assert_eq!(json["entity_count"], 2);
+        }
+    }
+
+    mod serde {
+        use super::*;
+
+        mod tree {
+            use super::*;
+
+            #[test]
+            fn serde_does_not_change_tree() {
+                let tree = new_tree();
+
+                let src_dir = env!("CARGO_MANIFEST_DIR");
+                let examples_dir = Path::new(&src_dir).join("examples");
+                let path = examples_dir.join("my_serialized_tree_for_testing.dapoltree");
+                let path_2 = tree.serialize(path.clone()).unwrap();
+                assert_eq!(path, path_2);
+
+                let tree_2 = DapolTree::deserialize(path).unwrap();
+
+                assert_eq!(tree.master_secret(), tree_2.master_secret());
+                assert_eq!(tree.height(), tree_2.height());
+                assert_eq!(tree.max_liability(), tree_2.max_liability());
+                assert_eq!(tree.salt_b(), tree_2.salt_b());
+                assert_eq!(tree.salt_s(), tree_2.salt_s());
+                assert_eq!(tree.accumulator_type(), tree_2.accumulator_type());
+                assert_eq!(tree.entity_mapping(), tree_2.entity_mapping());
+                assert_eq!(
+                    tree.public_root_data().generated_at,
+                    tree_2.public_root_data().generated_at
+                );
+            }
+
+            #[test]
+            fn serde_without_padding_gives_same_proofs() {
+                let tree = new_tree();
+                let entity_id = EntityId::from_str("id").unwrap();
+
+                let src_dir = env!("CARGO_MANIFEST_DIR");
+                let examples_dir = Path::new(&src_dir).join("examples");
+                let path = examples_dir.join("my_tree_without_padding_for_testing.dapoltree");
+                tree.serialize_without_padding(path.clone()).unwrap();
+
+                let tree_2 = DapolTree::deserialize(path.clone()).unwrap();
+                std::fs::remove_file(path).unwrap();
+
+                assert!(
+                    tree_2.accumulator.stored_node_count() < tree.accumulator.stored_node_count()
+                );
+                assert_eq!(tree.root_hash(), tree_2.root_hash());
+                tree_2.verify_integrity().unwrap();
+
+                // Range proofs are randomized so compare the path nodes.
+                let x_coord = *tree.entity_mapping().unwrap().get(&entity_id).unwrap();
+                for y in 0..tree.height().as_y_coord() {
+                    let sibling_coord = Coordinate {
+                        x: (x_coord >> y) ^ 1,
+                        y,
+                    };
+                    let node = tree.node_at(sibling_coord.clone()).unwrap();
+                    let node_2 = tree_2.node_at(sibling_coord).unwrap();
+
+                    assert_eq!(node.content.hash, node_2.content.hash);
+                    assert_eq!(node.content.commitment, node_2.content.commitment);
+                }
+
+                let proof = tree_2.generate_inclusion_proof(&entity_id).unwrap();
+                proof.verify(*tree.root_hash()).unwrap();
+            }
+
+            #[test]
+            fn deserializing_tree_with_different_format_version_gives_error() {
+                let src_dir = env!("CARGO_MANIFEST_DIR");
+                let examples_dir = Path::new(&src_dir).join("examples");
+                let path = examples_dir.join("my_old_serialized_tree_for_testing.dapoltree");
+
+                let found = SERIALIZED_TREE_FORMAT_VERSION + 1;
+                read_write_utils::serialize_to_bin_file(&found, path.clone()).unwrap();
+
+                let res = DapolTree::deserialize(path.clone());
+                std::fs::remove_file(path).unwrap();
+
+                assert_err!(
+                    res,
+                    Err(DapolTreeError::IncompatibleFormatVersion {
+                        found: _,
+                        supported: SERIALIZED_TREE_FORMAT_VERSION,
+                    })
+                );
+            }
+
+            #[test]
+            fn serialization_path_parser_fails_for_unsupported_extensions() {
+                let path = PathBuf::from_str("./mytree.myext").unwrap();
+
+                let res = DapolTree::parse_tree_serialization_path(path);
+                assert_err!(
+                    res,
+                    Err(read_write_utils::ReadWriteError::UnsupportedFileExtension {
+                        expected: _,
+                        actual: _
+                    })
+                );
+            }
+
+            #[test]
+            fn serialization_path_parser_gives_correct_file_prefix() {
+                let path = PathBuf::from_str("./").unwrap();
+                let path = DapolTree::parse_tree_serialization_path(path).unwrap();
+                assert!(path
+                    .to_str()
+                    .unwrap()
+                    .contains("proof_of_liabilities_merkle_sum_tree_"));
+            }
+        }
+
+        mod public_root_data {
+            use super::*;
+
+            #[test]
+            fn serde_does_not_change_public_root_data() {
+                let tree = new_tree();
+                let public_root_data = tree.public_root_data();
+
+                let src_dir = env!("CARGO_MANIFEST_DIR");
+                let examples_dir = Path::new(&src_dir).join("examples");
+                let path = examples_dir.join("my_public_root_data_for_testing.json");
+                let path_2 = tree.serialize_public_root_data(path.clone()).unwrap();
+                assert_eq!(path, path_2);
+
+                let public_root_data_2 =
+                    DapolTree::deserialize_public_root_data(path.clone()).unwrap();
+                std::fs::remove_file(path).unwrap();
+
+                assert_eq!(public_root_data, public_root_data_2);
+                assert_eq!(
+                    public_root_data.generated_at,
+                    public_root_data_2.generated_at
+                );
+                assert_eq!(
+                    public_root_data.crate_version,
+                    public_root_data_2.crate_version
+                );
+            }
+
+            #[test]
+            fn public_root_data_has_metadata() {
+                let public_root_data = new_tree().public_root_data();
+
+                assert!(public_root_data.generated_at.is_some());
+                assert_eq!(public_root_data.crate_version, env!("CARGO_PKG_VERSION"));
+            }
+
+            #[test]
+            fn public_root_data_timestamp_is_taken_from_the_tree() {
+                let tree = new_tree();
+                let public_root_data = tree.public_root_data();
+
+                std::thread::sleep(Duration::from_millis(1100));
+
+                assert_eq!(
+                    tree.public_root_data().generated_at,
+                    public_root_data.generated_at
+                );
+                assert_eq!(public_root_data.generated_at, tree.root_generated_at);
+            }
+
+            #[test]
+            fn public_root_data_without_metadata_deserializes() {
+                let src_dir = env!("CARGO_MANIFEST_DIR");
+                let examples_dir = Path::new(&src_dir).join("examples");
+                // This file was written before the metadata fields were added.
+                let path = examples_dir.join("public_root_data.json");
+
+                let public_root_data = DapolTree::deserialize_public_root_data(path).unwrap();
+
+                assert_eq!(public_root_data.generated_at, None);
+                assert_eq!(public_root_data.crate_version, "");
+            }
+
+            #[test]
+            fn equality_ignores_metadata() {
+                let public_root_data = new_tree().public_root_data();
+
+                let mut other = public_root_data.clone();
+                other.generated_at = None;
+                other.crate_version = "0.0.0".to_string();
+
+                assert_eq!(public_root_data, other);
+            }
+
+            #[test]
+            fn public_root_data_serialization_path_parser_fails_for_unsupported_extensions() {
+                let path = PathBuf::from_str("./public_root_data.myext").unwrap();
+
+                let res = DapolTree::parse_public_root_data_serialization_path(path);
+                assert_err!(
+                    res,
+                    Err(read_write_utils::ReadWriteError::UnsupportedFileExtension {
+                        expected: _,
+                        actual: _
+                    })
+                );
+            }
+
+            #[test]
+            fn public_root_data_serialization_path_parser_gives_correct_file_prefix() {
+                let path = PathBuf::from_str("./").unwrap();
+                let path = DapolTree::parse_public_root_data_serialization_path(path).unwrap();
+                assert!(path.to_str().unwrap().contains("public_root_data_"));
+            }
+        }
+
+        mod secret_root_data {
+            use super::*;
+
+            #[test]
+            fn serde_does_not_change_secret_root_data() {
+                let tree = new_tree();
+                let secret_root_data = tree.secret_root_data();
+
+                let src_dir = env!("CARGO_MANIFEST_DIR");
+                let examples_dir = Path::new(&src_dir).join("examples");
+                let path = examples_dir.join("secret_root_data.json");
+                let path_2 = tree.serialize_secret_root_data(path.clone()).unwrap();
+                assert_eq!(path, path_2);
+
+                let secret_root_data_2 = DapolTree::deserialize_secret_root_data(path).unwrap();
+
+                assert_eq!(secret_root_data, secret_root_data_2);
+            }
+
+            #[test]
+            fn secret_root_data_serialization_path_parser_fails_for_unsupported_extensions() {
+                let path = PathBuf::from_str("./secret_root_data.myext").unwrap();
+
+                let res = DapolTree::parse_secret_root_data_serialization_path(path);
+                assert_err!(
+                    res,
+                    Err(read_write_utils::ReadWriteError::UnsupportedFileExtension {
+                        expected: _,
+                        actual: _
+                    })
+                );
+            }
+
+            #[test]
+            fn secret_root_data_serialization_path_parser_gives_correct_file_prefix() {
+                let path = PathBuf::from_str("./").unwrap();
+                let path = DapolTree::parse_secret_root_data_serialization_path(path).unwrap();
+                assert!(path.to_str().unwrap().contains("secret_root_data_"));
+            }
+        }
+
+        mod entity_mapping {
+            use super::*;
+
+            #[test]
+            fn serde_does_not_change_entity_mapping() {
+                let tree = new_tree();
+
+                let src_dir = env!("CARGO_MANIFEST_DIR");
+                let examples_dir = Path::new(&src_dir).join("examples");
+                let path = examples_dir.join("entity_mapping_for_testing.json");
+                let path_2 = tree.serialize_entity_mapping(path.clone()).unwrap();
+                assert_eq!(path, path_2);
+
+                let entity_mapping = DapolTree::load_entity_mapping(path.clone(), tree.height());
+                std::fs::remove_file(path).unwrap();
+
+                assert_eq!(Some(&entity_mapping.unwrap()), tree.entity_mapping());
+            }
+
+            #[test]
+            fn loading_fails_for_duplicate_x_coords() {
+                let src_dir = env!("CARGO_MANIFEST_DIR");
+                let examples_dir = Path::new(&src_dir).join("examples");
+                let path = examples_dir.join("duplicate_entity_mapping_for_testing.json");
+                std::fs::write(&path, r#"{"id1": 3, "id2": 3}"#).unwrap();
+
+                let res = DapolTree::load_entity_mapping(path.clone(), &Height::expect_from(8));
+                std::fs::remove_file(path).unwrap();
+
+                assert_err!(
+                    res,
+                    Err(DapolTreeError::DuplicateMappedXCoord { x_coord: 3 })
+                );
+            }
+
+            #[test]
+            fn loading_fails_for_x_coord_outside_tree() {
+                let src_dir = env!("CARGO_MANIFEST_DIR");
+                let examples_dir = Path::new(&src_dir).join("examples");
+                let path = examples_dir.join("out_of_bounds_entity_mapping_for_testing.json");
+                std::fs::write(&path, r#"{"id1": 3, "id2": 128}"#).unwrap();
+
+                let res = DapolTree::load_entity_mapping(path.clone(), &Height::expect_from(8));
+                std::fs::remove_file(path).unwrap();
+
+                assert_err!(
+                    res,
+                    Err(DapolTreeError::CoordinateOutOfBounds {
+                        coord: Coordinate { x: 128, y: 0 },
+                        height: _
+                    })
+                );
+            }
+
+            #[test]
+            fn entity_mapping_serialization_path_parser_gives_correct_file_prefix() {
+                let path = PathBuf::from_str("./").unwrap();
+                let path = DapolTree::parse_entity_mapping_serialization_path(path).unwrap();
+                assert!(path.to_str().unwrap().contains("entity_mapping_"));
+            }
+        }
+    }
+
+    mod root_hash_hex {
+        use super::*;
+
+        #[test]
+        fn hash_hex_is_canonical() {
+            let tree = new_tree();
+            let hex = tree.public_root_data().hash_hex();
+
+            assert!(hex.starts_with("0x"));
+            assert_eq!(hex.len(), 66);
+            assert_eq!(hex, hex.to_lowercase());
+        }
+
+        #[test]
+        fn hash_hex_round_trips() {
+            let tree = new_tree();
+            let hex = tree.public_root_data().hash_hex();
+
+            let hash = RootPublicData::hash_from_hex(&hex).unwrap();
+            assert_eq!(&hash, tree.root_hash());
+        }
+
+        #[test]
+        fn hash_from_hex_accepts_no_prefix_and_upper_case() {
+            let tree = new_tree();
+            let hex = tree.public_root_data().hash_hex();
+            let upper_no_prefix = hex.trim_start_matches("0x").to_uppercase();
+
+            let hash = RootPublicData::hash_from_hex(&upper_no_prefix).unwrap();
+            assert_eq!(&hash, tree.root_hash());
+        }
+
+        #[test]
+        fn hash_from_hex_fails_for_wrong_length() {
+            let res = RootPublicData::hash_from_hex("0x1234");
+            assert_err!(
+                res,
+                Err(RootHashParserError::InvalidLength {
+                    found: 4,
+                    expected: 64
+                })
+            );
+        }
+
+        #[test]
+        fn hash_from_hex_fails_for_invalid_character() {
+            let hex = format!("0x{}g", "0".repeat(63));
+
+            let res = RootPublicData::hash_from_hex(&hex);
+            assert_err!(
+                res,
+                Err(RootHashParserError::InvalidCharacter {
+                    character: 'g',
+                    index: 63
+                })
+            );
+        }
+    }
+
+    mod onchain_bytes {
+        use super::*;
+
+        #[test]
+        fn onchain_bytes_round_trip() {
+            let tree = new_tree();
+            let public_root_data = tree.public_root_data();
+
+            let (hash, commitment) = public_root_data.to_onchain_bytes();
+            let decoded = RootPublicData::from_onchain_bytes(hash, commitment).unwrap();
+
+            assert_eq!(decoded, public_root_data);
+        }
+
+        #[test]
+        fn from_onchain_bytes_fails_for_invalid_commitment() {
+            let tree = new_tree();
+            let (hash, _) = tree.public_root_data().to_onchain_bytes();
+
+            let res = RootPublicData::from_onchain_bytes(hash, [0xff; 32]);
+            assert_err!(res, Err(DapolTreeError::InvalidRootCommitment));
+        }
+    }
+
+    mod public_root_comparison {
+        use super::*;
+
+        #[test]
+        fn commitment_hex_is_canonical() {
+            let tree = new_tree();
+            let hex = tree.public_root_data().commitment_hex();
+
+            assert!(hex.starts_with("0x"));
+            assert_eq!(hex.len(), 66);
+            assert_eq!(hex, hex.to_lowercase());
+            assert_eq!(hex, new_tree().public_root_data().commitment_hex());
+        }
+
+        #[test]
+        fn tree_matches_its_own_public_root() {
+            let tree = new_tree();
+            assert!(tree.matches_public_root(&tree.public_root_data()));
+        }
+
+        #[test]
+        fn tree_does_not_match_modified_public_root() {
+            let tree = new_tree();
+
+            let mut wrong_hash = tree.public_root_data();
+            wrong_hash.hash = H256::default();
+            assert!(!tree.matches_public_root(&wrong_hash));
+
+            let mut wrong_commitment = tree.public_root_data();
+            wrong_commitment.commitment = RistrettoPoint::default();
+            assert!(!tree.matches_public_root(&wrong_commitment));
+        }
+    }
+
+    mod inclusion_proofs {
+        use super::*;
+
+        #[test]
+        fn generate_inclusion_proof_works() {
+            let tree = new_tree();
+            assert!(tree
+                .generate_inclusion_proof(&EntityId::from_str("id").unwrap())
+                .is_ok());
+        }
+
+        #[test]
+        fn generate_inclusion_proof_with_aggregation_factor_works() {
+            let tree = new_tree();
+            let agg = AggregationFactor::Divisor(2u8);
+            assert!(tree
+                .generate_inclusion_proof_with(&EntityId::from_str("id").unwrap(), agg)
+                .is_ok());
+        }
+
+        #[test]
+        fn regenerate_proof_with_gives_verifiable_proof() {
+            let tree = new_tree();
+            let agg = AggregationFactor::Number(3u8);
+
+            let proof = tree
+                .regenerate_proof_with(&EntityId::from_str("id").unwrap(), agg, 32u8)
+                .unwrap();
+
+            proof.verify(*tree.root_hash()).unwrap();
+        }
+
+        #[test]
+        fn proofs_for_aggregation_factors_are_in_order_and_verifiable() {
+            use crate::percentage::ONE_HUNDRED_PERCENT;
+
+            let tree = new_tree();
+            let aggregation_factors = vec![
+                AggregationFactor::Number(0u8),
+                AggregationFactor::Percent(ONE_HUNDRED_PERCENT),
+            ];
+
+            let proofs = tree
+                .generate_inclusion_proofs_for_aggregation_factors(
+                    &EntityId::from_str("id").unwrap(),
+                    &aggregation_factors,
+                )
+                .unwrap();
+
+            assert_eq!(proofs.len(), 2);
+            for proof in proofs.iter() {
+                proof.verify(*tree.root_hash()).unwrap();
+            }
+
+            let fully_individual = proofs[0].to_string();
+            let fully_aggregated = proofs[1].to_string();
+            assert!(fully_individual.contains("aggregated ranges: 0"));
+            assert!(fully_aggregated.contains("individual range proofs: 0"));
+        }
+
+        #[test]
+        fn recommended_aggregation_factor_for_min_size_is_fully_aggregated() {
+            use crate::percentage::ONE_HUNDRED_PERCENT;
+
+            let tree = new_tree();
+            let aggregation_factor = tree
+                .recommend_aggregation_factor(
+                    &EntityId::from_str("id").unwrap(),
+                    OptimizationTarget::MinSize,
+                )
+                .unwrap();
+
+            assert!(aggregation_factor.is_max(tree.height()));
+            assert_eq!(
+                aggregation_factor.to_string(),
+                AggregationFactor::Percent(ONE_HUNDRED_PERCENT).to_string()
+            );
+        }
+
+        #[test]
+        fn recommend_aggregation_factor_fails_for_unknown_entity() {
+            let tree = new_tree();
+            let res = tree.recommend_aggregation_factor(
+                &EntityId::from_str("unknown").unwrap(),
+                OptimizationTarget::Balanced,
+            );
+            assert!(res.is_err());
+        }
+
+        #[test]
+        fn proof_verifies_against_public_root_file() {
+            let tree = new_tree();
+            let proof = tree
+                .generate_inclusion_proof(&EntityId::from_str("id").unwrap())
+                .unwrap();
+
+            let path = std::env::temp_dir().join("dapol_verify_against_public_root.json");
+            tree.serialize_public_root_data(path.clone()).unwrap();
+
+            proof.verify_against_public_root_file(path).unwrap();
+        }
+
+        #[test]
+        fn proof_fails_against_public_root_with_wrong_commitment() {
+            use crate::InclusionProofError;
+
+            let tree = new_tree();
+            let proof = tree
+                .generate_inclusion_proof(&EntityId::from_str("id").unwrap())
+                .unwrap();
+
+            let mut public_root_data = tree.public_root_data();
+            public_root_data.commitment = RistrettoPoint::default();
+
+            assert_err!(
+                proof.verify_against_public_root_data(&public_root_data),
+                Err(InclusionProofError::RootCommitmentMismatch)
+            );
+        }
+
+        #[test]
+        fn proof_fails_against_missing_public_root_file() {
+            use crate::InclusionProofError;
+
+            let tree = new_tree();
+            let proof = tree
+                .generate_inclusion_proof(&EntityId::from_str("id").unwrap())
+                .unwrap();
+
+            let path = std::env::temp_dir().join("dapol_public_root_that_does_not_exist.json");
+
+            assert_err!(
+                proof.verify_against_public_root_file(path),
+                Err(InclusionProofError::PublicRootDataReadError(_))
+            );
+        }
+
+        #[test]
+        fn proof_by_coord_verifies_for_entity_leaf() {
+            let tree = new_tree();
+            let x_coord = *tree
+                .entity_mapping()
+                .unwrap()
+                .get(&EntityId::from_str("id").unwrap())
+                .unwrap();
+
+            let proof = tree.generate_inclusion_proof_by_coord(x_coord).unwrap();
+            proof.verify(*tree.root_hash()).unwrap();
+        }
+
+        #[test]
+        fn proof_by_coord_verifies_for_padding_leaf() {
+            let tree = new_tree();
+            let entity_x_coord = *tree
+                .entity_mapping()
+                .unwrap()
+                .get(&EntityId::from_str("id").unwrap())
+                .unwrap();
+            let x_coord = if entity_x_coord == 0 { 1 } else { 0 };
+
+            let proof = tree.generate_inclusion_proof_by_coord(x_coord).unwrap();
+            proof.verify(*tree.root_hash()).unwrap();
+        }
+
+        #[test]
+        fn proof_verifies_for_its_entity() {
+            let tree = new_tree();
+            let entity_id = EntityId::from_str("id").unwrap();
+            let proof = tree.generate_inclusion_proof(&entity_id).unwrap();
+
+            proof
+                .verify_entity(&entity_id, tree.master_secret(), tree.salt_s())
+                .unwrap();
+        }
+
+        #[test]
+        fn proof_does_not_verify_for_other_entity() {
+            use crate::InclusionProofError;
+
+            let tree = new_tree();
+            let proof = tree
+                .generate_inclusion_proof(&EntityId::from_str("id").unwrap())
+                .unwrap();
+            let other_entity_id = EntityId::from_str("other id").unwrap();
+
+            assert_err!(
+                proof.verify_entity(&other_entity_id, tree.master_secret(), tree.salt_s()),
+                Err(InclusionProofError::EntityMismatch(_))
+            );
+        }
+
+        #[test]
+        fn proof_verifies_for_its_entity_with_domain_separator() {
+            use crate::InclusionProofError;
+
+            let entity_id = EntityId::from_str("id").unwrap();
+            let entity = Entity {
+                liability: 1u64,
+                id: entity_id.clone(),
+                salt: None,
+                blinding_factor: None,
+            };
+            let tree = DapolTree::new_with_options(
+                AccumulatorType::NdmSmt,
+                Secret::from_str("master_secret").unwrap(),
+                Salt::from_str("salt_b").unwrap(),
+                Salt::from_str("salt_s").unwrap(),
+                Height::expect_from(8),
+                vec![entity],
+                BuildOptions::default()
+                    .with_max_liability(MaxLiability::from(10_000_000))
+                    .with_domain_separator("my_domain".to_string()),
+            )
+            .unwrap();
+            let proof = tree.generate_inclusion_proof(&entity_id).unwrap();
+
+            proof
+                .verify_entity_with_domain_separator(
+                    &entity_id,
+                    tree.master_secret(),
+                    tree.salt_s(),
+                    "my_domain",
+                )
+                .unwrap();
+            assert_err!(
+                proof.verify_entity(&entity_id, tree.master_secret(), tree.salt_s()),
+                Err(InclusionProofError::EntityMismatch(_))
+            );
+        }
+
+        #[test]
+        fn proof_by_coord_fails_for_coord_outside_bottom_layer() {
+            let tree = new_tree();
+            let x_coord = tree.height().max_bottom_layer_nodes();
+
+            assert_err!(
+                tree.generate_inclusion_proof_by_coord(x_coord),
+                Err(NdmSmtError::XCoordOutOfBounds { .. })
+            );
+        }
+    }
+
+    mod inclusion_proofs_iter {
+        use super::*;
+
+        #[test]
+        fn proofs_are_generated_in_order_and_verify() {
+            let tree = new_tree();
+            let entity_ids = vec![EntityId::from_str("id").unwrap(); 3];
+
+            let mut count = 0;
+            for (entity_id, proof) in tree.inclusion_proofs_iter(&entity_ids) {
+                assert_eq!(entity_id, entity_ids[count]);
+                proof.unwrap().verify(*tree.root_hash()).unwrap();
+                count += 1;
+            }
+
+            assert_eq!(count, entity_ids.len());
+        }
+
+        #[test]
+        fn unknown_entity_gives_error_without_stopping_iteration() {
+            let tree = new_tree();
+            let entity_ids = vec![
+                EntityId::from_str("unknown").unwrap(),
+                EntityId::from_str("id").unwrap(),
+            ];
+
+            let results: Vec<_> = tree.inclusion_proofs_iter(&entity_ids).collect();
+
+            assert_eq!(results.len(), 2);
+            assert_err!(results[0].1, Err(NdmSmtError::EntityIdNotFound(_)));
+            assert!(results[1].1.is_ok());
+        }
+
+        #[test]
+        fn iterator_can_be_advanced_one_proof_at_a_time() {
+            let tree = new_tree();
+            let entity_ids = vec![
+                EntityId::from_str("id").unwrap(),
+                EntityId::from_str("unknown").unwrap(),
+            ];
+
+            let mut iter = tree.inclusion_proofs_iter(&entity_ids);
+            let (entity_id, proof) = iter.next().unwrap();
+
+            assert_eq!(entity_id, entity_ids[0]);
+            assert!(proof.is_ok());
+            assert_eq!(iter.size_hint(), (1, Some(1)));
+        }
+    }
+
+    mod membership_proof {
+        use super::*;
+        use crate::MembershipProofError;
+
+        fn new_tree_with_entities(num_entities: u64) -> DapolTree {
+            new_test_tree(6, test_entities(num_entities), 1)
+        }
+
+        #[test]
+        fn proof_verifies_for_all_entities() {
+            let tree = new_tree_with_entities(8);
+
+            for i in 0..8 {
+                let entity_id = EntityId::from_str(&format!("id{}", i)).unwrap();
+                let proof = tree.generate_membership_proof(&entity_id).unwrap();
+                proof.verify_membership_only(*tree.root_hash()).unwrap();
+            }
+        }
+
+        #[test]
+        fn proof_fails_for_wrong_root_hash() {
+            let tree = new_tree_with_entities(8);
+            let entity_id = EntityId::from_str("id0").unwrap();
+
+            let proof = tree.generate_membership_proof(&entity_id).unwrap();
+
+            assert_err!(
+                proof.verify_membership_only(H256::zero()),
+                Err(MembershipProofError::RootMismatch)
+            );
+        }
+
+        #[test]
+        fn leaf_node_matches_tree_leaf_node() {
+            let tree = new_tree_with_entities(8);
+            let entity_id = EntityId::from_str("id3").unwrap();
+
+            let proof = tree.generate_membership_proof(&entity_id).unwrap();
+
+            assert_eq!(proof.leaf_node(), &tree.leaf_node_for(&entity_id).unwrap());
+        }
+
+        #[test]
+        fn proof_is_smaller_than_inclusion_proof() {
+            let tree = new_tree_with_entities(8);
+            let entity_id = EntityId::from_str("id0").unwrap();
+
+            let membership_proof = tree.generate_membership_proof(&entity_id).unwrap();
+            let inclusion_proof = tree.generate_inclusion_proof(&entity_id).unwrap();
+
+            let membership_proof_size = bincode::serialize(&membership_proof).unwrap().len();
+            let inclusion_proof_size = bincode::serialize(&inclusion_proof).unwrap().len();
+            assert!(membership_proof_size < inclusion_proof_size);
+        }
+
+        #[test]
+        fn unknown_entity_gives_error() {
+            let tree = new_tree_with_entities(8);
+            let entity_id = EntityId::from_str("unknown").unwrap();
+
+            assert_err!(
+                tree.generate_membership_proof(&entity_id),
+                Err(NdmSmtError::EntityIdNotFound(_))
+            );
+        }
+    }
+
+    mod joint_inclusion_proof {
+        use super::*;
+        use crate::JointInclusionProofError;
+
+        fn new_tree_with_entities(num_entities: u64) -> DapolTree {
+            new_test_tree(6, test_entities(num_entities), 1)
+        }
+
+        #[test]
+        fn proof_verifies_for_all_pairs_of_entities() {
+            let tree = new_tree_with_entities(8);
+
+            for a in 0..8 {
+                for b in (a + 1)..8 {
+                    let entity_id_a = EntityId::from_str(&format!("id{}", a)).unwrap();
+                    let entity_id_b = EntityId::from_str(&format!("id{}", b)).unwrap();
+
+                    let proof = tree
+                        .generate_joint_inclusion_proof(&entity_id_a, &entity_id_b)
+                        .unwrap();
+
+                    proof.verify(*tree.root_hash()).unwrap();
+
+                    // 2 independent proofs would need 2 * (height - 1) siblings.
+                    assert!(proof.sibling_count() < 2 * tree.height().as_usize() - 2);
+                }
+            }
+        }
+
+        #[test]
+        fn proof_fails_for_wrong_root_hash() {
+            let tree = new_tree_with_entities(8);
+            let entity_id_a = EntityId::from_str("id0").unwrap();
+            let entity_id_b = EntityId::from_str("id1").unwrap();
+
+            let proof = tree
+                .generate_joint_inclusion_proof(&entity_id_a, &entity_id_b)
+                .unwrap();
+
+            assert_err!(
+                proof.verify(H256::default()),
+                Err(JointInclusionProofError::RootMismatch)
+            );
+        }
+
+        #[test]
+        fn same_entity_twice_gives_error() {
+            let tree = new_tree_with_entities(8);
+            let entity_id = EntityId::from_str("id0").unwrap();
+
+            assert_err!(
+                tree.generate_joint_inclusion_proof(&entity_id, &entity_id),
+                Err(NdmSmtError::JointInclusionProofGenerationError(
+                    JointInclusionProofError::SameLeafNode(_)
+                ))
+            );
+        }
+
+        #[test]
+        fn unknown_entity_gives_error() {
+            let tree = new_tree_with_entities(8);
+            let entity_id_a = EntityId::from_str("id0").unwrap();
+            let entity_id_b = EntityId::from_str("not-in-tree").unwrap();
+
+            assert_err!(
+                tree.generate_joint_inclusion_proof(&entity_id_a, &entity_id_b),
+                Err(NdmSmtError::EntityIdNotFound(_))
+            );
+        }
+    }
+
+    mod subtree_proofs {
+        use super::*;
+        use crate::binary_tree::Coordinate;
+
+        fn new_tree_with_entities(num_entities: u64) -> DapolTree {
+            new_test_tree(4, test_entities(num_entities), 1)
+        }
+
+        #[test]
+        fn root_coord_gives_proofs_for_all_entities() {
+            let tree = new_tree_with_entities(5);
+            let coord = Coordinate {
+                x: 0,
+                y: tree.height().as_y_coord(),
+            };
+
+            let proofs = tree.generate_inclusion_proofs_for_subtree(coord).unwrap();
+
+            assert_eq!(proofs.len(), 5);
+            for (_, proof) in proofs {
+                proof.verify(*tree.root_hash()).unwrap();
+            }
+        }
+
+        #[test]
+        fn only_entities_in_subtree_are_included() {
+            let tree = new_tree_with_entities(5);
+            let mapping = tree.entity_mapping().unwrap();
+
+            // Left half of the bottom layer.
+            let coord = Coordinate { x: 0, y: 2 };
+            let (x_min, x_max) = coord.subtree_x_coord_bounds();
+
+            let proofs = tree.generate_inclusion_proofs_for_subtree(coord).unwrap();
+
+            let expected_count = mapping
+                .values()
+                .filter(|x| (x_min..=x_max).contains(*x))
+                .count();
+            assert_eq!(proofs.len(), expected_count);
+
+            for (id, proof) in proofs {
+                let x = *mapping.get(&id).unwrap();
+                assert!(x_min <= x && x <= x_max);
+                proof.verify(*tree.root_hash()).unwrap();
+            }
+        }
+
+        #[test]
+        fn padding_only_subtree_gives_no_proofs() {
+            let tree = new_tree_with_entities(5);
+            let mapping = tree.entity_mapping().unwrap();
+
+            let padding_x = (0..tree.height().max_bottom_layer_nodes())
+                .find(|x| !mapping.values().any(|mapped_x| mapped_x == x))
+                .unwrap();
+
+            let proofs = tree
+                .generate_inclusion_proofs_for_subtree(Coordinate { x: padding_x, y: 0 })
+                .unwrap();
+
+            assert!(proofs.is_empty());
+        }
+
+        #[test]
+        fn coord_outside_tree_gives_error() {
+            let tree = new_tree_with_entities(5);
+            let coord = Coordinate {
+                x: 0,
+                y: tree.height().as_u8(),
+            };
+
+            let res = tree.generate_inclusion_proofs_for_subtree(coord);
+            assert_err!(res, Err(DapolTreeError::CoordinateOutOfBounds { .. }));
+        }
+    }
+
+    mod compact_proof_store {
+        use super::*;
+
+        #[test]
+        fn compact_proof_store_gives_verifiable_proofs() {
+            let tree = new_tree();
+            let entity_id = EntityId::from_str("id").unwrap();
+
+            let proof_store = tree.to_compact_proof_store();
+
+            let proof = proof_store.generate_inclusion_proof(&entity_id).unwrap();
+            proof.verify(*tree.root_hash()).unwrap();
+        }
+
+        #[test]
+        fn deserialized_compact_proof_store_gives_verifiable_proofs() {
+            let tree = new_tree();
+            let entity_id = EntityId::from_str("id").unwrap();
+
+            let src_dir = env!("CARGO_MANIFEST_DIR");
+            let examples_dir = Path::new(&src_dir).join("examples");
+            let path = examples_dir.join("my_compact_proof_store_for_testing.dapolproofstore");
+            tree.to_compact_proof_store()
+                .serialize(path.clone())
+                .unwrap();
+
+            let proof_store = CompactProofStore::deserialize(path.clone()).unwrap();
+            std::fs::remove_file(path).unwrap();
+
+            let proof = proof_store.generate_inclusion_proof(&entity_id).unwrap();
+            proof.verify(*tree.root_hash()).unwrap();
+        }
+
+        #[test]
+        fn compact_proof_store_is_smaller_than_tree() {
+            let tree = new_tree();
+
+            let tree_size = bincode::serialize(&tree).unwrap().len();
+            let proof_store_size = bincode::serialize(&tree.to_compact_proof_store())
+                .unwrap()
+                .len();
+
+            assert!(proof_store_size < tree_size);
+        }
+    }
+
+    mod proof_server {
+        use super::*;
+
+        #[test]
+        fn proof_server_gives_verifiable_proofs() {
+            let tree = new_tree();
+            let root_hash = *tree.root_hash();
+            let entity_id = EntityId::from_str("id").unwrap();
+
+            let proof_server = tree.into_proof_server().unwrap();
+            assert_eq!(proof_server.root_hash(), &root_hash);
+
+            let proof = proof_server.generate_inclusion_proof(&entity_id).unwrap();
+            proof.verify(root_hash).unwrap();
+        }
+
+        #[test]
+        fn proof_server_gives_error_for_unknown_entity() {
+            let proof_server = new_tree().into_proof_server().unwrap();
+            let entity_id = EntityId::from_str("unknown").unwrap();
+
+            let res = proof_server.generate_inclusion_proof(&entity_id);
+            assert_err!(res, Err(NdmSmtError::EntityIdNotFound(_)));
+        }
+
+        #[test]
+        fn proof_server_serialization_does_not_contain_master_secret() {
+            let tree = new_tree();
+            let master_secret_bytes = *tree.master_secret().as_bytes();
+
+            let proof_server = tree.into_proof_server().unwrap();
+            let encoded = bincode::serialize(&proof_server).unwrap();
+
+            assert!(!encoded
+                .windows(master_secret_bytes.len())
+                .any(|window| window == master_secret_bytes));
+        }
+    }
+
+    #[cfg(feature = "async")]
+    mod async_wrappers {
+        use super::*;
+
+        fn runtime() -> tokio::runtime::Runtime {
+            tokio::runtime::Builder::new_current_thread()
+                .build()
+                .unwrap()
+        }
+
+        #[test]
+        fn new_async_builds_tree() {
+            let entities = vec![Entity {
+                liability: 1u64,
+                id: EntityId::from_str("id").unwrap(),
+                salt: None,
+                blinding_factor: None,
+            }];
+
+            let tree = runtime()
+                .block_on(DapolTree::new_async(
+                    AccumulatorType::NdmSmt,
+                    Secret::from_str("master_secret").unwrap(),
+                    Salt::from_str("salt_b").unwrap(),
+                    Salt::from_str("salt_s").unwrap(),
+                    MaxLiability::from(10_000_000),
+                    MaxThreadCount::from(8),
+                    Height::expect_from(8),
+                    entities,
+                ))
+                .unwrap();
+
+            assert_eq!(tree.root_liability(), 1);
+        }
+
+        #[test]
+        fn deserialize_async_gives_same_tree() {
+            let tree = new_tree();
+            let path = std::env::temp_dir().join("async_serialized_tree_for_testing.dapoltree");
+            tree.serialize(path.clone()).unwrap();
+
+            let tree_2 = runtime()
+                .block_on(DapolTree::deserialize_async(path.clone()))
+                .unwrap();
+            std::fs::remove_file(path).unwrap();
+
+            assert_eq!(tree.root_hash(), tree_2.root_hash());
+            assert_eq!(tree.entity_mapping(), tree_2.entity_mapping());
+        }
+    }
+
+    mod zeroize {
+        use super::*;
+
+        #[test]
+        fn zeroize_wipes_master_secret() {
+            let mut tree = new_tree();
+            assert_ne!(tree.master_secret().as_bytes(), &[0u8; 32]);
+
+            tree.zeroize();
+            assert_eq!(tree.master_secret().as_bytes(), &[0u8; 32]);
+        }
+
+        #[test]
+        fn zeroize_does_not_affect_clone_used_for_serialization() {
+            let tree = new_tree();
+            let mut tree_clone = tree.clone();
+            tree_clone.zeroize();
+
+            let encoded = bincode::serialize(&tree).unwrap();
+            let decoded: DapolTree = bincode::deserialize(&encoded).unwrap();
+
+            assert_eq!(decoded.master_secret(), tree.master_secret());
+        }
+    }
+
+    mod clone {
+        use super::*;
+
+        #[test]
+        fn cloned_tree_matches_original() {
+            let tree = new_tree();
+            let tree_clone = tree.clone();
+
+            assert_eq!(tree.root_hash(), tree_clone.root_hash());
+            assert_eq!(tree.root_commitment(), tree_clone.root_commitment());
+            assert_eq!(tree.entity_mapping(), tree_clone.entity_mapping());
+            assert_eq!(tree.master_secret(), tree_clone.master_secret());
+        }
+
+        #[test]
+        fn cloned_tree_can_be_used_from_another_thread() {
+            let tree = new_tree();
+            let tree_clone = tree.clone();
+            let entity_id = EntityId::from_str("id").unwrap();
+
+            let proof = std::thread::spawn(move || {
+                tree_clone.generate_inclusion_proof(&entity_id).unwrap()
+            })
+            .join()
+            .unwrap();
+
+            proof.verify(*tree.root_hash()).unwrap();
+        }
+    }
+
+    mod audit_summary {
+        use super::*;
+
+        #[test]
+        fn audit_summary_gives_correct_public_data() {
+            let tree = new_tree();
+            let summary = tree.audit_summary();
+
+            assert_eq!(summary.accumulator_type, tree.accumulator_type());
+            assert_eq!(&summary.height, tree.height());
+            assert_eq!(&summary.root_hash, tree.root_hash());
+            assert_eq!(&summary.root_commitment, tree.root_commitment());
+            assert_eq!(summary.entity_count, 1);
+            assert_eq!(summary.salt_b.len(), 2 + 64);
+            assert!(summary.salt_b.starts_with("0x"));
+        }
+
+        #[test]
+        fn audit_summary_does_not_contain_secrets() {
+            let tree = new_tree();
+            let json = serde_json::to_string(&tree.audit_summary()).unwrap();
+
+            let master_secret_hex = bytes_to_hex(tree.master_secret().as_bytes());
+            assert!(!json.contains(&master_secret_hex));
+            assert!(!json.contains("master_secret"));
+            assert!(!json.contains("liability"));
+        }
+    }
+
+    mod commitment_equality {
+        use super::*;
+        use crate::EqualityProofError;
+
+        fn new_tree_with_liability(liability: u64, random_seed: u64) -> DapolTree {
+            let entity = Entity {
+                liability,
+                id: EntityId::from_str("id").unwrap(),
+                salt: None,
+                blinding_factor: None,
+            };
+
+            new_test_tree(8, vec![entity], random_seed)
+        }
+
+        #[test]
+        fn proof_verifies_for_trees_with_same_total() {
+            let tree_a = new_tree_with_liability(1000, 1);
+            let tree_b = new_tree_with_liability(1000, 2);
+            assert_ne!(tree_a.root_commitment(), tree_b.root_commitment());
+
+            let proof = DapolTree::prove_commitment_equality(
+                tree_b.root_commitment(),
+                &tree_a.secret_root_data(),
+                &tree_b.secret_root_data().blinding_factor,
+                0,
+            )
+            .unwrap();
+
+            proof
+                .verify(tree_a.root_commitment(), tree_b.root_commitment())
+                .unwrap();
+        }
+
+        #[test]
+        fn proof_verifies_for_trees_with_known_difference() {
+            let tree_a = new_tree_with_liability(1500, 1);
+            let tree_b = new_tree_with_liability(1000, 2);
+
+            let proof = DapolTree::prove_commitment_equality(
+                tree_b.root_commitment(),
+                &tree_a.secret_root_data(),
+                &tree_b.secret_root_data().blinding_factor,
+                500,
+            )
+            .unwrap();
+
+            proof
+                .verify(tree_a.root_commitment(), tree_b.root_commitment())
+                .unwrap();
+        }
+
+        #[test]
+        fn proof_fails_for_trees_with_different_totals() {
+            let tree_a = new_tree_with_liability(1001, 1);
+            let tree_b = new_tree_with_liability(1000, 2);
+
+            assert_err!(
+                DapolTree::prove_commitment_equality(
+                    tree_b.root_commitment(),
+                    &tree_a.secret_root_data(),
+                    &tree_b.secret_root_data().blinding_factor,
+                    0,
+                ),
+                Err(EqualityProofError::StatementNotSatisfied)
+            );
+        }
+    }
+
+    mod liability_stats {
+        use super::*;
+
+        #[test]
+        fn stats_match_entity_liabilities_and_root() {
+            let entities: Vec<Entity> = (1..=10)
+                .map(|i| Entity {
+                    liability: i,
+                    id: EntityId::from_str(&format!("id{}", i)).unwrap(),
+                    salt: None,
+                    blinding_factor: None,
+                })
+                .collect();
+
+            let tree = DapolTree::new_with_options(
+                AccumulatorType::NdmSmt,
+                Secret::from_str("master_secret").unwrap(),
+                Salt::from_str("salt_b").unwrap(),
+                Salt::from_str("salt_s").unwrap(),
+                Height::expect_from(8),
+                entities,
+                BuildOptions::default()
+                    .with_max_liability(MaxLiability::from(10_000_000))
+                    .with_random_seed(1),
+            )
+            .unwrap();
+
+            let stats = tree.liability_stats();
+
+            assert_eq!(
+                stats,
+                LiabilityStats {
+                    count: 10,
+                    sum: 55,
+                    min: 1,
+                    max: 10,
+                    mean: 5.5,
+                }
+            );
+            assert_eq!(stats.sum, tree.root_liability());
+        }
+    }
+
+    mod domain_separation {
+        use super::*;
+
+        fn new_domain_separated_tree(domain_separator: &str) -> DapolTree {
+            let entities = vec![
+                Entity {
+                    liability: 1u64,
+                    id: EntityId::from_str("id1").unwrap(),
+                    salt: None,
+                    blinding_factor: None,
+                },
+                Entity {
+                    liability: 2u64,
+                    id: EntityId::from_str("id2").unwrap(),
+                    salt: None,
+                    blinding_factor: None,
+                },
+            ];
+
+            DapolTree::new_with_options(
+                AccumulatorType::NdmSmt,
+                Secret::from_str("master_secret").unwrap(),
+                Salt::from_str("salt_b").unwrap(),
+                Salt::from_str("salt_s").unwrap(),
+                Height::expect_from(8),
+                entities,
+                BuildOptions::default()
+                    .with_max_liability(MaxLiability::from(10_000_000))
+                    .with_domain_separator(domain_separator.to_string()),
+            )
+            .unwrap()
+        }
+
+        #[test]
+        fn proofs_verify_for_domain_separated_tree() {
+            let tree = new_domain_separated_tree("deployment 1");
+            assert_eq!(tree.domain_separator(), Some("deployment 1"));
+
+            let proof = tree
+                .generate_inclusion_proof(&EntityId::from_str("id1").unwrap())
+                .unwrap();
+            proof.verify(*tree.root_hash()).unwrap();
+
+            tree.verify_integrity().unwrap();
+        }
+
+        #[test]
+        fn tree_without_domain_separator_has_none() {
+            assert_eq!(new_tree().domain_separator(), None);
+        }
+
+        #[test]
+        fn serde_does_not_change_domain_separator() {
+            let tree = new_domain_separated_tree("deployment 2");
+
+            let src_dir = env!("CARGO_MANIFEST_DIR");
+            let examples_dir = Path::new(&src_dir).join("examples");
+            let path = examples_dir.join("my_domain_separated_tree_for_testing.dapoltree");
+            tree.serialize(path.clone()).unwrap();
+
+            let tree_2 = DapolTree::deserialize(path.clone()).unwrap();
+            std::fs::remove_file(path).unwrap();
+
+            assert_eq!(tree_2.domain_separator(), Some("deployment 2"));
+            tree_2.verify_integrity().unwrap();
+        }
+    }
+
+    mod integrity {
+        use super::*;
+
+        #[test]
+        fn new_tree_passes_integrity_check() {
+            let tree = new_tree();
+            tree.verify_integrity().unwrap();
+        }
+
+        #[test]
+        fn deserialized_tree_passes_integrity_check() {
+            let tree = new_tree();
+
+            let src_dir = env!("CARGO_MANIFEST_DIR");
+            let examples_dir = Path::new(&src_dir).join("examples");
+            let path = examples_dir.join("my_integrity_checked_tree_for_testing.dapoltree");
+            tree.serialize(path.clone()).unwrap();
+
+            let tree = DapolTree::deserialize(path.clone()).unwrap();
+            std::fs::remove_file(path).unwrap();
+
+            tree.verify_integrity().unwrap();
+        }
+
+        #[test]
+        fn integrity_check_of_sparse_tree_at_default_height_works() {
+            // The bottom layer has 2^31 nodes, so this only finishes in
+            // reasonable time if the check does not scan the whole layer.
+            let entities = (0..10)
+                .map(|i| Entity {
+                    liability: i,
+                    id: EntityId::from_str(&format!("id{}", i)).unwrap(),
+                    salt: None,
+                    blinding_factor: None,
+                })
+                .collect();
+
+            let tree = DapolTree::new(
+                AccumulatorType::NdmSmt,
+                Secret::from_str("master_secret").unwrap(),
+                Salt::from_str("salt_b").unwrap(),
+                Salt::from_str("salt_s").unwrap(),
+                MaxLiability::from(10_000_000),
+                MaxThreadCount::from(8),
+                Height::default(),
+                entities,
+            )
+            .unwrap();
+
+            let start = std::time::Instant::now();
+            tree.verify_integrity().unwrap();
+            assert!(start.elapsed() < std::time::Duration::from_secs(10));
+        }
+
+        #[test]
+        fn tree_with_corrupted_salt_fails_integrity_check() {
+            let mut tree = new_tree();
+
+            // The padding nodes are regenerated using salt_s so they will not
+            // match the stored ones.
+            tree.salt_s = Salt::from_str("corrupted_salt").unwrap();
+
+            let res = tree.verify_integrity();
+            assert_err!(res, Err(DapolTreeError::IntegrityCheckFailed));
+        }
+
+        #[test]
+        fn new_tree_has_all_leaves_present() {
+            let tree = new_tree();
+            tree.verify_leaves_present().unwrap();
+        }
+
+        #[test]
+        fn deserialized_tree_without_padding_has_all_leaves_present() {
+            let tree = new_tree();
+
+            let src_dir = env!("CARGO_MANIFEST_DIR");
+            let examples_dir = Path::new(&src_dir).join("examples");
+            let path = examples_dir.join("my_leaves_present_tree_for_testing.dapoltree");
+            tree.serialize_without_padding(path.clone()).unwrap();
+
+            let tree = DapolTree::deserialize(path.clone()).unwrap();
+            std::fs::remove_file(path).unwrap();
+
+            tree.verify_leaves_present().unwrap();
+        }
+    }
+
+    mod verify_root_commitments_batch {
+        use super::*;
+
+        fn root_pairs(num_roots: u64) -> Vec<(RistrettoPoint, RootSecretData)> {
+            (0..num_roots)
+                .map(|i| {
+                    let secret_root_data = RootSecretData {
+                        liability: i * 100,
+                        blinding_factor: Scalar::from(i + 7),
+                    };
+                    let commitment = PedersenGens::default().commit(
+                        Scalar::from(secret_root_data.liability),
+                        secret_root_data.blinding_factor,
+                    );
+                    (commitment, secret_root_data)
+                })
+                .collect()
+        }
+
+        #[test]
+        fn passes_for_matching_roots() {
+            DapolTree::verify_root_commitments_batch(&root_pairs(10)).unwrap();
+        }
+
+        #[test]
+        fn passes_for_no_roots() {
+            DapolTree::verify_root_commitments_batch(&[]).unwrap();
+        }
+
+        #[test]
+        fn passes_for_tree_root() {
+            let tree = new_tree();
+            let pairs = vec![(*tree.root_commitment(), tree.secret_root_data())];
+            DapolTree::verify_root_commitments_batch(&pairs).unwrap();
+        }
+
+        #[test]
+        fn gives_index_of_first_mismatch() {
+            let mut pairs = root_pairs(10);
+            pairs[3].1.liability += 1;
+            pairs[7].1.blinding_factor = Scalar::from(1u64);
+
+            assert_err!(
+                DapolTree::verify_root_commitments_batch(&pairs),
+                Err(DapolTreeError::BatchRootVerificationError { index: 3 })
+            );
+        }
+    }
+
+    mod expected_store_size {
+        use super::*;
+
+        #[test]
+        fn bounds_stored_node_count_of_built_tree() {
+            let tree = new_tree();
+            let height = *tree.height();
+
+            let expected = DapolTree::expected_store_size(height, 1, height.as_u8());
+
+            assert!(tree.stored_node_count() as u64 <= expected);
+        }
+    }
+
+    mod to_dot {
+        use super::*;
+
+        #[test]
+        fn includes_all_stored_nodes_and_edges() {
+            let tree = new_tree();
+            let dot = tree.to_dot(usize::MAX);
+
+            assert!(dot.starts_with("digraph dapol_tree {"));
+            assert!(dot.ends_with("}\n"));
+
+            let node_lines = dot.lines().filter(|line| line.contains("[label=")).count();
+            assert_eq!(node_lines, tree.stored_node_count());
+
+            let x_coord = tree
+                .entity_mapping()
+                .unwrap()
+                .get(&EntityId::from_str("id").unwrap())
+                .unwrap();
+            let leaf_line = dot
+                .lines()
+                .find(|line| line.starts_with(&format!("    n_0_{} ", x_coord)))
+                .unwrap();
+            assert!(leaf_line.contains("fillcolor=lightblue"));
+
+            let root_line = dot
+                .lines()
+                .find(|line| line.starts_with("    n_7_0 "))
+                .unwrap();
+            assert!(root_line.contains("style=solid"));
+            assert!(dot.contains("    n_7_0 -> n_6_"));
+        }
+
+        #[test]
+        fn output_is_capped_at_max_nodes() {
+            let tree = new_tree();
+            let dot = tree.to_dot(1);
+
+            let node_lines: Vec<&str> = dot
+                .lines()
+                .filter(|line| line.contains("[label="))
+                .collect();
+            assert_eq!(node_lines.len(), 1);
+            assert!(node_lines[0].starts_with("    n_7_0 "));
+            assert!(!dot.contains("->"));
+        }
+    }
+
+    mod update_liability {
+        use super::*;
+
+        fn new_tree_with_liability(liability: u64) -> DapolTree {
+            let entities = vec![
+                Entity {
+                    liability,
+                    id: EntityId::from_str("id").unwrap(),
+                    salt: None,
+                    blinding_factor: None,
+                },
+                Entity {
+                    liability: 3u64,
+                    id: EntityId::from_str("other id").unwrap(),
+                    salt: None,
+                    blinding_factor: None,
+                },
+            ];
+
+            new_test_tree(8, entities, 1)
+        }
+
+        #[test]
+        fn updated_tree_matches_tree_built_with_new_liability() {
+            let mut tree = new_tree_with_liability(1);
+            let expected = new_tree_with_liability(5);
+            assert_ne!(tree.public_root_data(), expected.public_root_data());
+
+            tree.update_liability(&EntityId::from_str("id").unwrap(), 5)
+                .unwrap();
+
+            assert_eq!(tree.public_root_data(), expected.public_root_data());
+            assert_eq!(tree.secret_root_data(), expected.secret_root_data());
+            tree.verify_integrity().unwrap();
+        }
+
+        #[test]
+        fn old_proofs_fail_and_new_proofs_pass() {
+            let entity_id = EntityId::from_str("id").unwrap();
+            let mut tree = new_tree_with_liability(1);
+            let old_proof = tree.generate_inclusion_proof(&entity_id).unwrap();
+
+            tree.update_liability(&entity_id, 5).unwrap();
+
+            assert!(old_proof.verify(*tree.root_hash()).is_err());
+            let new_proof = tree.generate_inclusion_proof(&entity_id).unwrap();
+            new_proof.verify(*tree.root_hash()).unwrap();
+        }
+
+        #[test]
+        fn clones_are_not_affected() {
+            let mut tree = new_tree_with_liability(1);
+            let clone = tree.clone();
+
+            tree.update_liability(&EntityId::from_str("id").unwrap(), 5)
+                .unwrap();
+
+            assert_eq!(
+                clone.public_root_data(),
+                new_tree_with_liability(1).public_root_data()
+            );
+            clone.verify_integrity().unwrap();
+        }
+
+        #[test]
+        fn unknown_entity_gives_error() {
+            let mut tree = new_tree_with_liability(1);
+            let res = tree.update_liability(&EntityId::from_str("unknown").unwrap(), 5);
+            assert_err!(
+                res,
+                Err(DapolTreeError::NdmSmtConstructionError(
+                    NdmSmtError::EntityIdNotFound(_)
+                ))
+            );
+        }
+
+        #[test]
+        fn liability_above_max_gives_error() {
+            let mut tree = new_tree_with_liability(1);
+            let res = tree.update_liability(&EntityId::from_str("id").unwrap(), 10_000_001);
+            assert_err!(
+                res,
+                Err(DapolTreeError::LiabilityExceedsMax {
+                    liability: 10_000_001,
+                    max_liability: 10_000_000,
+                })
+            );
+        }
+    }
+
+    mod root_history {
+        use super::*;
+
+        fn new_tree_with_history(max_root_history: usize) -> DapolTree {
+            let mut tree = new_tree();
+            tree.set_max_root_history(max_root_history);
+            tree
+        }
+
+        #[test]
+        fn history_is_empty_by_default() {
+            let mut tree = new_tree();
+            let entity_id = EntityId::from_str("id").unwrap();
+
+            tree.update_liability(&entity_id, 2).unwrap();
+
+            assert_eq!(tree.epoch(), 1);
+            assert!(tree.root_history().is_empty());
+        }
+
+        #[test]
+        fn old_proof_verifies_against_root_in_history() {
+            let mut tree = new_tree_with_history(3);
+            let entity_id = EntityId::from_str("id").unwrap();
+            let old_root = tree.public_root_data();
+            let old_proof = tree.generate_inclusion_proof(&entity_id).unwrap();
+
+            tree.update_liability(&entity_id, 2).unwrap();
+
+            assert_eq!(tree.root_history(), &[(0, old_root.clone())]);
+            assert!(old_proof.verify(*tree.root_hash()).is_err());
+            old_proof.verify(tree.root_history()[0].1.hash).unwrap();
+        }
+
+        #[test]
+        fn oldest_roots_are_dropped_when_history_is_full() {
+            let mut tree = new_tree_with_history(2);
+            let entity_id = EntityId::from_str("id").unwrap();
+
+            let mut roots = vec![tree.public_root_data()];
+            for liability in 2..5 {
+                tree.update_liability(&entity_id, liability).unwrap();
+                roots.push(tree.public_root_data());
+            }
+
+            assert_eq!(tree.epoch(), 3);
+            assert_eq!(
+                tree.root_history(),
+                &[(1, roots[1].clone()), (2, roots[2].clone())]
+            );
+
+            tree.set_max_root_history(1);
+            assert_eq!(tree.root_history(), &[(2, roots[2].clone())]);
+        }
+
+        #[test]
+        fn failed_update_does_not_change_history() {
+            let mut tree = new_tree_with_history(2);
+            let entity_id = EntityId::from_str("unknown").unwrap();
+
+            assert!(tree.update_liability(&entity_id, 2).is_err());
+
+            assert_eq!(tree.epoch(), 0);
+            assert!(tree.root_history().is_empty());
+        }
+    }
+
+    mod verify_master_secret {
+        use super::*;
+
+        #[test]
+        fn passes_for_master_secret_used_to_build_tree() {
+            let tree = new_tree();
+            let master_secret = Secret::from_str("master_secret").unwrap();
+            assert!(tree.verify_master_secret(&master_secret));
+        }
+
+        #[test]
+        fn fails_for_different_master_secret() {
+            let tree = new_tree();
+            let master_secret = Secret::from_str("wrong_secret").unwrap();
+            assert!(!tree.verify_master_secret(&master_secret));
+        }
+
+        #[test]
+        fn fails_when_stored_master_secret_was_replaced() {
+            let mut tree = new_tree();
+            let new_secret = Secret::from_str("replaced_secret").unwrap();
+            tree.master_secret = new_secret.clone();
+            assert!(!tree.verify_master_secret(&new_secret));
+        }
+    }
+
+    mod recompute_leaf_content {
+        use super::*;
+
+        #[test]
+        fn matches_stored_leaf_node() {
+            let tree = new_tree();
+            let entity_id = EntityId::from_str("id").unwrap();
+
+            let content = tree.recompute_leaf_content(&entity_id).unwrap();
+
+            let leaf = tree.leaf_node_for(&entity_id).unwrap();
+            assert_eq!(content.liability, 1);
+            assert_eq!(content.hash, leaf.content.hash);
+            assert_eq!(content.commitment, leaf.content.commitment);
+        }
+
+        #[test]
+        fn fails_for_entity_with_its_own_salt() {
+            let entity_id = EntityId::from_str("id").unwrap();
+            let entities = vec![Entity {
+                liability: 1u64,
+                id: entity_id.clone(),
+                salt: Some(Salt::from_str("externally_managed_salt").unwrap()),
+                blinding_factor: None,
+            }];
+
+            let tree = DapolTree::new_with_options(
+                AccumulatorType::NdmSmt,
+                Secret::from_str("master_secret").unwrap(),
+                Salt::from_str("salt_b").unwrap(),
+                Salt::from_str("salt_s").unwrap(),
+                Height::expect_from(8),
+                entities,
+                BuildOptions::default()
+                    .with_max_liability(MaxLiability::from(10_000_000))
+                    .with_random_seed(1),
+            )
+            .unwrap();
+
+            assert_err!(
+                tree.recompute_leaf_content(&entity_id),
+                Err(DapolTreeError::NdmSmtConstructionError(
+                    NdmSmtError::LeafContentMismatch(_)
+                ))
+            );
+        }
+
+        #[test]
+        fn fails_for_unknown_entity() {
+            let tree = new_tree();
+            let entity_id = EntityId::from_str("unknown").unwrap();
+
+            assert_err!(
+                tree.recompute_leaf_content(&entity_id),
+                Err(DapolTreeError::NdmSmtConstructionError(
+                    NdmSmtError::EntityIdNotFound(_)
+                ))
+            );
+        }
+    }
+
+    mod dump_full_tree {
+        use super::*;
+        use crate::binary_tree::Coordinate;
+        use std::collections::HashMap;
+
+        #[test]
+        fn parent_liability_is_sum_of_children_liabilities() {
+            let entities = (0..10u64)
+                .map(|i| Entity {
+                    liability: i * 100,
+                    id: EntityId::from_str(&format!("id{}", i)).unwrap(),
+                    salt: None,
+                    blinding_factor: None,
+                })
+                .collect::<Vec<Entity>>();
+            let total_liability: u64 = entities.iter().map(|entity| entity.liability).sum();
+
+            let tree = DapolTree::new_with_options(
+                AccumulatorType::NdmSmt,
+                Secret::from_str("master_secret").unwrap(),
+                Salt::from_str("salt_b").unwrap(),
+                Salt::from_str("salt_s").unwrap(),
+                Height::expect_from(6),
+                entities,
+                BuildOptions::default()
+                    .with_max_liability(MaxLiability::from(10_000_000))
+                    .with_random_seed(1),
+            )
+            .unwrap();
+
+            let nodes = tree
+                .dump_full_tree()
+                .unwrap()
+                .into_iter()
+                .map(|node| (node.coord.clone(), node.content))
+                .collect::<HashMap<Coordinate, _>>();
+
+            let root_coord = Coordinate {
+                x: 0,
+                y: tree.height().as_y_coord(),
+            };
+            let root = nodes.get(&root_coord).unwrap();
+            assert_eq!(&root.hash, tree.root_hash());
+            assert_eq!(&root.commitment, tree.root_commitment());
+            assert_eq!(root.liability, total_liability);
+
+            for x in tree.entity_mapping().unwrap().values() {
+                assert!(nodes.contains_key(&Coordinate { x: *x, y: 0 }));
+            }
+
+            for (coord, content) in nodes.iter().filter(|(coord, _)| coord.y > 0) {
+                let left = nodes.get(&Coordinate {
+                    x: 2 * coord.x,
+                    y: coord.y - 1,
+                });
+                let right = nodes.get(&Coordinate {
+                    x: 2 * coord.x + 1,
+                    y: coord.y - 1,
+                });
+
+                // Padding nodes are not merged from their children, and their
+                // children are not included.
+                let (Some(left), Some(right)) = (left, right) else {
+                    assert_eq!(content.liability, 0);
+                    continue;
+                };
+
+                assert_eq!(content.liability, left.liability + right.liability);
+                assert_eq!(content.commitment, left.commitment + right.commitment);
+            }
+        }
+    }
+
+    mod leaf_node_for {
+        use super::*;
+        use crate::binary_tree::Coordinate;
+
+        #[test]
+        fn gives_leaf_node_at_mapped_x_coord() {
+            let tree = new_tree();
+            let entity_id = EntityId::from_str("id").unwrap();
+            let x_coord = *tree.entity_mapping().unwrap().get(&entity_id).unwrap();
+
+            let leaf = tree.leaf_node_for(&entity_id).unwrap();
+
+            assert_eq!(leaf.coord, Coordinate { x: x_coord, y: 0 });
+            assert_eq!(leaf, tree.node_at(leaf.coord.clone()).unwrap());
+        }
+
+        #[test]
+        fn unknown_entity_gives_none() {
+            let tree = new_tree();
+            let entity_id = EntityId::from_str("not-in-tree").unwrap();
+
+            assert!(tree.leaf_node_for(&entity_id).is_none());
+        }
+    }
+
+    mod shard_manifest {
+        use super::*;
+
+        fn new_tree_with_entities(num_entities: u64) -> DapolTree {
+            new_test_tree(8, test_entities(num_entities), 1)
+        }
+
+        #[test]
+        fn manifest_covers_bottom_layer_and_verifies_for_all_shard_counts() {
+            let tree = new_tree_with_entities(50);
+
+            for k in [1, 2, 4, 16, 128] {
+                let shards = tree.shard_manifest(k).unwrap();
+
+                assert!(shards.len() <= k);
+                assert_eq!(*shards[0].x_range.start(), 0);
+                assert_eq!(*shards.last().unwrap().x_range.end(), 127);
+                for pair in shards.windows(2) {
+                    assert_eq!(*pair[0].x_range.end() + 1, *pair[1].x_range.start());
+                }
+                assert_eq!(shards.iter().map(|s| s.entity_count).sum::<u64>(), 50);
+
+                DapolTree::verify_shard_manifest(&shards, *tree.height(), &tree.public_root_data())
+                    .unwrap();
+            }
+        }
+
+        #[test]
+        fn empty_shards_are_merged() {
+            let tree = new_tree();
+
+            let shards = tree.shard_manifest(16).unwrap();
+
+            // The single entity is in 1 shard, and there is 1 empty sub-tree
+            // on each layer above it.
+            assert_eq!(shards.len(), 5);
+            assert_eq!(shards.iter().filter(|s| s.entity_count == 1).count(), 1);
+            DapolTree::verify_shard_manifest(&shards, *tree.height(), &tree.public_root_data())
+                .unwrap();
+        }
+
+        #[test]
+        fn invalid_shard_count_gives_error() {
+            let tree = new_tree();
+
+            assert_err!(
+                tree.shard_manifest(3),
+                Err(DapolTreeError::InvalidShardCount { .. })
+            );
+            assert_err!(
+                tree.shard_manifest(256),
+                Err(DapolTreeError::InvalidShardCount { .. })
+            );
+        }
+
+        #[test]
+        fn tampered_manifest_fails_verification() {
+            let tree = new_tree_with_entities(50);
+            let height = *tree.height();
+            let root_public_data = tree.public_root_data();
+            let shards = tree.shard_manifest(4).unwrap();
+
+            let mut tampered = shards.clone();
+            tampered[1].subtree_root_hash = H256::zero();
+            assert_err!(
+                DapolTree::verify_shard_manifest(&tampered, height, &root_public_data),
+                Err(DapolTreeError::ShardManifestVerificationError)
+            );
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::utils::test_utils::assert_err;
-    use crate::{
-        AccumulatorType, DapolTree, Entity, EntityId, Height, MaxLiability, MaxThreadCount, Salt,
-        Secret,
-    };
-    use std::path::{Path, PathBuf};
-    use std::str::FromStr;
+            let missing = &shards[1..];
+            assert_err!(
+                DapolTree::verify_shard_manifest(missing, height, &root_public_data),
+                Err(DapolTreeError::ShardManifestVerificationError)
+            );
+        }
 
-    fn new_tree() -> DapolTree {
-        let accumulator_type = AccumulatorType::NdmSmt;
-        let height = Height::expect_from(8);
-        let salt_b = Salt::from_str("salt_b").unwrap();
-        let salt_s = Salt::from_str("salt_s").unwrap();
-        let master_secret = Secret::from_str("master_secret").unwrap();
-        let max_liability = MaxLiability::from(10_000_000);
-        let max_thread_count = MaxThreadCount::from(8);
-        let random_seed = 1;
+        #[test]
+        fn manifest_not_covering_bottom_layer_fails_verification() {
+            let tree = new_tree();
+            let root_public_data = tree.public_root_data();
 
-        let entity = Entity {
-            liability: 1u64,
-            id: EntityId::from_str("id").unwrap(),
-        };
-        let entities = vec![entity.clone()];
+            // A single leaf-sized shard whose hash & commitment are those of
+            // the root, which would combine to the root if the height was not
+            // checked.
+            let shards = vec![ShardInfo {
+                x_range: 0..=0,
+                subtree_root_hash: root_public_data.hash,
+                subtree_root_commitment: root_public_data.commitment,
+                entity_count: 1,
+            }];
 
-        DapolTree::new_with_random_seed(
-            accumulator_type.clone(),
-            master_secret.clone(),
-            salt_b.clone(),
-            salt_s.clone(),
-            max_liability.clone(),
-            max_thread_count.clone(),
-            height.clone(),
-            entities,
-            random_seed,
-        )
-        .unwrap()
+            assert_err!(
+                DapolTree::verify_shard_manifest(&shards, *tree.height(), &root_public_data),
+                Err(DapolTreeError::ShardManifestVerificationError)
+            );
+        }
     }
 
-    mod construction {
+    mod occupied_leaf_x_coords {
         use super::*;
 
         #[test]
-        fn constructor_and_getters_work() {
-            let accumulator_type = AccumulatorType::NdmSmt;
-            let height = Height::expect_from(8);
-            let salt_b = Salt::from_str("salt_b").unwrap();
-            let salt_s = Salt::from_str("salt_s").unwrap();
-            let master_secret = Secret::from_str("master_secret").unwrap();
-            let max_liability = MaxLiability::from(10_000_000);
-            let max_thread_count = MaxThreadCount::from(8);
-            let random_seed = 1u64;
-
-            let entity = Entity {
-                liability: 1u64,
-                id: EntityId::from_str("id").unwrap(),
-            };
-            let entities = vec![entity.clone()];
+        fn x_coords_are_sorted_and_match_entity_mapping() {
+            let entities = (0..50u64)
+                .map(|i| Entity {
+                    liability: i,
+                    id: EntityId::from_str(&format!("id{}", i)).unwrap(),
+                    salt: None,
+                    blinding_factor: None,
+                })
+                .collect();
 
-            let tree = DapolTree::new_with_random_seed(
-                accumulator_type.clone(),
-                master_secret.clone(),
-                salt_b.clone(),
-                salt_s.clone(),
-                max_liability.clone(),
-                max_thread_count.clone(),
-                height.clone(),
+            let tree = DapolTree::new_with_options(
+                AccumulatorType::NdmSmt,
+                Secret::from_str("master_secret").unwrap(),
+                Salt::from_str("salt_b").unwrap(),
+                Salt::from_str("salt_s").unwrap(),
+                Height::expect_from(8),
                 entities,
-                random_seed,
+                BuildOptions::default()
+                    .with_max_liability(MaxLiability::from(10_000_000))
+                    .with_random_seed(1),
             )
             .unwrap();
 
-            assert_eq!(tree.master_secret(), &master_secret);
-            assert_eq!(tree.height(), &height);
-            assert_eq!(tree.max_liability(), &max_liability);
-            assert_eq!(tree.salt_b(), &salt_b);
-            assert_eq!(tree.salt_s(), &salt_s);
-            assert_eq!(tree.accumulator_type(), accumulator_type);
+            let x_coords = tree.occupied_leaf_x_coords();
 
-            assert!(tree.entity_mapping().is_some());
-            assert!(tree.entity_mapping().unwrap().get(&entity.id).is_some());
+            assert_eq!(x_coords.len(), 50);
+            assert!(x_coords.windows(2).all(|pair| pair[0] < pair[1]));
+            for x_coord in tree.entity_mapping().unwrap().values() {
+                assert!(x_coords.binary_search(x_coord).is_ok());
+            }
         }
     }
 
-    mod serde {
+    mod nodes_per_level {
         use super::*;
 
-        mod tree {
-            use super::*;
+        #[test]
+        fn nodes_per_level_covers_all_levels_and_sums_to_stored_node_count() {
+            let tree = new_tree();
+            let counts = tree.nodes_per_level();
 
-            #[test]
-            fn serde_does_not_change_tree() {
-                let tree = new_tree();
+            assert_eq!(counts.len(), tree.height().as_usize());
+            for (i, (y, _)) in counts.iter().enumerate() {
+                assert_eq!(*y as usize, i);
+            }
 
-                let src_dir = env!("CARGO_MANIFEST_DIR");
-                let examples_dir = Path::new(&src_dir).join("examples");
-                let path = examples_dir.join("my_serialized_tree_for_testing.dapoltree");
-                let path_2 = tree.serialize(path.clone()).unwrap();
-                assert_eq!(path, path_2);
+            let total: usize = counts.iter().map(|(_, count)| count).sum();
+            assert_eq!(total, tree.stored_node_count());
 
-                let tree_2 = DapolTree::deserialize(path).unwrap();
+            // The leaf node is on the bottom layer and the root on the top.
+            assert!(counts.first().unwrap().1 >= 1);
+            assert_eq!(counts.last().unwrap().1, 1);
+        }
 
-                assert_eq!(tree.master_secret(), tree_2.master_secret());
-                assert_eq!(tree.height(), tree_2.height());
-                assert_eq!(tree.max_liability(), tree_2.max_liability());
-                assert_eq!(tree.salt_b(), tree_2.salt_b());
-                assert_eq!(tree.salt_s(), tree_2.salt_s());
-                assert_eq!(tree.accumulator_type(), tree_2.accumulator_type());
-                assert_eq!(tree.entity_mapping(), tree_2.entity_mapping());
+        #[test]
+        fn nodes_per_level_works_for_both_build_strategies() {
+            let build = |build_strategy| {
+                let entities = (0..10)
+                    .map(|i| Entity {
+                        liability: i,
+                        id: EntityId::from_str(&format!("id{}", i)).unwrap(),
+                        salt: None,
+                        blinding_factor: None,
+                    })
+                    .collect();
+
+                DapolTree::new_with_options(
+                    AccumulatorType::NdmSmt,
+                    Secret::from_str("master_secret").unwrap(),
+                    Salt::from_str("salt_b").unwrap(),
+                    Salt::from_str("salt_s").unwrap(),
+                    Height::expect_from(8),
+                    entities,
+                    BuildOptions::default()
+                        .with_max_liability(MaxLiability::from(10_000_000))
+                        .with_build_strategy(build_strategy),
+                )
+                .unwrap()
+            };
+
+            for build_strategy in [BuildStrategy::SingleThreaded, BuildStrategy::MultiThreaded] {
+                let tree = build(build_strategy);
+                let counts = tree.nodes_per_level();
+
+                let total: usize = counts.iter().map(|(_, count)| count).sum();
+                assert_eq!(total, tree.stored_node_count());
+                assert_eq!(counts.len(), 8);
+                assert!(counts[0].1 >= 10);
             }
+        }
+    }
 
-            #[test]
-            fn serialization_path_parser_fails_for_unsupported_extensions() {
-                let path = PathBuf::from_str("./mytree.myext").unwrap();
+    mod stored_coordinates {
+        use super::*;
+        use crate::binary_tree::Coordinate;
 
-                let res = DapolTree::parse_tree_serialization_path(path);
-                assert_err!(
-                    res,
-                    Err(read_write_utils::ReadWriteError::UnsupportedFileExtension {
-                        expected: _,
-                        actual: _
+        #[test]
+        fn stored_coordinates_are_sorted_and_include_leaf_and_root() {
+            let tree = new_tree();
+            let coords = tree.stored_coordinates();
+
+            let mut sorted = coords.clone();
+            sorted.sort_by_key(|coord| (coord.y, coord.x));
+            assert_eq!(coords, sorted);
+
+            let entity_id = EntityId::from_str("id").unwrap();
+            let x_coord = *tree.entity_mapping().unwrap().get(&entity_id).unwrap();
+            assert!(coords.contains(&Coordinate { x: x_coord, y: 0 }));
+
+            let root_coord = Coordinate {
+                x: 0,
+                y: tree.height().as_y_coord(),
+            };
+            assert_eq!(coords.last(), Some(&root_coord));
+        }
+
+        #[test]
+        fn stored_node_count_matches_stored_coordinates_for_both_build_strategies() {
+            for build_strategy in [BuildStrategy::SingleThreaded, BuildStrategy::MultiThreaded] {
+                let entities = (0..10)
+                    .map(|i| Entity {
+                        liability: i,
+                        id: EntityId::from_str(&format!("id{}", i)).unwrap(),
+                        salt: None,
+                        blinding_factor: None,
                     })
-                );
+                    .collect();
+
+                let tree = DapolTree::new_with_options(
+                    AccumulatorType::NdmSmt,
+                    Secret::from_str("master_secret").unwrap(),
+                    Salt::from_str("salt_b").unwrap(),
+                    Salt::from_str("salt_s").unwrap(),
+                    Height::expect_from(8),
+                    entities,
+                    BuildOptions::default()
+                        .with_max_liability(MaxLiability::from(10_000_000))
+                        .with_build_strategy(build_strategy),
+                )
+                .unwrap();
+
+                assert_eq!(tree.stored_node_count(), tree.stored_coordinates().len());
+                // All the leaf nodes plus the root node must be stored.
+                assert!(tree.stored_node_count() >= 11);
             }
+        }
 
-            #[test]
-            fn serialization_path_parser_gives_correct_file_prefix() {
-                let path = PathBuf::from_str("./").unwrap();
-                let path = DapolTree::parse_tree_serialization_path(path).unwrap();
-                assert!(path
-                    .to_str()
-                    .unwrap()
-                    .contains("proof_of_liabilities_merkle_sum_tree_"));
+        #[test]
+        fn stored_coordinates_are_all_in_the_tree() {
+            let tree = new_tree();
+
+            for coord in tree.stored_coordinates() {
+                assert!(tree.node_at(coord).is_ok());
             }
         }
+    }
 
-        mod public_root_data {
-            use super::*;
+    mod node_at {
+        use super::*;
+        use crate::binary_tree::{Coordinate, Mergeable};
 
-            #[test]
-            fn serde_does_not_change_public_root_data() {
-                let tree = new_tree();
-                let public_root_data = tree.public_root_data();
+        #[test]
+        fn root_coord_gives_root_node() {
+            let tree = new_tree();
+            let coord = Coordinate {
+                x: 0,
+                y: tree.height().as_y_coord(),
+            };
 
-                let src_dir = env!("CARGO_MANIFEST_DIR");
-                let examples_dir = Path::new(&src_dir).join("examples");
-                let path = examples_dir.join("public_root_data.json");
-                let path_2 = tree.serialize_public_root_data(path.clone()).unwrap();
-                assert_eq!(path, path_2);
+            let node = tree.node_at(coord).unwrap();
 
-                let public_root_data_2 = DapolTree::deserialize_public_root_data(path).unwrap();
+            assert_eq!(&node.content.hash, tree.root_hash());
+            assert_eq!(&node.content.commitment, tree.root_commitment());
+        }
 
-                assert_eq!(public_root_data, public_root_data_2);
-            }
+        #[test]
+        fn regenerated_node_matches_inclusion_proof_path() {
+            let tree = new_tree();
+            let entity_id = EntityId::from_str("id").unwrap();
+            let x_coord = *tree.entity_mapping().unwrap().get(&entity_id).unwrap();
 
-            #[test]
-            fn public_root_data_serialization_path_parser_fails_for_unsupported_extensions() {
-                let path = PathBuf::from_str("./public_root_data.myext").unwrap();
+            // The default store depth is half the height so this node is not
+            // in the store and has to be regenerated.
+            let coord = Coordinate {
+                x: x_coord / 2,
+                y: 1,
+            };
+            let node = tree.node_at(coord).unwrap();
 
-                let res = DapolTree::parse_public_root_data_serialization_path(path);
-                assert_err!(
-                    res,
-                    Err(read_write_utils::ReadWriteError::UnsupportedFileExtension {
-                        expected: _,
-                        actual: _
-                    })
-                );
-            }
+            let leaf = tree.node_at(Coordinate { x: x_coord, y: 0 }).unwrap();
+            let sibling = tree
+                .node_at(Coordinate {
+                    x: x_coord ^ 1,
+                    y: 0,
+                })
+                .unwrap();
+            let (left, right) = if x_coord % 2 == 0 {
+                (leaf, sibling)
+            } else {
+                (sibling, leaf)
+            };
 
-            #[test]
-            fn public_root_data_serialization_path_parser_gives_correct_file_prefix() {
-                let path = PathBuf::from_str("./").unwrap();
-                let path = DapolTree::parse_public_root_data_serialization_path(path).unwrap();
-                assert!(path.to_str().unwrap().contains("public_root_data_"));
-            }
+            let expected = HiddenNodeContent::merge(&left.content, &right.content);
+
+            assert_eq!(node.content, expected);
         }
 
-        mod secret_root_data {
-            use super::*;
+        #[test]
+        fn coord_outside_tree_gives_error() {
+            let tree = new_tree();
+            let coord = Coordinate {
+                x: 0,
+                y: tree.height().as_u8(),
+            };
 
-            #[test]
-            fn serde_does_not_change_secret_root_data() {
-                let tree = new_tree();
-                let secret_root_data = tree.secret_root_data();
+            let res = tree.node_at(coord);
+            assert_err!(res, Err(DapolTreeError::CoordinateOutOfBounds { .. }));
+        }
 
-                let src_dir = env!("CARGO_MANIFEST_DIR");
-                let examples_dir = Path::new(&src_dir).join("examples");
-                let path = examples_dir.join("secret_root_data.json");
-                let path_2 = tree.serialize_secret_root_data(path.clone()).unwrap();
-                assert_eq!(path, path_2);
+        #[test]
+        fn subtree_root_at_root_coord_gives_root_hash_and_commitment() {
+            let tree = new_tree();
+            let coord = Coordinate {
+                x: 0,
+                y: tree.height().as_y_coord(),
+            };
 
-                let secret_root_data_2 = DapolTree::deserialize_secret_root_data(path).unwrap();
+            let (hash, commitment) = tree.subtree_root(coord).unwrap();
 
-                assert_eq!(secret_root_data, secret_root_data_2);
-            }
+            assert_eq!(&hash, tree.root_hash());
+            assert_eq!(&commitment, tree.root_commitment());
+        }
 
-            #[test]
-            fn secret_root_data_serialization_path_parser_fails_for_unsupported_extensions() {
-                let path = PathBuf::from_str("./secret_root_data.myext").unwrap();
+        #[test]
+        fn subtree_roots_merge_to_parent() {
+            let tree = new_tree();
+            let entity_id = EntityId::from_str("id").unwrap();
+            let x_coord = *tree.entity_mapping().unwrap().get(&entity_id).unwrap();
 
-                let res = DapolTree::parse_secret_root_data_serialization_path(path);
-                assert_err!(
-                    res,
-                    Err(read_write_utils::ReadWriteError::UnsupportedFileExtension {
-                        expected: _,
-                        actual: _
-                    })
-                );
-            }
+            // The parent must contain a non-padding leaf in its sub-tree,
+            // otherwise it is a padding node and not the merge of its children.
+            let left_x = (x_coord / 2) & !1;
 
-            #[test]
-            fn secret_root_data_serialization_path_parser_gives_correct_file_prefix() {
-                let path = PathBuf::from_str("./").unwrap();
-                let path = DapolTree::parse_secret_root_data_serialization_path(path).unwrap();
-                assert!(path.to_str().unwrap().contains("secret_root_data_"));
-            }
+            let (left_hash, left_commitment) =
+                tree.subtree_root(Coordinate { x: left_x, y: 1 }).unwrap();
+            let (right_hash, right_commitment) = tree
+                .subtree_root(Coordinate {
+                    x: left_x + 1,
+                    y: 1,
+                })
+                .unwrap();
+            let (parent_hash, parent_commitment) = tree
+                .subtree_root(Coordinate {
+                    x: left_x / 2,
+                    y: 2,
+                })
+                .unwrap();
+
+            let expected = HiddenNodeContent::merge(
+                &HiddenNodeContent::new(left_commitment, left_hash),
+                &HiddenNodeContent::new(right_commitment, right_hash),
+            );
+
+            assert_eq!(parent_hash, expected.hash);
+            assert_eq!(parent_commitment, expected.commitment);
+        }
+
+        #[test]
+        fn subtree_root_outside_tree_gives_error() {
+            let tree = new_tree();
+            let coord = Coordinate {
+                x: tree.height().max_bottom_layer_nodes(),
+                y: 0,
+            };
+
+            let res = tree.subtree_root(coord);
+            assert_err!(res, Err(DapolTreeError::CoordinateOutOfBounds { .. }));
         }
     }
 
-    mod inclusion_proofs {
+    mod padding_fn {
         use super::*;
 
+        fn padding_fn() -> impl Fn(&Coordinate) -> FullNodeContent + Send + Sync + 'static {
+            |coord: &Coordinate| {
+                FullNodeContent::new_pad((coord.x + 1).into(), coord, (coord.y as u64 + 1).into())
+            }
+        }
+
+        fn new_tree_with_padding_fn() -> DapolTree {
+            let entities: Vec<Entity> = (0..10)
+                .map(|i| Entity {
+                    liability: i,
+                    id: EntityId::from_str(&format!("id{}", i)).unwrap(),
+                    salt: None,
+                    blinding_factor: None,
+                })
+                .collect();
+
+            DapolTree::new_with_options(
+                AccumulatorType::NdmSmt,
+                Secret::from_str("master_secret").unwrap(),
+                Salt::from_str("salt_b").unwrap(),
+                Salt::from_str("salt_s").unwrap(),
+                Height::expect_from(8),
+                entities,
+                BuildOptions::default()
+                    .with_max_liability(MaxLiability::from(10_000_000))
+                    .with_padding_fn(padding_fn()),
+            )
+            .unwrap()
+        }
+
         #[test]
-        fn generate_inclusion_proof_works() {
-            let tree = new_tree();
-            assert!(tree
-                .generate_inclusion_proof(&EntityId::from_str("id").unwrap())
-                .is_ok());
+        fn padding_nodes_are_generated_by_padding_fn() {
+            let tree = new_tree_with_padding_fn();
+            let x_coord = *tree
+                .entity_mapping()
+                .unwrap()
+                .get(&EntityId::from_str("id0").unwrap())
+                .unwrap();
+            let occupied = tree.occupied_leaf_x_coords();
+
+            let padding_coord = (0..tree.height().max_bottom_layer_nodes())
+                .find(|x| !occupied.contains(x))
+                .map(|x| Coordinate { x, y: 0 })
+                .unwrap();
+
+            let node = tree.node_at(padding_coord.clone()).unwrap();
+            let expected = padding_fn()(&padding_coord).compress();
+
+            assert_ne!(x_coord, padding_coord.x);
+            assert_eq!(node.content, expected);
         }
 
         #[test]
-        fn generate_inclusion_proof_with_aggregation_factor_works() {
-            let tree = new_tree();
-            let agg = AggregationFactor::Divisor(2u8);
-            assert!(tree
-                .generate_inclusion_proof_with(&EntityId::from_str("id").unwrap(), agg)
-                .is_ok());
+        fn proofs_verify_with_padding_fn() {
+            let tree = new_tree_with_padding_fn();
+
+            for i in 0..10 {
+                let proof = tree
+                    .generate_inclusion_proof(&EntityId::from_str(&format!("id{}", i)).unwrap())
+                    .unwrap();
+                proof.verify(*tree.root_hash()).unwrap();
+            }
+        }
+
+        #[test]
+        fn proof_generation_gives_error_until_padding_fn_is_set_after_deserialization() {
+            let tree = new_tree_with_padding_fn();
+            let entity_id = EntityId::from_str("id0").unwrap();
+
+            let src_dir = env!("CARGO_MANIFEST_DIR");
+            let examples_dir = Path::new(&src_dir).join("examples");
+            let path = examples_dir.join("my_tree_with_padding_fn_for_testing.dapoltree");
+            tree.serialize(path.clone()).unwrap();
+
+            let mut tree_2 = DapolTree::deserialize(path.clone()).unwrap();
+            std::fs::remove_file(path).unwrap();
+
+            let res = tree_2.generate_inclusion_proof(&entity_id);
+            assert_err!(res, Err(NdmSmtError::PaddingFnNotSet));
+            assert!(tree_2.verify_integrity().is_err());
+
+            tree_2.set_padding_fn(padding_fn());
+
+            let proof = tree_2.generate_inclusion_proof(&entity_id).unwrap();
+            proof.verify(*tree.root_hash()).unwrap();
         }
     }
 }
@@ -3,11 +3,19 @@ use serde_with::DeserializeFromStr;
 use std::convert::From;
 use std::str::FromStr;
 
+use curve25519_dalek_ng::scalar::Scalar;
+
+use crate::Salt;
+
+#[cfg(feature = "full")]
 mod entities_parser;
-pub use entities_parser::{EntitiesParser, EntitiesParserError};
+#[cfg(feature = "full")]
+pub use entities_parser::{DuplicatePolicy, EntitiesParser, EntitiesParserError, RowError};
 
 mod entity_ids_parser;
-pub use entity_ids_parser::{EntityIdsParser, EntityIdsParserError};
+#[cfg(feature = "full")]
+pub use entity_ids_parser::EntityIdsParser;
+pub use entity_ids_parser::EntityIdsParserError;
 
 // -------------------------------------------------------------------------------------------------
 // Main structs & implementations.
@@ -23,11 +31,30 @@ pub use entity_ids_parser::{EntityIdsParser, EntityIdsParserError};
 /// to people, or users. So an entity can be thought of as a user. 'Entity' was
 /// chosen above 'user' because it has a more general connotation.
 ///
-/// The entity struct has only 2 fields: ID and liability.
+/// The entity struct has 2 required fields, ID and liability, and an optional
+/// salt. If the salt is set then it is used for the leaf node hash instead of
+/// the salt derived from the master secret. This is useful when migrating
+/// from a system that has already committed to specific salts.
+///
+/// The blinding factor for the leaf node's Pedersen commitment can also be
+/// set, in which case it is used instead of the one derived from the master
+/// secret & `salt_b`. This allows reproducing commitments that were created by
+/// another system. The blinding factor cannot be read from an entities file,
+/// it can only be set directly.
+///
+/// **Security note**: the hiding property of the Pedersen commitment relies on
+/// the blinding factor being uniformly random, and unknown to anyone other
+/// than the tree owner (and the entity). Externally-supplied blinding factors
+/// must be generated with a cryptographically secure RNG and must never be
+/// reused, otherwise the entity's liability may be leaked.
 #[derive(Debug, Clone, Deserialize, PartialEq)]
 pub struct Entity {
     pub liability: u64,
     pub id: EntityId,
+    #[serde(default)]
+    pub salt: Option<Salt>,
+    #[serde(skip)]
+    pub blinding_factor: Option<Scalar>,
 }
 
 /// The max size of the entity ID is 512 bits, but this is a soft limit so it
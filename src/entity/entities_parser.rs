@@ -1,33 +1,105 @@
 //! Parser for files containing a list of entity records.
 //!
 //! Supported file types: csv
-//! Note that the file type is inferred from its path extension.
+//! Note that the file type is inferred from its path extension. Records read
+//! via [EntitiesParser::from_reader] are expected to be CSV.
 //!
 //! Formatting:
-//! CSV: `id,liability`
+//! CSV: `id,liability` with an optional `salt` column. The delimiter defaults
+//! to a comma and the first row is expected to be a header, but both can be
+//! changed. If there is no header row then the columns are expected to be in
+//! the order `id,liability,salt`.
 //!
 //! Fields:
 //! - `path`: path to the file containing the entity records
+//! - `reader`: source of entity records other than a file (e.g. stdin)
 //! - `num_entities`: number of entities to be randomly generated
+//! - `sample_fraction`: fraction of the entity records in the file to keep
+//! - `random_seed`: seed for the PRNG used for sampling (testing only)
+//! - `duplicate_policy`: what to do with records that have the same ID
+//! - `delimiter`: CSV field delimiter (defaults to `,`)
+//! - `has_headers`: whether the first CSV row is a header (defaults to true)
+//! - `decimal_scale`: number of decimal places for decimal liabilities
+//! - `error_tolerance`: whether malformed CSV rows are skipped rather than
+//!   failing the whole parse (defaults to false)
+//! - `mmap`: whether the file is memory-mapped rather than read via a buffered
+//!   reader (defaults to false, requires the `mmap` feature)
+//! - `combine`: whether `num_entities` random entities are appended to the
+//!   ones from the reader or file, rather than being ignored (defaults to
+//!   false)
 //!
-//! At least on of the 2 fields must be set for the parser to succeed. If both
-//! fields are set then the path is prioritized.
+//! At least on of the first 3 fields must be set for the parser to succeed.
+//! The reader is prioritized over the path, and the path over `num_entities`
+//! (unless `combine` is set).
 
-use std::{ffi::OsString, path::PathBuf, str::FromStr};
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsString,
+    io::Read,
+    path::PathBuf,
+    str::FromStr,
+};
 
 use rand::{
     distributions::{Alphanumeric, DistString, Uniform},
-    thread_rng, Rng,
+    rngs::StdRng,
+    thread_rng, Rng, SeedableRng,
 };
 
 use log::{debug, warn};
 use logging_timer::time;
 
 use super::{Entity, EntityId, ENTITY_ID_MAX_BYTES};
+use crate::Salt;
 
 pub struct EntitiesParser {
     path: Option<PathBuf>,
+    reader: Option<Box<dyn Read>>,
     num_entities: Option<u64>,
+    sample_fraction: Option<f64>,
+    random_seed: Option<u64>,
+    duplicate_policy: DuplicatePolicy,
+    delimiter: Option<u8>,
+    has_headers: Option<bool>,
+    decimal_scale: Option<u32>,
+    error_tolerance: bool,
+    #[cfg(feature = "mmap")]
+    mmap: bool,
+    combine: bool,
+}
+
+/// Field delimiter used if none is set.
+pub const DEFAULT_DELIMITER: u8 = b',';
+
+/// Column order assumed for CSV records when there is no header row.
+const HEADERLESS_COLUMNS: [&str; 3] = ["id", "liability", "salt"];
+
+/// Entity record with the liability as a decimal string, e.g. `1234.56`.
+#[derive(Deserialize)]
+struct DecimalEntityRecord {
+    id: EntityId,
+    liability: String,
+    #[serde(default)]
+    salt: Option<Salt>,
+}
+
+/// What to do when more than 1 entity record has the same ID.
+///
+/// The tree requires entity IDs to be unique, so duplicates must either be
+/// merged into a single record or rejected.
+///
+/// When records are merged the salt of the first record is kept.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicatePolicy {
+    /// Return an error if any ID appears more than once.
+    #[default]
+    Error,
+    /// Merge the records by summing their liabilities.
+    Sum,
+    /// Merge the records by taking the largest of their liabilities.
+    Max,
 }
 
 /// Supported file types for the parser.
@@ -39,7 +111,18 @@ impl EntitiesParser {
     pub fn new() -> Self {
         EntitiesParser {
             path: None,
+            reader: None,
             num_entities: None,
+            sample_fraction: None,
+            random_seed: None,
+            duplicate_policy: DuplicatePolicy::default(),
+            delimiter: None,
+            has_headers: None,
+            decimal_scale: None,
+            error_tolerance: false,
+            #[cfg(feature = "mmap")]
+            mmap: false,
+            combine: false,
         }
     }
 
@@ -52,6 +135,16 @@ impl EntitiesParser {
         self.with_path_opt(Some(path))
     }
 
+    /// Read CSV entity records from `reader` instead of from a file.
+    ///
+    /// This is useful for reading entities from stdin, for example.
+    pub fn from_reader(reader: impl Read + 'static) -> Self {
+        EntitiesParser {
+            reader: Some(Box::new(reader)),
+            ..Self::new()
+        }
+    }
+
     pub fn with_num_entities_opt(mut self, num_entities: Option<u64>) -> Self {
         self.num_entities = num_entities;
         self
@@ -61,42 +154,402 @@ impl EntitiesParser {
         self.with_num_entities_opt(Some(num_entities))
     }
 
+    pub fn with_sample_fraction_opt(mut self, sample_fraction: Option<f64>) -> Self {
+        self.sample_fraction = sample_fraction;
+        self
+    }
+
+    /// Only keep a random sample of the entity records in the file.
+    ///
+    /// Each record is kept with probability `sample_fraction`, which must be
+    /// in the range `(0, 1]`. This is useful for building realistic test trees
+    /// from a large entities file without having to truncate the file.
+    ///
+    /// Note that the sample is not stable across runs unless a seed is set
+    /// using [with_random_seed], which is only available with the `testing`
+    /// feature.
+    pub fn with_sample_fraction(self, sample_fraction: f64) -> Self {
+        self.with_sample_fraction_opt(Some(sample_fraction))
+    }
+
+    /// Set what to do with entity records that have the same ID.
+    ///
+    /// Defaults to [DuplicatePolicy::Error]. The policy is only applied to
+    /// records read from a file or reader, not to randomly generated ones.
+    pub fn with_duplicate_policy(mut self, duplicate_policy: DuplicatePolicy) -> Self {
+        self.duplicate_policy = duplicate_policy;
+        self
+    }
+
+    pub fn with_delimiter_opt(mut self, delimiter: Option<u8>) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// Set the field delimiter for CSV records, e.g. `b'\t'` for
+    /// tab-separated values.
+    ///
+    /// Defaults to [DEFAULT_DELIMITER].
+    pub fn with_delimiter(self, delimiter: u8) -> Self {
+        self.with_delimiter_opt(Some(delimiter))
+    }
+
+    pub fn with_has_headers_opt(mut self, has_headers: Option<bool>) -> Self {
+        self.has_headers = has_headers;
+        self
+    }
+
+    /// Set whether the first CSV row is a header row.
+    ///
+    /// Defaults to true. If false then the columns are expected to be in the
+    /// order `id,liability,salt`, with the salt column being optional.
+    pub fn with_has_headers(self, has_headers: bool) -> Self {
+        self.with_has_headers_opt(Some(has_headers))
+    }
+
+    pub fn with_decimal_scale_opt(mut self, decimal_scale: Option<u32>) -> Self {
+        self.decimal_scale = decimal_scale;
+        self
+    }
+
+    /// Parse liabilities as decimal values with up to `decimal_scale`
+    /// fractional digits, e.g. `1234.56` for currency amounts.
+    ///
+    /// The decimal value is multiplied by `10^decimal_scale` to get the
+    /// integer liability, so `1234.56` with a scale of 2 gives a liability of
+    /// `123456`. Values with more fractional digits than `decimal_scale` are
+    /// rejected rather than rounded, so that no liability is lost.
+    ///
+    /// If not set then liabilities must be integers.
+    pub fn with_decimal_scale(self, decimal_scale: u32) -> Self {
+        self.with_decimal_scale_opt(Some(decimal_scale))
+    }
+
+    /// Set whether malformed CSV rows are skipped instead of failing the
+    /// whole parse.
+    ///
+    /// Defaults to false (fail fast). When true, each row that cannot be read
+    /// or deserialized is recorded as a [RowError] and parsing continues with
+    /// the next row. Use [parse_file_with_row_errors] or
+    /// [parse_reader_with_row_errors] to get the row errors, so that the
+    /// caller can decide whether to proceed with the good rows.
+    ///
+    /// IO errors are never tolerated since the rest of the input cannot be
+    /// read either.
+    pub fn with_error_tolerance(mut self, error_tolerance: bool) -> Self {
+        self.error_tolerance = error_tolerance;
+        self
+    }
+
+    /// Set whether the file is memory-mapped instead of being read through a
+    /// buffered reader.
+    ///
+    /// Defaults to false. For very large files this lets the OS page the file
+    /// in & out as the records are parsed, rather than copying it through the
+    /// reader's buffer. Only applies to [parse_file]; records read via
+    /// [from_reader] are not affected.
+    ///
+    /// The file must not be modified while it is being parsed, otherwise the
+    /// parsed records are undefined.
+    #[cfg(feature = "mmap")]
+    pub fn with_mmap(mut self, mmap: bool) -> Self {
+        self.mmap = mmap;
+        self
+    }
+
+    /// Set whether `num_entities` randomly generated entities are appended to
+    /// the entities from the reader or file in
+    /// [parse_file_and_maybe_generate_random].
+    ///
+    /// Defaults to false, in which case `num_entities` is ignored if there is
+    /// a reader or file. This is useful for padding a set of real entities
+    /// with random ones to reach a target number of entities, e.g. for
+    /// testing at scale.
+    pub fn with_combine(mut self, combine: bool) -> Self {
+        self.combine = combine;
+        self
+    }
+
+    #[cfg(any(test, feature = "testing"))]
+    pub fn with_random_seed_opt(mut self, random_seed: Option<u64>) -> Self {
+        self.random_seed = random_seed;
+        self
+    }
+
+    /// For seeding the PRNG used for sampling so that the output is
+    /// deterministic.
+    ///
+    /// Note: This is **not** cryptographically secure and should only be used
+    /// for testing.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn with_random_seed(self, random_seed: u64) -> Self {
+        self.with_random_seed_opt(Some(random_seed))
+    }
+
     /// Open and parse the file, returning a vector of entities.
     /// The file is expected to hold 1 or more entity records.
     ///
+    /// If `sample_fraction` is set then only a random sample of the records
+    /// is returned. Records with the same ID are handled according to
+    /// `duplicate_policy`.
+    ///
     /// An error is returned if:
     /// a) the file cannot be opened
     /// b) the file type is not supported
     /// c) deserialization of any of the records in the file fails
-    #[time("debug", "EntitiesParser::{}")]
+    /// d) `sample_fraction` is not in the range `(0, 1]`
+    /// e) duplicate IDs are found and cannot be merged
+    ///
+    /// If `error_tolerance` is set then c) only applies to IO errors, and the
+    /// malformed rows are skipped with a warning. Use
+    /// [parse_file_with_row_errors] to get the skipped rows.
     pub fn parse_file(self) -> Result<Vec<Entity>, EntitiesParserError> {
+        let (entities, row_errors) = self.parse_file_with_row_errors()?;
+        warn_row_errors(&row_errors);
+        Ok(entities)
+    }
+
+    /// Same as [parse_file] but the malformed rows that were skipped are also
+    /// returned.
+    ///
+    /// The vector of [RowError] is always empty unless `error_tolerance` is
+    /// set, since the first malformed row gives an error otherwise.
+    #[time("debug", "EntitiesParser::{}")]
+    pub fn parse_file_with_row_errors(
+        self,
+    ) -> Result<(Vec<Entity>, Vec<RowError>), EntitiesParserError> {
         debug!(
             "Attempting to parse {:?} as a file containing a list of entity IDs and liabilities",
             &self.path
         );
 
-        let path = self.path.ok_or(EntitiesParserError::PathNotSet)?;
+        let path = self.path.clone().ok_or(EntitiesParserError::PathNotSet)?;
 
         let ext = path.extension().and_then(|s| s.to_str()).ok_or(
             EntitiesParserError::UnknownFileType(path.clone().into_os_string()),
         )?;
 
+        let (entities, row_errors) = match FileType::from_str(ext)? {
+            #[cfg(feature = "mmap")]
+            FileType::Csv if self.mmap => {
+                let file = std::fs::File::open(path).map_err(csv::Error::from)?;
+                // SAFETY: the mapped bytes are only read, and the caller is
+                // told not to modify the file while it is being parsed (see
+                // [with_mmap]).
+                let mmap = unsafe { memmap2::Mmap::map(&file) }.map_err(csv::Error::from)?;
+                self.deserialize_csv(self.csv_reader_builder().from_reader(&mmap[..]))?
+            }
+            FileType::Csv => self.deserialize_csv(self.csv_reader_builder().from_path(path)?)?,
+        };
+
+        debug!("Successfully parsed entities file",);
+
+        Ok((entities, row_errors))
+    }
+
+    /// Parse the CSV entity records from the reader set in [from_reader],
+    /// returning a vector of entities.
+    ///
+    /// If `sample_fraction` is set then only a random sample of the records
+    /// is returned. Records with the same ID are handled according to
+    /// `duplicate_policy`.
+    ///
+    /// An error is returned if:
+    /// a) the reader is not set
+    /// b) deserialization of any of the records fails
+    /// c) `sample_fraction` is not in the range `(0, 1]`
+    /// d) duplicate IDs are found and cannot be merged
+    ///
+    /// If `error_tolerance` is set then b) only applies to IO errors, and the
+    /// malformed rows are skipped with a warning. Use
+    /// [parse_reader_with_row_errors] to get the skipped rows.
+    pub fn parse_reader(self) -> Result<Vec<Entity>, EntitiesParserError> {
+        let (entities, row_errors) = self.parse_reader_with_row_errors()?;
+        warn_row_errors(&row_errors);
+        Ok(entities)
+    }
+
+    /// Same as [parse_reader] but the malformed rows that were skipped are
+    /// also returned.
+    ///
+    /// The vector of [RowError] is always empty unless `error_tolerance` is
+    /// set, since the first malformed row gives an error otherwise.
+    #[time("debug", "EntitiesParser::{}")]
+    pub fn parse_reader_with_row_errors(
+        mut self,
+    ) -> Result<(Vec<Entity>, Vec<RowError>), EntitiesParserError> {
+        debug!("Attempting to parse entity IDs and liabilities from reader");
+
+        let reader = self
+            .reader
+            .take()
+            .ok_or(EntitiesParserError::ReaderNotSet)?;
+
+        let (entities, row_errors) =
+            self.deserialize_csv(self.csv_reader_builder().from_reader(reader))?;
+
+        debug!("Successfully parsed entities from reader");
+
+        Ok((entities, row_errors))
+    }
+
+    /// CSV reader builder with the configured delimiter & header handling.
+    fn csv_reader_builder(&self) -> csv::ReaderBuilder {
+        let mut builder = csv::ReaderBuilder::new();
+        builder
+            .delimiter(self.delimiter.unwrap_or(DEFAULT_DELIMITER))
+            .has_headers(self.has_headers.unwrap_or(true));
+        builder
+    }
+
+    /// Deserialize all the records from the CSV reader, applying sampling if
+    /// `sample_fraction` is set, and then the duplicate policy.
+    ///
+    /// If `error_tolerance` is set then rows that fail to be read or
+    /// deserialized are collected instead of returning an error.
+    fn deserialize_csv<R: Read>(
+        &self,
+        mut reader: csv::Reader<R>,
+    ) -> Result<(Vec<Entity>, Vec<RowError>), EntitiesParserError> {
+        if let Some(sample_fraction) = self.sample_fraction {
+            if !(sample_fraction > 0f64 && sample_fraction <= 1f64) {
+                return Err(EntitiesParserError::InvalidSampleFraction(sample_fraction));
+            }
+        }
+
+        let mut rng = match self.random_seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+
+        let headers = if reader.has_headers() {
+            reader.headers()?.clone()
+        } else {
+            csv::StringRecord::from(HEADERLESS_COLUMNS.to_vec())
+        };
+
         let mut entities = Vec::<Entity>::new();
+        let mut row_errors = Vec::<RowError>::new();
+        let mut record = csv::ByteRecord::new();
+
+        loop {
+            // The record is filled in even if reading it fails (e.g. because
+            // it has the wrong number of fields), so it can be used for the
+            // row error.
+            let entity = match reader.read_byte_record(&mut record) {
+                Ok(false) => break,
+                Ok(true) => self.deserialize_record(&record, headers.as_byte_record()),
+                Err(err) if err.is_io_error() => return Err(err.into()),
+                Err(err) => Err(err.into()),
+            };
+
+            let entity = match entity {
+                Ok(entity) => entity,
+                Err(err) if self.error_tolerance => {
+                    row_errors.push(RowError {
+                        line: record.position().map_or(0, |position| position.line()),
+                        content: self.raw_record_content(&record),
+                        source: err,
+                    });
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+
+            if let Some(sample_fraction) = self.sample_fraction {
+                if !rng.gen_bool(sample_fraction) {
+                    continue;
+                }
+            }
+
+            entities.push(entity);
+        }
 
-        match FileType::from_str(ext)? {
-            FileType::Csv => {
-                let mut reader = csv::Reader::from_path(path)?;
+        Ok((self.apply_duplicate_policy(entities)?, row_errors))
+    }
+
+    /// Deserialize a single CSV record into an entity, scaling the liability
+    /// if `decimal_scale` is set.
+    fn deserialize_record(
+        &self,
+        record: &csv::ByteRecord,
+        headers: &csv::ByteRecord,
+    ) -> Result<Entity, EntitiesParserError> {
+        let entity = match self.decimal_scale {
+            None => record.deserialize(Some(headers))?,
+            Some(decimal_scale) => {
+                let decimal_record: DecimalEntityRecord = record.deserialize(Some(headers))?;
+
+                let liability = parse_decimal_liability(&decimal_record.liability, decimal_scale)
+                    .ok_or_else(|| EntitiesParserError::InvalidDecimalLiability {
+                    line: record.position().map_or(0, |position| position.line()),
+                    value: decimal_record.liability.clone(),
+                    decimal_scale,
+                })?;
 
-                for record in reader.deserialize() {
-                    let entity: Entity = record?;
-                    entities.push(entity);
+                Entity {
+                    liability,
+                    id: decimal_record.id,
+                    salt: decimal_record.salt,
+                    blinding_factor: None,
                 }
             }
         };
 
-        debug!("Successfully parsed entities file",);
+        Ok(entity)
+    }
 
-        Ok(entities)
+    /// The fields of the record joined by the delimiter, for error reporting.
+    ///
+    /// Invalid UTF-8 is replaced, and quotes around fields are not kept.
+    fn raw_record_content(&self, record: &csv::ByteRecord) -> String {
+        let delimiter = [self.delimiter.unwrap_or(DEFAULT_DELIMITER)];
+        let fields: Vec<&[u8]> = record.iter().collect();
+        String::from_utf8_lossy(&fields.join(&delimiter[..])).into_owned()
+    }
+
+    /// Merge or reject records with the same ID, depending on
+    /// `duplicate_policy`.
+    ///
+    /// The order of the records is preserved, with merged records taking the
+    /// position of the first record with that ID.
+    fn apply_duplicate_policy(
+        &self,
+        entities: Vec<Entity>,
+    ) -> Result<Vec<Entity>, EntitiesParserError> {
+        let num_records = entities.len();
+        let mut positions = HashMap::<EntityId, usize>::with_capacity(num_records);
+        let mut deduplicated = Vec::<Entity>::with_capacity(num_records);
+
+        for entity in entities {
+            let Some(&position) = positions.get(&entity.id) else {
+                positions.insert(entity.id.clone(), deduplicated.len());
+                deduplicated.push(entity);
+                continue;
+            };
+
+            let existing = &mut deduplicated[position];
+
+            existing.liability = match self.duplicate_policy {
+                DuplicatePolicy::Error => {
+                    return Err(EntitiesParserError::DuplicateEntityId(entity.id))
+                }
+                DuplicatePolicy::Sum => existing
+                    .liability
+                    .checked_add(entity.liability)
+                    .ok_or(EntitiesParserError::LiabilitySumOverflow(entity.id))?,
+                DuplicatePolicy::Max => existing.liability.max(entity.liability),
+            };
+        }
+
+        if deduplicated.len() < num_records {
+            debug!(
+                "Merged {} entity records with duplicate IDs",
+                num_records - deduplicated.len()
+            );
+        }
+
+        Ok(deduplicated)
     }
 
     /// Generate a vector of entities with random IDs & liabilities.
@@ -111,36 +564,144 @@ impl EntitiesParser {
             .num_entities
             .ok_or(EntitiesParserError::NumEntitiesNotSet)?;
 
-        let mut rng = thread_rng();
         let mut result = Vec::with_capacity(num_entities as usize);
-
-        let liability_range = Uniform::new(0u64, u64::MAX / num_entities);
-
-        for _i in 0..num_entities {
-            let liability = rng.sample(liability_range);
-            let rand_str = Alphanumeric.sample_string(&mut rng, ENTITY_ID_MAX_BYTES);
-            let id = EntityId::from_str(&rand_str).expect("A failure should not be possible here because the length of the random string exactly matches the max allowed length");
-
-            result.push(Entity { liability, id })
-        }
+        append_random_entities(&mut result, num_entities);
 
         Ok(result)
     }
 
-    /// If a file path is present then parse the file, otherwise generate
-    /// entity records randomly. The number of entity records generated must
-    /// be provided.
+    /// If a reader is present then parse from the reader, else if a file path
+    /// is present then parse the file, otherwise generate entity records
+    /// randomly. The number of entity records generated must be provided.
+    ///
+    /// If `combine` is set and `num_entities` is set then that many random
+    /// entities are appended to the ones from the reader or file. Their IDs
+    /// do not collide with the parsed ones.
     ///
     /// Errors are returned if:
-    /// a) a file is present and [parse] gives an error
-    /// b) neither a file nor a number of entities are present
-    pub fn parse_file_or_generate_random(self) -> Result<Vec<Entity>, EntitiesParserError> {
-        if self.path.is_some() {
-            self.parse_file()
+    /// a) a reader or file is present and parsing gives an error
+    /// b) neither a reader, file nor a number of entities are present
+    pub fn parse_file_and_maybe_generate_random(self) -> Result<Vec<Entity>, EntitiesParserError> {
+        let num_random_entities = if self.combine {
+            self.num_entities
+        } else {
+            None
+        };
+
+        let mut entities = if self.reader.is_some() {
+            self.parse_reader()?
+        } else if self.path.is_some() {
+            self.parse_file()?
         } else {
             warn!("No entity file provided, defaulting to generating random entities");
-            self.generate_random()
+            return self.generate_random();
+        };
+
+        if let Some(num_random_entities) = num_random_entities {
+            debug!(
+                "Appending {} random entities to the {} parsed ones",
+                num_random_entities,
+                entities.len()
+            );
+            append_random_entities(&mut entities, num_random_entities);
         }
+
+        Ok(entities)
+    }
+}
+
+/// Append `num_entities` entities with random IDs & liabilities to
+/// `entities`.
+///
+/// The random IDs are guaranteed not to collide with the IDs already in
+/// `entities`. Each random liability is less than `u64::MAX / n`, where `n` is
+/// the total number of entities, so that the random liabilities cannot
+/// overflow when summed. The existing entities are not changed.
+fn append_random_entities(entities: &mut Vec<Entity>, num_entities: u64) {
+    if num_entities == 0 {
+        return;
+    }
+
+    let mut rng = thread_rng();
+    let total_entities = entities.len() as u64 + num_entities;
+    let liability_range = Uniform::new(0u64, u64::MAX / total_entities);
+
+    let mut ids: HashSet<EntityId> = entities.iter().map(|entity| entity.id.clone()).collect();
+    entities.reserve(num_entities as usize);
+
+    for _i in 0..num_entities {
+        let id = loop {
+            let rand_str = Alphanumeric.sample_string(&mut rng, ENTITY_ID_MAX_BYTES);
+            let id = EntityId::from_str(&rand_str).expect("A failure should not be possible here because the length of the random string exactly matches the max allowed length");
+            if ids.insert(id.clone()) {
+                break id;
+            }
+        };
+
+        entities.push(Entity {
+            liability: rng.sample(liability_range),
+            id,
+            salt: None,
+            blinding_factor: None,
+        })
+    }
+}
+
+/// Log the rows that were skipped because they were malformed.
+fn warn_row_errors(row_errors: &[RowError]) {
+    if row_errors.is_empty() {
+        return;
+    }
+
+    warn!(
+        "Skipped {} malformed entity records, first one: {}",
+        row_errors.len(),
+        row_errors[0]
+    );
+}
+
+/// Convert a decimal string to an integer by multiplying it by
+/// `10^decimal_scale`.
+///
+/// `None` is returned if the string is not a non-negative decimal number, if
+/// it has more than `decimal_scale` fractional digits, or if the result does
+/// not fit in a u64.
+fn parse_decimal_liability(value: &str, decimal_scale: u32) -> Option<u64> {
+    let value = value.trim();
+    let (integer_part, fractional_part) = value.split_once('.').unwrap_or((value, ""));
+
+    let is_digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+
+    if (integer_part.is_empty() && fractional_part.is_empty())
+        || !is_digits(integer_part)
+        || !is_digits(fractional_part)
+        || fractional_part.len() > decimal_scale as usize
+    {
+        return None;
+    }
+
+    let integer_part = match integer_part {
+        "" => 0,
+        digits => digits.parse::<u64>().ok()?,
+    };
+
+    // Shift the fractional part so that it has exactly `decimal_scale` digits.
+    let fractional_part = match fractional_part {
+        "" => 0,
+        digits => digits
+            .parse::<u64>()
+            .ok()?
+            .checked_mul(10u64.checked_pow(decimal_scale - digits.len() as u32)?)?,
+    };
+
+    integer_part
+        .checked_mul(10u64.checked_pow(decimal_scale)?)?
+        .checked_add(fractional_part)
+}
+
+impl Default for EntitiesParser {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -158,10 +719,25 @@ impl FromStr for FileType {
 // -------------------------------------------------------------------------------------------------
 // Errors.
 
+/// A CSV row that could not be parsed into an entity, see
+/// [EntitiesParser::with_error_tolerance].
+#[derive(thiserror::Error, Debug)]
+#[error("Malformed entity record on line {line}: {content:?}")]
+pub struct RowError {
+    /// Line number of the row in the input, starting from 1.
+    pub line: u64,
+    /// The fields of the row joined by the delimiter.
+    pub content: String,
+    #[source]
+    pub source: EntitiesParserError,
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum EntitiesParserError {
     #[error("Expected path to be set but found none")]
     PathNotSet,
+    #[error("Expected reader to be set but found none")]
+    ReaderNotSet,
     #[error("Expected num_entities to be set but found none")]
     NumEntitiesNotSet,
     #[error("Unable to find file extension for path {0:?}")]
@@ -170,6 +746,18 @@ pub enum EntitiesParserError {
     UnsupportedFileType { ext: String },
     #[error("Error opening or reading CSV file")]
     CsvError(#[from] csv::Error),
+    #[error("Sample fraction {0} is not in the range (0, 1]")]
+    InvalidSampleFraction(f64),
+    #[error("Entity ID {0:?} appears more than once")]
+    DuplicateEntityId(EntityId),
+    #[error("Summing the liabilities of the records with entity ID {0:?} overflows")]
+    LiabilitySumOverflow(EntityId),
+    #[error("Liability {value:?} on line {line} is not a valid decimal with at most {decimal_scale} decimal places, or it is too big")]
+    InvalidDecimalLiability {
+        line: u64,
+        value: String,
+        decimal_scale: u32,
+    },
 }
 
 // -------------------------------------------------------------------------------------------------
@@ -192,11 +780,15 @@ mod tests {
         let first_entity = Entity {
             id: EntityId::from_str("john.doe@example.com").unwrap(),
             liability: 893267u64,
+            salt: None,
+            blinding_factor: None,
         };
 
         let last_entity = Entity {
             id: EntityId::from_str("david.martin@example.com").unwrap(),
             liability: 142798u64,
+            salt: None,
+            blinding_factor: None,
         };
 
         assert!(entities.contains(&first_entity));
@@ -205,6 +797,26 @@ mod tests {
         assert_eq!(entities.len(), 100);
     }
 
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn mmap_gives_same_entities_as_buffered_reader() {
+        let src_dir = env!("CARGO_MANIFEST_DIR");
+        let resources_dir = Path::new(&src_dir).join("examples");
+        let path = resources_dir.join("entities_example.csv");
+
+        let buffered = EntitiesParser::new()
+            .with_path(path.clone())
+            .parse_file()
+            .unwrap();
+        let mapped = EntitiesParser::new()
+            .with_path(path)
+            .with_mmap(true)
+            .parse_file()
+            .unwrap();
+
+        assert_eq!(mapped, buffered);
+    }
+
     // TODO fuzz on num entities
     #[test]
     fn generate_random_entities_happy_case() {
@@ -216,6 +828,328 @@ mod tests {
         assert_eq!(entities.len(), num_entities as usize);
     }
 
+    #[test]
+    fn sampling_with_same_seed_gives_same_entities() {
+        let src_dir = env!("CARGO_MANIFEST_DIR");
+        let resources_dir = Path::new(&src_dir).join("examples");
+        let path = resources_dir.join("entities_example.csv");
+
+        let sample_1 = EntitiesParser::new()
+            .with_path(path.clone())
+            .with_sample_fraction(0.5)
+            .with_random_seed(7)
+            .parse_file()
+            .unwrap();
+
+        let sample_2 = EntitiesParser::new()
+            .with_path(path)
+            .with_sample_fraction(0.5)
+            .with_random_seed(7)
+            .parse_file()
+            .unwrap();
+
+        assert_eq!(sample_1, sample_2);
+        assert!(sample_1.len() > 0 && sample_1.len() < 100);
+    }
+
+    #[test]
+    fn sample_fraction_of_one_keeps_all_entities() {
+        let src_dir = env!("CARGO_MANIFEST_DIR");
+        let resources_dir = Path::new(&src_dir).join("examples");
+        let path = resources_dir.join("entities_example.csv");
+
+        let entities = EntitiesParser::new()
+            .with_path(path)
+            .with_sample_fraction(1.0)
+            .parse_file()
+            .unwrap();
+
+        assert_eq!(entities.len(), 100);
+    }
+
+    #[test]
+    fn fail_when_sample_fraction_out_of_range() {
+        let src_dir = env!("CARGO_MANIFEST_DIR");
+        let resources_dir = Path::new(&src_dir).join("examples");
+        let path = resources_dir.join("entities_example.csv");
+
+        let res = EntitiesParser::new()
+            .with_path(path)
+            .with_sample_fraction(1.5)
+            .parse_file();
+
+        assert_err!(res, Err(EntitiesParserError::InvalidSampleFraction(_)));
+    }
+
+    #[test]
+    fn parser_reader_happy_case() {
+        let csv = "id,liability\njohn.doe@example.com,893267\njane.doe@example.com,12\n";
+
+        let entities = EntitiesParser::from_reader(std::io::Cursor::new(csv))
+            .parse_reader()
+            .unwrap();
+
+        assert_eq!(
+            entities,
+            vec![
+                Entity {
+                    id: EntityId::from_str("john.doe@example.com").unwrap(),
+                    liability: 893267u64,
+                    salt: None,
+                    blinding_factor: None,
+                },
+                Entity {
+                    id: EntityId::from_str("jane.doe@example.com").unwrap(),
+                    liability: 12u64,
+                    salt: None,
+                    blinding_factor: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parser_reads_tab_separated_records_without_header() {
+        let csv = "john.doe@example.com\t893267\t\njane.doe@example.com\t12\tmy_salt\n";
+
+        let entities = EntitiesParser::from_reader(std::io::Cursor::new(csv))
+            .with_delimiter(b'\t')
+            .with_has_headers(false)
+            .parse_reader()
+            .unwrap();
+
+        assert_eq!(
+            entities,
+            vec![
+                Entity {
+                    id: EntityId::from_str("john.doe@example.com").unwrap(),
+                    liability: 893267u64,
+                    salt: None,
+                    blinding_factor: None,
+                },
+                Entity {
+                    id: EntityId::from_str("jane.doe@example.com").unwrap(),
+                    liability: 12u64,
+                    salt: Some(Salt::from_str("my_salt").unwrap()),
+                    blinding_factor: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parser_scales_decimal_liabilities() {
+        let csv = "id,liability\njohn.doe@example.com,1234.56\njane.doe@example.com,12.5\njoe.doe@example.com,7\n";
+
+        let entities = EntitiesParser::from_reader(std::io::Cursor::new(csv))
+            .with_decimal_scale(2)
+            .parse_reader()
+            .unwrap();
+
+        let liabilities: Vec<u64> = entities.iter().map(|e| e.liability).collect();
+        assert_eq!(liabilities, vec![123456, 1250, 700]);
+    }
+
+    #[test]
+    fn parser_rejects_decimal_liability_with_too_many_decimal_places() {
+        let csv = "id,liability\njohn.doe@example.com,1234.56\njane.doe@example.com,12.505\n";
+
+        let res = EntitiesParser::from_reader(std::io::Cursor::new(csv))
+            .with_decimal_scale(2)
+            .parse_reader();
+
+        match res {
+            Err(EntitiesParserError::InvalidDecimalLiability { line, value, .. }) => {
+                assert_eq!(line, 3);
+                assert_eq!(value, "12.505");
+            }
+            _ => panic!("Expected InvalidDecimalLiability error, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn parser_rejects_decimal_liability_without_scale() {
+        let csv = "id,liability\njohn.doe@example.com,1234.56\n";
+
+        let res = EntitiesParser::from_reader(std::io::Cursor::new(csv)).parse_reader();
+
+        assert_err!(res, Err(EntitiesParserError::CsvError(_)));
+    }
+
+    #[test]
+    fn decimal_liability_parsing_handles_edge_cases() {
+        assert_eq!(parse_decimal_liability("0.01", 2), Some(1));
+        assert_eq!(parse_decimal_liability(".5", 1), Some(5));
+        assert_eq!(parse_decimal_liability("5.", 1), Some(50));
+        assert_eq!(parse_decimal_liability("42", 0), Some(42));
+        assert_eq!(parse_decimal_liability("4.2", 0), None);
+        assert_eq!(parse_decimal_liability("-1.00", 2), None);
+        assert_eq!(parse_decimal_liability("1e5", 2), None);
+        assert_eq!(parse_decimal_liability(".", 2), None);
+        assert_eq!(parse_decimal_liability("", 2), None);
+        assert_eq!(parse_decimal_liability("18446744073709551615", 1), None);
+    }
+
+    #[test]
+    fn parser_reads_optional_salt_column() {
+        let csv =
+            "id,liability,salt\njohn.doe@example.com,893267,my_salt\njane.doe@example.com,12,\n";
+
+        let entities = EntitiesParser::from_reader(std::io::Cursor::new(csv))
+            .parse_reader()
+            .unwrap();
+
+        assert_eq!(entities[0].salt, Some(Salt::from_str("my_salt").unwrap()));
+        assert_eq!(entities[1].salt, None);
+    }
+
+    #[test]
+    fn reader_is_prioritized_over_path_and_random() {
+        let csv = "id,liability\njohn.doe@example.com,893267\n";
+
+        let entities = EntitiesParser::from_reader(std::io::Cursor::new(csv))
+            .with_path(PathBuf::from("./examples/entities_example.csv"))
+            .with_num_entities(10)
+            .parse_file_and_maybe_generate_random()
+            .unwrap();
+
+        assert_eq!(entities.len(), 1);
+    }
+
+    #[test]
+    fn combine_appends_random_entities_to_parsed_ones() {
+        let src_dir = env!("CARGO_MANIFEST_DIR");
+        let resources_dir = Path::new(&src_dir).join("examples");
+        let path = resources_dir.join("entities_example.csv");
+
+        let parsed = EntitiesParser::new()
+            .with_path(path.clone())
+            .parse_file()
+            .unwrap();
+
+        let combined = EntitiesParser::new()
+            .with_path(path)
+            .with_num_entities(50)
+            .with_combine(true)
+            .parse_file_and_maybe_generate_random()
+            .unwrap();
+
+        assert_eq!(combined.len(), parsed.len() + 50);
+        assert_eq!(combined[..parsed.len()], parsed[..]);
+
+        let ids: HashSet<&EntityId> = combined.iter().map(|entity| &entity.id).collect();
+        assert_eq!(ids.len(), combined.len());
+    }
+
+    #[test]
+    fn duplicate_ids_give_error_by_default() {
+        let csv = "id,liability\njohn.doe@example.com,10\njohn.doe@example.com,12\n";
+
+        let res = EntitiesParser::from_reader(std::io::Cursor::new(csv)).parse_reader();
+
+        assert_err!(res, Err(EntitiesParserError::DuplicateEntityId(_)));
+    }
+
+    #[test]
+    fn duplicate_ids_are_merged_with_sum_policy() {
+        let csv = "id,liability\njohn.doe@example.com,10\njane.doe@example.com,3\njohn.doe@example.com,12\n";
+
+        let entities = EntitiesParser::from_reader(std::io::Cursor::new(csv))
+            .with_duplicate_policy(DuplicatePolicy::Sum)
+            .parse_reader()
+            .unwrap();
+
+        assert_eq!(entities.len(), 2);
+        assert_eq!(
+            entities[0].id,
+            EntityId::from_str("john.doe@example.com").unwrap()
+        );
+        assert_eq!(entities[0].liability, 22u64);
+        assert_eq!(entities[1].liability, 3u64);
+    }
+
+    #[test]
+    fn duplicate_ids_are_merged_with_max_policy() {
+        let csv = "id,liability\njohn.doe@example.com,10\njohn.doe@example.com,12\njohn.doe@example.com,4\n";
+
+        let entities = EntitiesParser::from_reader(std::io::Cursor::new(csv))
+            .with_duplicate_policy(DuplicatePolicy::Max)
+            .parse_reader()
+            .unwrap();
+
+        assert_eq!(entities.len(), 1);
+        assert_eq!(entities[0].liability, 12u64);
+    }
+
+    #[test]
+    fn fail_when_summed_liabilities_overflow() {
+        let csv = format!(
+            "id,liability\njohn.doe@example.com,{}\njohn.doe@example.com,1\n",
+            u64::MAX
+        );
+
+        let res = EntitiesParser::from_reader(std::io::Cursor::new(csv))
+            .with_duplicate_policy(DuplicatePolicy::Sum)
+            .parse_reader();
+
+        assert_err!(res, Err(EntitiesParserError::LiabilitySumOverflow(_)));
+    }
+
+    #[test]
+    fn malformed_row_gives_error_by_default() {
+        let csv = "id,liability\njohn.doe@example.com,10\njane.doe@example.com,abc\n";
+
+        let res = EntitiesParser::from_reader(std::io::Cursor::new(csv)).parse_reader();
+
+        assert_err!(res, Err(EntitiesParserError::CsvError(_)));
+    }
+
+    #[test]
+    fn malformed_rows_are_collected_with_error_tolerance() {
+        let csv = "id,liability\njohn.doe@example.com,10\njane.doe@example.com,abc\nbad_row\njoe.doe@example.com,7\n";
+
+        let (entities, row_errors) = EntitiesParser::from_reader(std::io::Cursor::new(csv))
+            .with_error_tolerance(true)
+            .parse_reader_with_row_errors()
+            .unwrap();
+
+        let liabilities: Vec<u64> = entities.iter().map(|e| e.liability).collect();
+        assert_eq!(liabilities, vec![10, 7]);
+
+        assert_eq!(row_errors.len(), 2);
+        assert_eq!(row_errors[0].line, 3);
+        assert_eq!(row_errors[0].content, "jane.doe@example.com,abc");
+        assert_eq!(row_errors[1].line, 4);
+        assert_eq!(row_errors[1].content, "bad_row");
+    }
+
+    #[test]
+    fn invalid_decimal_liability_is_collected_with_error_tolerance() {
+        let csv = "id,liability\njohn.doe@example.com,1.5\njane.doe@example.com,12.505\n";
+
+        let (entities, row_errors) = EntitiesParser::from_reader(std::io::Cursor::new(csv))
+            .with_decimal_scale(2)
+            .with_error_tolerance(true)
+            .parse_reader_with_row_errors()
+            .unwrap();
+
+        assert_eq!(entities.len(), 1);
+        assert_eq!(row_errors.len(), 1);
+        match &row_errors[0].source {
+            EntitiesParserError::InvalidDecimalLiability { line, value, .. } => {
+                assert_eq!(*line, 3);
+                assert_eq!(value, "12.505");
+            }
+            err => panic!("Expected InvalidDecimalLiability error, got {:?}", err),
+        }
+    }
+
+    #[test]
+    fn fail_when_reader_not_set() {
+        let res = EntitiesParser::new().parse_reader();
+        assert_err!(res, Err(EntitiesParserError::ReaderNotSet));
+    }
+
     #[test]
     fn fail_when_unsupproted_file_type() {
         let this_file = std::file!();
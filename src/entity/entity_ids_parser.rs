@@ -1,9 +1,13 @@
-use std::str::FromStr;
-use std::{ffi::OsString, path::PathBuf};
+use std::ffi::OsString;
+#[cfg(feature = "full")]
+use std::{path::PathBuf, str::FromStr};
 
-use log::{debug, info};
+#[cfg(feature = "full")]
+use log::debug;
 
-use crate::entity::{EntityId, ENTITY_ID_MAX_BYTES};
+#[cfg(feature = "full")]
+use crate::entity::EntityId;
+use crate::entity::ENTITY_ID_MAX_BYTES;
 
 /// Parser for files containing a list of entity IDs.
 ///
@@ -21,16 +25,19 @@ use crate::entity::{EntityId, ENTITY_ID_MAX_BYTES};
 /// path.push("./examples/entities_example.csv");
 /// let entities = EntityIdsParser::from(path).parse().unwrap();
 /// ```
+#[cfg(feature = "full")]
 pub struct EntityIdsParser {
     path: Option<PathBuf>,
     entity_ids_list: Option<String>,
 }
 
 /// Supported file types for the parser.
+#[cfg(feature = "full")]
 enum FileType {
     Csv,
 }
 
+#[cfg(feature = "full")]
 impl EntityIdsParser {
     /// Parse the input.
     ///
@@ -104,6 +111,7 @@ impl EntityIdsParser {
     }
 }
 
+#[cfg(feature = "full")]
 impl From<PathBuf> for EntityIdsParser {
     fn from(path: PathBuf) -> Self {
         Self {
@@ -113,6 +121,7 @@ impl From<PathBuf> for EntityIdsParser {
     }
 }
 
+#[cfg(feature = "full")]
 impl FromStr for EntityIdsParser {
     type Err = EntityIdsParserError;
 
@@ -124,6 +133,7 @@ impl FromStr for EntityIdsParser {
     }
 }
 
+#[cfg(feature = "full")]
 impl FromStr for FileType {
     type Err = EntityIdsParserError;
 
@@ -147,6 +157,7 @@ pub enum EntityIdsParserError {
     UnknownFileType(OsString),
     #[error("The file type with extension {ext:?} is not supported")]
     UnsupportedFileType { ext: String },
+    #[cfg(feature = "full")]
     #[error("Error opening or reading CSV file")]
     CsvError(#[from] csv::Error),
     #[error("Problem serializing/deserializing with serde_json")]
@@ -160,7 +171,7 @@ pub enum EntityIdsParserError {
 // -------------------------------------------------------------------------------------------------
 // Unit tests
 
-#[cfg(test)]
+#[cfg(all(test, feature = "full"))]
 mod tests {
     use super::*;
     use std::path::Path;
@@ -43,6 +43,23 @@ impl Hasher {
         Hasher(blake3::Hasher::new())
     }
 
+    /// Hasher with an initial state derived from `domain_separator`, using the
+    /// [key derivation mode] of blake3.
+    ///
+    /// Hashes from hashers with different domain separators are unrelated,
+    /// even for the same input, and are also unrelated to the hashes from
+    /// [Hasher::new]. The domain separator should be a hardcoded, unique
+    /// context string e.g. `"example.com 2024-01-01 liabilities"`.
+    ///
+    /// Only the hashes computed with the returned hasher are domain-separated.
+    /// In the tree this is just the leaf & padding node hashes, see
+    /// `BuildOptions::with_domain_separator`.
+    ///
+    /// [key derivation mode]: blake3::Hasher::new_derive_key
+    pub fn new_with_domain_separator(domain_separator: &str) -> Self {
+        Hasher(blake3::Hasher::new_derive_key(domain_separator))
+    }
+
     pub fn update(&mut self, input: &[u8]) -> &mut Self {
         self.0.update(input);
         self.0.update(DELIMITER);
@@ -94,4 +111,19 @@ mod tests {
                 .unwrap()
         );
     }
+
+    #[test]
+    fn domain_separator_changes_hash() {
+        let mut hasher = Hasher::new();
+        hasher.update("dapol".as_bytes());
+
+        let mut separated_1 = Hasher::new_with_domain_separator("deployment 1");
+        separated_1.update("dapol".as_bytes());
+
+        let mut separated_2 = Hasher::new_with_domain_separator("deployment 2");
+        separated_2.update("dapol".as_bytes());
+
+        assert_ne!(hasher.finalize(), separated_1.finalize());
+        assert_ne!(separated_1.finalize(), separated_2.finalize());
+    }
 }
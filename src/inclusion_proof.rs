@@ -1,13 +1,26 @@
+#[cfg(feature = "full")]
+use curve25519_dalek_ng::ristretto::RistrettoPoint;
 use primitive_types::H256;
+use rand::{thread_rng, CryptoRng, RngCore};
+#[cfg(feature = "full")]
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 
-use std::{fmt::Debug, path::PathBuf};
+use std::{
+    fmt::{self, Debug},
+    path::PathBuf,
+};
 
 use log::info;
 
-use crate::binary_tree::{Coordinate, Height, Node, PathSiblings};
+use crate::binary_tree::{Coordinate, Height, Node, PathInfoFormat, PathSiblings};
 use crate::binary_tree::{FullNodeContent, HiddenNodeContent};
+use crate::max_liability::ALLOWED_RANGE_PROOF_UPPER_BIT_SIZES;
+#[cfg(feature = "full")]
+use crate::{kdf, Salt, Secret};
 use crate::{read_write_utils, EntityId};
+#[cfg(feature = "full")]
+use crate::{DapolTree, RootPublicData};
 
 mod individual_range_proof;
 use individual_range_proof::IndividualRangeProof;
@@ -16,11 +29,76 @@ mod aggregated_range_proof;
 use aggregated_range_proof::AggregatedRangeProof;
 
 mod aggregation_factor;
-pub use aggregation_factor::AggregationFactor;
+pub use aggregation_factor::{AggregationFactor, OptimizationTarget};
+
+#[cfg(feature = "full")]
+mod batch_verifier;
+#[cfg(feature = "full")]
+pub use batch_verifier::{verify_proofs_multi_root, BatchVerificationError, ProofBatchVerifier};
+
+mod combined_proofs;
+pub use combined_proofs::{
+    CombinedProofReader, CombinedProofWriter, StreamingVerificationError, StreamingVerifier,
+    COMBINED_PROOFS_EXTENSION,
+};
+
+mod joint_inclusion_proof;
+pub use joint_inclusion_proof::{JointInclusionProof, JointInclusionProofError};
+
+mod membership_proof;
+pub use membership_proof::{MembershipProof, MembershipProofError};
+
+#[cfg(feature = "proto")]
+mod protobuf;
+#[cfg(feature = "proto")]
+pub use protobuf::ProtobufError;
 
 /// The file extension used when writing serialized binary files.
 const SERIALIZED_PROOF_EXTENSION: &str = "dapolproof";
 
+/// Bulletproofs only supports a few bit lengths, and the error it returns for
+/// other values does not say which value was used, so check it upfront.
+fn check_upper_bound_bit_length(upper_bound_bit_length: u8) -> Result<(), RangeProofError> {
+    if ALLOWED_RANGE_PROOF_UPPER_BIT_SIZES.contains(&upper_bound_bit_length) {
+        Ok(())
+    } else {
+        Err(RangeProofError::UnsupportedUpperBoundBitLength(
+            upper_bound_bit_length,
+        ))
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Range proof serde.
+
+/// [serde_with] adapter for [RangeProof](bulletproofs::RangeProof).
+///
+/// The serde implementation in [bulletproofs] can only deserialize from a byte
+/// array, which works for binary formats but not for formats like JSON where
+/// bytes are written as a sequence of integers. This adapter serializes the
+/// proof in exactly the same way, but deserializes from either a byte array
+/// or a sequence.
+pub(crate) struct RangeProofBytes;
+
+impl serde_with::SerializeAs<bulletproofs::RangeProof> for RangeProofBytes {
+    fn serialize_as<S: serde::Serializer>(
+        proof: &bulletproofs::RangeProof,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&proof.to_bytes())
+    }
+}
+
+impl<'de> serde_with::DeserializeAs<'de, bulletproofs::RangeProof> for RangeProofBytes {
+    fn deserialize_as<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<bulletproofs::RangeProof, D::Error> {
+        let bytes = serde_bytes::ByteBuf::deserialize(deserializer)?;
+        bulletproofs::RangeProof::from_bytes(&bytes)
+            .map_err(|err| serde::de::Error::custom(format!("{:?}", err)))
+    }
+}
+
 // -------------------------------------------------------------------------------------------------
 // Main struct & implementation.
 
@@ -88,6 +166,29 @@ impl InclusionProof {
         path_siblings: PathSiblings<FullNodeContent>,
         aggregation_factor: AggregationFactor,
         upper_bound_bit_length: u8,
+    ) -> Result<Self, InclusionProofError> {
+        Self::generate_with_rng(
+            leaf_node,
+            path_siblings,
+            aggregation_factor,
+            upper_bound_bit_length,
+            &mut thread_rng(),
+        )
+    }
+
+    /// Same as [InclusionProof::generate] but the randomness for the
+    /// Bulletproofs prover is taken from `rng`.
+    ///
+    /// The range proofs are generated sequentially from `rng` so a seeded
+    /// `rng` gives the exact same proof bytes every time, which is useful for
+    /// test vectors. `rng` must be cryptographically secure and must not be
+    /// seeded with a predictable value for proofs that are given to entities.
+    pub fn generate_with_rng<R: RngCore + CryptoRng>(
+        leaf_node: Node<FullNodeContent>,
+        path_siblings: PathSiblings<FullNodeContent>,
+        aggregation_factor: AggregationFactor,
+        upper_bound_bit_length: u8,
+        rng: &mut R,
     ) -> Result<Self, InclusionProofError> {
         // Is this cast safe? Yes because the tree height (which is the same as the
         // length of the input) is also stored as a u8, and so there would never
@@ -95,22 +196,31 @@ impl InclusionProof {
         // vector for siblings. If the tree height changes type for some
         // reason then this code would fail silently.
         let tree_height = Height::from_y_coord(path_siblings.len() as u8);
-        let aggregation_index = aggregation_factor.apply_to(&tree_height);
 
-        let mut nodes_for_aggregation = path_siblings.construct_path(leaf_node.clone())?;
-        let nodes_for_individual_proofs =
-            nodes_for_aggregation.split_off(aggregation_index as usize);
+        let path_nodes = path_siblings.construct_path(leaf_node.clone())?;
+        let (nodes_for_aggregation, nodes_for_individual_proofs) =
+            aggregation_factor.split(path_nodes, &tree_height);
 
         let aggregated_range_proof = match aggregation_factor.is_zero(&tree_height) {
             false => {
+                let coords = nodes_for_aggregation
+                    .iter()
+                    .map(|node| node.coord.clone())
+                    .collect();
                 let aggregation_tuples = nodes_for_aggregation
                     .into_iter()
                     .map(|node| (node.content.liability, node.content.blinding_factor))
                     .collect();
-                Some(AggregatedRangeProof::generate(
-                    &aggregation_tuples,
-                    upper_bound_bit_length,
-                )?)
+                Some(
+                    AggregatedRangeProof::generate(
+                        &aggregation_tuples,
+                        upper_bound_bit_length,
+                        rng,
+                    )
+                    .map_err(|source| {
+                        InclusionProofError::RangeProofGenerationError { coords, source }
+                    })?,
+                )
             }
             true => None,
         };
@@ -124,7 +234,14 @@ impl InclusionProof {
                             node.content.liability,
                             &node.content.blinding_factor,
                             upper_bound_bit_length,
+                            rng,
                         )
+                        .map_err(|source| {
+                            InclusionProofError::RangeProofGenerationError {
+                                coords: vec![node.coord],
+                                source,
+                            }
+                        })
                     })
                     .collect::<Result<Vec<_>, _>>()?,
             ),
@@ -141,6 +258,15 @@ impl InclusionProof {
         })
     }
 
+    /// The bit length used for the upper bound of the range proofs, i.e. the
+    /// range proofs show `0 <= liability < 2^upper_bound_bit_length`.
+    ///
+    /// Note that for a deserialized proof this value has not been validated
+    /// yet; it is checked when the proof is verified.
+    pub fn upper_bound_bit_length(&self) -> u8 {
+        self.upper_bound_bit_length
+    }
+
     /// Verify that an inclusion proof matches a the root hash.
     pub fn verify(&self, root_hash: H256) -> Result<(), InclusionProofError> {
         info!("Verifying inclusion proof..");
@@ -161,15 +287,92 @@ impl InclusionProof {
         Ok(())
     }
 
+    /// Verify only that the leaf node is in the tree with the given root hash.
+    ///
+    /// The Merkle path is checked but the range proofs are not. This is much
+    /// cheaper than [InclusionProof::verify] since Bulletproofs verification
+    /// is the expensive part of verifying an inclusion proof.
+    ///
+    /// Warning: this does NOT validate that the liability (or any of the
+    /// liabilities in the path) is within the range
+    /// [0, 2^upper_bound_bit_length). Without the range proofs a negative
+    /// liability could be hidden in the tree, so this check should only be
+    /// used when the range proofs are verified elsewhere.
+    pub fn verify_membership_only(&self, root_hash: H256) -> Result<(), InclusionProofError> {
+        info!("Verifying inclusion proof membership (range proofs skipped)..");
+
+        let tree_height = Height::from_y_coord(self.path_siblings.len() as u8);
+
+        let hidden_leaf_node: Node<HiddenNodeContent> = self.leaf_node.clone().convert();
+        let constructed_path = self.path_siblings.construct_path(hidden_leaf_node)?;
+
+        self.verify_merkle_path(root_hash, tree_height, &constructed_path)?;
+
+        info!("Succesfully verified proof membership");
+
+        Ok(())
+    }
+
+    /// Verify that an inclusion proof matches the published root node.
+    ///
+    /// Both the hash and the Pedersen commitment of the root node that is
+    /// reconstructed from the proof's path are checked against
+    /// `root_public_data`, and then the range proofs are verified.
+    #[cfg(feature = "full")]
+    pub fn verify_against_public_root_data(
+        &self,
+        root_public_data: &RootPublicData,
+    ) -> Result<(), InclusionProofError> {
+        info!("Verifying inclusion proof against public root data..");
+
+        let tree_height = Height::from_y_coord(self.path_siblings.len() as u8);
+
+        let hidden_leaf_node: Node<HiddenNodeContent> = self.leaf_node.clone().convert();
+        let constructed_path = self.path_siblings.construct_path(hidden_leaf_node)?;
+
+        self.verify_merkle_path(root_public_data.hash, tree_height, &constructed_path)?;
+        self.verify_root_commitment(root_public_data.commitment, &constructed_path)?;
+        self.verify_range_proofs(tree_height, &constructed_path)?;
+
+        info!("Succesfully verified proof");
+
+        Ok(())
+    }
+
+    /// Verify that an inclusion proof matches the root node published in the
+    /// given file.
+    ///
+    /// The file is deserialized using
+    /// [DapolTree::deserialize_public_root_data] (so it is expected to be
+    /// json), and then [InclusionProof::verify_against_public_root_data] is
+    /// called.
+    ///
+    /// An error is returned if
+    /// 1. The file cannot be read or deserialized.
+    /// 2. The root hash or root commitment does not match.
+    /// 3. The range proofs do not verify.
+    #[cfg(feature = "full")]
+    pub fn verify_against_public_root_file(
+        &self,
+        path: PathBuf,
+    ) -> Result<(), InclusionProofError> {
+        let root_public_data = DapolTree::deserialize_public_root_data(path)
+            .map_err(|err| InclusionProofError::PublicRootDataReadError(Box::new(err)))?;
+
+        self.verify_against_public_root_data(&root_public_data)
+    }
+
     /// Verify that an inclusion proof matches the root hash, and show path info.
     ///
     /// The path information is printed to stdout, and written to a json file
-    /// in the given location.
+    /// in the given location. The hashes & commitments in the json file are
+    /// encoded according to `format`.
     pub fn verify_and_show_path_info(
         self,
         root_hash: H256,
         dir: PathBuf,
-        mut file_name: OsString,
+        file_name: OsString,
+        format: PathInfoFormat,
     ) -> Result<(), InclusionProofError> {
         info!("Verifying inclusion proof..");
 
@@ -190,11 +393,81 @@ impl InclusionProof {
         info!("{}", path_str);
 
         self.path_siblings
-            .write_path_to_json(constructed_path, dir, file_name)?;
+            .write_path_to_json(constructed_path, dir, file_name, format)?;
 
         Ok(())
     }
 
+    /// Verify that the proof's leaf node belongs to `entity_id`.
+    ///
+    /// The entity's salt is derived from `master_secret` & `salt_s` and the
+    /// x-coord of the leaf node (see [crate::kdf]), and then the leaf hash
+    /// `H("leaf" | entity_id | entity_salt)` is recomputed and compared to the
+    /// hash of the leaf node in the proof. This binds the proof to a specific
+    /// entity, so that a valid proof cannot be passed off as belonging to a
+    /// different entity.
+    ///
+    /// Note that this does not verify the proof itself, use
+    /// [InclusionProof::verify] for that. If the tree was built with a
+    /// domain separator then use
+    /// [InclusionProof::verify_entity_with_domain_separator] instead. Entities
+    /// that were given an explicit salt when the tree was built cannot be
+    /// verified this way since their salt is not derived from the secrets.
+    ///
+    /// The tree's secrets are needed so this is only available with the `full`
+    /// feature, for the tree owner or an auditor that has been given the
+    /// secrets.
+    #[cfg(feature = "full")]
+    pub fn verify_entity(
+        &self,
+        entity_id: &EntityId,
+        master_secret: &Secret,
+        salt_s: &Salt,
+    ) -> Result<(), InclusionProofError> {
+        self.verify_entity_with_domain_separator_opt(entity_id, master_secret, salt_s, None)
+    }
+
+    /// Same as [InclusionProof::verify_entity] but for trees that were built
+    /// with a domain separator.
+    #[cfg(feature = "full")]
+    pub fn verify_entity_with_domain_separator(
+        &self,
+        entity_id: &EntityId,
+        master_secret: &Secret,
+        salt_s: &Salt,
+        domain_separator: &str,
+    ) -> Result<(), InclusionProofError> {
+        self.verify_entity_with_domain_separator_opt(
+            entity_id,
+            master_secret,
+            salt_s,
+            Some(domain_separator),
+        )
+    }
+
+    #[cfg(feature = "full")]
+    fn verify_entity_with_domain_separator_opt(
+        &self,
+        entity_id: &EntityId,
+        master_secret: &Secret,
+        salt_s: &Salt,
+        domain_separator: Option<&str>,
+    ) -> Result<(), InclusionProofError> {
+        let x_coord = self.leaf_node.coord.x;
+
+        let entity_salt: Secret =
+            kdf::derive_entity_salt(master_secret.as_bytes(), salt_s.as_bytes(), x_coord).into();
+
+        let expected_hash =
+            FullNodeContent::leaf_hash(entity_id.clone(), entity_salt, domain_separator);
+
+        if expected_hash == self.leaf_node.content.hash {
+            Ok(())
+        } else {
+            Err(InclusionProofError::EntityMismatch(entity_id.clone()))
+        }
+    }
+
     /// Merkle tree path verification.
     fn verify_merkle_path(
         &self,
@@ -229,7 +502,31 @@ impl InclusionProof {
         }
     }
 
+    /// Check that the commitment of the root node that was constructed from
+    /// the path matches the published commitment.
+    #[cfg(feature = "full")]
+    fn verify_root_commitment(
+        &self,
+        root_commitment: RistrettoPoint,
+        path_nodes: &[Node<HiddenNodeContent>],
+    ) -> Result<(), InclusionProofError> {
+        // this should never panic because the path construction checks for min length
+        let constructed_root = path_nodes.last().expect(
+            "[Bug in proof verification] there should have been at least 1 node in the path",
+        );
+
+        if constructed_root.content.commitment != root_commitment {
+            Err(InclusionProofError::RootCommitmentMismatch)
+        } else {
+            Ok(())
+        }
+    }
+
     /// Range proof verification.
+    ///
+    /// The individual range proofs are independent of each other and so are
+    /// verified in parallel (sequentially without the `full` feature). If any
+    /// of them fail then an error is returned.
     fn verify_range_proofs(
         &self,
         tree_height: Height,
@@ -237,24 +534,33 @@ impl InclusionProof {
     ) -> Result<(), InclusionProofError> {
         use curve25519_dalek_ng::ristretto::CompressedRistretto;
 
-        let aggregation_index = self.aggregation_factor.apply_to(&tree_height) as usize;
+        // A proof deserialized from a file could contain any bit length, and
+        // the Bulletproofs error for an unsupported one is not descriptive.
+        if !ALLOWED_RANGE_PROOF_UPPER_BIT_SIZES.contains(&self.upper_bound_bit_length) {
+            return Err(InclusionProofError::InvalidBitLength(
+                self.upper_bound_bit_length,
+            ));
+        }
 
-        let mut commitments_for_aggregated_proofs: Vec<CompressedRistretto> = path_nodes
+        let commitments: Vec<CompressedRistretto> = path_nodes
             .iter()
             .map(|node| node.content.commitment.compress())
             .collect();
 
-        let commitments_for_individual_proofs =
-            commitments_for_aggregated_proofs.split_off(aggregation_index);
+        let (commitments_for_aggregated_proofs, commitments_for_individual_proofs) =
+            self.aggregation_factor.split(commitments, &tree_height);
 
         let mut at_least_one_checked = false;
 
         if let Some(proofs) = &self.individual_range_proofs {
-            commitments_for_individual_proofs
-                .iter()
-                .zip(proofs.iter())
-                .map(|(com, proof)| proof.verify(com, self.upper_bound_bit_length))
-                .collect::<Result<Vec<_>, _>>()?;
+            #[cfg(feature = "full")]
+            let pairs = commitments_for_individual_proofs
+                .par_iter()
+                .zip(proofs.par_iter());
+            #[cfg(not(feature = "full"))]
+            let mut pairs = commitments_for_individual_proofs.iter().zip(proofs.iter());
+
+            pairs.try_for_each(|(com, proof)| proof.verify(com, self.upper_bound_bit_length))?;
 
             at_least_one_checked = true;
         }
@@ -274,10 +580,13 @@ impl InclusionProof {
         }
     }
 
-    /// Serialize the [InclusionProof] structure to a binary file.
+    /// Serialize the [InclusionProof] structure to a file in `dir`.
+    ///
+    /// The file name is the entity ID, with the extension determined by
+    /// `file_type`.
     ///
     /// An error is returned if
-    /// 1. [bincode] fails to serialize the file.
+    /// 1. The serializer fails.
     /// 2. There is an issue opening or writing the file.
     pub fn serialize(
         &self,
@@ -287,24 +596,77 @@ impl InclusionProof {
     ) -> Result<PathBuf, InclusionProofError> {
         let mut file_name = entity_id.to_string();
         file_name.push('.');
-        file_name.push_str(match file_type {
-            InclusionProofFileType::Binary => SERIALIZED_PROOF_EXTENSION,
-            InclusionProofFileType::Json => "json",
-        });
+        file_name.push_str(file_type.extension());
 
         let path = dir.join(file_name);
+        self.serialize_to_path(path.clone(), file_type)?;
+
+        Ok(path)
+    }
+
+    /// Serialize the [InclusionProof] structure to the file at `path`.
+    ///
+    /// The extension of `path` must match `file_type` so that the file can be
+    /// deserialized again with [InclusionProof::deserialize].
+    ///
+    /// An error is returned if
+    /// 1. The extension of `path` does not match `file_type`.
+    /// 2. The serializer fails.
+    /// 3. There is an issue opening or writing the file.
+    pub fn serialize_to_path(
+        &self,
+        path: PathBuf,
+        file_type: InclusionProofFileType,
+    ) -> Result<(), InclusionProofError> {
+        let expected_ext = file_type.extension();
+        if path.extension() != Some(std::ffi::OsStr::new(expected_ext)) {
+            return Err(InclusionProofError::FileExtensionMismatch {
+                expected: expected_ext.to_string(),
+                path: path.into_os_string(),
+            });
+        }
+
         info!("Serializing inclusion proof to path {:?}", path);
 
         match file_type {
-            InclusionProofFileType::Binary => {
-                read_write_utils::serialize_to_bin_file(&self, path.clone())?
-            }
-            InclusionProofFileType::Json => {
-                read_write_utils::serialize_to_json_file(&self, path.clone())?
+            InclusionProofFileType::Binary => read_write_utils::serialize_to_bin_file(&self, path)?,
+            InclusionProofFileType::Json => read_write_utils::serialize_to_json_file(&self, path)?,
+            InclusionProofFileType::MessagePack => {
+                read_write_utils::serialize_to_msgpack_file(&self, path)?
             }
         }
 
-        Ok(path)
+        Ok(())
+    }
+
+    /// Number of bytes the proof would take up if serialized as `file_type`.
+    ///
+    /// Nothing is written to disk. For the binary format the size is computed
+    /// without encoding the proof, but for JSON & MessagePack the proof is
+    /// encoded in memory and the length of the encoding is returned, so
+    /// expect this to cost about as much as serialization.
+    ///
+    /// The JSON size is for the pretty-printed format, which is what
+    /// [InclusionProof::serialize] writes.
+    pub fn serialized_size(
+        &self,
+        file_type: InclusionProofFileType,
+    ) -> Result<usize, InclusionProofError> {
+        use crate::read_write_utils::ReadWriteError;
+
+        let size = match file_type {
+            InclusionProofFileType::Binary => {
+                bincode::serialized_size(self).map_err(ReadWriteError::from)? as usize
+            }
+            InclusionProofFileType::Json => serde_json::to_vec_pretty(self)
+                .map_err(ReadWriteError::from)?
+                .len(),
+            InclusionProofFileType::MessagePack => rmp_serde::to_vec_named(self)
+                .map_err(ReadWriteError::from)?
+                .len(),
+        };
+
+        Ok(size)
     }
 
     /// Deserialize the [InclusionProof] structure from a binary file.
@@ -320,18 +682,53 @@ impl InclusionProof {
             InclusionProofError::UnknownFileType(file_path.clone().into_os_string()),
         )?;
 
+        let file_type = InclusionProofFileType::from_extension(ext)?;
+
         info!("Deserializing inclusion proof from file {:?}", file_path);
 
-        match ext {
-            SERIALIZED_PROOF_EXTENSION => {
+        match file_type {
+            InclusionProofFileType::Binary => {
                 Ok(read_write_utils::deserialize_from_bin_file(file_path)?)
             }
-            "json" => Ok(read_write_utils::deserialize_from_json_file(file_path)?),
-            _ => Err(InclusionProofError::UnsupportedFileType { ext: ext.into() }),
+            InclusionProofFileType::Json => {
+                Ok(read_write_utils::deserialize_from_json_file(file_path)?)
+            }
+            InclusionProofFileType::MessagePack => {
+                Ok(read_write_utils::deserialize_from_msgpack_file(file_path)?)
+            }
         }
     }
 }
 
+/// Concise summary of the structure of the proof, for logging.
+///
+/// Only public information about the proof is shown, so the liabilities and
+/// blinding factors are not included.
+impl fmt::Display for InclusionProof {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tree_height = Height::from_y_coord(self.path_siblings.len() as u8);
+
+        let individual_range_proof_count = self
+            .individual_range_proofs
+            .as_ref()
+            .map_or(0, |proofs| proofs.len());
+        let aggregated_range_count = self
+            .aggregated_range_proof
+            .as_ref()
+            .map_or(0, |proof| proof.input_size());
+
+        write!(
+            f,
+            "inclusion proof (tree height: {}, aggregation factor: {}, upper bound bit length: {}, individual range proofs: {}, aggregated ranges: {})",
+            tree_height.as_u8(),
+            self.aggregation_factor,
+            self.upper_bound_bit_length,
+            individual_range_proof_count,
+            aggregated_range_count,
+        )
+    }
+}
+
 // -------------------------------------------------------------------------------------------------
 // Supported (de)serialization file types.
 
@@ -347,6 +744,39 @@ pub enum InclusionProofFileType {
     ///
     /// Not the most efficient but is human readable.
     Json,
+
+    /// MessagePack file format.
+    ///
+    /// Compact binary format that, unlike [InclusionProofFileType::Binary],
+    /// has decoders available in most languages.
+    MessagePack,
+}
+
+impl InclusionProofFileType {
+    /// File extension used when serializing a proof with this file type.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            InclusionProofFileType::Binary => SERIALIZED_PROOF_EXTENSION,
+            InclusionProofFileType::Json => "json",
+            InclusionProofFileType::MessagePack => "msgpack",
+        }
+    }
+
+    /// The file type whose [InclusionProofFileType::extension] is `ext`.
+    ///
+    /// An error is returned if no file type uses `ext`. Note that this is not
+    /// the same as [InclusionProofFileType::from_str], which parses the name
+    /// of the file type (e.g. "binary" rather than "dapolproof").
+    pub fn from_extension(ext: &str) -> Result<Self, InclusionProofError> {
+        [
+            InclusionProofFileType::Binary,
+            InclusionProofFileType::Json,
+            InclusionProofFileType::MessagePack,
+        ]
+        .into_iter()
+        .find(|file_type| file_type.extension() == ext)
+        .ok_or(InclusionProofError::UnsupportedFileType { ext: ext.into() })
+    }
 }
 
 use std::str::FromStr;
@@ -358,14 +788,17 @@ impl FromStr for InclusionProofFileType {
         match ext.to_lowercase().as_str() {
             "binary" => Ok(InclusionProofFileType::Binary),
             "json" => Ok(InclusionProofFileType::Json),
+            "msgpack" => Ok(InclusionProofFileType::MessagePack),
             _ => Err(InclusionProofError::UnsupportedFileType { ext: ext.into() }),
         }
     }
 }
 
+#[cfg(feature = "full")]
 use clap::builder::{OsStr, Str};
 
 // From for OsStr (for the CLI).
+#[cfg(feature = "full")]
 impl From<InclusionProofFileType> for OsStr {
     fn from(file_type: InclusionProofFileType) -> OsStr {
         OsStr::from(Str::from(file_type.to_string()))
@@ -374,7 +807,11 @@ impl From<InclusionProofFileType> for OsStr {
 
 impl std::fmt::Display for InclusionProofFileType {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            InclusionProofFileType::Binary => write!(f, "binary"),
+            InclusionProofFileType::Json => write!(f, "json"),
+            InclusionProofFileType::MessagePack => write!(f, "msgpack"),
+        }
     }
 }
 
@@ -396,24 +833,48 @@ pub enum InclusionProofError {
     TreePathSiblingsError(#[from] crate::binary_tree::PathSiblingsError),
     #[error("Calculated root content does not match provided root content")]
     RootMismatch,
+    #[error("Calculated root commitment does not match provided root commitment")]
+    RootCommitmentMismatch,
+    #[cfg(feature = "full")]
+    #[error("Unable to read the public root data file")]
+    PublicRootDataReadError(#[source] Box<crate::DapolTreeError>),
     #[error("Issues with range proof")]
     RangeProofError(#[from] RangeProofError),
+    #[error("Range proof generation failed for the node(s) at {coords:?}")]
+    RangeProofGenerationError {
+        coords: Vec<Coordinate>,
+        #[source]
+        source: RangeProofError,
+    },
     #[error("No range proofs detected")]
     MissingRangeProof,
+    #[cfg(feature = "full")]
+    #[error("The leaf node of the proof does not belong to entity {0:?}")]
+    EntityMismatch(EntityId),
+    #[error("Upper bound bit length {0} in the proof is invalid, it must be one of {ALLOWED_RANGE_PROOF_UPPER_BIT_SIZES:?}")]
+    InvalidBitLength(u8),
     #[error("Error serializing/deserializing file")]
     SerdeError(#[from] crate::read_write_utils::ReadWriteError),
     #[error("The file type with extension {ext:?} is not supported")]
     UnsupportedFileType { ext: String },
     #[error("Unable to find file extension for path {0:?}")]
     UnknownFileType(OsString),
+    #[error("Expected file extension {expected:?} for path {path:?}")]
+    FileExtensionMismatch { expected: String, path: OsString },
     #[error("Error writing path info to file")]
     PathWriteError(#[from] crate::binary_tree::PathSiblingsWriteError),
 }
 
 #[derive(thiserror::Error, Debug)]
 pub enum RangeProofError {
-    #[error("Bulletproofs generation failed")]
-    BulletproofGenerationError(bulletproofs::ProofError),
+    #[error("Bulletproofs generation failed for upper bound bit length {upper_bound_bit_length}")]
+    BulletproofGenerationError {
+        upper_bound_bit_length: u8,
+        #[source]
+        source: bulletproofs::ProofError,
+    },
+    #[error("Upper bound bit length {0} is not supported by Bulletproofs, it must be one of {ALLOWED_RANGE_PROOF_UPPER_BIT_SIZES:?} (it is derived from the max liability)")]
+    UnsupportedUpperBoundBitLength(u8),
     #[error("Bulletproofs verification failed")]
     BulletproofVerificationError(bulletproofs::ProofError),
     #[error("The length of the Pedersen commitments vector did not match the length of the input used to generate the proof")]
@@ -430,6 +891,7 @@ mod tests {
     use super::*;
     use crate::binary_tree::Coordinate;
     use crate::hasher::Hasher;
+    use crate::utils::test_utils::assert_err;
 
     use bulletproofs::PedersenGens;
     use curve25519_dalek_ng::{ristretto::RistrettoPoint, scalar::Scalar};
@@ -596,6 +1058,349 @@ mod tests {
         proof.verify(root_hash).unwrap();
     }
 
+    #[test]
+    fn generate_error_has_bit_length_and_coords() {
+        let aggregation_factor = AggregationFactor::Divisor(2u8);
+        let upper_bound_bit_length = 12u8;
+
+        let (leaf, path, _, _) = build_test_path();
+        let res = InclusionProof::generate(leaf, path, aggregation_factor, upper_bound_bit_length);
+
+        match res {
+            Err(InclusionProofError::RangeProofGenerationError { coords, source }) => {
+                assert_eq!(
+                    coords,
+                    vec![Coordinate { x: 2, y: 0 }, Coordinate { x: 1, y: 1 }]
+                );
+                assert_err!(
+                    Err::<(), _>(source),
+                    Err(RangeProofError::UnsupportedUpperBoundBitLength(12))
+                );
+            }
+            res => panic!("expected RangeProofGenerationError but got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn display_summarizes_proof_structure() {
+        let aggregation_factor = AggregationFactor::Divisor(2u8);
+        let upper_bound_bit_length = 64u8;
+
+        let (leaf, path, _root_commitment, _root_hash) = build_test_path();
+
+        let proof =
+            InclusionProof::generate(leaf, path, aggregation_factor, upper_bound_bit_length)
+                .unwrap();
+
+        assert_eq!(
+            proof.to_string(),
+            "inclusion proof (tree height: 4, aggregation factor: divisor 2, upper bound bit length: 64, individual range proofs: 2, aggregated ranges: 2)"
+        );
+    }
+
+    #[test]
+    fn same_seeded_rng_gives_same_proof_bytes() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let aggregation_factor = AggregationFactor::Divisor(2u8);
+        let upper_bound_bit_length = 64u8;
+
+        let generate = |seed: u64| {
+            let (leaf, path, _root_commitment, _root_hash) = build_test_path();
+            InclusionProof::generate_with_rng(
+                leaf,
+                path,
+                aggregation_factor.clone(),
+                upper_bound_bit_length,
+                &mut StdRng::seed_from_u64(seed),
+            )
+            .unwrap()
+        };
+
+        let (_, _, _, root_hash) = build_test_path();
+        let proof = generate(1);
+        proof.verify(root_hash).unwrap();
+
+        let bytes = bincode::serialize(&proof).unwrap();
+        assert_eq!(bytes, bincode::serialize(&generate(1)).unwrap());
+        assert_ne!(bytes, bincode::serialize(&generate(2)).unwrap());
+    }
+
+    #[test]
+    fn verify_works_with_only_individual_range_proofs() {
+        let aggregation_factor = AggregationFactor::Number(0u8);
+        let upper_bound_bit_length = 64u8;
+
+        let (leaf, path, _root_commitment, root_hash) = build_test_path();
+
+        let proof =
+            InclusionProof::generate(leaf, path, aggregation_factor, upper_bound_bit_length)
+                .unwrap();
+
+        assert!(proof.aggregated_range_proof.is_none());
+        proof.verify(root_hash).unwrap();
+    }
+
+    #[test]
+    fn verify_works_with_explicit_layer_set() {
+        let aggregation_factor = AggregationFactor::Layers(vec![0, 2, 3]);
+        let upper_bound_bit_length = 64u8;
+
+        let (leaf, path, _root_commitment, root_hash) = build_test_path();
+        let tree_height = Height::from_y_coord(path.len() as u8);
+
+        let proof =
+            InclusionProof::generate(leaf, path, aggregation_factor, upper_bound_bit_length)
+                .unwrap();
+
+        assert!(proof.aggregated_range_proof.is_some());
+        assert_eq!(
+            proof.individual_range_proofs.as_ref().unwrap().len(),
+            tree_height.as_usize() - 3
+        );
+        proof.verify(root_hash).unwrap();
+    }
+
+    #[test]
+    fn verify_fails_when_an_individual_range_proof_is_invalid() {
+        let aggregation_factor = AggregationFactor::Number(0u8);
+        let upper_bound_bit_length = 64u8;
+
+        let (leaf, path, _root_commitment, root_hash) = build_test_path();
+
+        let mut proof =
+            InclusionProof::generate(leaf, path, aggregation_factor, upper_bound_bit_length)
+                .unwrap();
+
+        // Swapping 2 of the proofs means they no longer match their commitments.
+        proof
+            .individual_range_proofs
+            .as_mut()
+            .expect("All range proofs should be individual")
+            .swap(1, 2);
+
+        let res = proof.verify(root_hash);
+        assert_err!(res, Err(InclusionProofError::RangeProofError(_)));
+    }
+
+    #[test]
+    fn upper_bound_bit_length_getter_works() {
+        let aggregation_factor = AggregationFactor::Divisor(2u8);
+        let upper_bound_bit_length = 32u8;
+
+        let (leaf, path, _, _) = build_test_path();
+        let proof =
+            InclusionProof::generate(leaf, path, aggregation_factor, upper_bound_bit_length)
+                .unwrap();
+
+        assert_eq!(proof.upper_bound_bit_length(), upper_bound_bit_length);
+    }
+
+    #[test]
+    fn verify_fails_for_invalid_bit_length() {
+        let aggregation_factor = AggregationFactor::Divisor(2u8);
+        let upper_bound_bit_length = 64u8;
+
+        let (leaf, path, _, root_hash) = build_test_path();
+        let mut proof =
+            InclusionProof::generate(leaf, path, aggregation_factor, upper_bound_bit_length)
+                .unwrap();
+
+        // Simulates a proof file that was serialized with a bad value.
+        proof.upper_bound_bit_length = 12u8;
+
+        let res = proof.verify(root_hash);
+        assert_err!(res, Err(InclusionProofError::InvalidBitLength(12)));
+    }
+
+    #[test]
+    fn verify_membership_only_skips_range_proofs() {
+        let aggregation_factor = AggregationFactor::Number(0u8);
+        let upper_bound_bit_length = 64u8;
+
+        let (leaf, path, _root_commitment, root_hash) = build_test_path();
+
+        let mut proof =
+            InclusionProof::generate(leaf, path, aggregation_factor, upper_bound_bit_length)
+                .unwrap();
+
+        // Invalidate the range proofs, which should not affect membership.
+        proof
+            .individual_range_proofs
+            .as_mut()
+            .expect("All range proofs should be individual")
+            .swap(1, 2);
+
+        proof.verify_membership_only(root_hash).unwrap();
+        assert_err!(
+            proof.verify(root_hash),
+            Err(InclusionProofError::RangeProofError(_))
+        );
+    }
+
+    #[test]
+    fn verify_membership_only_fails_for_wrong_root_hash() {
+        let (leaf, path, _root_commitment, _root_hash) = build_test_path();
+
+        let proof =
+            InclusionProof::generate(leaf, path, AggregationFactor::Divisor(2u8), 64u8).unwrap();
+
+        let res = proof.verify_membership_only(H256::zero());
+        assert_err!(res, Err(InclusionProofError::RootMismatch));
+    }
+
+    // Does not generate anything, so it is also run by verify-only builds.
+    #[test]
+    fn checked_in_proof_deserializes_and_verifies() {
+        let root_hash =
+            H256::from_str("0x1fc726923546aba949f61a23d25f87666d9ea58b856655bf68ec2b942221d814")
+                .unwrap();
+
+        let src_dir = env!("CARGO_MANIFEST_DIR");
+        let path = PathBuf::from(src_dir)
+            .join("examples")
+            .join("inclusion_proof_example.dapolproof");
+
+        let proof = InclusionProof::deserialize(path).unwrap();
+        proof.verify(root_hash).unwrap();
+    }
+
+    #[test]
+    fn msgpack_serde_gives_verifiable_proof() {
+        let aggregation_factor = AggregationFactor::Divisor(2u8);
+        let upper_bound_bit_length = 64u8;
+
+        let (leaf, path, _root_commitment, root_hash) = build_test_path();
+
+        let proof =
+            InclusionProof::generate(leaf, path, aggregation_factor, upper_bound_bit_length)
+                .unwrap();
+
+        let src_dir = env!("CARGO_MANIFEST_DIR");
+        let dir = PathBuf::from(src_dir).join("examples");
+        let entity_id = EntityId::from_str("msgpack_test_entity").unwrap();
+
+        let path = proof
+            .serialize(&entity_id, dir, InclusionProofFileType::MessagePack)
+            .unwrap();
+        assert_eq!(path.extension().unwrap(), "msgpack");
+
+        let decoded = InclusionProof::deserialize(path.clone()).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        decoded.verify(root_hash).unwrap();
+    }
+
+    #[test]
+    fn converting_binary_to_json_to_binary_gives_verifiable_proof() {
+        let aggregation_factor = AggregationFactor::Divisor(2u8);
+        let upper_bound_bit_length = 64u8;
+
+        let (leaf, path, _root_commitment, root_hash) = build_test_path();
+
+        let proof =
+            InclusionProof::generate(leaf, path, aggregation_factor, upper_bound_bit_length)
+                .unwrap();
+
+        let src_dir = env!("CARGO_MANIFEST_DIR");
+        let dir = PathBuf::from(src_dir).join("examples");
+        let entity_id = EntityId::from_str("convert_test_entity").unwrap();
+
+        let bin_path = proof
+            .serialize(&entity_id, dir, InclusionProofFileType::Binary)
+            .unwrap();
+        let json_path = bin_path.with_extension("json");
+
+        InclusionProof::deserialize(bin_path.clone())
+            .unwrap()
+            .serialize_to_path(json_path.clone(), InclusionProofFileType::Json)
+            .unwrap();
+        std::fs::remove_file(bin_path.clone()).unwrap();
+
+        let from_json = InclusionProof::deserialize(json_path.clone()).unwrap();
+        from_json.verify(root_hash).unwrap();
+
+        from_json
+            .serialize_to_path(bin_path.clone(), InclusionProofFileType::Binary)
+            .unwrap();
+        std::fs::remove_file(json_path).unwrap();
+
+        let from_bin = InclusionProof::deserialize(bin_path.clone()).unwrap();
+        std::fs::remove_file(bin_path).unwrap();
+
+        from_bin.verify(root_hash).unwrap();
+    }
+
+    #[test]
+    fn serialize_to_path_fails_for_wrong_extension() {
+        let (leaf, path, _, _) = build_test_path();
+        let proof =
+            InclusionProof::generate(leaf, path, AggregationFactor::Divisor(2u8), 64u8).unwrap();
+
+        let res = proof.serialize_to_path(
+            PathBuf::from("./proof.json"),
+            InclusionProofFileType::MessagePack,
+        );
+        assert_err!(res, Err(InclusionProofError::FileExtensionMismatch { .. }));
+    }
+
+    #[test]
+    fn serialized_size_matches_file_size() {
+        let (leaf, path, _, _) = build_test_path();
+        let proof =
+            InclusionProof::generate(leaf, path, AggregationFactor::Divisor(2u8), 64u8).unwrap();
+
+        let src_dir = env!("CARGO_MANIFEST_DIR");
+        let dir = PathBuf::from(src_dir).join("examples");
+        let entity_id = EntityId::from_str("serialized_size_test_entity").unwrap();
+
+        for file_type in [
+            InclusionProofFileType::Binary,
+            InclusionProofFileType::Json,
+            InclusionProofFileType::MessagePack,
+        ] {
+            let predicted_size = proof.serialized_size(file_type.clone()).unwrap();
+
+            let path = proof.serialize(&entity_id, dir.clone(), file_type).unwrap();
+            let file_size = std::fs::metadata(&path).unwrap().len();
+            std::fs::remove_file(path).unwrap();
+
+            assert_eq!(predicted_size as u64, file_size);
+        }
+    }
+
+    #[test]
+    fn file_type_display_and_from_str_round_trip() {
+        for file_type in [
+            InclusionProofFileType::Binary,
+            InclusionProofFileType::Json,
+            InclusionProofFileType::MessagePack,
+        ] {
+            let parsed = InclusionProofFileType::from_str(&file_type.to_string()).unwrap();
+            assert_eq!(parsed.to_string(), file_type.to_string());
+        }
+
+        assert_eq!(InclusionProofFileType::MessagePack.to_string(), "msgpack");
+    }
+
+    #[test]
+    fn file_type_extension_and_from_extension_round_trip() {
+        for file_type in [
+            InclusionProofFileType::Binary,
+            InclusionProofFileType::Json,
+            InclusionProofFileType::MessagePack,
+        ] {
+            let parsed = InclusionProofFileType::from_extension(file_type.extension()).unwrap();
+            assert_eq!(parsed.to_string(), file_type.to_string());
+        }
+
+        assert_eq!(InclusionProofFileType::Binary.extension(), "dapolproof");
+        assert_err!(
+            InclusionProofFileType::from_extension("binary"),
+            Err(InclusionProofError::UnsupportedFileType { .. })
+        );
+    }
+
     // TODO test correct error translation from lower layers (probably should
     // mock the error responses rather than triggering them from the code in the
     // lower layers)
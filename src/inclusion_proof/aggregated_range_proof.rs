@@ -38,20 +38,25 @@
 use bulletproofs::{BulletproofGens, PedersenGens, RangeProof};
 use curve25519_dalek_ng::{ristretto::CompressedRistretto, scalar::Scalar};
 use merlin::Transcript;
+use rand::{CryptoRng, RngCore};
 use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
 
-use super::RangeProofError;
+use super::{check_upper_bound_bit_length, RangeProofBytes, RangeProofError};
 
 /// `input_size` is u8 because it will be directly related to the length of a
 /// tree path, which is equal to the height of the tree, which is also stored as
 /// u8.
+#[serde_as]
 #[derive(Debug, Serialize, Deserialize)]
 pub enum AggregatedRangeProof {
     Padding {
+        #[serde_as(as = "RangeProofBytes")]
         proof: RangeProof,
         input_size: u8,
     },
     Splitting {
+        #[serde_as(as = "Vec<(RangeProofBytes, _)>")]
         proofs: Vec<(RangeProof, usize)>, /* the 2nd value is the number of values in the
                                            * aggregated proof */
         input_size: u8,
@@ -82,10 +87,15 @@ impl AggregatedRangeProof {
     /// used. The code currently just naively checks whether the size lies
     /// in the first or second half of the gap between the 2 powers of 2 on
     /// either side if the size value.
-    pub fn generate(
+    ///
+    /// The randomness for the Bulletproofs prover is taken from `rng`.
+    pub fn generate<R: RngCore + CryptoRng>(
         secrets_blindings_tuples: &Vec<(u64, Scalar)>,
         upper_bound_bit_length: u8,
+        rng: &mut R,
     ) -> Result<AggregatedRangeProof, RangeProofError> {
+        check_upper_bound_bit_length(upper_bound_bit_length)?;
+
         let size = secrets_blindings_tuples.len();
         let next_pow_2 = size.next_power_of_two();
         let prev_pow_2 = next_pow_2 / 2;
@@ -93,9 +103,9 @@ impl AggregatedRangeProof {
         // TODO this choice of split is fairly arbitrary, one should run the numbers and
         // figure out where the best split is
         if size < (next_pow_2 - prev_pow_2) / 2 {
-            Self::generate_with_splitting(secrets_blindings_tuples, upper_bound_bit_length)
+            Self::generate_with_splitting(secrets_blindings_tuples, upper_bound_bit_length, rng)
         } else {
-            Self::generate_with_padding(secrets_blindings_tuples, upper_bound_bit_length)
+            Self::generate_with_padding(secrets_blindings_tuples, upper_bound_bit_length, rng)
         }
     }
 
@@ -104,10 +114,12 @@ impl AggregatedRangeProof {
     /// `secrets_blindings_tuples` is a vector of secret & blinding_factor
     /// tuples. `upper_bound_bit_length` is the power of 2 that the range
     /// proof will show the secret value to be less than i.e. `secret <
-    /// 2^upper_bound_bit_length`.
-    pub fn generate_with_padding(
+    /// 2^upper_bound_bit_length`. The randomness for the Bulletproofs prover
+    /// is taken from `rng`.
+    pub fn generate_with_padding<R: RngCore + CryptoRng>(
         secrets_blindings_tuples: &Vec<(u64, Scalar)>,
         upper_bound_bit_length: u8,
+        rng: &mut R,
     ) -> Result<AggregatedRangeProof, RangeProofError> {
         // We want a mutable vector so that we can add padding to it.
         // Since proofs will be for paths in a binary tree the length of the input
@@ -131,15 +143,19 @@ impl AggregatedRangeProof {
         let (secrets, blinding_factors): (Vec<u64>, Vec<Scalar>) =
             secrets_blindings_tuples_clone.into_iter().unzip();
 
-        match RangeProof::prove_multiple(
+        match RangeProof::prove_multiple_with_rng(
             &bp_gens,
             &pc_gens,
             &mut new_transcript(),
             &secrets,
             &blinding_factors,
             upper_bound_bit_length as usize,
+            rng,
         ) {
-            Err(underlying_err) => Err(RangeProofError::BulletproofGenerationError(underlying_err)),
+            Err(source) => Err(RangeProofError::BulletproofGenerationError {
+                upper_bound_bit_length,
+                source,
+            }),
             Ok((proof, _commitments)) => Ok(AggregatedRangeProof::Padding { proof, input_size }),
         }
     }
@@ -149,10 +165,12 @@ impl AggregatedRangeProof {
     /// `secrets_blindings_tuples` is a vector of secret & blinding_factor
     /// tuples. `upper_bound_bit_length` is the power of 2 that the range
     /// proof will show the secret value to be less than i.e. `secret <
-    /// 2^upper_bound_bit_length`.
-    pub fn generate_with_splitting(
+    /// 2^upper_bound_bit_length`. The randomness for the Bulletproofs prover
+    /// is taken from `rng`.
+    pub fn generate_with_splitting<R: RngCore + CryptoRng>(
         secrets_blindings_tuples: &Vec<(u64, Scalar)>,
         upper_bound_bit_length: u8,
+        rng: &mut R,
     ) -> Result<AggregatedRangeProof, RangeProofError> {
         let pc_gens = PedersenGens::default();
 
@@ -184,15 +202,19 @@ impl AggregatedRangeProof {
                     BulletproofGens::new(upper_bound_bit_length as usize, next_pow_2 as usize);
                 let index = secrets.len() - next_pow_2 as usize;
 
-                let (proof, _commitments) = RangeProof::prove_multiple(
+                let (proof, _commitments) = RangeProof::prove_multiple_with_rng(
                     &bp_gens,
                     &pc_gens,
                     &mut prover_transcript,
                     &secrets.split_off(index),
                     &blinding_factors.split_off(index),
                     upper_bound_bit_length as usize,
+                    rng,
                 )
-                .map_err(RangeProofError::BulletproofGenerationError)?;
+                .map_err(|source| RangeProofError::BulletproofGenerationError {
+                    upper_bound_bit_length,
+                    source,
+                })?;
 
                 proofs.push((proof, next_pow_2 as usize));
             }
@@ -238,7 +260,7 @@ impl AggregatedRangeProof {
                     &bp_gens,
                     &pc_gens,
                     &mut prover_transcript,
-                    commitments,
+                    &commitments_clone,
                     upper_bound_bit_length as usize,
                 )
             }
@@ -261,7 +283,8 @@ impl AggregatedRangeProof {
         .map_err(RangeProofError::BulletproofVerificationError)
     }
 
-    fn input_size(&self) -> u8 {
+    /// Number of ranges proved by the aggregated proof, excluding any padding.
+    pub fn input_size(&self) -> u8 {
         match self {
             AggregatedRangeProof::Padding {
                 proof: _,
@@ -283,6 +306,7 @@ mod tests {
 
     use super::*;
     use crate::utils::test_utils::assert_err;
+    use rand::thread_rng;
 
     // This test does not call any of the above code but it just checks to make sure
     // that there is no drop in efficiency with the `next_power_of_two`
@@ -319,6 +343,7 @@ mod tests {
             AggregatedRangeProof::generate_with_padding(
                 &build_secrets_blindings_tuples(),
                 upper_bound_bit_length,
+                &mut thread_rng(),
             )
             .unwrap();
         }
@@ -337,9 +362,36 @@ mod tests {
                 })
                 .collect();
 
-            let proof =
-                AggregatedRangeProof::generate_with_padding(&values, upper_bound_bit_length)
-                    .unwrap();
+            let proof = AggregatedRangeProof::generate_with_padding(
+                &values,
+                upper_bound_bit_length,
+                &mut thread_rng(),
+            )
+            .unwrap();
+
+            proof.verify(&commitments, upper_bound_bit_length).unwrap();
+        }
+
+        #[test]
+        fn verify_works_for_padding_when_input_size_is_not_power_of_2() {
+            let upper_bound_bit_length = 32u8;
+            let mut values = build_secrets_blindings_tuples();
+            values.push((13u64, Scalar::from(5u64)));
+            let commitments: Vec<CompressedRistretto> = values
+                .iter()
+                .map(|(secret, blinding_factor)| {
+                    PedersenGens::default()
+                        .commit(Scalar::from(*secret), *blinding_factor)
+                        .compress()
+                })
+                .collect();
+
+            let proof = AggregatedRangeProof::generate_with_padding(
+                &values,
+                upper_bound_bit_length,
+                &mut thread_rng(),
+            )
+            .unwrap();
 
             proof.verify(&commitments, upper_bound_bit_length).unwrap();
         }
@@ -358,8 +410,12 @@ mod tests {
                 .compress()];
             let input = vec![(secret, blinding_factor)];
 
-            let proof =
-                AggregatedRangeProof::generate_with_padding(&input, valid_upper_bound).unwrap();
+            let proof = AggregatedRangeProof::generate_with_padding(
+                &input,
+                valid_upper_bound,
+                &mut thread_rng(),
+            )
+            .unwrap();
 
             let res = proof.verify(&commitment, invalid_upper_bound);
 
@@ -386,8 +442,12 @@ mod tests {
 
             // NOTE the proof generation succeeds even though the secret value is greater
             // than the bound
-            let proof = AggregatedRangeProof::generate_with_padding(&input, upper_bound_bit_length)
-                .unwrap();
+            let proof = AggregatedRangeProof::generate_with_padding(
+                &input,
+                upper_bound_bit_length,
+                &mut thread_rng(),
+            )
+            .unwrap();
 
             let res = proof.verify(&commitment, upper_bound_bit_length);
 
@@ -413,8 +473,12 @@ mod tests {
 
             let upper_bound_bit_length = 32u8;
 
-            let proof = AggregatedRangeProof::generate_with_padding(&input, upper_bound_bit_length)
-                .unwrap();
+            let proof = AggregatedRangeProof::generate_with_padding(
+                &input,
+                upper_bound_bit_length,
+                &mut thread_rng(),
+            )
+            .unwrap();
 
             let res = proof.verify(&commitment, upper_bound_bit_length);
 
@@ -436,6 +500,7 @@ mod tests {
             AggregatedRangeProof::generate_with_splitting(
                 &build_secrets_blindings_tuples(),
                 upper_bound_bit_length,
+                &mut thread_rng(),
             )
             .unwrap();
         }
@@ -454,9 +519,12 @@ mod tests {
                 })
                 .collect();
 
-            let proof =
-                AggregatedRangeProof::generate_with_splitting(&values, upper_bound_bit_length)
-                    .unwrap();
+            let proof = AggregatedRangeProof::generate_with_splitting(
+                &values,
+                upper_bound_bit_length,
+                &mut thread_rng(),
+            )
+            .unwrap();
 
             proof.verify(&commitments, upper_bound_bit_length).unwrap();
         }
@@ -475,9 +543,12 @@ mod tests {
                 .compress()];
             let input = vec![(secret, blinding_factor)];
 
-            let proof =
-                AggregatedRangeProof::generate_with_splitting(&input, upper_bound_bit_length)
-                    .unwrap();
+            let proof = AggregatedRangeProof::generate_with_splitting(
+                &input,
+                upper_bound_bit_length,
+                &mut thread_rng(),
+            )
+            .unwrap();
 
             let res = proof.verify(&commitment, other_upper_bound_bit_length);
 
@@ -504,9 +575,12 @@ mod tests {
 
             // NOTE the proof generation succeeds even though the secret value is greater
             // than the bound
-            let proof =
-                AggregatedRangeProof::generate_with_splitting(&input, upper_bound_bit_length)
-                    .unwrap();
+            let proof = AggregatedRangeProof::generate_with_splitting(
+                &input,
+                upper_bound_bit_length,
+                &mut thread_rng(),
+            )
+            .unwrap();
 
             let res = proof.verify(&commitment, upper_bound_bit_length);
 
@@ -532,8 +606,12 @@ mod tests {
 
         let upper_bound_bit_length = 32u8;
 
-        let proof =
-            AggregatedRangeProof::generate_with_splitting(&input, upper_bound_bit_length).unwrap();
+        let proof = AggregatedRangeProof::generate_with_splitting(
+            &input,
+            upper_bound_bit_length,
+            &mut thread_rng(),
+        )
+        .unwrap();
 
         let res = proof.verify(&commitment, upper_bound_bit_length);
 
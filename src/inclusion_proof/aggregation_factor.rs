@@ -4,6 +4,7 @@ use crate::{
 };
 
 use serde::{Deserialize, Serialize};
+use std::fmt;
 
 /// For adjusting range proof aggregation in the Bulletproofs protocol.
 ///
@@ -13,7 +14,7 @@ use serde::{Deserialize, Serialize};
 /// aggregated (proved together). Those that do not form part of the aggregated
 /// proof are just proved individually.
 ///
-/// [AggregationFactor] is an enum with 3 options:
+/// [AggregationFactor] is an enum with 4 options:
 ///
 /// Divisor: divide the number of nodes by this number to get the ratio of the
 /// nodes to be used in the aggregated proof i.e.
@@ -33,11 +34,19 @@ use serde::{Deserialize, Serialize};
 /// Number: the exact number of nodes to be used in the aggregated proof. Note
 /// that if this number is `> tree_height` it is treated as if it was equal to
 /// `tree_height`.
+///
+/// Layers: the exact set of layers whose nodes are used in the aggregated
+/// proof, with layer 0 being the bottom layer (the leaf node) and layer
+/// `tree_height - 1` being the root node. Nodes in all other layers are proved
+/// individually. This gives full control over which parts of the path are
+/// aggregated, e.g. `Layers((0..=10).collect())` aggregates the bottom 11
+/// layers. Note that layers `>= tree_height` and duplicate layers are ignored.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum AggregationFactor {
     Divisor(u8),
     Percent(Percentage),
     Number(u8),
+    Layers(Vec<u8>),
 }
 
 /// The default number of proofs to aggregate is all of them because this gives
@@ -62,6 +71,9 @@ impl AggregationFactor {
             }
             Self::Percent(per) => per.apply_to(tree_height.as_u8()),
             Self::Number(num) => *num.min(&tree_height.as_u8()),
+            Self::Layers(layers) => (0..tree_height.as_u8())
+                .filter(|y| layers.contains(y))
+                .count() as u8,
         }
     }
 
@@ -71,6 +83,7 @@ impl AggregationFactor {
             Self::Divisor(div) => *div == 0 || *div > tree_height.as_u8(),
             Self::Percent(per) => per.value() == 0,
             Self::Number(num) => *num == 0,
+            Self::Layers(layers) => !layers.iter().any(|y| *y < tree_height.as_u8()),
         }
     }
 
@@ -80,10 +93,104 @@ impl AggregationFactor {
             Self::Divisor(div) => *div == 1,
             Self::Percent(per) => per == &ONE_HUNDRED_PERCENT,
             Self::Number(num) => *num >= tree_height.as_u8(),
+            Self::Layers(layers) => (0..tree_height.as_u8()).all(|y| layers.contains(&y)),
+        }
+    }
+
+    /// Split `items` into those that should be used in the aggregated proof
+    /// (1st element of the returned tuple) and those that should be proved
+    /// individually (2nd element).
+    ///
+    /// `items` are expected to be ordered by layer, starting with the bottom
+    /// layer (leaf node) and ending with the root node. The relative order of
+    /// the items is kept in both of the returned vectors.
+    pub(crate) fn split<T>(&self, mut items: Vec<T>, tree_height: &Height) -> (Vec<T>, Vec<T>) {
+        match self {
+            Self::Layers(layers) => {
+                let mut aggregated = Vec::with_capacity(items.len());
+                let mut individual = Vec::with_capacity(items.len());
+
+                for (y, item) in items.into_iter().enumerate() {
+                    if layers.iter().any(|layer| *layer as usize == y) {
+                        aggregated.push(item);
+                    } else {
+                        individual.push(item);
+                    }
+                }
+
+                (aggregated, individual)
+            }
+            _ => {
+                let individual = items.split_off(self.apply_to(tree_height) as usize);
+                (items, individual)
+            }
         }
     }
 }
 
+impl fmt::Display for AggregationFactor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Divisor(div) => write!(f, "divisor {}", div),
+            Self::Percent(per) => write!(f, "{}%", per.value()),
+            Self::Number(num) => write!(f, "number {}", num),
+            Self::Layers(layers) => write!(f, "layers {:?}", layers),
+        }
+    }
+}
+
+/// What to optimize for when picking an aggregation factor, see
+/// [DapolTree::recommend_aggregation_factor].
+///
+/// [DapolTree::recommend_aggregation_factor]: crate::DapolTree::recommend_aggregation_factor
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OptimizationTarget {
+    /// Smallest serialized proof.
+    MinSize,
+    /// Fastest proof generation.
+    MinGenerationTime,
+    /// Best combined score, where size & generation time are each measured
+    /// relative to the best candidate and weighted equally.
+    #[default]
+    Balanced,
+}
+
+impl OptimizationTarget {
+    /// Return the index of the best measurement for this target.
+    ///
+    /// Each measurement is the generation time & serialized size (in bytes) of
+    /// a proof. Ties go to the earliest measurement. `None` is returned if
+    /// `measurements` is empty.
+    #[cfg(feature = "full")]
+    pub(crate) fn select(&self, measurements: &[(std::time::Duration, usize)]) -> Option<usize> {
+        let min_time = measurements.iter().map(|(time, _)| *time).min()?;
+        let min_size = measurements.iter().map(|(_, size)| *size).min()?;
+
+        let score = |(time, size): &(std::time::Duration, usize)| -> f64 {
+            let time_ratio = time.as_secs_f64() / min_time.as_secs_f64().max(f64::EPSILON);
+            let size_ratio = *size as f64 / (min_size as f64).max(1.0);
+
+            match self {
+                Self::MinSize => size_ratio,
+                Self::MinGenerationTime => time_ratio,
+                Self::Balanced => time_ratio + size_ratio,
+            }
+        };
+
+        measurements
+            .iter()
+            .enumerate()
+            .fold(None, |best: Option<(usize, f64)>, (i, measurement)| {
+                let score = score(measurement);
+                match best {
+                    Some((_, best_score)) if best_score <= score => best,
+                    _ => Some((i, score)),
+                }
+            })
+            .map(|(i, _)| i)
+    }
+}
+
 // -------------------------------------------------------------------------------------------------
 // Unit tests
 
@@ -229,4 +336,84 @@ mod tests {
             assert!(aggregation_factor.is_max(&tree_height));
         }
     }
+
+    mod layers {
+        use super::super::*;
+        use crate::Height;
+
+        #[test]
+        fn empty_layer_set_gives_zero_aggregation() {
+            let tree_height = Height::expect_from(10);
+            let aggregation_factor = AggregationFactor::Layers(vec![]);
+            assert_eq!(aggregation_factor.apply_to(&tree_height), 0);
+            assert!(aggregation_factor.is_zero(&tree_height));
+            assert!(!aggregation_factor.is_max(&tree_height));
+        }
+
+        #[test]
+        fn all_layers_gives_full_aggregation() {
+            let tree_height = Height::expect_from(10);
+            let aggregation_factor = AggregationFactor::Layers((0..10).collect());
+            assert_eq!(
+                aggregation_factor.apply_to(&tree_height),
+                tree_height.as_u8()
+            );
+            assert!(!aggregation_factor.is_zero(&tree_height));
+            assert!(aggregation_factor.is_max(&tree_height));
+        }
+
+        #[test]
+        fn layers_outside_tree_and_duplicates_are_ignored() {
+            let tree_height = Height::expect_from(10);
+            let aggregation_factor = AggregationFactor::Layers(vec![3, 3, 10, 200]);
+            assert_eq!(aggregation_factor.apply_to(&tree_height), 1);
+            assert!(!aggregation_factor.is_zero(&tree_height));
+            assert!(!aggregation_factor.is_max(&tree_height));
+
+            let aggregation_factor = AggregationFactor::Layers(vec![10, 200]);
+            assert!(aggregation_factor.is_zero(&tree_height));
+        }
+
+        #[test]
+        fn split_separates_exactly_the_given_layers() {
+            let tree_height = Height::expect_from(6);
+            let aggregation_factor = AggregationFactor::Layers(vec![4, 0, 1]);
+            let (aggregated, individual) =
+                aggregation_factor.split((0..6u8).collect(), &tree_height);
+            assert_eq!(aggregated, vec![0, 1, 4]);
+            assert_eq!(individual, vec![2, 3, 5]);
+        }
+    }
+
+    #[test]
+    fn split_for_number_takes_bottom_layers() {
+        let tree_height = crate::Height::expect_from(6);
+        let aggregation_factor = super::AggregationFactor::Number(2);
+        let (aggregated, individual) = aggregation_factor.split((0..6u8).collect(), &tree_height);
+        assert_eq!(aggregated, vec![0, 1]);
+        assert_eq!(individual, vec![2, 3, 4, 5]);
+    }
+
+    #[cfg(feature = "full")]
+    mod optimization_target {
+        use super::super::*;
+        use std::time::Duration;
+
+        #[test]
+        fn select_picks_best_measurement_for_each_target() {
+            let measurements = [
+                (Duration::from_millis(10), 3000),
+                (Duration::from_millis(30), 1000),
+                (Duration::from_millis(14), 1400),
+            ];
+
+            assert_eq!(OptimizationTarget::MinSize.select(&measurements), Some(1));
+            assert_eq!(
+                OptimizationTarget::MinGenerationTime.select(&measurements),
+                Some(0)
+            );
+            assert_eq!(OptimizationTarget::Balanced.select(&measurements), Some(2));
+            assert_eq!(OptimizationTarget::Balanced.select(&[]), None);
+        }
+    }
 }
@@ -0,0 +1,356 @@
+//! Verification of many inclusion proofs from the same tree.
+//!
+//! Inclusion proofs generated from the same tree share the nodes at the top of
+//! their paths (all of them share the root node, for example). Verifying each
+//! proof individually means these shared nodes are reconstructed over and
+//! over again. [ProofBatchVerifier] keeps a cache of the nodes it has already
+//! reconstructed, keyed by coordinate, so that shared path segments are only
+//! hashed once.
+//!
+//! The range proofs of the different inclusion proofs are independent of each
+//! other and so are verified in parallel.
+//!
+//! Proofs from different trees (e.g. from different organizations) can be
+//! verified in parallel against their own roots using
+//! [verify_proofs_multi_root].
+
+use primitive_types::H256;
+use rayon::prelude::*;
+
+use std::collections::HashMap;
+
+use log::info;
+
+use super::{InclusionProof, InclusionProofError};
+use crate::binary_tree::{Coordinate, Height, HiddenNodeContent, Mergeable, Node};
+
+/// Verifier for a batch of [InclusionProof]s that were generated from the same
+/// tree.
+///
+/// Example:
+/// ```ignore
+/// let mut verifier = ProofBatchVerifier::new(root_hash);
+/// verifier.add_proof(proof_1);
+/// verifier.add_proof(proof_2);
+/// verifier.verify()?;
+/// ```
+pub struct ProofBatchVerifier {
+    root_hash: H256,
+    proofs: Vec<InclusionProof>,
+}
+
+/// Parent node content together with the 2 children that were merged to
+/// produce it.
+struct CachedMerge {
+    left: HiddenNodeContent,
+    right: HiddenNodeContent,
+    parent: Node<HiddenNodeContent>,
+}
+
+impl ProofBatchVerifier {
+    /// Constructor.
+    ///
+    /// `root_hash` is the public root hash of the tree that all the proofs are
+    /// expected to have been generated from.
+    pub fn new(root_hash: H256) -> Self {
+        ProofBatchVerifier {
+            root_hash,
+            proofs: Vec::new(),
+        }
+    }
+
+    /// Add a proof to the batch.
+    pub fn add_proof(&mut self, proof: InclusionProof) -> &mut Self {
+        self.proofs.push(proof);
+        self
+    }
+
+    /// Add multiple proofs to the batch.
+    pub fn add_proofs(&mut self, proofs: Vec<InclusionProof>) -> &mut Self {
+        self.proofs.extend(proofs);
+        self
+    }
+
+    /// Number of proofs in the batch.
+    pub fn len(&self) -> usize {
+        self.proofs.len()
+    }
+
+    /// True if there are no proofs in the batch.
+    pub fn is_empty(&self) -> bool {
+        self.proofs.is_empty()
+    }
+
+    /// Verify all the proofs in the batch against the root hash.
+    ///
+    /// The paths are reconstructed sequentially, sharing a cache of merged
+    /// nodes. A cached parent is only reused if both its children exactly
+    /// match (hash & commitment) the children in the path being
+    /// reconstructed. The range proofs are then verified in parallel.
+    ///
+    /// An error is returned if any of the proofs fail verification. The error
+    /// contains the index (in the order the proofs were added) of a proof that
+    /// failed, but if multiple proofs fail there is no guarantee that it is the
+    /// first one.
+    pub fn verify(&self) -> Result<(), BatchVerificationError> {
+        info!(
+            "Verifying batch of {} inclusion proofs..",
+            self.proofs.len()
+        );
+
+        let mut cache = HashMap::<Coordinate, CachedMerge>::new();
+
+        let paths = self
+            .proofs
+            .iter()
+            .enumerate()
+            .map(|(index, proof)| {
+                let tree_height = Height::from_y_coord(proof.path_siblings.len() as u8);
+                let hidden_leaf_node: Node<HiddenNodeContent> = proof.leaf_node.clone().convert();
+
+                let path = proof
+                    .path_siblings
+                    .construct_path_using(hidden_leaf_node, |left, right| {
+                        merge_with_cache(&mut cache, left, right)
+                    })
+                    .map_err(|err| BatchVerificationError {
+                        index,
+                        source: err.into(),
+                    })?;
+
+                proof
+                    .verify_merkle_path(self.root_hash, tree_height, &path)
+                    .map_err(|source| BatchVerificationError { index, source })?;
+
+                Ok((tree_height, path))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.proofs
+            .par_iter()
+            .zip(paths.par_iter())
+            .enumerate()
+            .map(|(index, (proof, (tree_height, path)))| {
+                proof
+                    .verify_range_proofs(*tree_height, path)
+                    .map_err(|source| BatchVerificationError { index, source })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        info!("Succesfully verified batch of proofs");
+
+        Ok(())
+    }
+}
+
+/// Verify each proof against the root hash it is paired with, in parallel.
+///
+/// The proofs can come from different trees. The results are in the same
+/// order as `items`, so the result at index `i` is for the proof at index
+/// `i`. Each proof is verified independently: the Bulletproofs transcripts
+/// and generators are created per verification, so no mutable state is
+/// shared between threads.
+pub fn verify_proofs_multi_root(
+    items: &[(InclusionProof, H256)],
+) -> Vec<Result<(), InclusionProofError>> {
+    info!(
+        "Verifying {} inclusion proofs against their roots..",
+        items.len()
+    );
+
+    items
+        .par_iter()
+        .map(|(proof, root_hash)| proof.verify(*root_hash))
+        .collect()
+}
+
+/// Merge the 2 nodes, reusing a previously merged parent if the children
+/// match those that were used to produce it.
+fn merge_with_cache(
+    cache: &mut HashMap<Coordinate, CachedMerge>,
+    left: &Node<HiddenNodeContent>,
+    right: &Node<HiddenNodeContent>,
+) -> Node<HiddenNodeContent> {
+    let parent_coord = Coordinate {
+        x: left.coord.x / 2,
+        y: left.coord.y + 1,
+    };
+
+    // PartialEq for HiddenNodeContent only checks the hash, but leaf hashes do
+    // not depend on the commitment so we need to check both.
+    let children_match = |cached: &CachedMerge| {
+        cached.left.hash == left.content.hash
+            && cached.left.commitment == left.content.commitment
+            && cached.right.hash == right.content.hash
+            && cached.right.commitment == right.content.commitment
+    };
+
+    if let Some(cached) = cache.get(&parent_coord) {
+        if children_match(cached) {
+            return cached.parent.clone();
+        }
+    }
+
+    let parent = Node {
+        coord: parent_coord.clone(),
+        content: HiddenNodeContent::merge(&left.content, &right.content),
+    };
+
+    cache.insert(
+        parent_coord,
+        CachedMerge {
+            left: left.content.clone(),
+            right: right.content.clone(),
+            parent: parent.clone(),
+        },
+    );
+
+    parent
+}
+
+// -------------------------------------------------------------------------------------------------
+// Errors.
+
+/// Error returned when a proof in the batch fails verification.
+#[derive(thiserror::Error, Debug)]
+#[error("Verification failed for the inclusion proof at index {index}")]
+pub struct BatchVerificationError {
+    pub index: usize,
+    #[source]
+    pub source: InclusionProofError,
+}
+
+// -------------------------------------------------------------------------------------------------
+// Unit tests.
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_utils::assert_err;
+    use crate::{
+        AccumulatorType, AggregationFactor, BuildOptions, DapolTree, Entity, EntityId,
+        MaxLiability, Salt, Secret,
+    };
+    use std::str::FromStr;
+
+    fn new_tree_with_proofs(num_entities: u64) -> (DapolTree, Vec<InclusionProof>) {
+        let entities: Vec<Entity> = (0..num_entities)
+            .map(|i| Entity {
+                liability: i * 10,
+                id: EntityId::from_str(&format!("id{}", i)).unwrap(),
+                salt: None,
+                blinding_factor: None,
+            })
+            .collect();
+
+        let tree = DapolTree::new_with_options(
+            AccumulatorType::NdmSmt,
+            Secret::from_str("master_secret").unwrap(),
+            Salt::from_str("salt_b").unwrap(),
+            Salt::from_str("salt_s").unwrap(),
+            Height::expect_from(8),
+            entities.clone(),
+            BuildOptions::default()
+                .with_max_liability(MaxLiability::from(10_000_000))
+                .with_random_seed(1),
+        )
+        .unwrap();
+
+        let proofs = entities
+            .iter()
+            .map(|entity| {
+                tree.generate_inclusion_proof_with(&entity.id, AggregationFactor::Divisor(2))
+                    .unwrap()
+            })
+            .collect();
+
+        (tree, proofs)
+    }
+
+    #[test]
+    fn batch_of_valid_proofs_verifies() {
+        let (tree, proofs) = new_tree_with_proofs(5);
+
+        let mut verifier = ProofBatchVerifier::new(*tree.root_hash());
+        verifier.add_proofs(proofs);
+
+        assert_eq!(verifier.len(), 5);
+        verifier.verify().unwrap();
+    }
+
+    #[test]
+    fn wrong_root_hash_fails_verification() {
+        let (_tree, proofs) = new_tree_with_proofs(3);
+
+        let mut verifier = ProofBatchVerifier::new(H256::default());
+        verifier.add_proofs(proofs);
+
+        let res = verifier.verify();
+        assert_err!(
+            res,
+            Err(BatchVerificationError {
+                index: 0,
+                source: InclusionProofError::RootMismatch,
+            })
+        );
+    }
+
+    #[test]
+    fn proof_from_different_tree_fails_verification() {
+        let (tree, proofs) = new_tree_with_proofs(3);
+        let (_other_tree, mut other_proofs) = new_tree_with_proofs(4);
+
+        let mut verifier = ProofBatchVerifier::new(*tree.root_hash());
+        verifier.add_proofs(proofs);
+        verifier.add_proof(other_proofs.pop().unwrap());
+
+        let res = verifier.verify();
+        assert_err!(
+            res,
+            Err(BatchVerificationError {
+                index: 3,
+                source: InclusionProofError::RootMismatch,
+            })
+        );
+    }
+
+    #[test]
+    fn multi_root_verification_gives_results_in_order() {
+        let (tree, proofs) = new_tree_with_proofs(3);
+        let (other_tree, other_proofs) = new_tree_with_proofs(4);
+
+        let mut items: Vec<(InclusionProof, H256)> = proofs
+            .into_iter()
+            .map(|proof| (proof, *tree.root_hash()))
+            .collect();
+        items.extend(
+            other_proofs
+                .into_iter()
+                .map(|proof| (proof, *other_tree.root_hash())),
+        );
+
+        let results = verify_proofs_multi_root(&items);
+
+        assert_eq!(results.len(), 7);
+        assert!(results.iter().all(|res| res.is_ok()));
+    }
+
+    #[test]
+    fn multi_root_verification_fails_only_for_mismatched_root() {
+        let (tree, mut proofs) = new_tree_with_proofs(3);
+        let (_other_tree, mut other_proofs) = new_tree_with_proofs(4);
+        proofs.insert(1, other_proofs.pop().unwrap());
+
+        let items: Vec<(InclusionProof, H256)> = proofs
+            .into_iter()
+            .map(|proof| (proof, *tree.root_hash()))
+            .collect();
+
+        let results = verify_proofs_multi_root(&items);
+
+        assert!(results[0].is_ok());
+        assert_err!(results[1], Err(InclusionProofError::RootMismatch));
+        assert!(results[2].is_ok());
+        assert!(results[3].is_ok());
+    }
+}
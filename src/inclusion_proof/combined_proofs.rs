@@ -0,0 +1,405 @@
+//! Many inclusion proofs in a single file.
+//!
+//! Writing 1 file per entity is impractical when there are millions of
+//! entities, so the proofs can instead be written one after the other to a
+//! single combined file using [CombinedProofWriter]. Each proof is written,
+//! together with the ID of the entity it was generated for, as a
+//! length-prefixed frame (see [read_write_utils::write_framed]).
+//!
+//! The framing means the combined file can be read back 1 proof at a time
+//! with [CombinedProofReader], or verified 1 proof at a time with
+//! [StreamingVerifier], so that memory usage is bounded by the size of a
+//! single proof rather than the size of the whole file.
+//!
+//! Example:
+//! ```ignore
+//! let mut writer = CombinedProofWriter::create(path.clone())?;
+//! for (entity_id, proof) in tree.inclusion_proofs_iter(&entity_ids) {
+//!     writer.write_proof(&entity_id, &proof?)?;
+//! }
+//! writer.finish()?;
+//!
+//! for result in StreamingVerifier::open(path, *tree.root_hash())? {
+//!     let entity_id = result?;
+//! }
+//! ```
+
+use primitive_types::H256;
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use log::info;
+
+use super::{InclusionProof, InclusionProofError};
+use crate::read_write_utils::{self, ReadWriteError};
+use crate::EntityId;
+
+/// The file extension used for combined proof files.
+pub const COMBINED_PROOFS_EXTENSION: &str = "dapolproofs";
+
+fn check_extension(path: &Path) -> Result<(), InclusionProofError> {
+    if path.extension() != Some(std::ffi::OsStr::new(COMBINED_PROOFS_EXTENSION)) {
+        return Err(InclusionProofError::FileExtensionMismatch {
+            expected: COMBINED_PROOFS_EXTENSION.to_string(),
+            path: path.as_os_str().to_os_string(),
+        });
+    }
+
+    Ok(())
+}
+
+// -------------------------------------------------------------------------------------------------
+// Writer.
+
+/// Writes inclusion proofs one after the other in the combined format.
+///
+/// Each proof is encoded & written as soon as it is given, so the proofs never
+/// have to be held in memory at the same time.
+#[derive(Debug)]
+pub struct CombinedProofWriter<W: Write> {
+    writer: W,
+    num_proofs: usize,
+}
+
+impl CombinedProofWriter<BufWriter<File>> {
+    /// Create the file at `path` and write the proofs to it.
+    ///
+    /// An error is returned if
+    /// 1. The extension of `path` is not [COMBINED_PROOFS_EXTENSION].
+    /// 2. The file cannot be created.
+    pub fn create(path: PathBuf) -> Result<Self, InclusionProofError> {
+        check_extension(&path)?;
+
+        info!("Writing combined inclusion proofs to file {:?}", path);
+
+        let file = File::create(path).map_err(ReadWriteError::from)?;
+        Ok(CombinedProofWriter::new(BufWriter::new(file)))
+    }
+}
+
+impl<W: Write> CombinedProofWriter<W> {
+    /// Constructor.
+    pub fn new(writer: W) -> Self {
+        CombinedProofWriter {
+            writer,
+            num_proofs: 0,
+        }
+    }
+
+    /// Write the proof for `entity_id` as the next frame.
+    pub fn write_proof(
+        &mut self,
+        entity_id: &EntityId,
+        proof: &InclusionProof,
+    ) -> Result<(), InclusionProofError> {
+        read_write_utils::write_framed(&mut self.writer, &(entity_id, proof))?;
+        self.num_proofs += 1;
+        Ok(())
+    }
+
+    /// Number of proofs written so far.
+    pub fn num_proofs(&self) -> usize {
+        self.num_proofs
+    }
+
+    /// Flush the underlying writer, returning the number of proofs written.
+    pub fn finish(mut self) -> Result<usize, InclusionProofError> {
+        self.writer.flush().map_err(ReadWriteError::from)?;
+        Ok(self.num_proofs)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Reader.
+
+/// Iterator over the proofs in a combined proof file, in the order they were
+/// written.
+///
+/// Only 1 proof is read into memory at a time. If a frame cannot be read (e.g.
+/// the file is truncated or corrupted) then the error is returned and the
+/// iteration stops, since the position of the next frame is not known.
+#[derive(Debug)]
+pub struct CombinedProofReader<R: Read> {
+    reader: R,
+    done: bool,
+}
+
+impl CombinedProofReader<BufReader<File>> {
+    /// Open the combined proof file at `path`.
+    ///
+    /// An error is returned if
+    /// 1. The extension of `path` is not [COMBINED_PROOFS_EXTENSION].
+    /// 2. The file cannot be opened.
+    pub fn open(path: PathBuf) -> Result<Self, InclusionProofError> {
+        check_extension(&path)?;
+
+        info!("Reading combined inclusion proofs from file {:?}", path);
+
+        let file = File::open(path).map_err(ReadWriteError::from)?;
+        Ok(CombinedProofReader::new(BufReader::new(file)))
+    }
+}
+
+impl<R: Read> CombinedProofReader<R> {
+    /// Constructor.
+    pub fn new(reader: R) -> Self {
+        CombinedProofReader {
+            reader,
+            done: false,
+        }
+    }
+}
+
+impl<R: Read> Iterator for CombinedProofReader<R> {
+    type Item = Result<(EntityId, InclusionProof), InclusionProofError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        match read_write_utils::read_framed(&mut self.reader) {
+            Ok(Some(item)) => Some(Ok(item)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(err) => {
+                self.done = true;
+                Some(Err(err.into()))
+            }
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Streaming verifier.
+
+/// Verifies the proofs in a combined proof file against a root hash, 1 proof
+/// at a time.
+///
+/// The iterator yields the entity ID of each proof that was verified
+/// successfully, or an error for each proof that failed, so the caller can
+/// report the results as they come in. As with [CombinedProofReader], the
+/// iteration stops after an error reading a frame.
+#[derive(Debug)]
+pub struct StreamingVerifier<R: Read> {
+    proofs: CombinedProofReader<R>,
+    root_hash: H256,
+    index: usize,
+}
+
+impl StreamingVerifier<BufReader<File>> {
+    /// Open the combined proof file at `path` for verification against
+    /// `root_hash`.
+    ///
+    /// An error is returned if
+    /// 1. The extension of `path` is not [COMBINED_PROOFS_EXTENSION].
+    /// 2. The file cannot be opened.
+    pub fn open(path: PathBuf, root_hash: H256) -> Result<Self, InclusionProofError> {
+        Ok(StreamingVerifier::new(
+            CombinedProofReader::open(path)?,
+            root_hash,
+        ))
+    }
+}
+
+impl<R: Read> StreamingVerifier<R> {
+    /// Constructor.
+    pub fn new(proofs: CombinedProofReader<R>, root_hash: H256) -> Self {
+        StreamingVerifier {
+            proofs,
+            root_hash,
+            index: 0,
+        }
+    }
+}
+
+impl<R: Read> Iterator for StreamingVerifier<R> {
+    type Item = Result<EntityId, StreamingVerificationError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.proofs.next()?;
+        let index = self.index;
+        self.index += 1;
+
+        let res = match item {
+            Ok((entity_id, proof)) => match proof.verify(self.root_hash) {
+                Ok(()) => Ok(entity_id),
+                Err(source) => Err(StreamingVerificationError {
+                    index,
+                    entity_id: Some(entity_id),
+                    source,
+                }),
+            },
+            Err(source) => Err(StreamingVerificationError {
+                index,
+                entity_id: None,
+                source,
+            }),
+        };
+
+        Some(res)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Errors.
+
+/// Error returned when a proof in a combined proof file fails verification.
+///
+/// `entity_id` is `None` if the proof could not be read from the file.
+#[derive(thiserror::Error, Debug)]
+#[error("Verification failed for the inclusion proof at index {index} (entity ID {entity_id:?})")]
+pub struct StreamingVerificationError {
+    pub index: usize,
+    pub entity_id: Option<EntityId>,
+    #[source]
+    pub source: InclusionProofError,
+}
+
+// -------------------------------------------------------------------------------------------------
+// Unit tests.
+
+#[cfg(all(test, feature = "full"))]
+mod tests {
+    use super::*;
+    use crate::utils::test_utils::assert_err;
+    use crate::{
+        AccumulatorType, DapolTree, Entity, Height, MaxLiability, MaxThreadCount, Salt, Secret,
+    };
+
+    use std::str::FromStr;
+
+    fn new_tree() -> DapolTree {
+        let entities = (0..10)
+            .map(|i| Entity {
+                liability: i * 10,
+                id: EntityId::from_str(&format!("id{}", i)).unwrap(),
+                salt: None,
+                blinding_factor: None,
+            })
+            .collect();
+
+        DapolTree::new(
+            AccumulatorType::NdmSmt,
+            Secret::from_str("master_secret").unwrap(),
+            Salt::from_str("salt_b").unwrap(),
+            Salt::from_str("salt_s").unwrap(),
+            MaxLiability::from(10_000_000),
+            MaxThreadCount::from(8),
+            Height::expect_from(8),
+            entities,
+        )
+        .unwrap()
+    }
+
+    fn write_proofs(tree: &DapolTree, entity_ids: &[EntityId]) -> Vec<u8> {
+        let mut writer = CombinedProofWriter::new(Vec::new());
+        for (entity_id, proof) in tree.inclusion_proofs_iter(entity_ids) {
+            writer.write_proof(&entity_id, &proof.unwrap()).unwrap();
+        }
+        assert_eq!(writer.num_proofs(), entity_ids.len());
+        writer.writer
+    }
+
+    #[test]
+    fn streaming_verifier_verifies_all_written_proofs() {
+        let tree = new_tree();
+        let entity_ids: Vec<EntityId> = (0..5)
+            .map(|i| EntityId::from_str(&format!("id{}", i)).unwrap())
+            .collect();
+
+        let buf = write_proofs(&tree, &entity_ids);
+
+        let verified =
+            StreamingVerifier::new(CombinedProofReader::new(buf.as_slice()), *tree.root_hash())
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+
+        assert_eq!(verified, entity_ids);
+    }
+
+    #[test]
+    fn streaming_verifier_reports_proofs_that_fail() {
+        let tree = new_tree();
+        let entity_ids = vec![EntityId::from_str("id1").unwrap()];
+
+        let buf = write_proofs(&tree, &entity_ids);
+
+        let mut verifier =
+            StreamingVerifier::new(CombinedProofReader::new(buf.as_slice()), H256::zero());
+
+        let res = verifier.next().unwrap();
+        assert_err!(
+            res,
+            Err(StreamingVerificationError {
+                index: 0,
+                entity_id: Some(_),
+                source: InclusionProofError::RootMismatch,
+            })
+        );
+        assert!(verifier.next().is_none());
+    }
+
+    #[test]
+    fn reader_stops_after_truncated_frame() {
+        let tree = new_tree();
+        let entity_ids = vec![
+            EntityId::from_str("id1").unwrap(),
+            EntityId::from_str("id2").unwrap(),
+        ];
+
+        let mut buf = write_proofs(&tree, &entity_ids);
+        buf.truncate(buf.len() - 10);
+
+        let mut reader = CombinedProofReader::new(buf.as_slice());
+        assert!(reader.next().unwrap().is_ok());
+        assert_err!(
+            reader.next().unwrap(),
+            Err(InclusionProofError::SerdeError(
+                ReadWriteError::TruncatedFrame { .. }
+            ))
+        );
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn file_round_trip_gives_verifiable_proofs() {
+        let tree = new_tree();
+        let entity_ids = vec![EntityId::from_str("id3").unwrap()];
+
+        let src_dir = env!("CARGO_MANIFEST_DIR");
+        let path = PathBuf::from(src_dir)
+            .join("examples")
+            .join("combined_proofs_for_testing.dapolproofs");
+
+        let mut writer = CombinedProofWriter::create(path.clone()).unwrap();
+        for (entity_id, proof) in tree.inclusion_proofs_iter(&entity_ids) {
+            writer.write_proof(&entity_id, &proof.unwrap()).unwrap();
+        }
+        assert_eq!(writer.finish().unwrap(), 1);
+
+        let verified = StreamingVerifier::open(path.clone(), *tree.root_hash())
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(verified.unwrap(), entity_ids);
+    }
+
+    #[test]
+    fn wrong_extension_gives_error() {
+        let path = PathBuf::from("./proofs.json");
+        assert_err!(
+            CombinedProofReader::open(path.clone()),
+            Err(InclusionProofError::FileExtensionMismatch { .. })
+        );
+        assert_err!(
+            CombinedProofWriter::create(path),
+            Err(InclusionProofError::FileExtensionMismatch { .. })
+        );
+    }
+}
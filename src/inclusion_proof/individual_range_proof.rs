@@ -13,12 +13,15 @@
 use bulletproofs::{BulletproofGens, PedersenGens, RangeProof};
 use curve25519_dalek_ng::{ristretto::CompressedRistretto, scalar::Scalar};
 use merlin::Transcript;
+use rand::{CryptoRng, RngCore};
 use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
 
-use super::RangeProofError;
+use super::{check_upper_bound_bit_length, RangeProofBytes, RangeProofError};
 
+#[serde_as]
 #[derive(Debug, Serialize, Deserialize)]
-pub struct IndividualRangeProof(RangeProof);
+pub struct IndividualRangeProof(#[serde_as(as = "RangeProofBytes")] pub(super) RangeProof);
 
 /// Maximum number of parties that can produce an aggregated proof.
 ///
@@ -41,23 +44,32 @@ impl IndividualRangeProof {
     ///
     /// `upper_bound_bit_length` is in u8 because it is not expected to require
     /// bounds higher than $2^256$.
-    pub fn generate(
+    ///
+    /// The randomness for the Bulletproofs prover is taken from `rng`.
+    pub fn generate<R: RngCore + CryptoRng>(
         secret: u64,
         blinding_factor: &Scalar,
         upper_bound_bit_length: u8,
+        rng: &mut R,
     ) -> Result<IndividualRangeProof, RangeProofError> {
+        check_upper_bound_bit_length(upper_bound_bit_length)?;
+
         let pc_gens = PedersenGens::default();
         let bp_gens = BulletproofGens::new(upper_bound_bit_length as usize, PARTY_CAPACITY);
 
-        match RangeProof::prove_single(
+        match RangeProof::prove_single_with_rng(
             &bp_gens,
             &pc_gens,
             &mut new_transcript(),
             secret,
             blinding_factor,
             upper_bound_bit_length as usize,
+            rng,
         ) {
-            Err(underlying_err) => Err(RangeProofError::BulletproofGenerationError(underlying_err)),
+            Err(source) => Err(RangeProofError::BulletproofGenerationError {
+                upper_bound_bit_length,
+                source,
+            }),
             Ok((proof, _commitment)) => Ok(IndividualRangeProof(proof)),
         }
     }
@@ -111,6 +123,7 @@ mod tests {
 
     use super::*;
     use crate::utils::test_utils::assert_err;
+    use rand::thread_rng;
 
     #[test]
     fn generate_works() {
@@ -118,7 +131,32 @@ mod tests {
         let blinding_factor = Scalar::from_bytes_mod_order(*b"33334444555566667777888811112222");
         let upper_bound_bit_length = 32u8;
 
-        IndividualRangeProof::generate(secret, &blinding_factor, upper_bound_bit_length).unwrap();
+        IndividualRangeProof::generate(
+            secret,
+            &blinding_factor,
+            upper_bound_bit_length,
+            &mut thread_rng(),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn generate_fails_for_unsupported_bit_length() {
+        let secret = 7u64;
+        let blinding_factor = Scalar::from_bytes_mod_order(*b"33334444555566667777888811112222");
+        let upper_bound_bit_length = 10u8;
+
+        let res = IndividualRangeProof::generate(
+            secret,
+            &blinding_factor,
+            upper_bound_bit_length,
+            &mut thread_rng(),
+        );
+
+        assert_err!(
+            res,
+            Err(RangeProofError::UnsupportedUpperBoundBitLength(10))
+        );
     }
 
     // this is unexpected but verification will definitely fail so it's not a
@@ -131,8 +169,13 @@ mod tests {
         let secret = 2u64.pow(10u32);
         let blinding_factor = Scalar::from_bytes_mod_order(*b"33334444555566667777888811112222");
 
-        let _ =
-            IndividualRangeProof::generate(secret, &blinding_factor, invalid_upper_bound).unwrap();
+        let _ = IndividualRangeProof::generate(
+            secret,
+            &blinding_factor,
+            invalid_upper_bound,
+            &mut thread_rng(),
+        )
+        .unwrap();
     }
 
     #[test]
@@ -142,9 +185,13 @@ mod tests {
         let commitment = PedersenGens::default().commit(Scalar::from(secret), blinding_factor);
         let upper_bound_bit_length = 32u8;
 
-        let proof =
-            IndividualRangeProof::generate(secret, &blinding_factor, upper_bound_bit_length)
-                .unwrap();
+        let proof = IndividualRangeProof::generate(
+            secret,
+            &blinding_factor,
+            upper_bound_bit_length,
+            &mut thread_rng(),
+        )
+        .unwrap();
 
         proof
             .verify(&commitment.compress(), upper_bound_bit_length)
@@ -161,8 +208,13 @@ mod tests {
         let blinding_factor = Scalar::from_bytes_mod_order(*b"33334444555566667777888811112222");
         let commitment = PedersenGens::default().commit(Scalar::from(secret), blinding_factor);
 
-        let proof =
-            IndividualRangeProof::generate(secret, &blinding_factor, valid_upper_bound).unwrap();
+        let proof = IndividualRangeProof::generate(
+            secret,
+            &blinding_factor,
+            valid_upper_bound,
+            &mut thread_rng(),
+        )
+        .unwrap();
 
         let res = proof.verify(&commitment.compress(), invalid_upper_bound);
 
@@ -184,8 +236,13 @@ mod tests {
         let blinding_factor = Scalar::from_bytes_mod_order(*b"33334444555566667777888811112222");
         let commitment = PedersenGens::default().commit(Scalar::from(secret), blinding_factor);
 
-        let proof =
-            IndividualRangeProof::generate(secret, &blinding_factor, invalid_upper_bound).unwrap();
+        let proof = IndividualRangeProof::generate(
+            secret,
+            &blinding_factor,
+            invalid_upper_bound,
+            &mut thread_rng(),
+        )
+        .unwrap();
 
         let res = proof.verify(&commitment.compress(), valid_upper_bound);
 
@@ -208,9 +265,13 @@ mod tests {
 
         // NOTE the proof generation succeeds even though the secret value is greater
         // than the bound
-        let proof =
-            IndividualRangeProof::generate(secret, &blinding_factor, upper_bound_bit_length)
-                .unwrap();
+        let proof = IndividualRangeProof::generate(
+            secret,
+            &blinding_factor,
+            upper_bound_bit_length,
+            &mut thread_rng(),
+        )
+        .unwrap();
 
         let res = proof.verify(&commitment.compress(), upper_bound_bit_length);
 
@@ -233,9 +294,13 @@ mod tests {
 
         let upper_bound_bit_length = 32u8;
 
-        let proof =
-            IndividualRangeProof::generate(secret, &blinding_factor, upper_bound_bit_length)
-                .unwrap();
+        let proof = IndividualRangeProof::generate(
+            secret,
+            &blinding_factor,
+            upper_bound_bit_length,
+            &mut thread_rng(),
+        )
+        .unwrap();
 
         let res = proof.verify(&commitment.compress(), upper_bound_bit_length);
 
@@ -259,9 +324,13 @@ mod tests {
 
         let upper_bound_bit_length = 32u8;
 
-        let proof =
-            IndividualRangeProof::generate(secret, &blinding_factor, upper_bound_bit_length)
-                .unwrap();
+        let proof = IndividualRangeProof::generate(
+            secret,
+            &blinding_factor,
+            upper_bound_bit_length,
+            &mut thread_rng(),
+        )
+        .unwrap();
 
         let res = proof.verify(&commitment.compress(), upper_bound_bit_length);
 
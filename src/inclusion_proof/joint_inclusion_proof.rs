@@ -0,0 +1,146 @@
+//! Proof that 2 entities are in the same tree.
+//!
+//! The paths of 2 leaf nodes join at their lowest common ancestor, and from
+//! there on they are the same path. [JointInclusionProof] holds the siblings
+//! of each path up to (but not including) the common ancestor, and a single
+//! set of siblings from the common ancestor to the root. This is more compact
+//! than 2 independent [InclusionProof]s when the leaf nodes are close to each
+//! other in the tree.
+//!
+//! Only the Merkle paths are checked, there are no range proofs. See
+//! [InclusionProof::verify_membership_only] for the implications of this.
+//!
+//! [InclusionProof]: super::InclusionProof
+//! [InclusionProof::verify_membership_only]: super::InclusionProof::verify_membership_only
+
+use primitive_types::H256;
+use serde::{Deserialize, Serialize};
+
+use log::info;
+
+use crate::binary_tree::{
+    Coordinate, FullNodeContent, HiddenNodeContent, Node, PathSiblings, PathSiblingsError,
+};
+
+/// Proof that 2 leaf nodes are in the same tree.
+///
+/// The leaf nodes are given with their hidden content, so the liabilities &
+/// blinding factors of the entities are not revealed by the proof.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JointInclusionProof {
+    leaf_node_a: Node<HiddenNodeContent>,
+    leaf_node_b: Node<HiddenNodeContent>,
+    siblings_a: PathSiblings<HiddenNodeContent>,
+    siblings_b: PathSiblings<HiddenNodeContent>,
+    shared_siblings: PathSiblings<HiddenNodeContent>,
+}
+
+impl JointInclusionProof {
+    /// Generate a joint proof from the full path siblings of each leaf node.
+    ///
+    /// The path siblings are split at the lowest common ancestor of the 2
+    /// leaf nodes. Below the common ancestor each path keeps its own
+    /// siblings, except for the layer just below the common ancestor where
+    /// the 2 path nodes are siblings of each other, so neither needs to be
+    /// stored. Above the common ancestor the siblings are the same for both
+    /// paths so only 1 copy is kept. The secret values of the leaf nodes &
+    /// siblings are dropped.
+    ///
+    /// An error is returned if the 2 leaf nodes have the same coordinate, or
+    /// if the path siblings do not have the same length.
+    pub fn generate(
+        leaf_node_a: Node<FullNodeContent>,
+        path_siblings_a: PathSiblings<FullNodeContent>,
+        leaf_node_b: Node<FullNodeContent>,
+        path_siblings_b: PathSiblings<FullNodeContent>,
+    ) -> Result<Self, JointInclusionProofError> {
+        if leaf_node_a.coord == leaf_node_b.coord {
+            return Err(JointInclusionProofError::SameLeafNode(leaf_node_a.coord));
+        }
+
+        if path_siblings_a.len() != path_siblings_b.len() {
+            return Err(JointInclusionProofError::PathLengthMismatch);
+        }
+
+        // Number of layers from the leaf nodes to their common ancestor.
+        let common_ancestor_layer =
+            (u64::BITS - (leaf_node_a.coord.x ^ leaf_node_b.coord.x).leading_zeros()) as usize;
+
+        let mut siblings_a = path_siblings_a.convert::<HiddenNodeContent>().0;
+        let mut siblings_b = path_siblings_b.convert::<HiddenNodeContent>().0;
+
+        let shared_siblings = siblings_a.split_off(common_ancestor_layer);
+        siblings_b.truncate(common_ancestor_layer);
+
+        // The sibling in the layer below the common ancestor is the node in
+        // the other path, which the verifier reconstructs.
+        siblings_a.pop();
+        siblings_b.pop();
+
+        Ok(JointInclusionProof {
+            leaf_node_a: leaf_node_a.convert(),
+            leaf_node_b: leaf_node_b.convert(),
+            siblings_a: PathSiblings(siblings_a),
+            siblings_b: PathSiblings(siblings_b),
+            shared_siblings: PathSiblings(shared_siblings),
+        })
+    }
+
+    /// Verify that both leaf nodes are in the tree with the given root hash.
+    ///
+    /// The path of each leaf node is reconstructed up to the common ancestor,
+    /// and then the shared path is reconstructed up to the root.
+    pub fn verify(&self, root_hash: H256) -> Result<(), JointInclusionProofError> {
+        info!("Verifying joint inclusion proof..");
+
+        if self.leaf_node_a.coord.y != 0 || self.leaf_node_b.coord.y != 0 {
+            return Err(JointInclusionProofError::LeafNotOnBottomLayer);
+        }
+
+        let node_a = self
+            .siblings_a
+            .construct_ancestor(self.leaf_node_a.clone())?;
+        let node_b = self
+            .siblings_b
+            .construct_ancestor(self.leaf_node_b.clone())?;
+
+        let common_ancestor = PathSiblings(vec![node_b]).construct_ancestor(node_a)?;
+        let root = self.shared_siblings.construct_ancestor(common_ancestor)?;
+
+        let root_y_coord = self.siblings_a.len() + 1 + self.shared_siblings.len();
+        let root_coord = Coordinate {
+            x: 0,
+            y: root_y_coord as u8,
+        };
+
+        if root.coord != root_coord || root.content.hash != root_hash {
+            return Err(JointInclusionProofError::RootMismatch);
+        }
+
+        info!("Succesfully verified joint inclusion proof");
+
+        Ok(())
+    }
+
+    /// Total number of sibling nodes in the proof.
+    pub fn sibling_count(&self) -> usize {
+        self.siblings_a.len() + self.siblings_b.len() + self.shared_siblings.len()
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Errors.
+
+#[derive(thiserror::Error, Debug)]
+pub enum JointInclusionProofError {
+    #[error("Both leaf nodes have the same coordinate {0:?}")]
+    SameLeafNode(Coordinate),
+    #[error("The path siblings of the 2 leaf nodes have different lengths")]
+    PathLengthMismatch,
+    #[error("Leaf nodes are expected to be on the bottom layer of the tree")]
+    LeafNotOnBottomLayer,
+    #[error("Siblings were not valid for the leaf nodes")]
+    InvalidSiblings(#[from] PathSiblingsError),
+    #[error("Calculated root content does not match provided root content")]
+    RootMismatch,
+}
@@ -0,0 +1,95 @@
+//! Proof that an entity is in the tree, without any range proofs.
+//!
+//! [MembershipProof] holds the leaf node of the entity and the siblings of
+//! the path from the leaf node to the root, all with their secret values
+//! removed. Generating Bulletproofs range proofs is by far the most expensive
+//! part of generating an [InclusionProof], so this proof is much cheaper to
+//! produce, and it is also smaller.
+//!
+//! Warning: a membership proof gives NO guarantee on the liabilities in the
+//! tree, it only shows that the entity's leaf node hashes up to the root
+//! hash. Without range proofs a negative liability could be hidden in the
+//! tree, see [InclusionProof::verify_membership_only].
+//!
+//! [InclusionProof]: super::InclusionProof
+//! [InclusionProof::verify_membership_only]: super::InclusionProof::verify_membership_only
+
+use primitive_types::H256;
+use serde::{Deserialize, Serialize};
+
+use log::info;
+
+use crate::binary_tree::{
+    Coordinate, FullNodeContent, HiddenNodeContent, Node, PathSiblings, PathSiblingsError,
+};
+
+/// Proof that a leaf node is in the tree, without range proofs.
+///
+/// The leaf node is given with its hidden content, so the liability &
+/// blinding factor of the entity are not revealed by the proof.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MembershipProof {
+    leaf_node: Node<HiddenNodeContent>,
+    path_siblings: PathSiblings<HiddenNodeContent>,
+}
+
+impl MembershipProof {
+    /// Generate a membership proof from the tree path siblings.
+    ///
+    /// The secret values of the leaf node & siblings are dropped, no range
+    /// proofs are generated.
+    pub fn generate(
+        leaf_node: Node<FullNodeContent>,
+        path_siblings: PathSiblings<FullNodeContent>,
+    ) -> Self {
+        MembershipProof {
+            leaf_node: leaf_node.convert(),
+            path_siblings: path_siblings.convert(),
+        }
+    }
+
+    /// Verify that the leaf node is in the tree with the given root hash.
+    ///
+    /// Only the Merkle path is checked. See the [module docs](self) for what
+    /// this does not guarantee.
+    pub fn verify_membership_only(&self, root_hash: H256) -> Result<(), MembershipProofError> {
+        info!("Verifying membership proof..");
+
+        if self.leaf_node.coord.y != 0 {
+            return Err(MembershipProofError::LeafNotOnBottomLayer);
+        }
+
+        let root = self.path_siblings.construct_root_node(&self.leaf_node)?;
+
+        let root_coord = Coordinate {
+            x: 0,
+            y: self.path_siblings.len() as u8,
+        };
+
+        if root.coord != root_coord || root.content.hash != root_hash {
+            return Err(MembershipProofError::RootMismatch);
+        }
+
+        info!("Succesfully verified membership proof");
+
+        Ok(())
+    }
+
+    /// The leaf node of the entity, with its secret values removed.
+    pub fn leaf_node(&self) -> &Node<HiddenNodeContent> {
+        &self.leaf_node
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Errors.
+
+#[derive(thiserror::Error, Debug)]
+pub enum MembershipProofError {
+    #[error("Leaf node is expected to be on the bottom layer of the tree")]
+    LeafNotOnBottomLayer,
+    #[error("Siblings were not valid for the leaf node")]
+    InvalidSiblings(#[from] PathSiblingsError),
+    #[error("Calculated root content does not match provided root content")]
+    RootMismatch,
+}
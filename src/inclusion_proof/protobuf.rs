@@ -0,0 +1,580 @@
+//! Protobuf encoding of [InclusionProof].
+//!
+//! The schema is defined in `proto/inclusion_proof.proto` at the root of the
+//! repository, which can be used to generate clients in other languages. The
+//! message structs in this file mirror the schema, and are converted to & from
+//! the inclusion proof struct.
+//!
+//! Only available with the `proto` feature.
+
+use bulletproofs::RangeProof;
+use curve25519_dalek_ng::{
+    ristretto::{CompressedRistretto, RistrettoPoint},
+    scalar::Scalar,
+};
+use primitive_types::H256;
+use prost::Message;
+
+use crate::binary_tree::{Coordinate, FullNodeContent, HiddenNodeContent, Node, PathSiblings};
+use crate::percentage::{Percentage, PercentageParserError};
+
+use super::{AggregatedRangeProof, AggregationFactor, InclusionProof, IndividualRangeProof};
+
+// -------------------------------------------------------------------------------------------------
+// Messages.
+
+#[derive(Clone, PartialEq, Message)]
+struct CoordinateMsg {
+    #[prost(uint64, tag = "1")]
+    x: u64,
+    #[prost(uint32, tag = "2")]
+    y: u32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct HiddenNodeMsg {
+    #[prost(message, optional, tag = "1")]
+    coord: Option<CoordinateMsg>,
+    #[prost(bytes = "vec", tag = "2")]
+    commitment: Vec<u8>,
+    #[prost(bytes = "vec", tag = "3")]
+    hash: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct FullNodeMsg {
+    #[prost(message, optional, tag = "1")]
+    coord: Option<CoordinateMsg>,
+    #[prost(uint64, tag = "2")]
+    liability: u64,
+    #[prost(bytes = "vec", tag = "3")]
+    blinding_factor: Vec<u8>,
+    #[prost(bytes = "vec", tag = "4")]
+    commitment: Vec<u8>,
+    #[prost(bytes = "vec", tag = "5")]
+    hash: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct AggregationFactorMsg {
+    #[prost(oneof = "AggregationFactorKind", tags = "1, 2, 3, 4")]
+    factor: Option<AggregationFactorKind>,
+}
+
+#[derive(Clone, PartialEq, prost::Oneof)]
+enum AggregationFactorKind {
+    #[prost(uint32, tag = "1")]
+    Divisor(u32),
+    #[prost(uint32, tag = "2")]
+    Percent(u32),
+    #[prost(uint32, tag = "3")]
+    Number(u32),
+    #[prost(bytes = "vec", tag = "4")]
+    Layers(Vec<u8>),
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct IndividualRangeProofsMsg {
+    #[prost(bytes = "vec", repeated, tag = "1")]
+    proofs: Vec<Vec<u8>>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct PaddingRangeProofMsg {
+    #[prost(bytes = "vec", tag = "1")]
+    proof: Vec<u8>,
+    #[prost(uint32, tag = "2")]
+    input_size: u32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct SplittingRangeProofPartMsg {
+    #[prost(bytes = "vec", tag = "1")]
+    proof: Vec<u8>,
+    #[prost(uint64, tag = "2")]
+    num_values: u64,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct SplittingRangeProofMsg {
+    #[prost(message, repeated, tag = "1")]
+    parts: Vec<SplittingRangeProofPartMsg>,
+    #[prost(uint32, tag = "2")]
+    input_size: u32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct AggregatedRangeProofMsg {
+    #[prost(oneof = "AggregatedRangeProofKind", tags = "1, 2")]
+    proof: Option<AggregatedRangeProofKind>,
+}
+
+#[derive(Clone, PartialEq, prost::Oneof)]
+enum AggregatedRangeProofKind {
+    #[prost(message, tag = "1")]
+    Padding(PaddingRangeProofMsg),
+    #[prost(message, tag = "2")]
+    Splitting(SplittingRangeProofMsg),
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct InclusionProofMsg {
+    #[prost(message, optional, tag = "1")]
+    leaf_node: Option<FullNodeMsg>,
+    #[prost(message, repeated, tag = "2")]
+    path_siblings: Vec<HiddenNodeMsg>,
+    #[prost(message, optional, tag = "3")]
+    individual_range_proofs: Option<IndividualRangeProofsMsg>,
+    #[prost(message, optional, tag = "4")]
+    aggregated_range_proof: Option<AggregatedRangeProofMsg>,
+    #[prost(message, optional, tag = "5")]
+    aggregation_factor: Option<AggregationFactorMsg>,
+    #[prost(uint32, tag = "6")]
+    upper_bound_bit_length: u32,
+}
+
+// -------------------------------------------------------------------------------------------------
+// Encoding & decoding.
+
+impl InclusionProof {
+    /// Encode the proof using the protobuf schema in
+    /// `proto/inclusion_proof.proto`.
+    ///
+    /// Note that the leaf node's liability & blinding factor are included, just
+    /// like the other serialization formats.
+    pub fn to_protobuf(&self) -> Vec<u8> {
+        InclusionProofMsg::from(self).encode_to_vec()
+    }
+
+    /// Decode a proof that was encoded with [InclusionProof::to_protobuf].
+    ///
+    /// An error is returned if the bytes are not a valid protobuf message, or
+    /// if any of the fields cannot be converted back (e.g. a curve point that
+    /// is not on the curve). The proof itself is not verified.
+    pub fn from_protobuf(bytes: &[u8]) -> Result<Self, ProtobufError> {
+        InclusionProofMsg::decode(bytes)?.try_into()
+    }
+}
+
+impl From<&InclusionProof> for InclusionProofMsg {
+    fn from(proof: &InclusionProof) -> Self {
+        InclusionProofMsg {
+            leaf_node: Some(FullNodeMsg::from(&proof.leaf_node)),
+            path_siblings: proof
+                .path_siblings
+                .0
+                .iter()
+                .map(HiddenNodeMsg::from)
+                .collect(),
+            individual_range_proofs: proof.individual_range_proofs.as_ref().map(|proofs| {
+                IndividualRangeProofsMsg {
+                    proofs: proofs.iter().map(|proof| proof.0.to_bytes()).collect(),
+                }
+            }),
+            aggregated_range_proof: proof
+                .aggregated_range_proof
+                .as_ref()
+                .map(AggregatedRangeProofMsg::from),
+            aggregation_factor: Some(AggregationFactorMsg::from(&proof.aggregation_factor)),
+            upper_bound_bit_length: proof.upper_bound_bit_length as u32,
+        }
+    }
+}
+
+impl TryFrom<InclusionProofMsg> for InclusionProof {
+    type Error = ProtobufError;
+
+    fn try_from(msg: InclusionProofMsg) -> Result<Self, Self::Error> {
+        let leaf_node = msg
+            .leaf_node
+            .ok_or(ProtobufError::MissingField("leaf_node"))?
+            .try_into()?;
+
+        let path_siblings = msg
+            .path_siblings
+            .into_iter()
+            .map(Node::<HiddenNodeContent>::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let individual_range_proofs = msg
+            .individual_range_proofs
+            .map(|proofs| {
+                proofs
+                    .proofs
+                    .iter()
+                    .map(|bytes| Ok(IndividualRangeProof(range_proof(bytes)?)))
+                    .collect::<Result<Vec<_>, ProtobufError>>()
+            })
+            .transpose()?;
+
+        let aggregated_range_proof = msg
+            .aggregated_range_proof
+            .map(AggregatedRangeProof::try_from)
+            .transpose()?;
+
+        let aggregation_factor = msg
+            .aggregation_factor
+            .ok_or(ProtobufError::MissingField("aggregation_factor"))?
+            .try_into()?;
+
+        Ok(InclusionProof {
+            path_siblings: PathSiblings(path_siblings),
+            leaf_node,
+            individual_range_proofs,
+            aggregated_range_proof,
+            aggregation_factor,
+            upper_bound_bit_length: to_u8(msg.upper_bound_bit_length, "upper_bound_bit_length")?,
+        })
+    }
+}
+
+impl From<&Coordinate> for CoordinateMsg {
+    fn from(coord: &Coordinate) -> Self {
+        CoordinateMsg {
+            x: coord.x,
+            y: coord.y as u32,
+        }
+    }
+}
+
+impl TryFrom<Option<CoordinateMsg>> for Coordinate {
+    type Error = ProtobufError;
+
+    fn try_from(msg: Option<CoordinateMsg>) -> Result<Self, Self::Error> {
+        let msg = msg.ok_or(ProtobufError::MissingField("coord"))?;
+
+        Ok(Coordinate {
+            x: msg.x,
+            y: to_u8(msg.y, "coord.y")?,
+        })
+    }
+}
+
+impl From<&Node<HiddenNodeContent>> for HiddenNodeMsg {
+    fn from(node: &Node<HiddenNodeContent>) -> Self {
+        HiddenNodeMsg {
+            coord: Some(CoordinateMsg::from(&node.coord)),
+            commitment: node.content.commitment.compress().to_bytes().to_vec(),
+            hash: node.content.hash.as_bytes().to_vec(),
+        }
+    }
+}
+
+impl TryFrom<HiddenNodeMsg> for Node<HiddenNodeContent> {
+    type Error = ProtobufError;
+
+    fn try_from(msg: HiddenNodeMsg) -> Result<Self, Self::Error> {
+        Ok(Node {
+            coord: msg.coord.try_into()?,
+            content: HiddenNodeContent::new(point(&msg.commitment)?, hash(&msg.hash)?),
+        })
+    }
+}
+
+impl From<&Node<FullNodeContent>> for FullNodeMsg {
+    fn from(node: &Node<FullNodeContent>) -> Self {
+        FullNodeMsg {
+            coord: Some(CoordinateMsg::from(&node.coord)),
+            liability: node.content.liability,
+            blinding_factor: node.content.blinding_factor.to_bytes().to_vec(),
+            commitment: node.content.commitment.compress().to_bytes().to_vec(),
+            hash: node.content.hash.as_bytes().to_vec(),
+        }
+    }
+}
+
+impl TryFrom<FullNodeMsg> for Node<FullNodeContent> {
+    type Error = ProtobufError;
+
+    fn try_from(msg: FullNodeMsg) -> Result<Self, Self::Error> {
+        Ok(Node {
+            coord: msg.coord.try_into()?,
+            content: FullNodeContent {
+                liability: msg.liability,
+                blinding_factor: scalar(&msg.blinding_factor)?,
+                commitment: point(&msg.commitment)?,
+                hash: hash(&msg.hash)?,
+            },
+        })
+    }
+}
+
+impl From<&AggregationFactor> for AggregationFactorMsg {
+    fn from(aggregation_factor: &AggregationFactor) -> Self {
+        let factor = match aggregation_factor {
+            AggregationFactor::Divisor(div) => AggregationFactorKind::Divisor(*div as u32),
+            AggregationFactor::Percent(per) => AggregationFactorKind::Percent(per.value() as u32),
+            AggregationFactor::Number(num) => AggregationFactorKind::Number(*num as u32),
+            AggregationFactor::Layers(layers) => AggregationFactorKind::Layers(layers.clone()),
+        };
+
+        AggregationFactorMsg {
+            factor: Some(factor),
+        }
+    }
+}
+
+impl TryFrom<AggregationFactorMsg> for AggregationFactor {
+    type Error = ProtobufError;
+
+    fn try_from(msg: AggregationFactorMsg) -> Result<Self, Self::Error> {
+        match msg
+            .factor
+            .ok_or(ProtobufError::MissingField("aggregation_factor"))?
+        {
+            AggregationFactorKind::Divisor(div) => Ok(AggregationFactor::Divisor(to_u8(
+                div,
+                "aggregation_factor.divisor",
+            )?)),
+            AggregationFactorKind::Percent(per) => Ok(AggregationFactor::Percent(
+                Percentage::try_new(to_u8(per, "aggregation_factor.percent")?)?,
+            )),
+            AggregationFactorKind::Number(num) => Ok(AggregationFactor::Number(to_u8(
+                num,
+                "aggregation_factor.number",
+            )?)),
+            AggregationFactorKind::Layers(layers) => Ok(AggregationFactor::Layers(layers)),
+        }
+    }
+}
+
+impl From<&AggregatedRangeProof> for AggregatedRangeProofMsg {
+    fn from(aggregated_range_proof: &AggregatedRangeProof) -> Self {
+        let proof = match aggregated_range_proof {
+            AggregatedRangeProof::Padding { proof, input_size } => {
+                AggregatedRangeProofKind::Padding(PaddingRangeProofMsg {
+                    proof: proof.to_bytes(),
+                    input_size: *input_size as u32,
+                })
+            }
+            AggregatedRangeProof::Splitting { proofs, input_size } => {
+                AggregatedRangeProofKind::Splitting(SplittingRangeProofMsg {
+                    parts: proofs
+                        .iter()
+                        .map(|(proof, num_values)| SplittingRangeProofPartMsg {
+                            proof: proof.to_bytes(),
+                            num_values: *num_values as u64,
+                        })
+                        .collect(),
+                    input_size: *input_size as u32,
+                })
+            }
+        };
+
+        AggregatedRangeProofMsg { proof: Some(proof) }
+    }
+}
+
+impl TryFrom<AggregatedRangeProofMsg> for AggregatedRangeProof {
+    type Error = ProtobufError;
+
+    fn try_from(msg: AggregatedRangeProofMsg) -> Result<Self, Self::Error> {
+        match msg
+            .proof
+            .ok_or(ProtobufError::MissingField("aggregated_range_proof"))?
+        {
+            AggregatedRangeProofKind::Padding(padding) => Ok(AggregatedRangeProof::Padding {
+                proof: range_proof(&padding.proof)?,
+                input_size: to_u8(padding.input_size, "padding.input_size")?,
+            }),
+            AggregatedRangeProofKind::Splitting(splitting) => {
+                let proofs = splitting
+                    .parts
+                    .iter()
+                    .map(|part| {
+                        let num_values = usize::try_from(part.num_values)
+                            .map_err(|_| ProtobufError::ValueTooLarge("splitting.num_values"))?;
+                        Ok((range_proof(&part.proof)?, num_values))
+                    })
+                    .collect::<Result<Vec<_>, ProtobufError>>()?;
+
+                Ok(AggregatedRangeProof::Splitting {
+                    proofs,
+                    input_size: to_u8(splitting.input_size, "splitting.input_size")?,
+                })
+            }
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Field conversion helpers.
+
+fn to_u8(value: u32, field: &'static str) -> Result<u8, ProtobufError> {
+    u8::try_from(value).map_err(|_| ProtobufError::ValueTooLarge(field))
+}
+
+fn bytes_32(bytes: &[u8], field: &'static str) -> Result<[u8; 32], ProtobufError> {
+    bytes
+        .try_into()
+        .map_err(|_| ProtobufError::InvalidBytes(field))
+}
+
+fn point(bytes: &[u8]) -> Result<RistrettoPoint, ProtobufError> {
+    CompressedRistretto(bytes_32(bytes, "commitment")?)
+        .decompress()
+        .ok_or(ProtobufError::InvalidBytes("commitment"))
+}
+
+fn scalar(bytes: &[u8]) -> Result<Scalar, ProtobufError> {
+    Scalar::from_canonical_bytes(bytes_32(bytes, "blinding_factor")?)
+        .ok_or(ProtobufError::InvalidBytes("blinding_factor"))
+}
+
+fn hash(bytes: &[u8]) -> Result<H256, ProtobufError> {
+    Ok(H256(bytes_32(bytes, "hash")?))
+}
+
+fn range_proof(bytes: &[u8]) -> Result<RangeProof, ProtobufError> {
+    RangeProof::from_bytes(bytes).map_err(|_| ProtobufError::InvalidBytes("range proof"))
+}
+
+// -------------------------------------------------------------------------------------------------
+// Errors.
+
+#[derive(thiserror::Error, Debug)]
+pub enum ProtobufError {
+    #[error("Bytes are not a valid protobuf message")]
+    DecodeError(#[from] prost::DecodeError),
+    #[error("Required field {0} is not set")]
+    MissingField(&'static str),
+    #[error("Value of field {0} is too large")]
+    ValueTooLarge(&'static str),
+    #[error("Bytes of field {0} could not be decoded")]
+    InvalidBytes(&'static str),
+    #[error("Invalid aggregation factor percentage")]
+    InvalidPercentage(#[from] PercentageParserError),
+}
+
+// -------------------------------------------------------------------------------------------------
+// Unit tests.
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_utils::assert_err;
+    use crate::{
+        AccumulatorType, DapolTree, Entity, EntityId, Height, MaxLiability, MaxThreadCount, Salt,
+        Secret,
+    };
+    use std::str::FromStr;
+
+    fn new_tree() -> DapolTree {
+        let entities = (0..10)
+            .map(|i| Entity {
+                liability: i,
+                id: EntityId::from_str(&format!("id{}", i)).unwrap(),
+                salt: None,
+                blinding_factor: None,
+            })
+            .collect();
+
+        DapolTree::new(
+            AccumulatorType::NdmSmt,
+            Secret::from_str("master_secret").unwrap(),
+            Salt::from_str("salt_b").unwrap(),
+            Salt::from_str("salt_s").unwrap(),
+            MaxLiability::from(10_000_000),
+            MaxThreadCount::from(8),
+            Height::expect_from(8),
+            entities,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn round_trip_preserves_verification() {
+        use crate::percentage::ONE_HUNDRED_PERCENT;
+
+        let tree = new_tree();
+        let entity_id = EntityId::from_str("id1").unwrap();
+
+        for aggregation_factor in [
+            AggregationFactor::Percent(ONE_HUNDRED_PERCENT),
+            AggregationFactor::Number(0),
+            AggregationFactor::Divisor(2),
+            AggregationFactor::Layers(vec![0, 1, 5]),
+        ] {
+            let proof = tree
+                .generate_inclusion_proof_with(&entity_id, aggregation_factor)
+                .unwrap();
+
+            let decoded = InclusionProof::from_protobuf(&proof.to_protobuf()).unwrap();
+
+            decoded.verify(*tree.root_hash()).unwrap();
+            assert_eq!(decoded.to_protobuf(), proof.to_protobuf());
+        }
+    }
+
+    #[test]
+    fn round_trip_preserves_splitting_range_proof() {
+        let tree = new_tree();
+        let mut proof = tree
+            .generate_inclusion_proof(&EntityId::from_str("id2").unwrap())
+            .unwrap();
+
+        let tuples = vec![
+            (1u64, Scalar::one()),
+            (2u64, Scalar::one()),
+            (3u64, Scalar::one()),
+        ];
+        proof.aggregated_range_proof = Some(
+            AggregatedRangeProof::generate_with_splitting(
+                &tuples,
+                proof.upper_bound_bit_length,
+                &mut rand::thread_rng(),
+            )
+            .unwrap(),
+        );
+
+        let decoded = InclusionProof::from_protobuf(&proof.to_protobuf()).unwrap();
+
+        assert!(matches!(
+            decoded.aggregated_range_proof,
+            Some(AggregatedRangeProof::Splitting { .. })
+        ));
+        assert_eq!(decoded.to_protobuf(), proof.to_protobuf());
+    }
+
+    #[test]
+    fn decoding_garbage_gives_error() {
+        let res = InclusionProof::from_protobuf(&[0xff, 0xff, 0xff]);
+        assert_err!(res, Err(ProtobufError::DecodeError(_)));
+    }
+
+    #[test]
+    fn decoding_empty_message_gives_missing_field_error() {
+        let res = InclusionProof::from_protobuf(&[]);
+        assert_err!(res, Err(ProtobufError::MissingField("leaf_node")));
+    }
+
+    #[test]
+    fn invalid_commitment_bytes_give_error() {
+        let tree = new_tree();
+        let proof = tree
+            .generate_inclusion_proof(&EntityId::from_str("id3").unwrap())
+            .unwrap();
+
+        let mut msg = InclusionProofMsg::from(&proof);
+        msg.path_siblings[0].commitment = vec![1u8; 31];
+
+        let res = InclusionProof::from_protobuf(&msg.encode_to_vec());
+        assert_err!(res, Err(ProtobufError::InvalidBytes("commitment")));
+    }
+
+    #[test]
+    fn percentage_over_100_gives_error() {
+        let tree = new_tree();
+        let proof = tree
+            .generate_inclusion_proof(&EntityId::from_str("id4").unwrap())
+            .unwrap();
+
+        let mut msg = InclusionProofMsg::from(&proof);
+        msg.aggregation_factor = Some(AggregationFactorMsg {
+            factor: Some(AggregationFactorKind::Percent(101)),
+        });
+
+        let res = InclusionProof::from_protobuf(&msg.encode_to_vec());
+        assert_err!(res, Err(ProtobufError::InvalidPercentage(_)));
+    }
+}
@@ -11,6 +11,48 @@
 //! For more information check out these resources:
 //! - [Cryptographic Extraction and Key Derivation: The HKDF Scheme](https://eprint.iacr.org/2010/264.pdf)
 //! - [Wikipedia entry for HKDF](https://en.wikipedia.org/wiki/HKDF)
+//!
+//! ## Derivation of node secrets
+//!
+//! The KDF is used to derive the per-node secrets from the tree's secret
+//! values. This derivation is stable, so a third party that knows the master
+//! secret & salts can recompute the blinding factor & salt of any node (e.g.
+//! to independently verify a leaf node). All byte arrays below are 32 bytes
+//! long, and `None` means the corresponding HKDF input is omitted.
+//!
+//! For a bottom-layer leaf node belonging to an entity mapped to x-coordinate
+//! `x` (a `u64`):
+//! ```text
+//! w               = generate_key(salt: None,         ikm: master_secret, info: Some(x.to_le_bytes()))
+//! blinding_factor = generate_key(salt: Some(salt_b), ikm: w,             info: None)
+//! entity_salt     = generate_key(salt: Some(salt_s), ikm: w,             info: None)
+//! ```
+//!
+//! This chain is implemented by [derive_entity_secrets].
+//!
+//! For a padding node at coordinate `coord`:
+//! ```text
+//! w               = generate_key(salt: None,         ikm: master_secret, info: Some(coord.to_bytes()))
+//! blinding_factor = generate_key(salt: Some(salt_b), ikm: w,             info: None)
+//! salt            = generate_key(salt: Some(salt_s), ikm: w,             info: None)
+//! ```
+//!
+//! where `master_secret`, `salt_b` & `salt_s` are the byte representations of
+//! the [Secret] and [Salt] values given to the tree (see
+//! [Secret::as_bytes] and [Salt::as_bytes]), and `coord.to_bytes()` is
+//! [Coordinate::to_bytes]. The blinding factor is converted to a Pedersen
+//! commitment scalar by interpreting the bytes as a little-endian integer and
+//! reducing modulo the group order (`Scalar::from_bytes_mod_order`).
+//!
+//! If an entity was given an explicit salt or blinding factor (see [Entity])
+//! then that value is used instead of the derived one.
+//!
+//! [Secret]: crate::Secret
+//! [Salt]: crate::Salt
+//! [Secret::as_bytes]: crate::Secret::as_bytes
+//! [Salt::as_bytes]: crate::Salt::as_bytes
+//! [Coordinate::to_bytes]: crate::Coordinate::to_bytes
+//! [Entity]: crate::Entity
 
 use hkdf::Hkdf;
 use log::error;
@@ -24,6 +66,8 @@ use std::convert::From;
 ///
 /// The output is 256 bits but this can be adjusted. If the size is adjusted the
 /// hash function may need to change too.
+///
+/// The raw bytes can be obtained via `<[u8; 32]>::from(key)`.
 pub struct Key([u8; 32]);
 
 impl From<Key> for [u8; 32] {
@@ -42,7 +86,22 @@ impl From<Key> for [u8; 32] {
 /// since this state is a potential security vulnerability, and should only be
 /// reachable if there is a bug in the code, a panic is the best option.
 ///
-/// The Output Key Material (OKM) is returned as a [Key] type.
+/// The Output Key Material (OKM) is returned as a [Key] type. The output
+/// length is always 32 bytes, and the hash function is SHA256.
+///
+/// See the [module-level docs](self) for how this function is used to derive
+/// the node secrets of the tree.
+///
+/// ```
+/// use dapol::kdf::generate_key;
+///
+/// let master_secret = [1u8; 32];
+/// let x_coord = 3u64;
+/// let entity_secret: [u8; 32] =
+///     generate_key(None, &master_secret, Some(&x_coord.to_le_bytes())).into();
+/// let salt_b = [2u8; 32];
+/// let blinding_factor: [u8; 32] = generate_key(Some(&salt_b), &entity_secret, None).into();
+/// ```
 pub fn generate_key(salt: Option<&[u8]>, ikm: &[u8], info: Option<&[u8]>) -> Key {
     if salt.is_none() && info.is_none() {
         error!("At least one of salt/info must be set when using the KDF to generate keys");
@@ -57,6 +116,47 @@ pub fn generate_key(salt: Option<&[u8]>, ikm: &[u8], info: Option<&[u8]>) -> Key
     Key(okm)
 }
 
+/// Derive the blinding factor & salt of the bottom-layer leaf node of the
+/// entity mapped to `x_coord`, in that order.
+///
+/// This is the derivation chain for leaf nodes given in the
+/// [module-level docs](self), with `w` being derived from `master_secret` &
+/// `x_coord`. Values given explicitly for the entity (see [Entity]) are not
+/// taken into account here.
+pub fn derive_entity_secrets(
+    master_secret: &[u8],
+    salt_b: &[u8],
+    salt_s: &[u8],
+    x_coord: u64,
+) -> (Key, Key) {
+    let entity_secret = entity_secret(master_secret, x_coord);
+
+    (
+        generate_key(Some(salt_b), &entity_secret, None),
+        generate_key(Some(salt_s), &entity_secret, None),
+    )
+}
+
+/// Derive only the blinding factor of the bottom-layer leaf node of the
+/// entity mapped to `x_coord`, see [derive_entity_secrets].
+pub fn derive_entity_blinding_factor(master_secret: &[u8], salt_b: &[u8], x_coord: u64) -> Key {
+    generate_key(Some(salt_b), &entity_secret(master_secret, x_coord), None)
+}
+
+/// Derive only the salt of the bottom-layer leaf node of the entity mapped to
+/// `x_coord`, see [derive_entity_secrets].
+///
+/// The salt does not depend on `salt_b`, so this can be used by an entity
+/// that only knows `master_secret` & `salt_s`.
+pub fn derive_entity_salt(master_secret: &[u8], salt_s: &[u8], x_coord: u64) -> Key {
+    generate_key(Some(salt_s), &entity_secret(master_secret, x_coord), None)
+}
+
+/// `w` in the DAPOL+ paper.
+fn entity_secret(master_secret: &[u8], x_coord: u64) -> [u8; 32] {
+    generate_key(None, master_secret, Some(&x_coord.to_le_bytes())).into()
+}
+
 // -------------------------------------------------------------------------------------------------
 // Unit tests.
 
@@ -88,4 +188,24 @@ mod tests {
         let key = generate_key(Some(&salt), ikm, Some(&info));
         assert_eq!(key.0, expected_okm);
     }
+
+    #[test]
+    fn derived_entity_secrets_follow_the_documented_chain() {
+        let (master_secret, salt_b, salt_s, x_coord) = ([1u8; 32], [2u8; 32], [3u8; 32], 7u64);
+
+        let w: [u8; 32] = generate_key(None, &master_secret, Some(&x_coord.to_le_bytes())).into();
+        let (blinding_factor, salt) =
+            derive_entity_secrets(&master_secret, &salt_b, &salt_s, x_coord);
+
+        assert_eq!(blinding_factor.0, generate_key(Some(&salt_b), &w, None).0);
+        assert_eq!(salt.0, generate_key(Some(&salt_s), &w, None).0);
+        assert_eq!(
+            derive_entity_blinding_factor(&master_secret, &salt_b, x_coord).0,
+            blinding_factor.0
+        );
+        assert_eq!(
+            derive_entity_salt(&master_secret, &salt_s, x_coord).0,
+            salt.0
+        );
+    }
 }
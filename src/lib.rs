@@ -60,7 +60,7 @@
 //! - verify an inclusion proof using a root hash (no tree required)
 //!
 //! ```
-#![doc = include_str!("../examples/main.rs")]
+#![cfg_attr(feature = "full", doc = include_str!("../examples/main.rs"))]
 //! ```
 //!
 //! ### Features
@@ -72,28 +72,73 @@
 //! ### Testing
 //!
 //! This feature opens up additional functions for use withing the library, for usage in tests. One such functionality is the seeding of the NDM-SMT random mapping mechanism. During tests it's useful to be able to get deterministic tree builds, which cannot be done with plain NDM-SMT because the entities are randomly mapped to bottom-layer nodes. So adding the `testing` feature exposes functions that allow calling code to provide seeds for the PRNG from [rand].
+//!
+//! ### Async
+//!
+//! This feature adds async versions of the tree constructor & deserializer (e.g. `DapolTree::new_async`) which run the blocking work on [tokio]'s blocking thread pool, so that they do not block the async runtime. The core of the library stays runtime-agnostic without this feature.
+//!
+//! ### Protobuf
+//!
+//! This feature adds `InclusionProof::to_protobuf` & `InclusionProof::from_protobuf`, which encode & decode inclusion proofs using the schema in [proto/inclusion_proof.proto](https://github.com/silversixpence-crypto/dapol/blob/main/proto/inclusion_proof.proto). This allows clients written in other languages to read the proofs.
+//!
+//! ### Full & verify-only
+//!
+//! The `full` feature is enabled by default and contains everything needed to build trees & generate proofs (including the CLI). Verifiers (auditors, browser clients) only need to check proofs, so they can leave it out to get a much smaller artifact without rayon, dashmap, clap and the file parsers:
+//!
+//! ```toml,ignore
+//! dapol = { version = "0.3", default-features = false, features = ["verify-only"] }
+//! ```
+//!
+//! Only the following APIs are available in verify-only mode:
+//! - [InclusionProof::deserialize] (and `InclusionProof::from_protobuf` with
+//!   the `proto` feature) for reading proofs
+//! - [InclusionProof::verify] & [InclusionProof::verify_membership_only] for
+//!   verifying proofs against a root hash
+//! - [MembershipProof] & [JointInclusionProof], and their verify functions
+//! - the types used by the above, such as [Node], [Coordinate], [Height],
+//!   [EntityId], [AggregationFactor] & [Hasher]
+//!
+//! `RootPublicData` needs the `full` feature, but a proof can be verified
+//! against published root data by passing the root hash to
+//! [InclusionProof::verify].
 
-mod kdf;
+pub mod kdf;
 
+#[cfg(feature = "full")]
 pub mod cli;
 pub mod percentage;
 pub mod read_write_utils;
 pub mod utils;
 
+#[cfg(feature = "full")]
 mod dapol_tree;
+#[cfg(all(feature = "full", any(test, feature = "testing")))]
+pub use dapol_tree::LiabilityStats;
+#[cfg(feature = "full")]
 pub use dapol_tree::{
-    DapolTree, DapolTreeError, RootPublicData, RootSecretData, SERIALIZED_ROOT_PUB_FILE_PREFIX,
-    SERIALIZED_ROOT_PVT_FILE_PREFIX, SERIALIZED_TREE_EXTENSION, SERIALIZED_TREE_FILE_PREFIX,
+    AuditSummary, BuildReport, DapolTree, DapolTreeError, RootHashParserError, RootPublicData,
+    RootSecretData, ShardInfo, SERIALIZED_ENTITY_MAPPING_FILE_PREFIX,
+    SERIALIZED_ROOT_PUB_FILE_PREFIX, SERIALIZED_ROOT_PVT_FILE_PREFIX, SERIALIZED_TREE_EXTENSION,
+    SERIALIZED_TREE_FILE_PREFIX,
 };
 
 pub use curve25519_dalek_ng::{ristretto::RistrettoPoint, scalar::Scalar};
 
+#[cfg(feature = "full")]
+mod build_options;
+#[cfg(feature = "full")]
+pub use build_options::BuildOptions;
+
+#[cfg(feature = "full")]
 mod dapol_config;
+#[cfg(feature = "full")]
 pub use dapol_config::{
     DapolConfig, DapolConfigBuilder, DapolConfigBuilderError, DapolConfigError,
 };
 
+#[cfg(feature = "full")]
 mod accumulators;
+#[cfg(feature = "full")]
 pub use accumulators::AccumulatorType;
 
 mod salt;
@@ -102,7 +147,17 @@ pub use salt::Salt;
 mod hasher;
 pub use hasher::Hasher;
 
+mod commitment_equality;
+pub use commitment_equality::{EqualityProof, EqualityProofError};
+
+#[cfg(feature = "full")]
+mod cancellation_token;
+#[cfg(feature = "full")]
+pub use cancellation_token::CancellationToken;
+
+#[cfg(feature = "full")]
 mod max_thread_count;
+#[cfg(feature = "full")]
 pub use max_thread_count::{initialize_machine_parallelism, MaxThreadCount, MACHINE_PARALLELISM};
 
 mod max_liability;
@@ -111,16 +166,47 @@ pub use max_liability::{
 };
 
 mod binary_tree;
-pub use binary_tree::{Height, HeightError, MAX_HEIGHT, MIN_HEIGHT};
+#[cfg(feature = "full")]
+pub use binary_tree::BuildStrategy;
+#[cfg(all(feature = "full", any(test, feature = "testing")))]
+pub use binary_tree::{compute_root_only, InputLeafNode, TreeBuildError};
+pub use binary_tree::{
+    Coordinate, FullNodeContent, Height, HeightError, HiddenNodeContent, Node, PathInfoFormat,
+    MAX_HEIGHT, MIN_HEIGHT,
+};
 
 mod secret;
 pub use secret::{Secret, SecretParserError};
 
 mod inclusion_proof;
-pub use inclusion_proof::{AggregationFactor, InclusionProof, InclusionProofError, InclusionProofFileType};
+#[cfg(feature = "proto")]
+pub use inclusion_proof::ProtobufError;
+#[cfg(feature = "full")]
+pub use inclusion_proof::{verify_proofs_multi_root, BatchVerificationError, ProofBatchVerifier};
+pub use inclusion_proof::{
+    AggregationFactor, CombinedProofReader, CombinedProofWriter, InclusionProof,
+    InclusionProofError, InclusionProofFileType, JointInclusionProof, JointInclusionProofError,
+    MembershipProof, MembershipProofError, OptimizationTarget, StreamingVerificationError,
+    StreamingVerifier, COMBINED_PROOFS_EXTENSION,
+};
+
+#[cfg(feature = "full")]
+mod proof_server;
+#[cfg(feature = "full")]
+pub use proof_server::ProofServer;
+
+#[cfg(feature = "full")]
+mod compact_proof_store;
+#[cfg(feature = "full")]
+pub use compact_proof_store::{CompactProofStore, SERIALIZED_COMPACT_PROOF_STORE_EXTENSION};
 
 mod entity;
-pub use entity::{Entity, EntityId, EntityIdsParser, EntityIdsParserError};
+#[cfg(feature = "full")]
+pub use entity::{
+    DuplicatePolicy, EntitiesParser, EntitiesParserError, EntityIdsParser, EntityIdsParserError,
+    RowError,
+};
+pub use entity::{Entity, EntityId};
 
 /// Used for surfacing fuzzing tests to the fuzzing module in the ./fuzz
 /// directory.
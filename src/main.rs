@@ -1,14 +1,14 @@
-use std::{path::PathBuf, str::FromStr};
+use std::{path::Path, str::FromStr};
 
 use clap::Parser;
-use log::debug;
+use log::{debug, error, info};
 
 use dapol::{
     cli::{BuildKindCommand, Cli, Command},
     initialize_machine_parallelism,
     utils::{activate_logging, Consume, IfNoneThen, LogOnErr, LogOnErrUnwrap},
-    AggregationFactor, DapolConfig, DapolConfigBuilder, DapolTree, EntityIdsParser, InclusionProof,
-    InclusionProofFileType,
+    AggregationFactor, DapolConfig, DapolConfigBuilder, DapolTree, EntitiesParser, EntityIdsParser,
+    InclusionProof, InclusionProofFileType,
 };
 use patharg::InputArg;
 
@@ -23,6 +23,7 @@ fn main() {
             gen_proofs,
             serialize,
             root_serialize,
+            output_dir,
         } => {
             initialize_machine_parallelism();
 
@@ -45,6 +46,10 @@ fn main() {
                     None
                 };
 
+            if gen_proofs.is_some() {
+                create_proofs_dir(&output_dir);
+            }
+
             let dapol_tree: DapolTree = match build_kind {
                 BuildKindCommand::New {
                     accumulator_type,
@@ -53,24 +58,49 @@ fn main() {
                     height,
                     max_liability,
                     max_thread_count,
+                    build_algorithm,
                     secrets_file,
                     entity_source,
-                } => DapolConfigBuilder::default()
-                    .accumulator_type(accumulator_type)
-                    .salt_b_opt(salt_b)
-                    .salt_s_opt(salt_s)
-                    .max_liability(max_liability)
-                    .height(height)
-                    .max_thread_count(max_thread_count)
-                    .entities_file_path_opt(
-                        entity_source.entities_file.and_then(|arg| arg.into_path()),
-                    )
-                    .num_random_entities_opt(entity_source.random_entities)
-                    .secrets_file_path_opt(secrets_file.into_path())
-                    .build()
-                    .log_on_err_unwrap()
-                    .parse()
-                    .log_on_err_unwrap(),
+                    #[cfg(feature = "testing")]
+                    random_seed,
+                } => {
+                    let entity_records = if entity_source.entities_stdin {
+                        Some(
+                            EntitiesParser::from_reader(std::io::stdin())
+                                .parse_reader()
+                                .log_on_err_unwrap(),
+                        )
+                    } else {
+                        None
+                    };
+
+                    let mut config_builder = DapolConfigBuilder::default();
+                    config_builder
+                        .accumulator_type(accumulator_type)
+                        .salt_b_opt(salt_b)
+                        .salt_s_opt(salt_s)
+                        .max_liability(max_liability)
+                        .height_opt(height)
+                        .max_thread_count(max_thread_count)
+                        .build_strategy(build_algorithm)
+                        .entities_file_path_opt(
+                            entity_source.entities_file.and_then(|arg| arg.into_path()),
+                        )
+                        .num_random_entities_opt(entity_source.random_entities)
+                        .entity_records_opt(entity_records)
+                        .secrets_file_path_opt(secrets_file.into_path());
+
+                    #[cfg(feature = "testing")]
+                    if let Some(random_seed) = random_seed {
+                        config_builder.random_seed(random_seed);
+                    }
+
+                    config_builder
+                        .build()
+                        .log_on_err_unwrap()
+                        .parse()
+                        .log_on_err_unwrap()
+                }
                 BuildKindCommand::Deserialize { path } => DapolTree::deserialize(
                     path.into_path().expect("Expected file path, not stdout"),
                 )
@@ -100,16 +130,13 @@ fn main() {
                 .parse()
                 .log_on_err_unwrap();
 
-                let dir = PathBuf::from("./inclusion_proofs/");
-                std::fs::create_dir(dir.as_path()).log_on_err_unwrap();
-
                 for entity_id in entity_ids {
                     let proof = dapol_tree
                         .generate_inclusion_proof(&entity_id)
                         .log_on_err_unwrap();
 
                     proof
-                        .serialize(&entity_id, dir.clone(), InclusionProofFileType::Json)
+                        .serialize(&entity_id, output_dir.clone(), InclusionProofFileType::Json)
                         .log_on_err_unwrap();
                 }
             }
@@ -134,7 +161,10 @@ fn main() {
             tree_file,
             range_proof_aggregation,
             file_type,
+            output_dir,
         } => {
+            create_proofs_dir(&output_dir);
+
             let dapol_tree = DapolTree::deserialize(
                 tree_file
                     .into_path()
@@ -159,11 +189,6 @@ fn main() {
             .parse()
             .log_on_err_unwrap();
 
-            let dir = PathBuf::from("./inclusion_proofs/");
-            if !dir.exists() {
-                std::fs::create_dir(dir.as_path()).log_on_err_unwrap();
-            }
-
             let aggregation_factor = AggregationFactor::Percent(range_proof_aggregation);
 
             for entity_id in entity_ids {
@@ -172,7 +197,7 @@ fn main() {
                     .log_on_err_unwrap();
 
                 proof
-                    .serialize(&entity_id, dir.clone(), file_type.clone())
+                    .serialize(&entity_id, output_dir.clone(), file_type.clone())
                     .log_on_err_unwrap();
             }
         }
@@ -180,12 +205,14 @@ fn main() {
             file_path,
             root_hash,
             show_path,
+            path_format,
         } => {
             let file_path = file_path
                 .into_path()
                 .expect("Expected file path, not stdin");
 
             let proof = InclusionProof::deserialize(file_path.clone()).log_on_err_unwrap();
+            info!("Verifying {}", proof);
 
             if show_path {
                 proof
@@ -199,12 +226,34 @@ fn main() {
                             .file_name()
                             .expect("Expected file_path to have a file name")
                             .to_os_string(),
+                        path_format,
                     )
                     .log_on_err_unwrap();
             } else {
                 proof.verify(root_hash).log_on_err_unwrap();
             }
         }
+        Command::ConvertProof {
+            input,
+            out_type,
+            out,
+        } => {
+            let input = input.into_path().expect("Expected file path, not stdin");
+
+            let out = match out {
+                Some(patharg) => patharg.into_path().expect("Expected file path, not stdout"),
+                None => input.with_extension(out_type.extension()),
+            };
+
+            if let Some(parent) = out.parent() {
+                std::fs::create_dir_all(parent).log_on_err_unwrap();
+            }
+
+            InclusionProof::deserialize(input)
+                .log_on_err_unwrap()
+                .serialize_to_path(out, out_type)
+                .log_on_err_unwrap();
+        }
         Command::VerifyRoot { root_pub, root_pvt } => {
             let public_root_data = DapolTree::deserialize_public_root_data(
                 root_pub.into_path().expect("Expected file path, not stdin"),
@@ -218,6 +267,36 @@ fn main() {
             DapolTree::verify_root_commitment(&public_root_data.commitment, &secret_root_data)
                 .log_on_err_unwrap();
         }
+        Command::ExtractRoot {
+            tree_file,
+            out_dir,
+            public_only,
+        } => {
+            let dapol_tree = DapolTree::deserialize(
+                tree_file
+                    .into_path()
+                    .expect("Expected file path, not stdin"),
+            )
+            .log_on_err_unwrap();
+
+            // This makes sure the path is a directory, so that the default
+            // file names are used for the root data files.
+            std::fs::create_dir_all(&out_dir).log_on_err_unwrap();
+
+            let path = dapol_tree
+                .serialize_public_root_data(out_dir.clone())
+                .log_on_err_unwrap();
+            info!("Public root data written to {:?}", path);
+
+            if public_only {
+                debug!("Public-only flag set, skipping serialization of the secret root data");
+            } else {
+                let path = dapol_tree
+                    .serialize_secret_root_data(out_dir)
+                    .log_on_err_unwrap();
+                info!("Secret root data written to {:?}", path);
+            }
+        }
     }
 }
 
@@ -227,3 +306,14 @@ fn build_kind_is_deserialize(build_kind: &BuildKindCommand) -> bool {
     };
     std::mem::discriminant(build_kind) == std::mem::discriminant(&dummy)
 }
+
+/// Create the directory that inclusion proofs are written to, if it does not
+/// exist already.
+fn create_proofs_dir(dir: &Path) {
+    if dir.exists() && !dir.is_dir() {
+        error!("Proofs output path {:?} exists but is not a directory", dir);
+        panic!("Proofs output path {:?} exists but is not a directory", dir);
+    }
+
+    std::fs::create_dir_all(dir).log_on_err_unwrap();
+}
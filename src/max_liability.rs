@@ -1,6 +1,7 @@
-use log::error;
 use serde::{Deserialize, Serialize};
 
+use crate::Entity;
+
 /// The default max liability.
 ///
 /// We would like to accommodate as high a value as possible while still being
@@ -46,16 +47,56 @@ impl MaxLiability {
         self.0
     }
 
-    /// Take the logarithm of the underlying value and return the smallest
-    /// allowed bit length that is greater than this value.
+    /// Derive the max liability from the largest liability of the entities.
+    ///
+    /// The smallest allowed range proof bit length (see
+    /// [ALLOWED_RANGE_PROOF_UPPER_BIT_SIZES]) that covers the largest
+    /// liability is chosen, and the max liability is set to the largest
+    /// value that fits in that bit length. This avoids range proof failures
+    /// from a bound that is too small, and avoids the extra proof size of a
+    /// bound that is too large.
+    ///
+    /// Note that the bit length is public (it is part of every inclusion
+    /// proof), so deriving it from the data leaks a coarse upper bound on the
+    /// liabilities of the entities.
+    ///
+    /// Example:
+    /// ```
+    /// use dapol::{Entity, EntityId, MaxLiability};
+    /// use std::str::FromStr;
+    ///
+    /// let entities = vec![Entity {
+    ///     liability: 1000u64,
+    ///     id: EntityId::from_str("id").unwrap(),
+    ///     salt: None,
+    ///     blinding_factor: None,
+    /// }];
+    ///
+    /// let max_liability = MaxLiability::from_entities(&entities);
+    /// assert_eq!(max_liability.as_range_proof_upper_bound_bit_length(), 16);
+    /// ```
+    pub fn from_entities(entities: &[Entity]) -> MaxLiability {
+        let max = entities
+            .iter()
+            .map(|entity| entity.liability)
+            .max()
+            .unwrap_or(0);
+
+        let bit_length = MaxLiability(max).as_range_proof_upper_bound_bit_length();
+
+        MaxLiability(u64::MAX >> (64 - bit_length as u32))
+    }
+
+    /// Return the smallest allowed bit length that can hold the underlying
+    /// value.
     pub fn as_range_proof_upper_bound_bit_length(&self) -> u8 {
-        let pow_2 = (self.0 as f64).log2() as u8;
+        let num_bits = (u64::BITS - self.0.leading_zeros()) as u8;
         *ALLOWED_RANGE_PROOF_UPPER_BIT_SIZES
             .iter()
-            .find(|i| **i > pow_2)
+            .find(|i| **i >= num_bits)
             .unwrap_or_else(|| {
                 panic!(
-                    "[BUG] It should not be possible for the base 2 logarithm of {} to be above 64",
+                    "[BUG] It should not be possible for {} to need more than 64 bits",
                     self.0
                 )
             })
@@ -96,8 +137,10 @@ impl FromStr for MaxLiability {
 // -------------------------------------------------------------------------------------------------
 // Into for OsStr.
 
+#[cfg(feature = "full")]
 use clap::builder::{OsStr, Str};
 
+#[cfg(feature = "full")]
 impl From<MaxLiability> for OsStr {
     fn from(max_liability: MaxLiability) -> OsStr {
         OsStr::from(Str::from(max_liability.as_u64().to_string()))
@@ -144,5 +187,74 @@ mod tests {
         );
     }
 
-    // TODO test more cases for the upper_bound_bit_length function
+    #[test]
+    fn upper_bound_bit_length_works_at_bit_length_edges() {
+        assert_eq!(MaxLiability(0).as_range_proof_upper_bound_bit_length(), 8);
+        assert_eq!(MaxLiability(255).as_range_proof_upper_bound_bit_length(), 8);
+        assert_eq!(
+            MaxLiability(256).as_range_proof_upper_bound_bit_length(),
+            16
+        );
+        assert_eq!(
+            MaxLiability(DEFAULT_MAX_LIABILITY).as_range_proof_upper_bound_bit_length(),
+            64
+        );
+        assert_eq!(
+            MaxLiability(u64::MAX).as_range_proof_upper_bound_bit_length(),
+            64
+        );
+    }
+
+    mod from_entities {
+        use super::*;
+        use crate::EntityId;
+
+        fn entities_with_liabilities(liabilities: &[u64]) -> Vec<Entity> {
+            liabilities
+                .iter()
+                .enumerate()
+                .map(|(i, liability)| Entity {
+                    liability: *liability,
+                    id: EntityId::from_str(&format!("entity {}", i)).unwrap(),
+                    salt: None,
+                    blinding_factor: None,
+                })
+                .collect()
+        }
+
+        #[test]
+        fn smallest_covering_bit_length_is_chosen() {
+            let cases = [
+                (vec![0u64], 8u8),
+                (vec![3, 255, 7], 8),
+                (vec![3, 256, 7], 16),
+                (vec![65_535], 16),
+                (vec![1, 65_536], 32),
+                (vec![u32::MAX as u64], 32),
+                (vec![u32::MAX as u64 + 1], 64),
+                (vec![u64::MAX], 64),
+            ];
+
+            for (liabilities, bit_length) in cases {
+                let max_liability =
+                    MaxLiability::from_entities(&entities_with_liabilities(&liabilities));
+                assert_eq!(
+                    max_liability.as_range_proof_upper_bound_bit_length(),
+                    bit_length
+                );
+            }
+        }
+
+        #[test]
+        fn max_liability_is_largest_value_for_bit_length() {
+            let max_liability = MaxLiability::from_entities(&entities_with_liabilities(&[300]));
+            assert_eq!(max_liability.as_u64(), 65_535);
+        }
+
+        #[test]
+        fn no_entities_gives_smallest_bit_length() {
+            let max_liability = MaxLiability::from_entities(&[]);
+            assert_eq!(max_liability.as_u64(), 255);
+        }
+    }
 }
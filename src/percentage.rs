@@ -1,5 +1,6 @@
 //! Wrapper for holding an integer-valued percentage.
 
+#[cfg(feature = "full")]
 use clap::builder::{OsStr, Str};
 use serde::{Deserialize, Serialize};
 use std::{convert::TryFrom, num::ParseIntError, str::FromStr};
@@ -8,19 +9,41 @@ pub const ZERO_PERCENT: Percentage = Percentage { value: 0 };
 pub const FIFTY_PERCENT: Percentage = Percentage { value: 50 };
 pub const ONE_HUNDRED_PERCENT: Percentage = Percentage { value: 100 };
 
+/// Integer percentage in the range [0, 100].
+///
+/// The range is checked whenever a [Percentage] is constructed, including
+/// when it is deserialized, so a value greater than 100 can never be held.
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(try_from = "UncheckedPercentage")]
 pub struct Percentage {
     value: u8,
 }
 
+/// Same serialized format as [Percentage], used to check the range on
+/// deserialization.
+#[derive(Deserialize)]
+struct UncheckedPercentage {
+    value: u8,
+}
+
 impl Percentage {
+    /// Returns a new `Percentage` with the given value.
+    /// Returns an error if the value is greater than 100.
+    pub fn try_new(value: u8) -> Result<Percentage, PercentageParserError> {
+        if value > 100 {
+            Err(PercentageParserError::InputTooBig(value))
+        } else {
+            Ok(Percentage { value })
+        }
+    }
+
     /// Returns a new `Percentage` with the given value.
     /// Panics if the value is greater than 100.
     ///
     /// Note that if we try to implement the From trait then we have a
     /// collision.
     pub fn expect_from(value: u8) -> Percentage {
-        match Percentage::try_from(value) {
+        match Percentage::try_new(value) {
             Err(e) => panic!("{}", e),
             Ok(p) => p,
         }
@@ -43,11 +66,15 @@ impl TryFrom<u8> for Percentage {
     /// Returns a new `Percentage` with the given value.
     /// Returns an error if the value is greater than 100.
     fn try_from(value: u8) -> Result<Self, Self::Error> {
-        if value > 100 {
-            Err(PercentageParserError::InputTooBig(value))
-        } else {
-            Ok(Percentage { value })
-        }
+        Percentage::try_new(value)
+    }
+}
+
+impl TryFrom<UncheckedPercentage> for Percentage {
+    type Error = PercentageParserError;
+
+    fn try_from(unchecked: UncheckedPercentage) -> Result<Self, Self::Error> {
+        Percentage::try_new(unchecked.value)
     }
 }
 
@@ -71,10 +98,11 @@ impl FromStr for Percentage {
     /// Constructor that takes in a string slice.
     /// If the length of the str is greater than the max then Err is returned.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Percentage::try_from(u8::from_str(s)?)
+        Percentage::try_new(u8::from_str(s)?)
     }
 }
 
+#[cfg(feature = "full")]
 impl From<Percentage> for OsStr {
     fn from(percentage: Percentage) -> OsStr {
         OsStr::from(Str::from(percentage.value.to_string()))
@@ -101,6 +129,31 @@ mod tests {
         assert_err!(res, Err(PercentageParserError::InputTooBig(101)));
     }
 
+    #[test]
+    fn try_new_gives_err_if_value_is_over_100() {
+        assert_eq!(Percentage::try_new(100).unwrap().value(), 100);
+        let res = Percentage::try_new(150);
+        assert_err!(res, Err(PercentageParserError::InputTooBig(150)));
+    }
+
+    #[test]
+    fn deserialize_gives_err_if_value_is_over_100() {
+        let percentage: Percentage = serde_json::from_str(r#"{"value":50}"#).unwrap();
+        assert_eq!(percentage, Percentage::expect_from(50));
+
+        let res = serde_json::from_str::<Percentage>(r#"{"value":150}"#);
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn serialized_format_is_unchanged() {
+        let percentage = Percentage::expect_from(50);
+        assert_eq!(
+            serde_json::to_string(&percentage).unwrap(),
+            r#"{"value":50}"#
+        );
+    }
+
     #[test]
     fn from_str_happy_case() {
         Percentage::from_str("50").unwrap();
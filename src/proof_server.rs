@@ -0,0 +1,125 @@
+//! Inclusion proof generation without the tree secrets.
+//!
+//! A [DapolTree] needs the master secret & salts to generate inclusion proofs
+//! because nodes that are not in the tree's store (including padding nodes)
+//! have to be regenerated, and that requires the KDF. A [ProofServer] is
+//! created from a [DapolTree] by filling the tree's store (so that no node
+//! ever has to be regenerated) and then discarding the secrets. This means a
+//! machine that only serves inclusion proofs does not need to hold the master
+//! secret.
+//!
+//! Example:
+//! ```ignore
+//! let proof_server = dapol_tree.into_proof_server()?;
+//! let proof = proof_server.generate_inclusion_proof(&entity_id)?;
+//! ```
+//!
+//! [DapolTree]: crate::DapolTree
+
+use curve25519_dalek_ng::ristretto::RistrettoPoint;
+use primitive_types::H256;
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+
+use crate::{
+    accumulators::{Accumulator, NdmSmtError},
+    AggregationFactor, EntityId, Height, InclusionProof, MaxLiability, RootPublicData,
+};
+
+/// Inclusion proof generator that holds no secrets.
+///
+/// Created using [DapolTree::into_proof_server]. All the nodes of the tree
+/// are in the store so inclusion proofs are generated without regenerating
+/// any nodes, which is what would require the master secret & salts.
+///
+/// [DapolTree::into_proof_server]: crate::DapolTree::into_proof_server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProofServer {
+    accumulator: Accumulator,
+    max_liability: MaxLiability,
+    root_generated_at: Option<u64>,
+}
+
+impl ProofServer {
+    /// Constructor.
+    ///
+    /// The store of the tree in `accumulator` is expected to be full,
+    /// otherwise proof generation will fail for some entities.
+    pub(crate) fn new(
+        accumulator: Accumulator,
+        max_liability: MaxLiability,
+        root_generated_at: Option<u64>,
+    ) -> Self {
+        ProofServer {
+            accumulator,
+            max_liability,
+            root_generated_at,
+        }
+    }
+
+    /// Generate an inclusion proof for the given `entity_id`.
+    ///
+    /// Parameters:
+    /// - `entity_id`: unique ID for the entity that the proof will be generated
+    ///   for.
+    /// - `aggregation_factor`:
+    #[doc = include_str!("./shared_docs/aggregation_factor.md")]
+    pub fn generate_inclusion_proof_with(
+        &self,
+        entity_id: &EntityId,
+        aggregation_factor: AggregationFactor,
+    ) -> Result<InclusionProof, NdmSmtError> {
+        match &self.accumulator {
+            Accumulator::NdmSmt(ndm_smt) => ndm_smt.generate_inclusion_proof_from_store(
+                entity_id,
+                aggregation_factor,
+                self.max_liability.as_range_proof_upper_bound_bit_length(),
+            ),
+        }
+    }
+
+    /// Generate an inclusion proof for the given `entity_id`.
+    ///
+    /// Parameters:
+    /// - `entity_id`: unique ID for the entity that the proof will be generated
+    ///   for.
+    pub fn generate_inclusion_proof(
+        &self,
+        entity_id: &EntityId,
+    ) -> Result<InclusionProof, NdmSmtError> {
+        self.generate_inclusion_proof_with(entity_id, AggregationFactor::default())
+    }
+
+    #[doc = include_str!("./shared_docs/height.md")]
+    pub fn height(&self) -> &Height {
+        self.accumulator.height()
+    }
+
+    /// Mapping of [EntityId] to x-coord on the bottom layer of the tree.
+    ///
+    /// If the underlying accumulator is an NDM-SMT then a hashmap is returned
+    /// otherwise None is returned.
+    pub fn entity_mapping(&self) -> Option<&HashMap<EntityId, u64>> {
+        match &self.accumulator {
+            Accumulator::NdmSmt(ndm_smt) => Some(ndm_smt.entity_mapping()),
+        }
+    }
+
+    #[doc = include_str!("./shared_docs/root_hash.md")]
+    pub fn root_hash(&self) -> &H256 {
+        self.accumulator.root_hash()
+    }
+
+    #[doc = include_str!("./shared_docs/root_commitment.md")]
+    pub fn root_commitment(&self) -> &RistrettoPoint {
+        self.accumulator.root_commitment()
+    }
+
+    /// Hash & Pedersen commitment for the root node of the Merkle Sum Tree,
+    /// with the same metadata as for the tree the server was created from.
+    pub fn public_root_data(&self) -> RootPublicData {
+        RootPublicData::new(*self.root_hash(), *self.root_commitment())
+            .with_generated_at(self.root_generated_at)
+    }
+}
@@ -1,11 +1,10 @@
 //! Utility functions for reading and writing to files.
 
 use std::fmt::Debug;
-use std::io::{BufReader, Write};
+use std::io::{BufReader, Read, Write};
 use std::path::PathBuf;
 use std::{ffi::OsString, fs::File};
 
-use log::error;
 use logging_timer::{executing, finish, stime, stimer, Level};
 use serde::{de::DeserializeOwned, Serialize};
 
@@ -63,8 +62,8 @@ pub fn serialize_to_json_file<T: Serialize>(
     structure: &T,
     path: PathBuf,
 ) -> Result<(), ReadWriteError> {
-    let mut file = File::create(path)?;
-    let encoded = serde_json::to_writer_pretty(file, structure);
+    let file = File::create(path)?;
+    serde_json::to_writer_pretty(file, structure)?;
 
     Ok(())
 }
@@ -83,6 +82,123 @@ pub fn deserialize_from_json_file<T: DeserializeOwned>(path: PathBuf) -> Result<
     Ok(decoded)
 }
 
+/// Use [rmp_serde] to serialize `structure` to a MessagePack file at the given
+/// `path`.
+///
+/// Struct fields are encoded as maps keyed by field name (rather than as
+/// arrays) so that the file can be decoded without knowledge of the field
+/// order, which makes it easier to consume from other languages.
+///
+/// An error is returned if
+/// 1. [rmp_serde] fails to serialize the file.
+/// 2. There is an issue opening or writing the file.
+///
+/// Turning on debug-level logs will show timing.
+#[stime("debug")]
+pub fn serialize_to_msgpack_file<T: Serialize>(
+    structure: &T,
+    path: PathBuf,
+) -> Result<(), ReadWriteError> {
+    let encoded: Vec<u8> = rmp_serde::to_vec_named(structure)?;
+
+    let mut file = File::create(path)?;
+    file.write_all(&encoded)?;
+
+    Ok(())
+}
+
+/// Try to deserialize the given MessagePack file to the specified type.
+///
+/// An error is returned if
+/// 1. The file cannot be opened.
+/// 2. The [rmp_serde] deserializer fails.
+#[stime("debug")]
+pub fn deserialize_from_msgpack_file<T: DeserializeOwned>(
+    path: PathBuf,
+) -> Result<T, ReadWriteError> {
+    let file = File::open(path)?;
+    let buf_reader = BufReader::new(file);
+    let decoded: T = rmp_serde::from_read(buf_reader)?;
+
+    Ok(decoded)
+}
+
+/// Use [bincode] to serialize `structure` and write it to `writer` as a single
+/// length-prefixed frame.
+///
+/// The frame is the length of the encoded structure as an 8-byte little-endian
+/// integer, followed by the encoded structure. Many frames can be written to
+/// the same writer one after the other, and then read back one at a time with
+/// [read_framed], without ever holding more than 1 of them in memory.
+///
+/// An error is returned if
+/// 1. [bincode] fails to serialize the structure.
+/// 2. There is an issue writing to `writer`.
+pub fn write_framed<W: Write, T: Serialize>(
+    writer: &mut W,
+    structure: &T,
+) -> Result<(), ReadWriteError> {
+    let encoded: Vec<u8> = bincode::serialize(structure)?;
+
+    writer.write_all(&(encoded.len() as u64).to_le_bytes())?;
+    writer.write_all(&encoded)?;
+
+    Ok(())
+}
+
+/// Read the next frame written by [write_framed] from `reader` and deserialize
+/// it to the specified type.
+///
+/// `Ok(None)` is returned if `reader` is at the end of the stream i.e. there
+/// are no more frames.
+///
+/// An error is returned if
+/// 1. There is an issue reading from `reader`.
+/// 2. The stream ends part way through a frame.
+/// 3. The [bincode] deserializer fails.
+pub fn read_framed<R: Read, T: DeserializeOwned>(
+    reader: &mut R,
+) -> Result<Option<T>, ReadWriteError> {
+    let mut len_bytes = [0u8; 8];
+    let mut num_read = 0;
+
+    while num_read < len_bytes.len() {
+        match reader.read(&mut len_bytes[num_read..]) {
+            Ok(0) => break,
+            Ok(n) => num_read += n,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    if num_read == 0 {
+        return Ok(None);
+    }
+
+    if num_read < len_bytes.len() {
+        return Err(ReadWriteError::TruncatedFrame {
+            expected: len_bytes.len() as u64,
+            actual: num_read as u64,
+        });
+    }
+
+    let len = u64::from_le_bytes(len_bytes);
+
+    // The length is not trusted for the allocation, only the bytes that are
+    // actually in the stream are read.
+    let mut encoded = Vec::new();
+    reader.take(len).read_to_end(&mut encoded)?;
+
+    if (encoded.len() as u64) < len {
+        return Err(ReadWriteError::TruncatedFrame {
+            expected: len,
+            actual: encoded.len() as u64,
+        });
+    }
+
+    Ok(Some(bincode::deserialize(&encoded)?))
+}
+
 /// Parse `path` as one that points to a file that will be used for
 /// serialization.
 ///
@@ -191,6 +307,10 @@ pub enum ReadWriteError {
     BincodeSerdeError(#[from] bincode::Error),
     #[error("Problem serializing/deserializing with serde_json")]
     JsonSerdeError(#[from] serde_json::Error),
+    #[error("Problem serializing with rmp_serde")]
+    MsgPackSerializationError(#[from] rmp_serde::encode::Error),
+    #[error("Problem deserializing with rmp_serde")]
+    MsgPackDeserializationError(#[from] rmp_serde::decode::Error),
     #[error("Problem writing to file")]
     FileWriteError(#[from] std::io::Error),
     #[error("Unknown file extension {actual:?}, expected {expected}")]
@@ -199,6 +319,8 @@ pub enum ReadWriteError {
     NotAFile(OsString),
     #[error("No file extension found in path {0:?}")]
     NoFileExtension(OsString),
+    #[error("Frame is truncated: expected {expected} bytes but only {actual} were read")]
+    TruncatedFrame { expected: u64, actual: u64 },
 }
 
 // -------------------------------------------------------------------------------------------------
@@ -251,4 +373,77 @@ mod tests {
 
         // TODO test binary & json se/de workse
     }
+
+    mod msgpack {
+        use super::super::*;
+        use serde::Deserialize;
+
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct TestStruct {
+            number: u64,
+            text: String,
+        }
+
+        #[test]
+        fn msgpack_serde_does_not_change_structure() {
+            let structure = TestStruct {
+                number: 7,
+                text: "seven".to_string(),
+            };
+
+            let src_dir = env!("CARGO_MANIFEST_DIR");
+            let path = PathBuf::from(src_dir)
+                .join("examples")
+                .join("read_write_utils_test.msgpack");
+
+            serialize_to_msgpack_file(&structure, path.clone()).unwrap();
+            let decoded: TestStruct = deserialize_from_msgpack_file(path.clone()).unwrap();
+            std::fs::remove_file(path).unwrap();
+
+            assert_eq!(structure, decoded);
+        }
+    }
+
+    mod framed {
+        use super::super::*;
+        use crate::utils::test_utils::assert_err;
+
+        #[test]
+        fn frames_are_read_back_in_order() {
+            let mut buf = Vec::new();
+            write_framed(&mut buf, &1u64).unwrap();
+            write_framed(&mut buf, &"two".to_string()).unwrap();
+
+            let mut reader = buf.as_slice();
+            assert_eq!(read_framed::<_, u64>(&mut reader).unwrap(), Some(1));
+            assert_eq!(
+                read_framed::<_, String>(&mut reader).unwrap(),
+                Some("two".to_string())
+            );
+            assert_eq!(read_framed::<_, u64>(&mut reader).unwrap(), None);
+        }
+
+        #[test]
+        fn truncated_frame_gives_error() {
+            let mut buf = Vec::new();
+            write_framed(&mut buf, &"some text".to_string()).unwrap();
+            buf.truncate(buf.len() - 1);
+
+            let res = read_framed::<_, String>(&mut buf.as_slice());
+            assert_err!(res, Err(ReadWriteError::TruncatedFrame { .. }));
+        }
+
+        #[test]
+        fn truncated_length_prefix_gives_error() {
+            let buf = [1u8, 0, 0];
+            let res = read_framed::<_, u64>(&mut buf.as_slice());
+            assert_err!(
+                res,
+                Err(ReadWriteError::TruncatedFrame {
+                    expected: 8,
+                    actual: 3
+                })
+            );
+        }
+    }
 }
@@ -1,7 +1,7 @@
 use logging_timer::time;
 use rand::{
     distributions::{Alphanumeric, DistString},
-    thread_rng,
+    thread_rng, RngCore,
 };
 use serde_with::{DeserializeFromStr, SerializeDisplay};
 use std::convert::From;
@@ -39,8 +39,17 @@ impl Salt {
     /// Use a cryptographic PRNG to produce a random salt value.
     #[time("debug", "NdmSmt::NdmSmtSalts::{}")]
     pub fn generate_random() -> Self {
-        let mut rng = thread_rng();
-        let random_str = Alphanumeric.sample_string(&mut rng, MAX_LENGTH_BYTES);
+        Salt::random_with_rng(&mut thread_rng())
+    }
+
+    /// Produce a random salt value using the given PRNG.
+    ///
+    /// A seeded PRNG gives the same salt every time, which is useful for
+    /// building reproducible trees in tests. The PRNG should be
+    /// cryptographically secure when the salt is used in production; prefer
+    /// [Salt::generate_random] in that case.
+    pub fn random_with_rng(rng: &mut impl RngCore) -> Self {
+        let random_str = Alphanumeric.sample_string(rng, MAX_LENGTH_BYTES);
         Salt::from_str(&random_str).expect(STRING_CONVERSION_ERR_MSG)
     }
 }
@@ -114,8 +123,10 @@ impl From<u64> for Salt {
 // -------------------------------------------------------------------------------------------------
 // From for OsStr (for the CLI).
 
+#[cfg(feature = "full")]
 use clap::builder::OsStr;
 
+#[cfg(feature = "full")]
 impl From<Salt> for OsStr {
     // https://stackoverflow.com/questions/19076719/how-do-i-convert-a-vector-of-bytes-u8-to-a-string
     fn from(salt: Salt) -> OsStr {
@@ -167,4 +178,16 @@ mod tests {
                 < threshold
         );
     }
+
+    #[test]
+    fn same_seed_gives_same_salt() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let salt_1 = Salt::random_with_rng(&mut StdRng::seed_from_u64(7));
+        let salt_2 = Salt::random_with_rng(&mut StdRng::seed_from_u64(7));
+        let salt_3 = Salt::random_with_rng(&mut StdRng::seed_from_u64(8));
+
+        assert_eq!(salt_1, salt_2);
+        assert_ne!(salt_1, salt_3);
+    }
 }
@@ -1,6 +1,7 @@
 use serde_with::{DeserializeFromStr, SerializeDisplay};
 use std::convert::From;
 use std::fmt;
+use zeroize::{Zeroize, ZeroizeOnDrop};
 
 /// The max size of the secret is 256 bits, but this is a soft limit so it
 /// can be increased if necessary. Note that the underlying array length will
@@ -21,13 +22,30 @@ pub const MAX_LENGTH_BYTES: usize = 32;
 /// Currently there is no need for the functionality provided by something like
 /// [primitive_types][U256] or [num256][Uint256] but those are options for
 /// later need be.
-#[derive(Debug, Clone, PartialEq, SerializeDisplay, DeserializeFromStr)]
+///
+/// The underlying bytes are zeroized when the secret is dropped. This includes
+/// secrets that were created via deserialization, and any clones (each clone
+/// zeroizes its own copy). Note that copies of the bytes that have been taken
+/// out of the struct (e.g. via [Secret::as_bytes] or [Into]) are not
+/// zeroized.
+#[derive(Debug, Clone, PartialEq, SerializeDisplay, DeserializeFromStr, Zeroize, ZeroizeOnDrop)]
 pub struct Secret([u8; 32]);
 
 impl Secret {
     pub fn as_bytes(&self) -> &[u8; 32] {
         &self.0
     }
+
+    /// Compare the bytes of 2 secrets in constant time.
+    ///
+    /// Unlike [PartialEq], all bytes are always compared, so the time taken
+    /// does not leak how many leading bytes match.
+    #[cfg(feature = "full")]
+    pub(crate) fn constant_time_eq(&self, other: &Secret) -> bool {
+        use subtle::ConstantTimeEq;
+
+        self.0.ct_eq(&other.0).into()
+    }
 }
 
 // -------------------------------------------------------------------------------------------------
@@ -87,12 +105,37 @@ impl FromStr for Secret {
     }
 }
 
+// -------------------------------------------------------------------------------------------------
+// From for Salt.
+
+use crate::Salt;
+
+impl From<Salt> for Secret {
+    fn from(salt: Salt) -> Self {
+        Secret(salt.into())
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// From for Scalar.
+
+use curve25519_dalek_ng::scalar::Scalar;
+
+impl From<Scalar> for Secret {
+    /// The canonical little-endian encoding of the scalar is used, so reducing
+    /// the bytes mod the group order gives back the same scalar.
+    fn from(scalar: Scalar) -> Self {
+        Secret(scalar.to_bytes())
+    }
+}
+
 // -------------------------------------------------------------------------------------------------
 // Into for raw bytes.
 
 impl From<Secret> for [u8; 32] {
     fn from(item: Secret) -> Self {
-        item.0
+        // The bytes are copied out because `item` zeroizes them on drop.
+        *item.as_bytes()
     }
 }
 
@@ -5,8 +5,10 @@
 // -------------------------------------------------------------------------------------------------
 // Logging.
 
+#[cfg(feature = "full")]
 use clap_verbosity_flag::LevelFilter;
 
+#[cfg(feature = "full")]
 pub fn activate_logging(log_level: LevelFilter) {
     env_logger::Builder::new().filter_level(log_level).init();
 }
@@ -150,6 +152,7 @@ pub mod test_utils {
 
     /// Same as [assert_err] but without needing debug
     /// https://stackoverflow.com/a/65618681
+    #[cfg(feature = "full")]
     macro_rules! assert_err_simple {
         ($expression:expr, $($pattern:tt)+) => {
             match $expression {
@@ -158,12 +161,57 @@ pub mod test_utils {
             }
         }
     }
+    #[cfg(feature = "full")]
     pub(crate) use assert_err_simple;
 
+    #[cfg(feature = "full")]
     pub fn init_logger() {
         let _ =
             env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("trace"))
                 .is_test(true)
                 .try_init();
     }
+
+    /// Entities `id0..id{num_entities}` with liability equal to their index.
+    #[cfg(feature = "full")]
+    pub fn test_entities(num_entities: u64) -> Vec<crate::Entity> {
+        use std::str::FromStr;
+
+        (0..num_entities)
+            .map(|i| crate::Entity {
+                liability: i,
+                id: crate::EntityId::from_str(&format!("id{}", i)).unwrap(),
+                salt: None,
+                blinding_factor: None,
+            })
+            .collect()
+    }
+
+    /// NDM-SMT tree over `entities` with fixed salts.
+    ///
+    /// `random_seed` fixes both the entity placement & the master secret, so
+    /// the same seed always gives the same tree and different seeds give
+    /// trees with different blinding factors.
+    #[cfg(feature = "full")]
+    pub fn new_test_tree(
+        height: u8,
+        entities: Vec<crate::Entity>,
+        random_seed: u64,
+    ) -> crate::DapolTree {
+        use crate::{AccumulatorType, BuildOptions, DapolTree, Height, MaxLiability, Salt, Secret};
+        use std::str::FromStr;
+
+        DapolTree::new_with_options(
+            AccumulatorType::NdmSmt,
+            Secret::from_str(&format!("master_secret_{}", random_seed)).unwrap(),
+            Salt::from_str("salt_b").unwrap(),
+            Salt::from_str("salt_s").unwrap(),
+            Height::expect_from(height),
+            entities,
+            BuildOptions::default()
+                .with_max_liability(MaxLiability::from(10_000_000))
+                .with_random_seed(random_seed),
+        )
+        .unwrap()
+    }
 }